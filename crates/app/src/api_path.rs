@@ -0,0 +1,46 @@
+//! The paths of the service endpoints
+//!
+//! The service may be behind a reverse proxy that serves it under a prefix, e.g.
+//! `/tank/api/v1/sensor`. The prefix is set at build time with the `API_BASE_PATH` environment
+//! variable and is prepended to the path of every endpoint. A trailing `/` is ignored. A base
+//! path that doesn't start with `/` or that contains whitespace is invalid and is ignored.
+//!
+//! The paths are built without logging because the log uploader uses them too.
+
+use heapless::String;
+
+/// The prefix of the service endpoint paths, e.g. `/tank`
+const API_BASE_PATH: Option<&str> = option_env!("API_BASE_PATH");
+
+/// The maximum length of an endpoint path, including the base path
+pub const MAX_API_PATH_LENGTH: usize = 128;
+
+/// The path of a service endpoint, including the base path
+pub type ApiPath = String<MAX_API_PATH_LENGTH>;
+
+/// The path of the service endpoint with the given sub path, e.g. `/api/v1/sensor`
+pub fn api_path(sub_path: &str) -> ApiPath {
+    compose_api_path(API_BASE_PATH, sub_path)
+}
+
+/// The base path without the trailing `/`. Empty if the base path is not set or is invalid.
+fn api_base_path(value: Option<&str>) -> &str {
+    match value.map(|path| path.trim()) {
+        Some(path) if path.starts_with('/') && !path.contains(char::is_whitespace) => {
+            path.trim_end_matches('/')
+        }
+        _ => "",
+    }
+}
+
+/// Prepend the base path to the sub path. Only the sub path is used if the combined path is
+/// too long.
+fn compose_api_path(base_path: Option<&str>, sub_path: &str) -> ApiPath {
+    let mut path = ApiPath::new();
+    if path.push_str(api_base_path(base_path)).is_err() || path.push_str(sub_path).is_err() {
+        path.clear();
+        let _ = path.push_str(sub_path);
+    }
+
+    path
+}
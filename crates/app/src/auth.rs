@@ -0,0 +1,72 @@
+//! Authentication with the ingestion endpoints of the service
+
+use heapless::String;
+
+use log::warn;
+
+use tank_sensor_level_core::auth::sign_payload_at;
+pub use tank_sensor_level_core::auth::PayloadSignature;
+
+use crate::clock::unix_time_in_seconds;
+
+/// API key for the ingestion endpoints of the service. Set at build time with the
+/// `INGEST_API_KEY` environment variable. If not set, requests are sent without authentication.
+const INGEST_API_KEY: Option<&str> = option_env!("INGEST_API_KEY");
+
+/// The maximum length of the API key
+const MAX_API_KEY_LENGTH: usize = 128;
+
+/// The scheme prefix for the authorization header value
+const BEARER_PREFIX: &str = "Bearer ";
+
+/// The name of the authorization header
+pub const AUTHORIZATION_HEADER_NAME: &str = "Authorization";
+
+/// The value of the authorization header for the ingestion endpoints
+pub type AuthorizationHeaderValue = String<{ MAX_API_KEY_LENGTH + BEARER_PREFIX.len() }>;
+
+/// Create the value of the authorization header for the ingestion endpoints
+///
+/// Returns `None` if no API key is configured, or if the configured API key is empty or longer
+/// than `MAX_API_KEY_LENGTH`.
+pub fn ingest_authorization() -> Option<AuthorizationHeaderValue> {
+    let api_key = INGEST_API_KEY?;
+    if api_key.is_empty() || api_key.len() > MAX_API_KEY_LENGTH {
+        return None;
+    }
+
+    let mut value = AuthorizationHeaderValue::new();
+    value.push_str(BEARER_PREFIX).ok()?;
+    value.push_str(api_key).ok()?;
+
+    Some(value)
+}
+
+/// Shared secret used to sign the metric payloads. Set at build time with the
+/// `INGEST_HMAC_SECRET` environment variable. If not set, payloads are sent without a signature.
+const INGEST_HMAC_SECRET: Option<&str> = option_env!("INGEST_HMAC_SECRET");
+
+/// The name of the header that contains the payload signature
+pub const SIGNATURE_HEADER_NAME: &str = "X-Signature";
+
+/// The name of the header that contains the time at which the payload was signed
+pub const SIGNATURE_TIMESTAMP_HEADER_NAME: &str = "X-Signature-Timestamp";
+
+/// Sign a payload with the shared secret
+///
+/// The signature covers the timestamp as well as the payload so that the service can reject
+/// requests that are replayed later. Returns `None` if no secret is configured or if the time
+/// hasn't been received from the service yet.
+pub fn sign_payload(payload: &[u8]) -> Option<PayloadSignature> {
+    let secret = INGEST_HMAC_SECRET.filter(|s| !s.is_empty())?;
+
+    let unix_time = match unix_time_in_seconds() {
+        Some(t) => t,
+        None => {
+            warn!("The current time is not known. Cannot sign the payload.");
+            return None;
+        }
+    };
+
+    sign_payload_at(secret, unix_time, payload)
+}
@@ -0,0 +1,42 @@
+//! The reason the device booted
+//!
+//! The device normally boots because the deep sleep timer expired or, if one is configured, the
+//! wake GPIO woke it. Any other reason, e.g. a brownout or a software reset after WiFi failed to
+//! disconnect, points to a problem. The reason is sent with the metrics so that restart storms
+//! can be diagnosed.
+
+use esp_hal::reset::{reset_reason, wakeup_cause};
+use esp_hal::rtc_cntl::{SleepSource, SocResetReason};
+pub use tank_sensor_level_core::boot_reason::BootReason;
+
+/// Determine the boot reason from the reset reason and, for a wake up from deep sleep, the
+/// source that woke the device
+fn boot_reason_from_reset(reason: Option<SocResetReason>, wakeup_cause: SleepSource) -> BootReason {
+    match reason {
+        Some(SocResetReason::ChipPowerOn) => BootReason::PowerOn,
+        // The wake GPIO is an EXT1 wake up source
+        Some(SocResetReason::CoreDeepSleep) => BootReason::deep_sleep_wake(matches!(
+            wakeup_cause,
+            SleepSource::Ext0 | SleepSource::Ext1 | SleepSource::Gpio
+        )),
+        Some(SocResetReason::SysBrownOut) => BootReason::Brownout,
+        Some(SocResetReason::CoreSw | SocResetReason::Cpu0Sw) => BootReason::SoftwareReset,
+        Some(
+            SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::Cpu0Mwdt0
+            | SocResetReason::Cpu0Mwdt1
+            | SocResetReason::Cpu0RtcWdt
+            | SocResetReason::SysRtcWdt
+            | SocResetReason::SysSuperWdt,
+        ) => BootReason::Watchdog,
+        Some(_) => BootReason::Other,
+        None => BootReason::Unknown,
+    }
+}
+
+/// Read the reason for the current boot from the hardware
+pub fn boot_reason() -> BootReason {
+    boot_reason_from_reset(reset_reason(), wakeup_cause())
+}
@@ -0,0 +1,7 @@
+//! Helpers for reading configuration values from build time environment variables
+//!
+//! The parsers are in `tank_sensor_level_core::build_env` so that they can be tested on the host.
+
+pub use tank_sensor_level_core::build_env::{
+    parse_bool_or, parse_u64_in_range_or, parse_u64_or, parse_valid_i2c_address,
+};
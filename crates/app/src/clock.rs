@@ -0,0 +1,318 @@
+//! Wall clock time for the device
+//!
+//! The device has no real time clock. The time is obtained from an NTP server or, if none of the
+//! NTP servers can be reached, from the response of the service to the timing data. It is stored
+//! as an offset from the system timer so that the current time can be calculated later during
+//! the same wake up.
+//!
+//! The NTP servers are set at build time with the `NTP_SERVERS` environment variable, which is a
+//! comma separated list of host names that are tried in order.
+//!
+//! Querying NTP on every wake up costs power, so the device only resyncs once the resync interval
+//! has passed since the last successful sync. In between the time from the service is used. The
+//! service can change the resync interval with the response to the timing data.
+//!
+//! Each server gets a limited time to answer. A server that fails to answer is asked again in the
+//! same way as the requests to the service are retried, before moving on to the next server. If
+//! none of the servers answer the device carries on with the time it already has.
+//!
+//! The conversion between the time since boot and the unix time is in
+//! `tank_sensor_level_core::clock` so that it can be tested on the host.
+
+use core::cell::Cell;
+use core::future::Future;
+use core::net::SocketAddr;
+
+use critical_section::Mutex;
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::IpAddress;
+use embassy_net::Stack;
+use embassy_time::{with_timeout, Duration};
+
+use esp_hal::ram;
+use esp_hal::time::now;
+
+use log::{debug, info, warn};
+
+use sntpc::{get_time, NtpContext, NtpTimestampGenerator};
+
+use tank_sensor_level_core::clock::{
+    current_unix_time_in_seconds, unix_time_at_boot_in_micro_seconds, TimeSource,
+};
+
+use thiserror::Error;
+
+use crate::retry::{with_retry, Retryable};
+
+/// The NTP servers, separated by commas, in the order they should be tried
+const NTP_SERVERS: Option<&str> = option_env!("NTP_SERVERS");
+
+/// The NTP server if nothing is configured
+const DEFAULT_NTP_SERVERS: &str = "pool.ntp.org";
+
+/// The port on which NTP servers listen
+const NTP_PORT: u16 = 123;
+
+/// The time allowed for the DNS query and the NTP request for a single server
+const NTP_SERVER_TIMEOUT_IN_MILLISECONDS: u64 = 2000;
+
+/// The time between two NTP syncs if the service doesn't provide one
+const DEFAULT_NTP_RESYNC_INTERVAL_IN_SECONDS: u32 = 3600;
+
+/// The shortest time between two NTP syncs that the service can ask for
+const MIN_NTP_RESYNC_INTERVAL_IN_SECONDS: u32 = 60;
+
+/// The longest time between two NTP syncs that the service can ask for
+const MAX_NTP_RESYNC_INTERVAL_IN_SECONDS: u32 = 7 * 24 * 3600;
+
+/// Errors that can occur when getting the time from an NTP server
+#[derive(Error, Debug)]
+pub enum ClockError {
+    #[error("The host name of the NTP server could not be resolved.")]
+    DnsQueryFailed,
+
+    #[error("The NTP server did not respond in time.")]
+    Timeout,
+
+    #[error("The NTP request failed.")]
+    RequestFailed,
+
+    #[error("None of the NTP servers provided the time.")]
+    NoServerAvailable,
+}
+
+impl Retryable for ClockError {
+    fn is_retryable(&self) -> bool {
+        // Asking the same server again doesn't make any of the other servers available
+        !matches!(self, ClockError::NoServerAvailable)
+    }
+}
+
+/// The unix time, in micro seconds, at which the system timer started. `None` until the time has
+/// been received from the service.
+static UNIX_TIME_AT_BOOT_IN_MICRO_SECONDS: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// The unix time, in seconds, of the last successful NTP sync. `None` if the time has never
+/// been received from NTP.
+///
+/// This is placed in the RTC Fast memory, which survives deep sleep.
+#[ram(rtc_fast)]
+static LAST_NTP_SYNC_IN_SECONDS: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// The time between two NTP syncs as requested by the service. `None` if the service didn't
+/// ask for a specific interval.
+///
+/// This is placed in the RTC Fast memory, which survives deep sleep.
+#[ram(rtc_fast)]
+static NTP_RESYNC_INTERVAL_IN_SECONDS: Mutex<Cell<Option<u32>>> = Mutex::new(Cell::new(None));
+
+/// The system timer of the device, which starts at zero when the device boots or wakes up
+#[derive(Clone, Copy, Default)]
+struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now_micros(&self) -> u64 {
+        now().ticks()
+    }
+}
+
+/// Store the current unix time as provided by the service
+pub fn set_unix_time(unix_time_in_seconds: u64) {
+    set_unix_time_from(&SystemTimeSource, unix_time_in_seconds);
+}
+
+/// Store the current unix time as an offset from the time since boot of the time source
+fn set_unix_time_from(time_source: &impl TimeSource, unix_time_in_seconds: u64) {
+    let unix_time_at_boot = unix_time_at_boot_in_micro_seconds(time_source, unix_time_in_seconds);
+    critical_section::with(|cs| {
+        UNIX_TIME_AT_BOOT_IN_MICRO_SECONDS
+            .borrow(cs)
+            .set(Some(unix_time_at_boot))
+    });
+}
+
+/// The current unix time in seconds, if the time has been received from the service
+pub fn unix_time_in_seconds() -> Option<u64> {
+    unix_time_in_seconds_from(&SystemTimeSource)
+}
+
+/// The current unix time in seconds based on the time since boot of the time source
+fn unix_time_in_seconds_from(time_source: &impl TimeSource) -> Option<u64> {
+    let unix_time_at_boot =
+        critical_section::with(|cs| UNIX_TIME_AT_BOOT_IN_MICRO_SECONDS.borrow(cs).get())?;
+    Some(current_unix_time_in_seconds(time_source, unix_time_at_boot))
+}
+
+/// Store the time between two NTP syncs as requested by the service
+pub fn set_ntp_resync_interval(requested_interval_in_seconds: Option<u32>) {
+    critical_section::with(|cs| {
+        NTP_RESYNC_INTERVAL_IN_SECONDS
+            .borrow(cs)
+            .set(requested_interval_in_seconds)
+    });
+}
+
+/// Determine the time between two NTP syncs based on the interval the service asked for.
+/// Intervals outside the allowed range are clamped to the range.
+fn ntp_resync_interval_in_seconds(requested_interval_in_seconds: Option<u32>) -> u32 {
+    match requested_interval_in_seconds {
+        Some(seconds) => seconds.clamp(
+            MIN_NTP_RESYNC_INTERVAL_IN_SECONDS,
+            MAX_NTP_RESYNC_INTERVAL_IN_SECONDS,
+        ),
+        None => DEFAULT_NTP_RESYNC_INTERVAL_IN_SECONDS,
+    }
+}
+
+/// Determine if the time should be requested from NTP. This is the case if the current time
+/// isn't known, if NTP has never provided the time, or if the resync interval has passed.
+fn is_ntp_sync_due(
+    current_time_in_seconds: Option<u64>,
+    last_sync_in_seconds: Option<u64>,
+    resync_interval_in_seconds: u32,
+) -> bool {
+    match (current_time_in_seconds, last_sync_in_seconds) {
+        (Some(current), Some(last_sync)) => {
+            current.saturating_sub(last_sync) >= resync_interval_in_seconds as u64
+                // The clock went backwards, so something is wrong with the stored time
+                || current < last_sync
+        }
+        _ => true,
+    }
+}
+
+/// The NTP servers in the order in which they should be tried. Empty entries are skipped.
+fn ntp_servers(servers: &str) -> impl Iterator<Item = &str> {
+    servers
+        .split(',')
+        .map(|server| server.trim())
+        .filter(|server| !server.is_empty())
+}
+
+/// Try each server in order and return the first successful result
+async fn first_successful<'a, S, F, Fut, T>(servers: S, mut attempt: F) -> Option<T>
+where
+    S: Iterator<Item = &'a str>,
+    F: FnMut(&'a str) -> Fut,
+    Fut: Future<Output = Result<T, ClockError>>,
+{
+    for server in servers {
+        match attempt(server).await {
+            Ok(result) => return Some(result),
+            Err(e) => warn!("Failed to get the time from {server}: {e}"),
+        }
+    }
+
+    None
+}
+
+/// Timestamps for the NTP request. The device doesn't know the time yet so the time since boot
+/// is used, which only affects the offset calculated by the NTP client.
+#[derive(Clone, Copy, Default)]
+struct TimestampGenerator<T: TimeSource> {
+    time_source: T,
+    ticks_in_micro_seconds: u64,
+}
+
+impl<T: TimeSource + Copy> NtpTimestampGenerator for TimestampGenerator<T> {
+    fn init(&mut self) {
+        self.ticks_in_micro_seconds = self.time_source.now_micros();
+    }
+
+    fn timestamp_sec(&self) -> u64 {
+        self.ticks_in_micro_seconds / 1_000_000
+    }
+
+    fn timestamp_subsec_micros(&self) -> u32 {
+        (self.ticks_in_micro_seconds % 1_000_000) as u32
+    }
+}
+
+/// The address to send the NTP request to, which is the first address the DNS query returned
+fn first_address(addresses: &[IpAddress]) -> Result<IpAddress, ClockError> {
+    addresses.first().copied().ok_or(ClockError::DnsQueryFailed)
+}
+
+/// Get the unix time, in seconds, from a single NTP server
+async fn time_from_server(stack: Stack<'_>, server: &str) -> Result<u64, ClockError> {
+    let request = async {
+        let addresses = stack
+            .dns_query(server, DnsQueryType::A)
+            .await
+            .map_err(|_| ClockError::DnsQueryFailed)?;
+        let address = first_address(&addresses)?;
+
+        let mut rx_meta = [PacketMetadata::EMPTY; 4];
+        let mut rx_buffer = [0; 512];
+        let mut tx_meta = [PacketMetadata::EMPTY; 4];
+        let mut tx_buffer = [0; 512];
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        socket.bind(0).map_err(|_| ClockError::RequestFailed)?;
+
+        let result = get_time(
+            SocketAddr::new(address.into(), NTP_PORT),
+            &socket,
+            NtpContext::new(TimestampGenerator::<SystemTimeSource>::default()),
+        )
+        .await
+        .map_err(|_| ClockError::RequestFailed)?;
+
+        Ok(result.sec() as u64)
+    };
+
+    with_timeout(
+        Duration::from_millis(NTP_SERVER_TIMEOUT_IN_MILLISECONDS),
+        request,
+    )
+    .await
+    .map_err(|_| ClockError::Timeout)?
+}
+
+/// Get the time from the first NTP server that responds and store it. The request is skipped if
+/// the resync interval hasn't passed since the last sync.
+pub async fn sync_with_ntp(stack: Stack<'_>) -> Result<(), ClockError> {
+    let time_source = SystemTimeSource;
+    let (last_sync_in_seconds, requested_interval_in_seconds) = critical_section::with(|cs| {
+        (
+            LAST_NTP_SYNC_IN_SECONDS.borrow(cs).get(),
+            NTP_RESYNC_INTERVAL_IN_SECONDS.borrow(cs).get(),
+        )
+    });
+    let resync_interval_in_seconds = ntp_resync_interval_in_seconds(requested_interval_in_seconds);
+    if !is_ntp_sync_due(
+        unix_time_in_seconds_from(&time_source),
+        last_sync_in_seconds,
+        resync_interval_in_seconds,
+    ) {
+        debug!("The last NTP sync was less than {resync_interval_in_seconds}s ago. Skipping NTP.");
+        return Ok(());
+    }
+
+    let servers = NTP_SERVERS.unwrap_or(DEFAULT_NTP_SERVERS);
+    match first_successful(ntp_servers(servers), |server| {
+        debug!("Requesting the time from {server}...");
+        with_retry("NTP request", move || time_from_server(stack, server))
+    })
+    .await
+    {
+        Some(unix_time_in_seconds) => {
+            info!("Received the time from NTP: {unix_time_in_seconds}");
+            set_unix_time_from(&time_source, unix_time_in_seconds);
+            critical_section::with(|cs| {
+                LAST_NTP_SYNC_IN_SECONDS
+                    .borrow(cs)
+                    .set(Some(unix_time_in_seconds))
+            });
+            Ok(())
+        }
+        None => Err(ClockError::NoServerAvailable),
+    }
+}
@@ -0,0 +1,13 @@
+//! Gzip compression of the payloads that are sent to the service
+//!
+//! The encoder itself is in `tank_sensor_level_core::compression` so that it can be tested on
+//! the host.
+
+/// Compress the payloads before sending them. The service must accept gzip request bodies.
+pub const COMPRESS_PAYLOADS: bool = false;
+
+/// The name of the header that describes the encoding of the payload
+pub const CONTENT_ENCODING_HEADER_NAME: &str = "Content-Encoding";
+
+/// The content encoding of a gzip compressed payload
+pub const GZIP_CONTENT_ENCODING: &str = "gzip";
@@ -1,18 +1,27 @@
+use core::cell::Cell;
 use core::fmt::Write;
+use core::future::Future;
+
+use critical_section::Mutex;
 
-use embassy_net::tcp::client::TcpClientState;
 use embassy_net::Stack;
-use embassy_net::{dns::DnsSocket, tcp::client::TcpClient};
 
-use embassy_time::Duration;
+use esp_hal::ram;
 use esp_hal::time::{now, Instant};
-use heapless::String;
+use heapless::{String, Vec};
 
 use log::info;
-use log::{debug, error};
+use log::{debug, error, warn};
+
+use reqwless::headers::ContentType;
+
+use serde::Deserialize;
 
-use reqwless::client::HttpClient;
-use reqwless::{headers::ContentType, request::RequestBuilder};
+use tank_sensor_level_core::compression::{crc32, gzip, max_compressed_length};
+use tank_sensor_level_core::metrics::{
+    combine_send_results, metrics_urls, write_influx_string_field, write_influx_tag,
+};
+use tank_sensor_level_core::payload_queue::PayloadQueue;
 
 use thiserror::Error;
 
@@ -21,15 +30,84 @@ use uom::si::length::meter;
 use uom::si::pressure::pascal;
 use uom::si::{pressure::hectopascal, ratio::percent, thermodynamic_temperature::degree_celsius};
 
+use crate::api_path::{api_path, ApiPath};
+use crate::auth::{sign_payload, SIGNATURE_HEADER_NAME, SIGNATURE_TIMESTAMP_HEADER_NAME};
+use crate::boot_reason::BootReason;
+use crate::cell::SyncUnsafeCell;
+use crate::clock::unix_time_in_seconds;
+use crate::compression::{COMPRESS_PAYLOADS, CONTENT_ENCODING_HEADER_NAME, GZIP_CONTENT_ENCODING};
 use crate::device_meta::DEVICE_LOCATION;
+use crate::device_tags::{device_tags, MAX_DEVICE_TAGS, MAX_TAG_KEY_LENGTH, MAX_TAG_VALUE_LENGTH};
 use crate::meta::CARGO_PKG_VERSION;
-use crate::sensor_data::{Ads1115Data, Bme280Data};
-use crate::wifi::DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS;
+use crate::power::battery_percent;
+use crate::retry::{with_retry, Retryable};
+use crate::sensor_data::{Ads1115Data, Bme280Data, Ds18b20Data, NUMBER_OF_SAMPLES};
+use crate::tank::{tank_fill_percent, tank_volume_liters};
+use crate::tls::{tls_read_buffer_size, tls_write_buffer_size};
+use crate::upload::{post, upload_timeout_in_milliseconds, Upload, UploadError};
 
+/// The URLs of the servers that receive the metrics, separated by commas. The first URL is the
+/// service, which also receives the timing data. The other URLs only receive the metrics.
 const METRICS_URL: &str = env!("METRICS_URL");
+
+/// The name of the header that contains the CRC-32 of the uncompressed metrics payload, so that
+/// the service can tell a payload that was corrupted on the way from an invalid one
+const PAYLOAD_CRC_HEADER_NAME: &str = "X-Payload-CRC32";
+
+/// The TCP timeout, in milliseconds, of the metrics requests. Set at build time with the
+/// `METRICS_TIMEOUT_MS` environment variable.
+const METRICS_TIMEOUT_MS: u64 = upload_timeout_in_milliseconds(option_env!("METRICS_TIMEOUT_MS"));
+
 //const GRAFANA_USER_NAME: &str = env!("GRAFANA_USER_NAME");
 //const GRAFANA_API_KEY: &str = env!("GRAFANA_METRICS_API_KEY");
 
+/// The format of the metrics payload for the servers after the first one in `METRICS_URL`, either
+/// `json` or `influx` for the InfluxDB line protocol. The service, which is the first server,
+/// always receives JSON.
+const METRICS_FORMAT: Option<&str> = option_env!("METRICS_FORMAT");
+
+/// The path, including the query, to which the metrics are posted when they are sent with the
+/// InfluxDB line protocol
+const INFLUX_WRITE_PATH: Option<&str> = option_env!("INFLUX_WRITE_PATH");
+
+/// The InfluxDB write path if nothing is configured. The timestamps are in seconds.
+const DEFAULT_INFLUX_WRITE_PATH: &str = "/write?db=tank_sensor&precision=s";
+
+/// The InfluxDB measurement that the metrics are written to
+const INFLUX_MEASUREMENT: &str = "water_tank";
+
+/// Include the raw ADS1115 channel voltages in the metrics so that the voltage dividers can be
+/// calibrated. Disabled by default to keep the payloads small.
+const CALIBRATION_MODE: bool = false;
+
+/// The maximum size, in bytes, of a formatted metrics payload. Metrics that don't fit are not
+/// sent.
+const MAX_METRICS_LENGTH: usize = 1024;
+
+/// The maximum size, in bytes, of the device tags in the metrics payload. Each tag is written
+/// as `"key":"value",` inside `,"tags":{}`.
+const MAX_TAGS_LENGTH: usize =
+    10 + MAX_DEVICE_TAGS * (MAX_TAG_KEY_LENGTH + MAX_TAG_VALUE_LENGTH + 6);
+
+/// The maximum number of metric payloads that are kept for sending on a later wake up
+const MAX_QUEUED_METRICS: usize = 8;
+
+/// Metric payloads that failed to send
+///
+/// This is a statically allocated variable and it is placed in the RTC Fast
+/// memory, which survives deep sleep. The payloads are lost on power loss.
+#[ram(rtc_fast)]
+static QUEUED_METRICS: SyncUnsafeCell<MetricQueue> = SyncUnsafeCell::new(MetricQueue::new());
+
+/// A ring buffer of metric payloads that failed to send. When the buffer is full the
+/// oldest payload is overwritten.
+type MetricQueue = PayloadQueue<MAX_QUEUED_METRICS, MAX_METRICS_LENGTH>;
+
+/// The sequence number of the next reading during this boot. Together with the boot count it
+/// identifies a reading, so that the service can ignore a reading that is sent twice, e.g. when
+/// the response to the first attempt was lost.
+static NEXT_READING_SEQ: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
 /// A clock error
 #[derive(Error, Debug)]
 pub enum Error {
@@ -38,16 +116,204 @@ pub enum Error {
 
     #[error("The request failed to send.")]
     RequestFailed,
+
+    #[error("No server URL is configured.")]
+    NoServerConfigured,
+
+    #[error("The metrics do not fit in the payload.")]
+    MetricsTooLong,
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        // A rejected payload will be rejected again
+        matches!(self, Error::RequestFailed)
+    }
+}
+
+impl From<UploadError> for Error {
+    fn from(error: UploadError) -> Self {
+        match error {
+            UploadError::NonSuccessResponseCode(_) => Error::NonSuccessResponseCode,
+            UploadError::ConnectionFailed(_) | UploadError::RequestFailed(_) => {
+                Error::RequestFailed
+            }
+        }
+    }
+}
+
+/// The format in which the metrics are sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MetricsFormat {
+    /// JSON for the service
+    Json,
+
+    /// The InfluxDB line protocol
+    Influx,
+}
+
+impl MetricsFormat {
+    /// Get the configured metrics format. JSON if nothing or an unknown format is configured.
+    fn configured() -> Self {
+        match METRICS_FORMAT {
+            Some("json") | None => Self::Json,
+            Some("influx") => Self::Influx,
+            Some(other) => {
+                warn!("{other} is not a known metrics format. Using json.");
+                Self::Json
+            }
+        }
+    }
+
+    /// The format of the metrics for the server at the given position in `METRICS_URL`. The
+    /// first server is the service, which only understands JSON.
+    fn for_server(index: usize) -> Self {
+        if index == 0 {
+            Self::Json
+        } else {
+            Self::configured()
+        }
+    }
+
+    /// The path to which the metrics are posted. The InfluxDB write path doesn't get the API
+    /// base path because InfluxDB isn't behind the same proxy as the service.
+    fn path(&self) -> ApiPath {
+        match self {
+            Self::Json => api_path("/api/v1/sensor"),
+            Self::Influx => {
+                let mut path = ApiPath::new();
+                if path
+                    .push_str(INFLUX_WRITE_PATH.unwrap_or(DEFAULT_INFLUX_WRITE_PATH))
+                    .is_err()
+                {
+                    warn!("INFLUX_WRITE_PATH is too long. Using {DEFAULT_INFLUX_WRITE_PATH}.");
+                    path.clear();
+                    let _ = path.push_str(DEFAULT_INFLUX_WRITE_PATH);
+                }
+
+                path
+            }
+        }
+    }
+
+    /// The content type of the metrics payload
+    fn content_type(&self) -> ContentType {
+        match self {
+            Self::Json => ContentType::ApplicationJson,
+            Self::Influx => ContentType::TextPlain,
+        }
+    }
+}
+
+/// The part of the server response to the metrics that the device uses
+#[derive(Clone, Copy, Default, Deserialize)]
+pub struct MetricsResponse {
+    /// The number of seconds the server would like the device to sleep for
+    #[serde(default)]
+    pub next_sleep_seconds: Option<u32>,
+
+    /// `true` if the tank is in maintenance, in which case the device sleeps as long as it can
+    /// and skips the uploads it can do without
+    #[serde(default)]
+    pub quiet: Option<bool>,
+}
+
+impl MetricsResponse {
+    /// Determine if the server asked the device to be quiet
+    pub fn is_quiet(&self) -> bool {
+        self.quiet.unwrap_or(false)
+    }
+}
+
+/// Get the queue of metric payloads that failed to send
+fn queued_metrics() -> &'static mut MetricQueue {
+    // SAFETY:
+    // There is only one thread on the ESP32-C6 and the queue is only accessed from the main
+    // task, one call at a time.
+    let queue = unsafe { &mut *QUEUED_METRICS.get() };
+    if !queue.is_valid() {
+        warn!("The queued metrics are corrupt. Discarding them.");
+        *queue = MetricQueue::new();
+    }
+
+    queue
+}
+
+/// Store a metric payload that failed to send so that it can be sent on a later wake up
+pub fn queue_failed_metric(payload: &[u8]) {
+    let queue = queued_metrics();
+    match queue.push_back(payload) {
+        Ok(true) => warn!("Metric queue is full. Dropped the oldest queued metric."),
+        Ok(false) => {}
+        Err(e) => {
+            error!("Metric payload of {} bytes is too large to queue", e.length);
+            return;
+        }
+    }
+
+    info!(
+        "Queued metrics for a later wake up. {} queued.",
+        queue.len()
+    );
+}
+
+/// Send the metric payloads that failed to send during earlier wake ups, oldest first
+///
+/// Stops at the first payload that could not be sent so that it can be retried later.
+/// Payloads that are rejected by the server are dropped because sending them again will
+/// not succeed either.
+pub async fn flush_queued_metrics(stack: Stack<'static>) -> Result<(), Error> {
+    let queue = queued_metrics();
+    if queue.is_empty() {
+        return Ok(());
+    }
+
+    info!("Sending {} queued metrics to server ...", queue.len());
+    while let Some(payload) = queue.front() {
+        // The queued payloads are JSON, so they are only sent to the servers that receive JSON
+        let json_servers = metrics_servers().filter(|(_, format)| *format == MetricsFormat::Json);
+        match send_to_each_server(json_servers, |url, format| {
+            send_metrics_payload(stack, url, format, payload)
+        })
+        .await
+        {
+            Ok(_) => {}
+            Err(Error::NonSuccessResponseCode) => {
+                warn!("Server rejected the queued metrics. Dropping them.");
+            }
+            Err(e) => return Err(e),
+        }
+
+        queue.pop_front();
+    }
+
+    Ok(())
+}
+
+/// Take the sequence number for a new reading
+fn next_reading_seq() -> u32 {
+    critical_section::with(|cs| {
+        let seq = NEXT_READING_SEQ.borrow(cs);
+        let current = seq.get();
+        seq.set(current.wrapping_add(1));
+        current
+    })
 }
 
-// Use the influx line protocol from here: https://docs.influxdata.com/influxdb/v1/write_protocols/line_protocol_tutorial/
 fn format_metrics(
     boot_count: u32,
+    boot_reason: BootReason,
+    cold_boot: bool,
+    session_id: u32,
+    reading_seq: u32,
     bme280_data: Bme280Data,
     ads1115_data: Ads1115Data,
+    ds18b20_data: Option<Ds18b20Data>,
+    sensor_ok: bool,
     run_time_in_micro_seconds: u64,
     wifi_start_time: u64,
-) -> String<512> {
+    wifi_signal_strength: Option<i8>,
+) -> Result<String<MAX_METRICS_LENGTH>, core::fmt::Error> {
     let temperature = bme280_data.temperature;
     let humidity = bme280_data.humidity;
     let air_pressure = bme280_data.pressure;
@@ -56,31 +322,196 @@ fn format_metrics(
     let battery_voltage = ads1115_data.battery_voltage;
     let pressure_sensor_voltage = ads1115_data.pressure_sensor_voltage;
     let liquid_height = ads1115_data.height_above_sensor;
-    // liquid_temperature: f32
+    let liquid_volume = tank_volume_liters(liquid_height.get::<meter>());
+
+    // The fill percentage is reported as null when the height of a full tank isn't configured
+    let mut fill_percent: String<8> = String::new();
+    match tank_fill_percent(liquid_height.get::<meter>()) {
+        Some(percent) => write!(fill_percent, "{:.1}", percent),
+        None => write!(fill_percent, "null"),
+    }?;
+
+    // The fraction of the environmental samples that were genuine sensor readings
+    let sample_quality = bme280_data.real_sample_count as f32 / NUMBER_OF_SAMPLES as f32;
+
+    // The water temperature is reported as null when the DS18B20 could not be read so that it
+    // can't be mistaken for a real measurement
+    let mut liquid_temperature: String<8> = String::new();
+    match ds18b20_data {
+        Some(data) => write!(
+            liquid_temperature,
+            "{:.2}",
+            data.temperature.get::<degree_celsius>()
+        ),
+        None => write!(liquid_temperature, "null"),
+    }?;
 
-    // The influx timestamp should be in nano seconds
-    let mut buffer: String<512> = String::new();
+    // The raw channel voltages are only sent when calibrating the voltage dividers
+    let mut raw_voltages: String<96> = String::new();
+    if CALIBRATION_MODE {
+        let [a0, a1, a2, a3] = ads1115_data.raw_channel_voltages;
+        write!(
+            raw_voltages,
+            ",\"raw_voltages\":{{\"a0\":{:.4},\"a1\":{:.4},\"a2\":{:.4},\"a3\":{:.4}}}",
+            a0.get::<volt>(),
+            a1.get::<volt>(),
+            a2.get::<volt>(),
+            a3.get::<volt>(),
+        )?;
+    }
+
+    // The tags are only sent when they are configured
+    let mut tags: String<MAX_TAGS_LENGTH> = String::new();
+    let device_tags = device_tags();
+    if !device_tags.is_empty() {
+        write!(tags, ",\"tags\":{{")?;
+        for (index, (key, value)) in device_tags.iter().enumerate() {
+            if index > 0 {
+                write!(tags, ",")?;
+            }
+            write!(tags, "\"{key}\":\"{value}\"")?;
+        }
+        write!(tags, "}}")?;
+    }
+
+    let mut wifi_rssi: String<8> = String::new();
+    match wifi_signal_strength {
+        Some(rssi) => write!(wifi_rssi, "{rssi}"),
+        None => write!(wifi_rssi, "null"),
+    }?;
+
+    let mut buffer: String<MAX_METRICS_LENGTH> = String::new();
 
     writeln!(
         buffer,
-        "{{\"device_id\":\"{device_id}\",\"firmware_version\":\"{firmware_version}\",\"boot_count\":{boot_count},\"run_time_in_seconds\":{run_time:.3},\"wifi_start_time_in_seconds\":{wifi_start_time:.3},\"temperature_in_celcius\":{temperature:.2},\"humidity_in_percent\":{humidity:.2},\"pressure_in_pascal\":{pressure:.1},\"brightness_in_percent\":{brightness:.3},\"battery_voltage\":{battery_voltage:.3},\"pressure_sensor_voltage\":{pressure_sensor_voltage:.3},\"tank_level_in_meters\":{tank_level:.3},\"tank_temperature_in_celcius\":{tank_temperature:.2}}}",
+        "{{\"device_id\":\"{device_id}\",\"firmware_version\":\"{firmware_version}\",\"boot_count\":{boot_count},\"reading_seq\":{reading_seq},\"boot_reason\":\"{boot_reason}\",\"cold_boot\":{cold_boot},\"session_id\":{session_id},\"run_time_in_seconds\":{run_time:.3},\"wifi_start_time_in_seconds\":{wifi_start_time:.3},\"wifi_rssi_in_dbm\":{wifi_rssi},\"sensor_ok\":{sensor_ok},\"temperature_in_celcius\":{temperature:.2},\"humidity_in_percent\":{humidity:.2},\"pressure_in_pascal\":{pressure:.1},\"brightness_in_percent\":{brightness:.3},\"battery_voltage\":{battery_voltage:.3},\"battery_in_percent\":{battery_percent:.1},\"pressure_sensor_voltage\":{pressure_sensor_voltage:.3},\"pressure_sensor_fault\":{pressure_sensor_fault},\"tank_level_in_meters\":{tank_level:.3},\"tank_volume_in_liters\":{tank_volume:.1},\"tank_temperature_in_celcius\":{tank_temperature},\"sample_quality\":{sample_quality:.2},\"tank_fill_in_percent\":{tank_fill}{tags}{raw_voltages}}}",
         device_id=DEVICE_LOCATION,
         firmware_version=CARGO_PKG_VERSION.unwrap_or("NOT FOUND"),
         boot_count=boot_count,
+        reading_seq=reading_seq,
+        boot_reason=boot_reason.as_str(),
+        cold_boot=cold_boot,
+        session_id=session_id,
         run_time=(run_time_in_micro_seconds as f64) * 1e-6,
         wifi_start_time = (wifi_start_time as f64) * 1e-6,
+        wifi_rssi = wifi_rssi,
+        sensor_ok = sensor_ok,
         temperature=temperature.get::<degree_celsius>(),
         humidity=humidity.get::<percent>(),
         pressure=air_pressure.get::<pascal>(),
         brightness=brightness.get::<percent>(),
         battery_voltage=battery_voltage.get::<volt>(),
+        battery_percent=battery_percent(battery_voltage.get::<volt>()),
         pressure_sensor_voltage=pressure_sensor_voltage.get::<volt>(),
+        pressure_sensor_fault=ads1115_data.pressure_sensor_fault,
         tank_level=liquid_height.get::<meter>(),
-        tank_temperature=temperature.get::<degree_celsius>(),
-    )
-    .unwrap();
+        tank_volume=liquid_volume,
+        tank_temperature=liquid_temperature,
+        sample_quality=sample_quality,
+        tank_fill=fill_percent,
+        tags=tags,
+        raw_voltages=raw_voltages,
+    )?;
 
-    buffer
+    Ok(buffer)
+}
+
+/// Format the metrics with the InfluxDB line protocol, see
+/// https://docs.influxdata.com/influxdb/v1/write_protocols/line_protocol_tutorial/
+///
+/// The device ID and the device tags are the tags of the line. Values that are not known, e.g.
+/// the water temperature when the DS18B20 could not be read, are left out because the line
+/// protocol has no null value. The timestamp is in seconds and is left out when the time is not
+/// known, in which case InfluxDB uses the time at which it received the line.
+fn format_metrics_influx(
+    boot_count: u32,
+    boot_reason: BootReason,
+    cold_boot: bool,
+    session_id: u32,
+    reading_seq: u32,
+    bme280_data: Bme280Data,
+    ads1115_data: Ads1115Data,
+    ds18b20_data: Option<Ds18b20Data>,
+    sensor_ok: bool,
+    run_time_in_micro_seconds: u64,
+    wifi_start_time: u64,
+    wifi_signal_strength: Option<i8>,
+) -> Result<String<MAX_METRICS_LENGTH>, core::fmt::Error> {
+    let liquid_height = ads1115_data.height_above_sensor.get::<meter>();
+    let battery_voltage = ads1115_data.battery_voltage.get::<volt>();
+
+    let mut buffer: String<MAX_METRICS_LENGTH> = String::new();
+
+    write!(buffer, "{INFLUX_MEASUREMENT},device_id=")?;
+    write_influx_tag(&mut buffer, DEVICE_LOCATION)?;
+    for (key, value) in device_tags().iter() {
+        write!(buffer, ",")?;
+        write_influx_tag(&mut buffer, key)?;
+        write!(buffer, "=")?;
+        write_influx_tag(&mut buffer, value)?;
+    }
+
+    write!(buffer, " firmware_version=")?;
+    write_influx_string_field(&mut buffer, CARGO_PKG_VERSION.unwrap_or("NOT FOUND"))?;
+    write!(
+        buffer,
+        ",boot_count={boot_count}i,reading_seq={reading_seq}i,boot_reason=\"{boot_reason}\",cold_boot={cold_boot},session_id={session_id}i,run_time_in_seconds={run_time:.3},wifi_start_time_in_seconds={wifi_start_time:.3}",
+        boot_count = boot_count,
+        reading_seq = reading_seq,
+        boot_reason = boot_reason.as_str(),
+        cold_boot = cold_boot,
+        session_id = session_id,
+        run_time = (run_time_in_micro_seconds as f64) * 1e-6,
+        wifi_start_time = (wifi_start_time as f64) * 1e-6,
+    )?;
+    if let Some(rssi) = wifi_signal_strength {
+        write!(buffer, ",wifi_rssi_in_dbm={rssi}i")?;
+    }
+
+    write!(
+        buffer,
+        ",sensor_ok={sensor_ok},temperature_in_celcius={temperature:.2},humidity_in_percent={humidity:.2},pressure_in_pascal={pressure:.1},brightness_in_percent={brightness:.3},battery_voltage={battery_voltage:.3},battery_in_percent={battery_percent:.1},pressure_sensor_voltage={pressure_sensor_voltage:.3},pressure_sensor_fault={pressure_sensor_fault},tank_level_in_meters={tank_level:.3},tank_volume_in_liters={tank_volume:.1},sample_quality={sample_quality:.2}",
+        sensor_ok = sensor_ok,
+        temperature = bme280_data.temperature.get::<degree_celsius>(),
+        humidity = bme280_data.humidity.get::<percent>(),
+        pressure = bme280_data.pressure.get::<pascal>(),
+        brightness = ads1115_data.enclosure_relative_brightness.get::<percent>(),
+        battery_voltage = battery_voltage,
+        battery_percent = battery_percent(battery_voltage),
+        pressure_sensor_voltage = ads1115_data.pressure_sensor_voltage.get::<volt>(),
+        pressure_sensor_fault = ads1115_data.pressure_sensor_fault,
+        tank_level = liquid_height,
+        tank_volume = tank_volume_liters(liquid_height),
+        sample_quality = bme280_data.real_sample_count as f32 / NUMBER_OF_SAMPLES as f32,
+    )?;
+    if let Some(data) = ds18b20_data {
+        write!(
+            buffer,
+            ",tank_temperature_in_celcius={:.2}",
+            data.temperature.get::<degree_celsius>()
+        )?;
+    }
+    if let Some(percent) = tank_fill_percent(liquid_height) {
+        write!(buffer, ",tank_fill_in_percent={percent:.1}")?;
+    }
+    if CALIBRATION_MODE {
+        let [a0, a1, a2, a3] = ads1115_data.raw_channel_voltages;
+        write!(
+            buffer,
+            ",raw_voltage_a0={:.4},raw_voltage_a1={:.4},raw_voltage_a2={:.4},raw_voltage_a3={:.4}",
+            a0.get::<volt>(),
+            a1.get::<volt>(),
+            a2.get::<volt>(),
+            a3.get::<volt>(),
+        )?;
+    }
+
+    if let Some(timestamp) = unix_time_in_seconds() {
+        write!(buffer, " {timestamp}")?;
+    }
+    writeln!(buffer)?;
+
+    Ok(buffer)
 }
 
 fn log_ads1115_reading(sample: &Ads1115Data) {
@@ -94,9 +525,24 @@ fn log_ads1115_reading(sample: &Ads1115Data) {
         pressure_sensor_voltage
     );
     info!(
-        " ┗ Liquid height above sensor: {:.2} m",
+        " ┣ Liquid height above sensor: {:.2} m",
         height_above_sensor
     );
+    if sample.pressure_sensor_fault {
+        info!(" ┗ Pressure sensor:            disconnected");
+    } else {
+        info!(" ┗ Pressure sensor:            connected");
+    }
+}
+
+fn log_ds18b20_reading(sample: Option<&Ds18b20Data>) {
+    match sample {
+        Some(data) => info!(
+            " ┗ Water temperature: {:.2} C",
+            data.temperature.get::<degree_celsius>()
+        ),
+        None => info!(" ┗ Water temperature: not available"),
+    }
 }
 
 fn log_bme280_reading(sample: &Bme280Data) {
@@ -106,17 +552,27 @@ fn log_bme280_reading(sample: &Bme280Data) {
 
     info!(" ┣ Temperature: {:.2} C", temperature);
     info!(" ┣ Humidity:    {:.2} %", humidity);
-    info!(" ┗ Pressure:    {:.2} hPa", pressure);
+    info!(" ┣ Pressure:    {:.2} hPa", pressure);
+    info!(
+        " ┗ Samples:     {} of {}",
+        sample.real_sample_count, NUMBER_OF_SAMPLES
+    );
 }
 
 pub async fn send_metrics_to_server(
     stack: Stack<'static>,
     bme280_reading: Bme280Data,
     ads1115_reading: Ads1115Data,
+    ds18b20_reading: Option<Ds18b20Data>,
+    sensor_ok: bool,
     boot_count: u32,
+    boot_reason: BootReason,
+    cold_boot: bool,
+    session_id: u32,
     system_start_time: Instant,
     wifi_start_time: u64,
-) -> Result<(), Error> {
+    wifi_signal_strength: Option<i8>,
+) -> Result<Option<MetricsResponse>, Error> {
     info!("Sending metrics to server ...");
 
     let current_time = now();
@@ -127,52 +583,202 @@ pub async fn send_metrics_to_server(
 
     log_ads1115_reading(&ads1115_reading);
     log_bme280_reading(&bme280_reading);
+    log_ds18b20_reading(ds18b20_reading.as_ref());
 
+    // Both payloads describe the same reading, so they share the sequence number
+    let reading_seq = next_reading_seq();
+    let influx_metrics = if metrics_servers().any(|(_, format)| format == MetricsFormat::Influx) {
+        Some(format_metrics_influx(
+            boot_count,
+            boot_reason,
+            cold_boot,
+            session_id,
+            reading_seq,
+            bme280_reading.clone(),
+            ads1115_reading.clone(),
+            ds18b20_reading.clone(),
+            sensor_ok,
+            run_time_in_micro_seconds,
+            wifi_start_time,
+            wifi_signal_strength,
+        ))
+    } else {
+        None
+    };
     let metrics = format_metrics(
         boot_count,
+        boot_reason,
+        cold_boot,
+        session_id,
+        reading_seq,
         bme280_reading,
         ads1115_reading,
+        ds18b20_reading,
+        sensor_ok,
         run_time_in_micro_seconds,
         wifi_start_time,
+        wifi_signal_strength,
     );
-    let bytes = metrics.as_bytes();
-
-    let dns_socket = DnsSocket::new(stack);
-
-    let tcp_client_state = TcpClientState::<1, 4096, 4096>::new();
-    let mut tcp_client = TcpClient::new(stack, &tcp_client_state);
-    tcp_client.set_timeout(Some(Duration::from_millis(
-        DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS,
-    )));
-
-    debug!("Creating HTTP client ...");
-    let mut client = HttpClient::new(&tcp_client, &dns_socket);
-
-    debug!("Creating request ...");
-    let mut rx_buf = [0; 4096];
-    let mut resource = client.resource(METRICS_URL).await.unwrap();
-    let response = resource
-        .post("/api/v1/sensor")
-        .content_type(ContentType::ApplicationJson)
-        .body(bytes);
-
-    debug!("Sending request ...");
-    let response = response.send(&mut rx_buf).await;
-
-    debug!("Processing response ...");
-    match response {
-        Ok(r) => {
-            if r.status.is_successful() {
-                debug!("Sent metrics. Status code: {:?}", r.status);
-                Ok(())
-            } else {
-                error!("Failed to send metrics: Status code {:?}", r.status,);
-                Err(Error::NonSuccessResponseCode)
+
+    if let Err(e) = flush_queued_metrics(stack).await {
+        warn!("Failed to send the queued metrics: {e:?}");
+    }
+
+    // The metrics are longer than expected, e.g. because a sensor returned a huge value.
+    // Sending part of them is no use.
+    let too_long = |_| {
+        error!("The metrics do not fit in {MAX_METRICS_LENGTH} bytes. Not sending them.");
+        Error::MetricsTooLong
+    };
+    let metrics = metrics.map_err(too_long)?;
+    let influx_metrics = influx_metrics.transpose().map_err(too_long)?;
+
+    let payload = metrics.as_bytes();
+    let influx_payload = influx_metrics.as_ref().map_or(payload, |m| m.as_bytes());
+    let result = send_to_each_server(metrics_servers(), |url, format| {
+        let payload = match format {
+            MetricsFormat::Json => payload,
+            MetricsFormat::Influx => influx_payload,
+        };
+        with_retry("metrics", move || {
+            send_metrics_payload(stack, url, format, payload)
+        })
+    })
+    .await;
+
+    // Only the JSON payload is queued. It is sent to the servers that receive JSON.
+    if let Err(Error::RequestFailed) = result {
+        queue_failed_metric(payload);
+    }
+
+    result
+}
+
+/// The URLs of the servers that receive the metrics with the format that each server receives
+fn metrics_servers() -> impl Iterator<Item = (&'static str, MetricsFormat)> {
+    metrics_urls(METRICS_URL)
+        .enumerate()
+        .map(|(index, url)| (url, MetricsFormat::for_server(index)))
+}
+
+/// Send the metrics to every server, so that a failing server doesn't stop the others from
+/// receiving the metrics. Succeeds if at least one server accepted the metrics. The response
+/// is taken from the first server that sent one.
+async fn send_to_each_server<'a, U, F, Fut>(
+    urls: U,
+    mut send: F,
+) -> Result<Option<MetricsResponse>, Error>
+where
+    U: Iterator<Item = (&'a str, MetricsFormat)>,
+    F: FnMut(&'a str, MetricsFormat) -> Fut,
+    Fut: Future<Output = Result<Option<MetricsResponse>, Error>>,
+{
+    let mut combined = None;
+    for (url, format) in urls {
+        let result = send(url, format).await;
+        if let Err(e) = &result {
+            warn!("Failed to send the metrics to {url}: {e:?}");
+        }
+
+        combined = Some(match combined {
+            Some(previous) => combine_send_results(previous, result),
+            None => result,
+        });
+    }
+
+    combined.unwrap_or_else(|| {
+        error!("No metrics server is configured");
+        Err(Error::NoServerConfigured)
+    })
+}
+
+/// Send a metrics payload to the server and return the response of the server, if it sent one
+async fn send_metrics_payload(
+    stack: Stack<'static>,
+    url: &str,
+    format: MetricsFormat,
+    bytes: &[u8],
+) -> Result<Option<MetricsResponse>, Error> {
+    // The signature covers the uncompressed payload, which is what the service verifies after
+    // decompressing the payload
+    let signature = sign_payload(bytes);
+    let mut compressed = [0u8; max_compressed_length(MAX_METRICS_LENGTH)];
+    let compressed_length = if COMPRESS_PAYLOADS {
+        gzip(bytes, &mut compressed)
+    } else {
+        None
+    };
+
+    let mut checksum = String::<8>::new();
+    let _ = write!(checksum, "{:08x}", crc32(bytes));
+
+    let mut headers = Vec::<(&str, &str), 4>::new();
+    let _ = headers.push((PAYLOAD_CRC_HEADER_NAME, checksum.as_str()));
+    if let Some(s) = &signature {
+        let _ = headers.push((SIGNATURE_HEADER_NAME, s.signature.as_str()));
+        let _ = headers.push((SIGNATURE_TIMESTAMP_HEADER_NAME, s.timestamp.as_str()));
+    }
+    let body = match compressed_length {
+        Some(length) => {
+            let _ = headers.push((CONTENT_ENCODING_HEADER_NAME, GZIP_CONTENT_ENCODING));
+            &compressed[..length]
+        }
+        None => bytes,
+    };
+
+    let path = format.path();
+    let upload = Upload {
+        url,
+        path: &path,
+        content_type: format.content_type(),
+        headers: &headers,
+        body,
+        timeout_in_milliseconds: METRICS_TIMEOUT_MS,
+    };
+
+    debug!("Sending metrics ...");
+    let mut tls_read_buffer = [0u8; tls_read_buffer_size(METRICS_URL)];
+    let mut tls_write_buffer = [0u8; tls_write_buffer_size(METRICS_URL)];
+    let result = post(
+        stack,
+        upload,
+        &mut tls_read_buffer,
+        &mut tls_write_buffer,
+        |body| {
+            // InfluxDB doesn't send a response that the device uses
+            if format == MetricsFormat::Influx {
+                return None;
+            }
+
+            match body {
+                Ok(body) => parse_metrics_response(body),
+                Err(e) => {
+                    warn!("Failed to read the metrics response: {:?}", e);
+                    None
+                }
             }
+        },
+    )
+    .await;
+    match result {
+        Ok(response) => {
+            debug!("Sent metrics");
+            Ok(response)
         }
         Err(e) => {
-            error!("Failed to send metrics: error {:?}", e);
-            Err(Error::RequestFailed)
+            error!("Failed to send metrics: {e}");
+            Err(e.into())
+        }
+    }
+}
+
+/// Read the server response to the metrics
+fn parse_metrics_response(body: &[u8]) -> Option<MetricsResponse> {
+    match serde_json_core::from_slice::<MetricsResponse>(body) {
+        Ok((response, _)) => Some(response),
+        Err(e) => {
+            warn!("Failed to parse the metrics response: {:?}", e);
+            None
         }
     }
 }
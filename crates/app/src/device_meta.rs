@@ -1,2 +1,34 @@
 pub const MAX_DEVICE_NAME_LENGTH: usize = 265;
 pub const DEVICE_LOCATION: &str = env!("DEVICE_LOCATION");
+
+/// The maximum length of the device location. The service uses the location as the device ID and
+/// rejects longer IDs.
+const MAX_DEVICE_LOCATION_LENGTH: usize = 64;
+
+// The service rejects every payload with an invalid device ID, so the firmware isn't built with
+// one
+const _: () = assert!(
+    is_valid_device_location(DEVICE_LOCATION),
+    "DEVICE_LOCATION should be 1 to 64 letters, digits, '.', '-' or '_'"
+);
+
+/// Check that the location is not empty, not too long and only contains letters, digits, `.`,
+/// `-` and `_`
+const fn is_valid_device_location(location: &str) -> bool {
+    let bytes = location.as_bytes();
+    if bytes.is_empty() || bytes.len() > MAX_DEVICE_LOCATION_LENGTH {
+        return false;
+    }
+
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        if !(byte.is_ascii_alphanumeric() || byte == b'.' || byte == b'-' || byte == b'_') {
+            return false;
+        }
+
+        index += 1;
+    }
+
+    true
+}
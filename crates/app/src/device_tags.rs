@@ -0,0 +1,80 @@
+//! Tags that describe where the device is installed
+//!
+//! The tags are set at build time with the `DEVICE_TAGS` environment variable, which is a comma
+//! separated list of `key=value` pairs, e.g. `site=farm,zone=north`. They are sent with the
+//! metrics so that the readings of many sites can be grouped.
+//!
+//! Keys and values may only contain letters, digits, `_`, `-` and `.` so that they don't need
+//! to be escaped in the JSON. Tags that are invalid, too long or repeat a key are skipped.
+
+use heapless::Vec;
+
+use log::warn;
+
+/// The tags as a comma separated list of `key=value` pairs
+const DEVICE_TAGS: Option<&str> = option_env!("DEVICE_TAGS");
+
+/// The maximum number of tags
+pub const MAX_DEVICE_TAGS: usize = 4;
+
+/// The maximum length of a tag key
+pub const MAX_TAG_KEY_LENGTH: usize = 16;
+
+/// The maximum length of a tag value
+pub const MAX_TAG_VALUE_LENGTH: usize = 32;
+
+/// The tag keys and values in the order they are configured
+pub type DeviceTags<'a> = Vec<(&'a str, &'a str), MAX_DEVICE_TAGS>;
+
+/// The tags that are configured for the device
+pub fn device_tags() -> DeviceTags<'static> {
+    parse_device_tags(DEVICE_TAGS.unwrap_or(""))
+}
+
+/// Parse a comma separated list of `key=value` pairs. Invalid pairs and pairs beyond
+/// `MAX_DEVICE_TAGS` are skipped.
+fn parse_device_tags(value: &str) -> DeviceTags<'_> {
+    let mut tags = DeviceTags::new();
+    for pair in value
+        .split(',')
+        .map(|pair| pair.trim())
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                warn!("Device tag {pair} is not a key=value pair. Skipping it.");
+                continue;
+            }
+        };
+
+        if !is_valid_tag_part(key, MAX_TAG_KEY_LENGTH)
+            || !is_valid_tag_part(value, MAX_TAG_VALUE_LENGTH)
+        {
+            warn!(
+                "Device tag {pair} is empty, too long or contains invalid characters. Skipping it."
+            );
+            continue;
+        }
+
+        if tags.iter().any(|(existing, _)| *existing == key) {
+            warn!("Device tag {key} is set more than once. Using the first value.");
+            continue;
+        }
+
+        if tags.push((key, value)).is_err() {
+            warn!("Only {MAX_DEVICE_TAGS} device tags are supported. Skipping {pair}.");
+        }
+    }
+
+    tags
+}
+
+/// Determine if a tag key or value is valid
+fn is_valid_tag_part(part: &str, max_length: usize) -> bool {
+    !part.is_empty()
+        && part.len() <= max_length
+        && part
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.'))
+}
@@ -0,0 +1,185 @@
+//! Driver for the DS18B20 water temperature probe
+//!
+//! The probe is connected to a single GPIO pin with an external 4.7kΩ pull-up resistor and
+//! is the only device on the 1-Wire bus, so all commands are addressed with SKIP ROM.
+
+use embassy_time::{Duration, Timer};
+
+use esp_hal::delay::Delay;
+use esp_hal::gpio::{GpioPin, Level, OutputOpenDrain, Pull};
+
+use log::debug;
+
+use thiserror::Error;
+
+use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+/// Address all devices on the bus
+const SKIP_ROM_COMMAND: u8 = 0xCC;
+
+/// Start a temperature conversion
+const CONVERT_TEMPERATURE_COMMAND: u8 = 0x44;
+
+/// Read the 9 bytes of the scratchpad
+const READ_SCRATCHPAD_COMMAND: u8 = 0xBE;
+
+/// Maximum time a 12-bit temperature conversion takes
+const CONVERSION_TIME_IN_MILLISECONDS: u64 = 750;
+
+/// The number of bytes in the scratchpad, including the CRC byte
+const SCRATCHPAD_LENGTH: usize = 9;
+
+/// The temperature register value after power-on. Reading it back means no conversion took place.
+const POWER_ON_RESET_TEMPERATURE_RAW: i16 = 0x0550;
+
+/// Error while reading the DS18B20
+#[derive(Debug, Error)]
+pub enum Ds18b20Error {
+    #[error("No device answered the 1-Wire reset pulse.")]
+    NoDevicePresent,
+
+    #[error("The scratchpad CRC did not match.")]
+    CrcMismatch,
+
+    #[error("The temperature conversion did not complete.")]
+    ConversionNotCompleted,
+}
+
+/// A bit-banged 1-Wire bus with a single DS18B20 on it
+struct OneWire {
+    pin: OutputOpenDrain<'static>,
+    delay: Delay,
+}
+
+impl OneWire {
+    fn new(pin: GpioPin<19>) -> Self {
+        Self {
+            pin: OutputOpenDrain::new(pin, Level::High, Pull::None),
+            delay: Delay::new(),
+        }
+    }
+
+    /// Send a reset pulse and return whether a device answered with a presence pulse
+    fn reset(&mut self) -> bool {
+        critical_section::with(|_| {
+            self.pin.set_low();
+            self.delay.delay_micros(480);
+            self.pin.set_high();
+            self.delay.delay_micros(70);
+            let present = self.pin.is_low();
+            self.delay.delay_micros(410);
+            present
+        })
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        critical_section::with(|_| {
+            self.pin.set_low();
+            if bit {
+                self.delay.delay_micros(6);
+                self.pin.set_high();
+                self.delay.delay_micros(64);
+            } else {
+                self.delay.delay_micros(60);
+                self.pin.set_high();
+                self.delay.delay_micros(10);
+            }
+        });
+    }
+
+    fn read_bit(&mut self) -> bool {
+        critical_section::with(|_| {
+            self.pin.set_low();
+            self.delay.delay_micros(6);
+            self.pin.set_high();
+            self.delay.delay_micros(9);
+            let bit = self.pin.is_high();
+            self.delay.delay_micros(55);
+            bit
+        })
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        for n in 0..8 {
+            self.write_bit(byte & (1 << n) != 0);
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = 0;
+        for n in 0..8 {
+            if self.read_bit() {
+                byte |= 1 << n;
+            }
+        }
+
+        byte
+    }
+
+    fn send_command(&mut self, command: u8) -> Result<(), Ds18b20Error> {
+        if !self.reset() {
+            return Err(Ds18b20Error::NoDevicePresent);
+        }
+
+        self.write_byte(SKIP_ROM_COMMAND);
+        self.write_byte(command);
+        Ok(())
+    }
+}
+
+/// Dallas/Maxim CRC-8 as used by the 1-Wire devices
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0_u8;
+    for byte in bytes {
+        let mut value = *byte;
+        for _ in 0..8 {
+            let mix = (crc ^ value) & 0x01;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            value >>= 1;
+        }
+    }
+
+    crc
+}
+
+/// Start a temperature conversion, wait for it to complete and read the temperature
+pub async fn read_water_temperature(pin: GpioPin<19>) -> Result<Temperature, Ds18b20Error> {
+    let mut bus = OneWire::new(pin);
+
+    debug!("Starting DS18B20 temperature conversion ...");
+    bus.send_command(CONVERT_TEMPERATURE_COMMAND)?;
+    Timer::after(Duration::from_millis(CONVERSION_TIME_IN_MILLISECONDS)).await;
+
+    bus.send_command(READ_SCRATCHPAD_COMMAND)?;
+    let mut scratchpad = [0_u8; SCRATCHPAD_LENGTH];
+    for byte in scratchpad.iter_mut() {
+        *byte = bus.read_byte();
+    }
+
+    parse_scratchpad(&scratchpad)
+}
+
+/// Check the CRC of the scratchpad and get the temperature from it
+fn parse_scratchpad(scratchpad: &[u8; SCRATCHPAD_LENGTH]) -> Result<Temperature, Ds18b20Error> {
+    // A shorted data line reads as all zeros. The CRC of all zeros is zero, so the CRC check
+    // would pass.
+    if scratchpad.iter().all(|byte| *byte == 0x00) {
+        return Err(Ds18b20Error::NoDevicePresent);
+    }
+
+    if crc8(&scratchpad[..SCRATCHPAD_LENGTH - 1]) != scratchpad[SCRATCHPAD_LENGTH - 1] {
+        return Err(Ds18b20Error::CrcMismatch);
+    }
+
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    if raw == POWER_ON_RESET_TEMPERATURE_RAW {
+        return Err(Ds18b20Error::ConversionNotCompleted);
+    }
+
+    // The temperature register holds the temperature in 1/16 °C
+    Ok(Temperature::new::<degree_celsius>(f32::from(raw) / 16.0))
+}
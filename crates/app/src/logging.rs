@@ -8,11 +8,7 @@ use core::fmt::Write;
 use core::str::FromStr;
 
 use critical_section::Mutex;
-use embassy_net::dns::DnsSocket;
-use embassy_net::tcp::client::TcpClient;
-use embassy_net::tcp::client::TcpClientState;
 use embassy_net::Stack;
-use embassy_time::Duration;
 use esp_hal::time::now;
 use heapless::String;
 use heapless::Vec;
@@ -24,15 +20,19 @@ use log::Metadata;
 use log::Record;
 
 use esp_println::println;
-use reqwless::client::HttpClient;
 use reqwless::headers::ContentType;
-use reqwless::request::RequestBuilder;
 use serde::Serialize;
+use tank_sensor_level_core::compression::{gzip, max_compressed_length};
+use tank_sensor_level_core::logging::{chunk_length, push_log_entry, RepeatableEntry};
 use thiserror::Error;
 
+use crate::api_path::api_path;
+use crate::build_env::parse_u64_in_range_or;
+use crate::compression::{COMPRESS_PAYLOADS, CONTENT_ENCODING_HEADER_NAME, GZIP_CONTENT_ENCODING};
 use crate::device_meta::DEVICE_LOCATION;
 use crate::device_meta::MAX_DEVICE_NAME_LENGTH;
-use crate::wifi::DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS;
+use crate::tls::{tls_read_buffer_size, tls_write_buffer_size};
+use crate::upload::{post, upload_timeout_in_milliseconds, Upload, UploadError};
 
 // Constants for buffer sizes
 const MAX_STORED_LOGS: usize = 100;
@@ -42,6 +42,19 @@ const MAX_LOG_LENGTH: usize = 256;
 const LOGGING_URL: &str = env!("LOGGING_URL");
 const LOGGING_URL_SUB_PATH: &str = "/api/v1/logs";
 
+/// The TCP timeout, in milliseconds, of the log requests. Set at build time with the
+/// `LOGS_TIMEOUT_MS` environment variable.
+const LOGS_TIMEOUT_MS: u64 = upload_timeout_in_milliseconds(option_env!("LOGS_TIMEOUT_MS"));
+
+/// The size of the buffer for a chunk of logs formatted as JSON
+const LOG_JSON_BUFFER_SIZE: usize = 2048;
+
+/// The maximum number of log entries that are sent in one request. A chunk holds fewer entries
+/// if they don't fit in the JSON buffer. Set at build time with the `LOG_CHUNK_SIZE` environment
+/// variable.
+const LOG_CHUNK_SIZE: usize =
+    parse_u64_in_range_or(option_env!("LOG_CHUNK_SIZE"), 10, 1, MAX_STORED_LOGS as u64) as usize;
+
 // Create a static mutex-protected log buffer
 static LOG_BUFFER: Mutex<RefCell<heapless::Deque<LogEntry, MAX_STORED_LOGS>>> =
     Mutex::new(RefCell::new(heapless::Deque::new()));
@@ -65,8 +78,21 @@ struct LogEntry {
     level: String<32>,
     message: String<MAX_LOG_LENGTH>,
     boot_count: u32,
-    timestamp: u64, // Simple timestamp (milliseconds since boot)
+    timestamp: u64,  // Simple timestamp (milliseconds since boot)
+    count: u32,      // The number of times the message was logged in a row
+    session_id: u32, // The wake cycle the message was logged in. Set when the entry is sent.
 }
+
+impl RepeatableEntry for LogEntry {
+    fn is_repeated_by(&self, other: &LogEntry) -> bool {
+        self.level == other.level && self.message == other.message
+    }
+
+    fn count_repeat(&mut self) {
+        self.count = self.count.saturating_add(1);
+    }
+}
+
 // HTTP Logger implementation
 pub struct HttpLogger {
     boot_count: core::sync::atomic::AtomicU32,
@@ -109,22 +135,17 @@ impl HttpLogger {
             level: level_as_str,
             message,
             timestamp: now().ticks(),
+            count: 1,
+            session_id: 0,
         };
 
         // Get mutable access to the buffer through the mutex
         critical_section::with(|cs| {
             let mut buffer = LOG_BUFFER.borrow_ref_mut(cs);
-            // Try to store the log, removing oldest entry if full
-            if buffer.is_full() {
-                let _ = buffer.pop_front();
-            }
-
-            if buffer.push_back(entry).is_err() {
-                return Err(Error::PushLogToBuffer);
-            }
+            push_log_entry(&mut buffer, entry);
+        });
 
-            Ok(())
-        })
+        Ok(())
     }
 }
 
@@ -187,7 +208,9 @@ fn log_to_console(level: Level, target: &str, args: &fmt::Arguments) {
     );
 }
 
-pub async fn send_logs_to_server(stack: Stack<'static>) -> Result<(), Error> {
+/// Send the buffered logs to the server. The buffer is kept in RAM, which is cleared in deep
+/// sleep, so all the buffered logs belong to the wake cycle with the given session ID.
+pub async fn send_logs_to_server(stack: Stack<'static>, session_id: u32) -> Result<(), Error> {
     let mut temp_log_buffer: Vec<LogEntry, MAX_STORED_LOGS> = Vec::new();
 
     log_to_console(
@@ -206,7 +229,8 @@ pub async fn send_logs_to_server(stack: Stack<'static>) -> Result<(), Error> {
         critical_section::with(|cs| {
             let mut buffer = LOG_BUFFER.borrow_ref_mut(cs);
             while !buffer.is_empty() && !temp_log_buffer.is_full() {
-                if let Some(entry) = buffer.pop_front() {
+                if let Some(mut entry) = buffer.pop_front() {
+                    entry.session_id = session_id;
                     let _ = temp_log_buffer.push(entry);
                 }
             }
@@ -306,85 +330,91 @@ pub fn setup_logger(boot_count: u32) -> Result<(), Error> {
 }
 
 async fn transmit_logs(logs: &[LogEntry], stack: Stack<'_>, url: &str) -> Result<(), Error> {
-    let dns_socket = DnsSocket::new(stack);
-
-    let tcp_client_state = TcpClientState::<1, 4096, 4096>::new();
-    let mut tcp_client = TcpClient::new(stack, &tcp_client_state);
-    tcp_client.set_timeout(Some(Duration::from_millis(
-        DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS,
-    )));
-
-    log_to_console(
-        Level::Debug,
-        "tank_sensor_level_embedded::logging::transmit_logs()",
-        &format_args!("Creating HTTP client ..."),
-    );
-    let mut client = HttpClient::new(&tcp_client, &dns_socket);
-
-    let mut rx_buf = [0; 4096];
+    let path = api_path(LOGGING_URL_SUB_PATH);
 
     // Convert logs to JSON using serde_json_core (heapless)
-    let mut json_buffer = [0u8; 2048];
+    let mut json_buffer = [0u8; LOG_JSON_BUFFER_SIZE];
+    let mut compressed_buffer = [0u8; max_compressed_length(LOG_JSON_BUFFER_SIZE)];
+    let mut tls_read_buffer = [0u8; tls_read_buffer_size(LOGGING_URL)];
+    let mut tls_write_buffer = [0u8; tls_write_buffer_size(LOGGING_URL)];
 
     log_to_console(
         Level::Debug,
         "tank_sensor_level_embedded::logging::transmit_logs()",
         &format_args!("Selecting logs to send ..."),
     );
-    for chunk in logs.chunks(10) {
+    let mut remaining = logs;
+    while !remaining.is_empty() {
+        let length = chunk_length(
+            remaining
+                .iter()
+                .map(|entry| serde_json_core::to_slice(entry, &mut json_buffer).ok()),
+            LOG_CHUNK_SIZE,
+            LOG_JSON_BUFFER_SIZE,
+        );
+        let (chunk, rest) = remaining.split_at(length);
+        remaining = rest;
+
         match serde_json_core::to_slice(chunk, &mut json_buffer) {
             Ok(size) => {
-                let resource_result = client.resource(url).await;
-                let mut resource = match resource_result {
-                    Ok(r) => r,
-                    Err(_) => {
-                        log_to_console(
-                            Level::Error,
-                            "tank_sensor_level_embedded::logging::transmit_logs()",
-                            &format_args!("Failed to create request ..."),
-                        );
-                        return Err(Error::SendLogs);
+                let compressed_size = if COMPRESS_PAYLOADS {
+                    gzip(&json_buffer[..size], &mut compressed_buffer)
+                } else {
+                    None
+                };
+
+                let mut headers = Vec::<(&str, &str), 1>::new();
+                let body = match compressed_size {
+                    Some(compressed_size) => {
+                        let _ = headers.push((CONTENT_ENCODING_HEADER_NAME, GZIP_CONTENT_ENCODING));
+                        &compressed_buffer[..compressed_size]
                     }
+                    None => &json_buffer[..size],
                 };
 
-                let response = resource
-                    .post(LOGGING_URL_SUB_PATH)
-                    .content_type(ContentType::ApplicationJson)
-                    .body(&json_buffer[..size]);
+                let upload = Upload {
+                    url,
+                    path: &path,
+                    content_type: ContentType::ApplicationJson,
+                    headers: &headers,
+                    body,
+                    timeout_in_milliseconds: LOGS_TIMEOUT_MS,
+                };
 
                 log_to_console(
                     Level::Debug,
                     "tank_sensor_level_embedded::logging::transmit_logs()",
                     &format_args!("Sending log POST request ..."),
                 );
-                let response = response.send(&mut rx_buf).await;
-
-                log_to_console(
-                    Level::Debug,
-                    "tank_sensor_level_embedded::logging::transmit_logs()",
-                    &format_args!("Processing log POST response ..."),
-                );
-                match response {
-                    Ok(r) => {
-                        if r.status.is_successful() {
-                            log_to_console(
-                                Level::Debug,
-                                "tank_sensor_level_embedded::logging::transmit_logs()",
-                                &format_args!("Sent logs. Status code: {:?}", r.status),
-                            );
-                        } else {
-                            log_to_console(
-                                Level::Error,
-                                "tank_sensor_level_embedded::logging::transmit_logs()",
-                                &format_args!("Failed to send logs: Status code {:?}", r.status),
-                            );
-                        }
+                let result = post(
+                    stack,
+                    upload,
+                    &mut tls_read_buffer,
+                    &mut tls_write_buffer,
+                    |_| (),
+                )
+                .await;
+                match result {
+                    Ok(()) => {
+                        log_to_console(
+                            Level::Debug,
+                            "tank_sensor_level_embedded::logging::transmit_logs()",
+                            &format_args!("Sent logs"),
+                        );
+                    }
+                    Err(e @ UploadError::ConnectionFailed(_)) => {
+                        log_to_console(
+                            Level::Error,
+                            "tank_sensor_level_embedded::logging::transmit_logs()",
+                            &format_args!("Failed to send logs: {e}"),
+                        );
+                        return Err(Error::SendLogs);
                     }
                     Err(e) => {
                         log_to_console(
                             Level::Error,
                             "tank_sensor_level_embedded::logging::transmit_logs()",
-                            &format_args!("Failed to send logs: error {:?}", e),
+                            &format_args!("Failed to send logs: {e}"),
                         );
                     }
                 }
@@ -13,12 +13,13 @@ use embassy_sync::channel::Receiver;
 use esp_hal::peripherals::Peripherals;
 use esp_hal::peripherals::LPWR;
 use esp_hal::ram;
-use esp_hal::reset::software_reset;
 use esp_hal::time::now;
 use esp_hal_embassy::main;
 use esp_wifi::wifi::WifiController;
+use log::debug;
 use log::error;
 use log::info;
+use log::warn;
 
 use embassy_executor::Spawner;
 
@@ -32,54 +33,111 @@ use esp_hal::Config as EspConfig;
 
 use esp_hal_embassy::init as initialize_embassy;
 
+use esp_storage::FlashStorage;
+
 use logging::send_logs_to_server;
+use rand_core::RngCore as _;
 use thiserror::Error;
 
-use heapless::String;
+use uom::si::electric_potential::volt;
+use uom::si::length::meter;
 
 use esp_backtrace as _;
 use wifi::MonitorTaskResult;
 
+mod api_path;
+
+mod auth;
+
+mod boot_reason;
+use self::boot_reason::{boot_reason, BootReason};
+
 mod board_components;
 
+mod build_env;
+
 mod cell;
+
+mod clock;
 use self::cell::SyncUnsafeCell;
+use self::clock::sync_with_ntp;
+
+mod compression;
 
 mod data_recording;
 use self::data_recording::send_metrics_to_server;
 
 mod device_meta;
 
+mod device_tags;
+
+mod ds18b20;
+
 mod logging;
 use self::logging::setup_logger as setup_logging;
 
 mod meta;
 
+mod persistent_state;
+use self::persistent_state::{LastReading, PersistentState, StateStore};
+
+mod power;
+use self::power::power_profile;
+
+mod provisioning;
+use self::provisioning::{provision_device, set_device_token};
+
+mod radio_budget;
+use self::radio_budget::{configured_radio_budget, track_radio_time, RadioBudget};
+
 mod random;
 use self::random::RngWrapper;
 
+mod recovery;
+use self::recovery::{clear_fatal_errors, handle_fatal, FatalReason};
+
+mod retry;
+
 mod sensor;
 use self::sensor::read_sensor_data;
-use self::sensor::SensorPeripherals;
+use self::sensor::{fallback_sensor_data, SensorFailurePolicy, SensorPeripherals};
 
 mod sensor_data;
 
 mod sleep;
 use self::sleep::enter_deep as enter_deep_sleep;
 
+mod tank;
+
 mod timing;
 use self::timing::send_timing_data;
 
+mod tls;
+use self::tls::set_tls_rng;
+
+mod upload;
+
+mod watchdog;
+use self::watchdog::{sleep_after_watchdog_reset, start_awake_watchdog};
+
 mod wifi;
 use self::wifi::WifiConnectionError as WifiError;
 
-/// Duration of deep sleep
+/// Duration of deep sleep if the server doesn't ask for a different duration
 const DEEP_SLEEP_DURATION_IN_SECONDS: u32 = 30;
 
-/// SSID for WiFi network
+/// The shortest deep sleep that the server can ask for
+const MIN_DEEP_SLEEP_DURATION_IN_SECONDS: u32 = 10;
+
+/// The longest deep sleep that the server can ask for
+const MAX_DEEP_SLEEP_DURATION_IN_SECONDS: u32 = 3600;
+
+/// SSID of the WiFi network, or the SSIDs of several networks separated by commas in the order
+/// they should be tried
 const WIFI_SSID: &str = env!("WIFI_SSID");
 
-/// Password for WiFi network
+/// Password of the WiFi network. For several networks the passwords are separated by commas, in
+/// the same order as the SSIDs.
 const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
 
 /// Size of heap for dynamically-allocated memory
@@ -108,6 +166,9 @@ enum Error {
     #[error("The network was disconnected")]
     NetworkDisconnected,
 
+    #[error("The time budget for the radio is used up")]
+    RadioBudgetExhausted,
+
     /// An error within WiFi operations
     #[error("An error within WiFi operations")]
     Wifi {
@@ -136,28 +197,76 @@ async fn check_wifi_status(
     }
 }
 
+// Function to check that there is time left for uploads. If this function returns an error the
+// remaining uploads are skipped so that the radio is turned off.
+fn check_radio_budget(radio_budget: &RadioBudget) -> Result<(), Error> {
+    if radio_budget.is_exhausted() {
+        error!("The radio time budget is used up, skipping the remaining uploads");
+        Err(Error::RadioBudgetExhausted)
+    } else {
+        debug!(
+            "{:.1}s left in the radio time budget",
+            radio_budget.remaining_in_seconds()
+        );
+        Ok(())
+    }
+}
+
+/// Determine how long to sleep for based on the duration the server asked for. Durations
+/// outside the allowed range are clamped to the range.
+fn deep_sleep_duration_in_seconds(requested_duration_in_seconds: Option<u32>) -> u32 {
+    match requested_duration_in_seconds {
+        Some(seconds) => seconds.clamp(
+            MIN_DEEP_SLEEP_DURATION_IN_SECONDS,
+            MAX_DEEP_SLEEP_DURATION_IN_SECONDS,
+        ),
+        None => DEEP_SLEEP_DURATION_IN_SECONDS,
+    }
+}
+
 async fn disconnect_wifi_and_put_device_to_sleep(
     lpwr: LPWR,
     wifi_controller: &mut WifiController<'_>,
+    rng: Rng,
+    sleep_duration_in_seconds: u32,
 ) -> ! {
     // Ensure WiFi is disconnected properly before device state transition
-    let wifi_disconnect_result = wifi::disconnect_from_wifi(wifi_controller).await;
-    match wifi_disconnect_result {
-        Ok(_) => {
-            info!("WiFi disconnected successfully, entering deep sleep");
-            enter_deep_sleep(
-                lpwr,
-                hifitime::Duration::from_seconds(DEEP_SLEEP_DURATION_IN_SECONDS as f64),
-            );
-        }
-        Err(e) => {
-            error!("Failed to disconnect WiFi, performing software reset: {e}");
-            software_reset();
-        }
+    if let Err(e) = wifi::disconnect_from_wifi(wifi_controller, rng).await {
+        error!("Failed to disconnect WiFi: {e}");
+        handle_fatal(
+            lpwr,
+            FatalReason::WifiDisconnectFailed,
+            sleep_duration_in_seconds,
+        );
+    }
+
+    info!("WiFi disconnected successfully, entering deep sleep");
+    clear_fatal_errors();
+    enter_deep_sleep(
+        lpwr,
+        hifitime::Duration::from_seconds(sleep_duration_in_seconds as f64),
+    );
+}
+
+/// Disconnect from WiFi and end the wake cycle early. Whether the device sleeps or resets
+/// depends on the fatal error policy.
+async fn disconnect_wifi_and_handle_fatal(
+    lpwr: LPWR,
+    wifi_controller: &mut WifiController<'_>,
+    rng: Rng,
+    reason: FatalReason,
+    sleep_duration_in_seconds: u32,
+) -> ! {
+    if let Err(e) = wifi::disconnect_from_wifi(wifi_controller, rng).await {
+        error!("Failed to disconnect WiFi: {e}");
+        handle_fatal(
+            lpwr,
+            FatalReason::WifiDisconnectFailed,
+            sleep_duration_in_seconds,
+        );
     }
 
-    // This is unreachable as both deep_sleep and software_reset never return
-    unreachable!("Device should have entered deep sleep or reset");
+    handle_fatal(lpwr, reason, sleep_duration_in_seconds);
 }
 
 fn init_heap() {
@@ -188,46 +297,120 @@ async fn main(spawner: Spawner) {
     // SAFETY:
     // This is pointing to a valid value
     let boot_count: &'static mut _ = unsafe { boot_count.unwrap_unchecked() };
+
+    // Read the boot reason before anything else can reset the device
+    let boot_reason = boot_reason();
+
+    // The RTC memory is zeroed when the device loses power, so a boot count of zero means that
+    // this is the first boot after a power loss
+    let cold_boot = *boot_count == 0;
+
+    // The RTC memory is cleared when the device loses power, in which case the boot count
+    // continues after the boot counts that were reserved in flash
+    let mut state_store = StateStore::new(FlashStorage::new());
+    let mut persistent_state = match state_store.load() {
+        Ok(state) => state.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to load the state from flash: {e:?}");
+            PersistentState::default()
+        }
+    };
+    if !boot_reason.is_deep_sleep_wake() {
+        *boot_count = (*boot_count).max(persistent_state.boot_count);
+    }
+
     info!("Current boot count = {boot_count}");
     *boot_count += 1;
 
+    // The flash is only written once all the reserved boot counts are used
+    if persistent_state.reserve_boot_count(*boot_count) {
+        if let Err(e) = state_store.store(&persistent_state) {
+            warn!("Failed to store the boot count in flash: {e:?}");
+        }
+    }
+
     let logger_result = setup_logging(*boot_count);
     if logger_result.is_err() {
-        // Everything is stuffed. Just go back to sleep
+        // Everything is stuffed. Leave it to the fatal error policy
+        handle_fatal(
+            peripherals.LPWR,
+            FatalReason::LoggerSetupFailed,
+            DEEP_SLEEP_DURATION_IN_SECONDS,
+        );
+    }
+
+    info!(
+        "Boot reason = {}, cold boot = {cold_boot}",
+        boot_reason.as_str()
+    );
+
+    // A wake cycle that the watchdog had to end would most likely hang again, e.g. because the
+    // server is down. Try again after the normal sleep instead of right away.
+    if sleep_after_watchdog_reset(boot_reason) {
+        warn!("The watchdog ended the previous wake cycle. Going back to sleep.");
         enter_deep_sleep(
             peripherals.LPWR,
             hifitime::Duration::from_seconds(DEEP_SLEEP_DURATION_IN_SECONDS as f64),
         );
     }
+    if let Some(reading) = persistent_state.last_reading {
+        info!(
+            "Last reading in boot {}: tank level = {:.3}m, battery = {:.2}V",
+            reading.boot_count, reading.tank_level_in_meters, reading.battery_voltage
+        );
+    }
 
-    main_fallible(spawner, peripherals, *boot_count).await;
+    main_fallible(
+        spawner,
+        peripherals,
+        state_store,
+        persistent_state,
+        *boot_count,
+        boot_reason,
+        cold_boot,
+    )
+    .await;
 }
 
 /// Main task that can return an error
-async fn main_fallible(spawner: Spawner, mut peripherals: Peripherals, boot_count: u32) -> ! {
+async fn main_fallible(
+    spawner: Spawner,
+    mut peripherals: Peripherals,
+    mut state_store: StateStore<FlashStorage>,
+    mut persistent_state: PersistentState,
+    boot_count: u32,
+    boot_reason: BootReason,
+    cold_boot: bool,
+) -> ! {
     init_heap();
 
     let start_time = now();
     let systimer = SystemTimer::new(peripherals.SYSTIMER);
     initialize_embassy(systimer.alarm0);
 
+    // Make sure the device goes back to sleep, even if an operation hangs
+    if let Err(e) = start_awake_watchdog(spawner, peripherals.TIMG1) {
+        error!("Failed to start the awake watchdog: {e:?}");
+    }
+
     let rng = Rng::new(&mut peripherals.RNG);
+    set_tls_rng(rng);
 
-    // Connect to WiFi and get network stack
-    let ssid_result = String::<32>::try_from(WIFI_SSID);
-    let password_result = String::<64>::try_from(WIFI_PASSWORD);
+    // The session ID ties the timing data, the logs and the metrics of this wake cycle together
+    let session_id = RngWrapper::from(rng).next_u32();
+    info!("Session ID = {session_id}");
 
-    if ssid_result.is_err() || password_result.is_err() {
+    // Connect to WiFi and get network stack
+    let wifi_networks = wifi::parse_wifi_networks(WIFI_SSID, WIFI_PASSWORD);
+    if wifi_networks.is_empty() {
         error!("No valid Wifi SSID or password provided");
-        enter_deep_sleep(
+        handle_fatal(
             peripherals.LPWR,
-            hifitime::Duration::from_seconds(DEEP_SLEEP_DURATION_IN_SECONDS as f64),
+            FatalReason::NoWifiNetworks,
+            DEEP_SLEEP_DURATION_IN_SECONDS,
         );
     }
 
-    let ssid = ssid_result.unwrap();
-    let password = password_result.unwrap();
-
     info!("Connecting to WiFi network");
     let wifi_connect_result = wifi::connect_to_wifi(
         spawner,
@@ -235,8 +418,7 @@ async fn main_fallible(spawner: Spawner, mut peripherals: Peripherals, boot_coun
         peripherals.WIFI,
         peripherals.RADIO_CLK,
         rng,
-        ssid.clone(),
-        password.clone(),
+        &wifi_networks,
     )
     .await;
 
@@ -245,13 +427,20 @@ async fn main_fallible(spawner: Spawner, mut peripherals: Peripherals, boot_coun
             "Failed to connect to WiFi: {:?}",
             wifi_connect_result.err().unwrap()
         );
-        enter_deep_sleep(
+        handle_fatal(
             peripherals.LPWR,
-            hifitime::Duration::from_seconds(DEEP_SLEEP_DURATION_IN_SECONDS as f64),
+            FatalReason::WifiConnectFailed,
+            DEEP_SLEEP_DURATION_IN_SECONDS,
         );
     }
 
-    let (mut wifi_controller, stack) = wifi_connect_result.unwrap();
+    let (mut wifi_controller, stack, wifi_signal_strength) = wifi_connect_result.unwrap();
+
+    // The server may ask for a different sleep duration when the metrics are sent
+    let mut sleep_duration_in_seconds = DEEP_SLEEP_DURATION_IN_SECONDS;
+
+    // The uploads together may only keep the radio on for a limited time
+    let mut radio_budget = configured_radio_budget();
 
     // Create a channel to receive WiFi monitor task results
     let monitor_sender = WIFI_MONITOR_RESULT_CHANNEL.sender();
@@ -270,7 +459,14 @@ async fn main_fallible(spawner: Spawner, mut peripherals: Peripherals, boot_coun
         monitor_sender,
     )) {
         error!("Failed to spawn WiFi monitor task: {:?}", e);
-        disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::WifiMonitorSpawnFailed,
+            sleep_duration_in_seconds,
+        )
+        .await;
     }
 
     // Get duration for operations
@@ -284,21 +480,88 @@ async fn main_fallible(spawner: Spawner, mut peripherals: Peripherals, boot_coun
     let mut wifi_status_result = check_wifi_status(monitor_receiver).await;
     if wifi_status_result.is_err() {
         error!("Failed to keep network connection alive.");
-        disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::NetworkDisconnected,
+            sleep_duration_in_seconds,
+        )
+        .await;
+    }
+
+    // Once the device is provisioned the service only accepts its data with the device token
+    match persistent_state.device_token {
+        Some(device_token) => set_device_token(device_token),
+        None => match track_radio_time(&mut radio_budget, provision_device(stack)).await {
+            Ok(device_token) => {
+                set_device_token(device_token);
+                persistent_state.device_token = Some(device_token);
+                if let Err(e) = state_store.store(&persistent_state) {
+                    error!("Failed to store the device token in flash: {e:?}");
+                }
+            }
+            Err(e) => warn!("Failed to provision the device: {e:?}"),
+        },
     }
 
-    if let Err(e) = send_timing_data(stack, boot_count).await {
+    // The service provides the time, and tells the device how often it should get the more
+    // accurate time from NTP
+    if let Err(e) = track_radio_time(
+        &mut radio_budget,
+        send_timing_data(stack, boot_count, session_id),
+    )
+    .await
+    {
         error!("Failed to send timing data: {e:?}");
-        disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::TimingDataFailed,
+            sleep_duration_in_seconds,
+        )
+        .await;
+    }
+
+    if check_radio_budget(&radio_budget).is_err() {
+        disconnect_wifi_and_put_device_to_sleep(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            sleep_duration_in_seconds,
+        )
+        .await;
+    }
+
+    if let Err(e) = track_radio_time(&mut radio_budget, sync_with_ntp(stack)).await {
+        warn!("Failed to get the time from NTP: {e:?}");
     }
 
     wifi_status_result = check_wifi_status(monitor_receiver).await;
     if wifi_status_result.is_err() {
         error!("Failed to keep network connection alive.");
-        disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::NetworkDisconnected,
+            sleep_duration_in_seconds,
+        )
+        .await;
+    }
+
+    if check_radio_budget(&radio_budget).is_err() {
+        disconnect_wifi_and_put_device_to_sleep(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            sleep_duration_in_seconds,
+        )
+        .await;
     }
 
-    match send_logs_to_server(stack).await {
+    match track_radio_time(&mut radio_budget, send_logs_to_server(stack, session_id)).await {
         Ok(_) => (),
         Err(e) => {
             error!("Failed to send the logs to the server: {e:?}");
@@ -308,59 +571,163 @@ async fn main_fallible(spawner: Spawner, mut peripherals: Peripherals, boot_coun
     wifi_status_result = check_wifi_status(monitor_receiver).await;
     if wifi_status_result.is_err() {
         error!("Failed to keep network connection alive.");
-        disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::NetworkDisconnected,
+            sleep_duration_in_seconds,
+        )
+        .await;
     }
 
     let sensor_read_result = read_sensor_data(SensorPeripherals {
         sda: peripherals.GPIO10,
         scl: peripherals.GPIO11,
         pressure_sensor_enable: peripherals.GPIO18,
+        water_temperature_sensor: peripherals.GPIO19,
         i2c0: peripherals.I2C0,
         rng,
     })
     .await;
 
-    if sensor_read_result.is_err() {
-        error!("Failed to read sensor data");
-        disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
-    } else {
-        let (bme280_reading, ads1115_reading) = sensor_read_result.unwrap();
+    let sensor_readings = match sensor_read_result {
+        Ok((bme280_reading, ads1115_reading, ds18b20_reading)) => {
+            if !ads1115_reading.pressure_sensor_fault {
+                let reading = LastReading {
+                    boot_count,
+                    tank_level_in_meters: ads1115_reading.height_above_sensor.get::<meter>(),
+                    battery_voltage: ads1115_reading.battery_voltage.get::<volt>(),
+                };
+                if persistent_state.record_reading(reading) {
+                    if let Err(e) = state_store.store(&persistent_state) {
+                        warn!("Failed to store the last reading in flash: {e:?}");
+                    }
+                }
+            }
 
-        wifi_status_result = check_wifi_status(monitor_receiver).await;
-        if wifi_status_result.is_err() {
-            error!("Failed to keep network connection alive.");
-            disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+            Some((bme280_reading, ads1115_reading, ds18b20_reading, true))
         }
+        Err(e) => {
+            error!("Failed to read sensor data: {e:?}");
+            match SensorFailurePolicy::configured() {
+                SensorFailurePolicy::SendLastReading => {
+                    warn!("Sending the last good reading with the sensors marked as failed");
+                    let (bme280_reading, ads1115_reading) =
+                        fallback_sensor_data(persistent_state.last_reading);
+                    Some((bme280_reading, ads1115_reading, None, false))
+                }
+                SensorFailurePolicy::Sleep => None,
+            }
+        }
+    };
 
-        let _ = send_metrics_to_server(
+    let Some((bme280_reading, ads1115_reading, ds18b20_reading, sensor_ok)) = sensor_readings
+    else {
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::SensorReadFailed,
+            sleep_duration_in_seconds,
+        )
+        .await;
+    };
+    // The battery voltage of a fallback reading is out of date
+    let profile = sensor_ok.then(|| power_profile(ads1115_reading.battery_voltage.get::<volt>()));
+
+    wifi_status_result = check_wifi_status(monitor_receiver).await;
+    if wifi_status_result.is_err() {
+        error!("Failed to keep network connection alive.");
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::NetworkDisconnected,
+            sleep_duration_in_seconds,
+        )
+        .await;
+    }
+
+    if check_radio_budget(&radio_budget).is_err() {
+        disconnect_wifi_and_put_device_to_sleep(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            sleep_duration_in_seconds,
+        )
+        .await;
+    }
+
+    let mut quiet = false;
+    if let Ok(response) = track_radio_time(
+        &mut radio_budget,
+        send_metrics_to_server(
             stack,
             bme280_reading,
             ads1115_reading,
+            ds18b20_reading,
+            sensor_ok,
             boot_count,
+            boot_reason,
+            cold_boot,
+            session_id,
             start_time,
             wifi_start_time_in_micro_seconds,
-        )
-        .await;
+            wifi_signal_strength,
+        ),
+    )
+    .await
+    {
+        let response = response.unwrap_or_default();
+        sleep_duration_in_seconds = deep_sleep_duration_in_seconds(response.next_sleep_seconds);
+
+        // The tank is in maintenance so nothing is lost by reporting less often
+        quiet = response.is_quiet();
+        if quiet {
+            info!("The tank is in maintenance. Sleeping as long as possible.");
+            sleep_duration_in_seconds = MAX_DEEP_SLEEP_DURATION_IN_SECONDS;
+        }
+    }
+
+    // Sleep longer when the battery is low so that it can recharge
+    if let Some(profile) = profile {
+        sleep_duration_in_seconds =
+            sleep_duration_in_seconds.max(profile.minimum_sleep_duration_in_seconds);
     }
 
     // Prepare to shut down. Turn off the logger
-    info!(
-        "Entering deep sleep for {}s",
-        DEEP_SLEEP_DURATION_IN_SECONDS,
-    );
+    info!("Entering deep sleep for {}s", sleep_duration_in_seconds);
 
     wifi_status_result = check_wifi_status(monitor_receiver).await;
     if wifi_status_result.is_err() {
         error!("Failed to keep network connection alive.");
-        disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+        disconnect_wifi_and_handle_fatal(
+            peripherals.LPWR,
+            &mut wifi_controller,
+            rng,
+            FatalReason::NetworkDisconnected,
+            sleep_duration_in_seconds,
+        )
+        .await;
     }
 
-    match send_logs_to_server(stack).await {
-        Ok(_) => (),
-        Err(e) => {
-            error!("Failed to send the logs to the server: {e:?}");
-        }
-    };
+    if quiet {
+        info!("The tank is in maintenance. Skipping the log upload.");
+    } else if check_radio_budget(&radio_budget).is_ok() {
+        match send_logs_to_server(stack, session_id).await {
+            Ok(_) => (),
+            Err(e) => {
+                error!("Failed to send the logs to the server: {e:?}");
+            }
+        };
+    }
 
-    disconnect_wifi_and_put_device_to_sleep(peripherals.LPWR, &mut wifi_controller).await;
+    disconnect_wifi_and_put_device_to_sleep(
+        peripherals.LPWR,
+        &mut wifi_controller,
+        rng,
+        sleep_duration_in_seconds,
+    )
+    .await;
 }
@@ -0,0 +1,160 @@
+//! State that survives a power cycle
+//!
+//! The boot count is kept in RTC memory, which survives deep sleep but is cleared when the
+//! device loses power or browns out. The service uses the boot count to match the device ticks to
+//! the wall clock time, so a boot count that starts at zero again breaks that mapping. The boot
+//! count and the last successful reading are therefore also written to flash, and the boot count
+//! is restored from flash after a cold boot.
+//!
+//! The state is kept in its own data partition, `tank_state`, which is defined in
+//! `partitions.csv`. The partition is found through the partition table so that it doesn't
+//! overlap the NVS partition or the app.
+//!
+//! Flash sectors can only be erased a limited number of times, so the state is written as a log
+//! of fixed size records. Each write goes into the next slot and a sector is only erased when the
+//! log wraps around to it. The valid record with the highest sequence number is the current
+//! state. The partition has no valid records on the first ever boot, in which case the device
+//! starts from an empty state.
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use tank_sensor_level_core::partition_table::{
+    Partition, PARTITION_ENTRY_SIZE, PARTITION_TABLE_OFFSET, PARTITION_TABLE_SIZE,
+};
+use tank_sensor_level_core::persistent_state::RECORD_SIZE;
+pub use tank_sensor_level_core::persistent_state::{LastReading, PersistentState};
+
+use thiserror::Error;
+
+/// The label of the partition that holds the state
+const STATE_PARTITION_LABEL: &str = "tank_state";
+
+/// The size of a flash sector, which is the smallest area that can be erased
+const SECTOR_SIZE: u32 = 4096;
+
+/// Errors that can occur when the state is read from, or written to, flash
+#[derive(Error, Debug, Clone, Copy)]
+pub enum PersistentStateError {
+    #[error("The partition table doesn't contain the state partition.")]
+    PartitionNotFound,
+
+    #[error("The state partition is not aligned to the flash sectors.")]
+    PartitionNotAligned,
+
+    #[error("The state could not be read from flash.")]
+    ReadFailed,
+
+    #[error("The flash sector could not be erased.")]
+    EraseFailed,
+
+    #[error("The state could not be written to flash.")]
+    WriteFailed,
+}
+
+/// The state partition in flash
+pub struct StateStore<F> {
+    flash: F,
+    partition: Result<Partition, PersistentStateError>,
+}
+
+impl<F: NorFlash> StateStore<F> {
+    /// Find the state partition in the partition table. If it can't be found, loading and
+    /// storing the state fails with the reason.
+    pub fn new(mut flash: F) -> Self {
+        let partition = find_state_partition(&mut flash);
+        Self { flash, partition }
+    }
+
+    /// Read the state from flash. `None` if no state has been written yet.
+    pub fn load(&mut self) -> Result<Option<PersistentState>, PersistentStateError> {
+        let partition = self.partition?;
+        Ok(latest_record(&mut self.flash, &partition)?.map(|(_, _, state)| state))
+    }
+
+    /// Write the state to the slot after the most recent record. The sector is erased first if
+    /// the slot is the first one in its sector.
+    pub fn store(&mut self, state: &PersistentState) -> Result<(), PersistentStateError> {
+        let partition = self.partition?;
+        let (slot, sequence) = match latest_record(&mut self.flash, &partition)? {
+            Some((slot, sequence, _)) => (
+                (slot + 1) % slot_count(&partition),
+                sequence.wrapping_add(1),
+            ),
+            None => (0, 0),
+        };
+
+        let offset = slot_offset(&partition, slot);
+        if (offset - partition.offset) % SECTOR_SIZE == 0 {
+            self.flash
+                .erase(offset, offset + SECTOR_SIZE)
+                .map_err(|_| PersistentStateError::EraseFailed)?;
+        }
+
+        self.flash
+            .write(offset, &state.to_record(sequence))
+            .map_err(|_| PersistentStateError::WriteFailed)
+    }
+}
+
+/// Find the state partition in the partition table
+fn find_state_partition<F: ReadNorFlash>(flash: &mut F) -> Result<Partition, PersistentStateError> {
+    let mut offset = PARTITION_TABLE_OFFSET;
+    while offset < PARTITION_TABLE_OFFSET + PARTITION_TABLE_SIZE {
+        let mut entry = [0u8; PARTITION_ENTRY_SIZE];
+        flash
+            .read(offset, &mut entry)
+            .map_err(|_| PersistentStateError::ReadFailed)?;
+
+        let Some(partition) = Partition::from_entry(&entry) else {
+            break;
+        };
+
+        if partition.is_data_partition(STATE_PARTITION_LABEL) {
+            // Erasing works on whole sectors, so the partition may not share them
+            if partition.offset % SECTOR_SIZE != 0
+                || partition.size % SECTOR_SIZE != 0
+                || partition.size == 0
+            {
+                return Err(PersistentStateError::PartitionNotAligned);
+            }
+
+            return Ok(partition);
+        }
+
+        offset += PARTITION_ENTRY_SIZE as u32;
+    }
+
+    Err(PersistentStateError::PartitionNotFound)
+}
+
+/// The most recent record in the partition as the slot, the sequence number and the state
+fn latest_record<F: ReadNorFlash>(
+    flash: &mut F,
+    partition: &Partition,
+) -> Result<Option<(u32, u32, PersistentState)>, PersistentStateError> {
+    let mut latest: Option<(u32, u32, PersistentState)> = None;
+    for slot in 0..slot_count(partition) {
+        let mut record = [0u8; RECORD_SIZE];
+        flash
+            .read(slot_offset(partition, slot), &mut record)
+            .map_err(|_| PersistentStateError::ReadFailed)?;
+
+        if let Some((sequence, state)) = PersistentState::from_record(&record) {
+            if latest.is_none_or(|(_, latest_sequence, _)| sequence > latest_sequence) {
+                latest = Some((slot, sequence, state));
+            }
+        }
+    }
+
+    Ok(latest)
+}
+
+/// The number of records that fit in the partition
+fn slot_count(partition: &Partition) -> u32 {
+    partition.size / RECORD_SIZE as u32
+}
+
+/// The flash offset of the given record slot
+fn slot_offset(partition: &Partition, slot: u32) -> u32 {
+    partition.offset + slot * RECORD_SIZE as u32
+}
@@ -0,0 +1,97 @@
+//! Power management based on the battery voltage
+//!
+//! When the battery runs low the device takes fewer samples and sleeps longer so that the
+//! battery lasts until it can be recharged. The thresholds are set at build time with the
+//! following environment variables:
+//!
+//! * `BATTERY_LOW_VOLTAGE` - Below this voltage the device takes half the number of samples
+//! * `BATTERY_CRITICAL_VOLTAGE` - Below this voltage the device takes a single sample and
+//!   sleeps for at least `CRITICAL_BATTERY_MINIMUM_SLEEP_DURATION_IN_SECONDS`
+//! * `MIN_OPERATING_VOLTAGE` - Below this voltage the pressure sensor isn't powered, because
+//!   the current it draws could cause a brownout, and the device sleeps for at least
+//!   `LOW_VOLTAGE_SHUTDOWN_MINIMUM_SLEEP_DURATION_IN_SECONDS`
+//!
+//! The charge of the battery is estimated from the voltage with the discharge curve of the
+//! battery chemistry. The chemistry is set at build time with the `BATTERY_CHEMISTRY`
+//! environment variable, which is one of `lead_acid`, `li_ion` or `lifepo4`.
+
+use log::warn;
+pub use tank_sensor_level_core::power::PowerProfile;
+use tank_sensor_level_core::power::{
+    battery_percent_for_chemistry, BatteryChemistry, BatteryThresholds,
+};
+
+use crate::sensor_data::NUMBER_OF_SAMPLES;
+
+/// The battery voltage below which the battery is considered low
+const BATTERY_LOW_VOLTAGE: Option<&str> = option_env!("BATTERY_LOW_VOLTAGE");
+
+/// The battery voltage below which the battery is considered critically low
+const BATTERY_CRITICAL_VOLTAGE: Option<&str> = option_env!("BATTERY_CRITICAL_VOLTAGE");
+
+/// The battery voltage below which the pressure sensor isn't powered
+const MIN_OPERATING_VOLTAGE: Option<&str> = option_env!("MIN_OPERATING_VOLTAGE");
+
+/// The low battery voltage if nothing is configured. About half charge for a 12V lead-acid
+/// battery.
+const DEFAULT_BATTERY_LOW_VOLTAGE: f32 = 12.2;
+
+/// The critical battery voltage if nothing is configured. About a quarter charge for a 12V
+/// lead-acid battery.
+const DEFAULT_BATTERY_CRITICAL_VOLTAGE: f32 = 11.9;
+
+/// The minimum operating voltage if nothing is configured. About a tenth of the charge for a
+/// 12V lead-acid battery.
+const DEFAULT_MIN_OPERATING_VOLTAGE: f32 = 11.5;
+
+/// The chemistry of the battery
+const BATTERY_CHEMISTRY: Option<&str> = option_env!("BATTERY_CHEMISTRY");
+
+/// Get the configured battery chemistry. Lead-acid if nothing or an unknown chemistry is
+/// configured.
+fn configured_battery_chemistry() -> BatteryChemistry {
+    match BATTERY_CHEMISTRY {
+        Some("lead_acid") | None => BatteryChemistry::LeadAcid,
+        Some("li_ion") => BatteryChemistry::LiIon,
+        Some("lifepo4") => BatteryChemistry::LiFePo4,
+        Some(other) => {
+            warn!("{other} is not a known battery chemistry. Using lead_acid.");
+            BatteryChemistry::LeadAcid
+        }
+    }
+}
+
+/// Estimate the charge of the battery, in percent, from the voltage for the configured battery
+/// chemistry
+pub fn battery_percent(voltage_v: f32) -> f32 {
+    battery_percent_for_chemistry(configured_battery_chemistry(), voltage_v)
+}
+
+/// Parse a battery voltage threshold. Returns the default if the value is missing or invalid.
+fn parse_voltage(value: Option<&str>, default: f32) -> f32 {
+    match value.and_then(|v| v.trim().parse::<f32>().ok()) {
+        Some(voltage) if voltage.is_finite() && voltage > 0.0 => voltage,
+        _ => default,
+    }
+}
+
+/// The configured battery voltage thresholds
+fn battery_thresholds() -> BatteryThresholds {
+    BatteryThresholds {
+        low_voltage: parse_voltage(BATTERY_LOW_VOLTAGE, DEFAULT_BATTERY_LOW_VOLTAGE),
+        critical_voltage: parse_voltage(BATTERY_CRITICAL_VOLTAGE, DEFAULT_BATTERY_CRITICAL_VOLTAGE),
+        min_operating_voltage: parse_voltage(MIN_OPERATING_VOLTAGE, DEFAULT_MIN_OPERATING_VOLTAGE),
+    }
+}
+
+/// Determine if the battery is too low to power the pressure sensor. Powering the sensor from an
+/// almost empty battery can cause a brownout in the middle of the reading, which may corrupt
+/// the state that is kept in RTC memory.
+pub fn should_skip_pressure_read(battery_v: f32) -> bool {
+    battery_thresholds().should_skip_pressure_read(battery_v)
+}
+
+/// Determine the power profile for the given battery voltage
+pub fn power_profile(battery_v: f32) -> PowerProfile {
+    battery_thresholds().power_profile(battery_v, NUMBER_OF_SAMPLES)
+}
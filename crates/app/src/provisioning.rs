@@ -0,0 +1,200 @@
+//! Provisioning the device with the service
+//!
+//! A device provisions itself the first time it reaches the service. It sends its MAC address as
+//! the hardware ID and `DEVICE_LOCATION` as the device ID it would like to use. The service
+//! answers with a device token, which is stored in flash. Once a device is provisioned the
+//! service only accepts data for its device ID with the token, which is sent with every request.
+//!
+//! The service doesn't hand out the token again without proof of the token, so a device that
+//! loses its token, e.g. because the flash was erased, can't provision itself again until its
+//! registration is removed from the service.
+
+use core::cell::Cell;
+use core::fmt::Write;
+
+use critical_section::Mutex;
+use embassy_net::Stack;
+use esp_hal::efuse::Efuse;
+use heapless::String;
+use log::{debug, error, info, warn};
+use reqwless::headers::ContentType;
+use serde::Deserialize;
+use tank_sensor_level_core::metrics::metrics_urls;
+use tank_sensor_level_core::provisioning::{
+    format_device_token, format_hardware_id, parse_device_token, DeviceToken,
+    DEVICE_TOKEN_HEX_LENGTH,
+};
+use thiserror::Error;
+
+use crate::api_path::api_path;
+use crate::device_meta::DEVICE_LOCATION;
+use crate::retry::{with_retry, Retryable};
+use crate::tls::{tls_read_buffer_size, tls_write_buffer_size};
+use crate::upload::{post, upload_timeout_in_milliseconds, Upload, UploadError};
+
+/// The URLs of the servers that receive the metrics, separated by commas. The device only
+/// provisions itself with the first one, which is the service.
+const METRICS_URL: &str = env!("METRICS_URL");
+
+/// The TCP timeout, in milliseconds, of the provisioning request. Uses the timeout of the timing
+/// data, which is the other small request to the service.
+const PROVISION_TIMEOUT_MS: u64 = upload_timeout_in_milliseconds(option_env!("TIMING_TIMEOUT_MS"));
+
+/// The name of the header that contains the device token
+pub const DEVICE_TOKEN_HEADER_NAME: &str = "x-device-token";
+
+/// The token of the device. Set once it is loaded from flash or received from the service.
+static DEVICE_TOKEN: Mutex<Cell<Option<DeviceToken>>> = Mutex::new(Cell::new(None));
+
+/// Errors that can occur when the device provisions itself
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("The response code does not indicate success.")]
+    NonSuccessResponseCode,
+
+    #[error("The request failed to send.")]
+    RequestFailed,
+
+    #[error("The response did not contain a valid device token.")]
+    InvalidResponse,
+
+    #[error("The provisioning request does not fit in the buffer.")]
+    RequestTooLong,
+
+    #[error("No server URL is configured.")]
+    NoServerConfigured,
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        // A rejected request will be rejected again
+        matches!(self, Error::RequestFailed)
+    }
+}
+
+impl From<UploadError> for Error {
+    fn from(error: UploadError) -> Self {
+        match error {
+            UploadError::NonSuccessResponseCode(_) => Error::NonSuccessResponseCode,
+            UploadError::ConnectionFailed(_) | UploadError::RequestFailed(_) => {
+                Error::RequestFailed
+            }
+        }
+    }
+}
+
+/// The part of the service response to the provisioning request that the device uses
+#[derive(Deserialize)]
+struct ProvisionResponse<'a> {
+    /// The device ID that the service assigned to the device
+    #[serde(default)]
+    device_id: Option<&'a str>,
+
+    /// The hex encoded device token
+    #[serde(default)]
+    device_token: Option<&'a str>,
+}
+
+/// Use the given token for the requests to the service
+pub fn set_device_token(token: DeviceToken) {
+    critical_section::with(|cs| DEVICE_TOKEN.borrow(cs).set(Some(token)));
+}
+
+/// The value of the device token header. `None` if the device isn't provisioned.
+pub fn device_token_header_value() -> Option<String<DEVICE_TOKEN_HEX_LENGTH>> {
+    critical_section::with(|cs| DEVICE_TOKEN.borrow(cs).get())
+        .map(|token| format_device_token(&token))
+}
+
+/// Provision the device with the service and return the device token
+pub async fn provision_device(stack: Stack<'_>) -> Result<DeviceToken, Error> {
+    let hardware_id = format_hardware_id(&Efuse::read_base_mac_address());
+    info!("Provisioning the device with hardware ID {hardware_id}");
+
+    with_retry("provisioning", || {
+        send_provision_request(stack, hardware_id.as_str())
+    })
+    .await
+}
+
+async fn send_provision_request(stack: Stack<'_>, hardware_id: &str) -> Result<DeviceToken, Error> {
+    let url = match metrics_urls(METRICS_URL).next() {
+        Some(url) => url,
+        None => {
+            error!("No metrics server is configured to provision the device with");
+            return Err(Error::NoServerConfigured);
+        }
+    };
+
+    // The hardware ID and the device location only contain characters that don't need escaping
+    let mut body = String::<128>::new();
+    write!(
+        body,
+        "{{\"hardware_id\":\"{hardware_id}\",\"device_id\":\"{DEVICE_LOCATION}\"}}"
+    )
+    .map_err(|_| Error::RequestTooLong)?;
+
+    let mut tls_read_buffer = [0u8; tls_read_buffer_size(METRICS_URL)];
+    let mut tls_write_buffer = [0u8; tls_write_buffer_size(METRICS_URL)];
+    let path = api_path("/api/v1/provision");
+    let upload = Upload {
+        url,
+        path: &path,
+        content_type: ContentType::ApplicationJson,
+        headers: &[],
+        body: body.as_bytes(),
+        timeout_in_milliseconds: PROVISION_TIMEOUT_MS,
+    };
+
+    debug!("Sending the provisioning request ...");
+    let result = post(
+        stack,
+        upload,
+        &mut tls_read_buffer,
+        &mut tls_write_buffer,
+        |body| match body {
+            Ok(body) => parse_provision_response(body),
+            Err(e) => {
+                warn!("Failed to read the provisioning response: {:?}", e);
+                None
+            }
+        },
+    )
+    .await;
+    match result {
+        Ok(Some(token)) => {
+            info!("Provisioned the device");
+            Ok(token)
+        }
+        Ok(None) => Err(Error::InvalidResponse),
+        Err(e) => {
+            error!("Failed to provision the device: {e}");
+            Err(e.into())
+        }
+    }
+}
+
+/// Read the device token from the service response
+fn parse_provision_response(body: &[u8]) -> Option<DeviceToken> {
+    match serde_json_core::from_slice::<ProvisionResponse>(body) {
+        Ok((response, _)) => {
+            // The service keeps the device ID that was requested first. Data for any other
+            // device ID is rejected.
+            if response
+                .device_id
+                .is_some_and(|device_id| device_id != DEVICE_LOCATION)
+            {
+                error!(
+                    "The service provisioned the device as {:?} instead of {DEVICE_LOCATION}",
+                    response.device_id
+                );
+            }
+
+            response.device_token.and_then(parse_device_token)
+        }
+        Err(e) => {
+            warn!("Failed to parse the provisioning response: {:?}", e);
+            None
+        }
+    }
+}
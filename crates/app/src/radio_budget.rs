@@ -0,0 +1,36 @@
+//! Limit on the time the radio is used for uploads during a wake cycle
+//!
+//! Each upload retries on its own, so on a bad network the uploads together can keep the radio
+//! on for a long time and drain the battery. The time spent on the uploads is added up and once
+//! it reaches the budget the remaining uploads are skipped and the device goes back to sleep. The
+//! time it takes to connect to WiFi is not part of the budget. The budget is set at build time
+//! with the `RADIO_TIME_BUDGET_SECONDS` environment variable.
+
+use core::future::Future;
+
+use esp_hal::time::now;
+pub use tank_sensor_level_core::radio_budget::RadioBudget;
+
+use crate::build_env::parse_u64_in_range_or;
+
+/// The longest time, in seconds, that the uploads of one wake cycle may take together
+const RADIO_TIME_BUDGET_SECONDS: u64 =
+    parse_u64_in_range_or(option_env!("RADIO_TIME_BUDGET_SECONDS"), 60, 1, 3600);
+
+/// The budget that is set at build time
+pub fn configured_radio_budget() -> RadioBudget {
+    RadioBudget::new(RADIO_TIME_BUDGET_SECONDS * 1_000_000)
+}
+
+/// Run an upload and take the time it took from the budget
+pub async fn track_radio_time<F: Future>(radio_budget: &mut RadioBudget, upload: F) -> F::Output {
+    let start_time = now();
+    let result = upload.await;
+    let duration = now()
+        .checked_duration_since(start_time)
+        .map(|duration| duration.to_micros())
+        .unwrap_or(0);
+    radio_budget.spend(duration);
+
+    result
+}
@@ -0,0 +1,94 @@
+//! Recovery from errors that end the wake cycle early
+//!
+//! Errors that stop the device from completing a wake cycle, e.g. a WiFi connection that can't
+//! be made, all end in `handle_fatal`. The policy decides whether the device goes back to sleep
+//! and tries again on the next wake up, or resets so that it starts from a clean state. The
+//! policy is set at build time with the following environment variables:
+//!
+//! * `FATAL_ERROR_POLICY` - `sleep`, `reset` or `reset_after_failures`. Defaults to `sleep`.
+//! * `FATAL_ERROR_RESET_THRESHOLD` - The number of wake cycles in a row that have to fail before
+//!   the device resets with the `reset_after_failures` policy. Defaults to 3.
+//!
+//! The device always resets if WiFi can't be disconnected, whatever the policy, because it would
+//! never wake up from deep sleep with WiFi still on. The awake watchdog resets the device on its
+//! own because a hung operation never reaches `handle_fatal`.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+
+use esp_hal::peripherals::LPWR;
+use esp_hal::ram;
+use esp_hal::reset::software_reset;
+
+use log::error;
+
+pub use tank_sensor_level_core::recovery::FatalReason;
+use tank_sensor_level_core::recovery::{
+    parse_fatal_error_policy, recovery_action, FatalErrorPolicy, RecoveryAction,
+};
+
+use crate::build_env::parse_u64_in_range_or;
+use crate::sleep::enter_deep as enter_deep_sleep;
+
+/// The policy for fatal errors
+const FATAL_ERROR_POLICY: Option<&str> = option_env!("FATAL_ERROR_POLICY");
+
+/// The number of failed wake cycles in a row after which the device resets with the
+/// `reset_after_failures` policy
+const FATAL_ERROR_RESET_THRESHOLD: u32 =
+    parse_u64_in_range_or(option_env!("FATAL_ERROR_RESET_THRESHOLD"), 3, 1, 100) as u32;
+
+/// The number of wake cycles in a row that ended with a fatal error
+///
+/// This is placed in the RTC Fast memory, which survives deep sleep.
+#[ram(rtc_fast)]
+static FATAL_ERROR_COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Get the configured policy. Sleeps if nothing or an unknown policy is configured.
+fn configured_fatal_error_policy() -> FatalErrorPolicy {
+    parse_fatal_error_policy(FATAL_ERROR_POLICY, FATAL_ERROR_RESET_THRESHOLD)
+}
+
+/// Record that the wake cycle completed, so that the count of failed wake cycles starts again
+pub fn clear_fatal_errors() {
+    critical_section::with(|cs| FATAL_ERROR_COUNT.borrow(cs).set(0));
+}
+
+/// End the wake cycle after a fatal error. Depending on the policy the device either goes to
+/// sleep for the given duration or resets.
+///
+/// **NOTE**: WiFi must be turned off before this is called, unless the reason is that it
+/// couldn't be turned off.
+pub fn handle_fatal(lpwr: LPWR, reason: FatalReason, sleep_duration_in_seconds: u32) -> ! {
+    let failure_count = critical_section::with(|cs| {
+        let count = FATAL_ERROR_COUNT.borrow(cs);
+        count.set(count.get().saturating_add(1));
+        count.get()
+    });
+
+    match recovery_action(configured_fatal_error_policy(), reason, failure_count) {
+        RecoveryAction::Sleep => {
+            error!(
+                "Wake cycle ended early ({}, {failure_count} in a row). Entering deep sleep.",
+                reason.as_str()
+            );
+            enter_deep_sleep(
+                lpwr,
+                hifitime::Duration::from_seconds(sleep_duration_in_seconds as f64),
+            );
+        }
+        RecoveryAction::Reset => {
+            error!(
+                "Wake cycle ended early ({}, {failure_count} in a row). Performing software reset.",
+                reason.as_str()
+            );
+            // The reset is the recovery, so the next failure starts a new count
+            clear_fatal_errors();
+            software_reset();
+        }
+    }
+
+    // This is unreachable as both deep_sleep and software_reset never return
+    unreachable!("Device should have entered deep sleep or reset");
+}
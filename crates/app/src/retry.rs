@@ -0,0 +1,72 @@
+//! Retrying requests to the service
+//!
+//! Requests that fail to send, e.g. because of a dropped TCP connection, are retried with a
+//! delay that doubles with every attempt. Requests that the service rejects are not retried
+//! because sending them again will not succeed either. The retries are configured at build time
+//! with the following environment variables:
+//!
+//! * `HTTP_MAX_ATTEMPTS` - The maximum number of times a request is sent, including the first
+//!   attempt
+//! * `HTTP_RETRY_DELAY_MS` - The delay before the first retry in milliseconds
+
+use core::future::Future;
+
+use embassy_time::{Duration, Timer};
+use log::{debug, warn};
+use tank_sensor_level_core::backoff::backoff_delay_ms;
+pub use tank_sensor_level_core::upload::Retryable;
+
+use crate::build_env::parse_u64_or;
+
+/// The maximum number of times a request is sent. Together with the TCP timeout this limits the
+/// time spent on a request so that the device still goes to sleep.
+const HTTP_MAX_ATTEMPTS: u64 = parse_u64_or(option_env!("HTTP_MAX_ATTEMPTS"), 3);
+
+/// The upper limit for `HTTP_MAX_ATTEMPTS`
+const HTTP_MAX_ATTEMPTS_LIMIT: u64 = 5;
+
+/// The delay before the first retry in milliseconds
+const HTTP_RETRY_DELAY_MS: u64 = parse_u64_or(option_env!("HTTP_RETRY_DELAY_MS"), 200);
+
+/// The longest delay between two attempts in milliseconds
+const HTTP_RETRY_MAX_DELAY_MS: u64 = 2000;
+
+/// Determine if a request should be sent again after it failed with the given error
+///
+/// `attempt` is the one based number of the attempt that failed.
+fn should_retry<E: Retryable>(error: &E, attempt: u64, max_attempts: u64) -> bool {
+    attempt < max_attempts && error.is_retryable()
+}
+
+/// Send a request, retrying it if it fails with an error that may go away
+pub async fn with_retry<T, E, F, Fut>(name: &str, mut request: F) -> Result<T, E>
+where
+    E: Retryable + core::fmt::Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let max_attempts = HTTP_MAX_ATTEMPTS.clamp(1, HTTP_MAX_ATTEMPTS_LIMIT);
+
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Ok(result) => return Ok(result),
+            Err(e) if should_retry(&e, attempt, max_attempts) => {
+                let delay = backoff_delay_ms(
+                    (attempt - 1).min(u8::MAX as u64) as u8,
+                    HTTP_RETRY_DELAY_MS,
+                    HTTP_RETRY_MAX_DELAY_MS,
+                    0,
+                );
+                warn!("Attempt {attempt}/{max_attempts} to send the {name} failed: {e:?}. Retrying in {delay}ms");
+                Timer::after(Duration::from_millis(delay)).await;
+            }
+            Err(e) => {
+                debug!("Attempt {attempt}/{max_attempts} to send the {name} failed. Not retrying.");
+                return Err(e);
+            }
+        }
+
+        attempt += 1;
+    }
+}
@@ -26,8 +26,6 @@ use bme280_rs::SensorMode;
 
 use heapless::Vec;
 
-use libm::fabsf;
-
 use log::debug;
 use log::error;
 use log::info;
@@ -48,6 +46,16 @@ use uom::si::pressure::hectopascal;
 use uom::si::ratio::percent;
 use uom::si::thermodynamic_temperature::degree_celsius;
 
+use tank_sensor_level_core::sensor::average_ads1115_samples;
+use tank_sensor_level_core::sensor::calculate_ads1115_voltage;
+use tank_sensor_level_core::sensor::calculate_input_voltage_for_voltage_divider;
+use tank_sensor_level_core::sensor::ldr_to_brightness_percent;
+use tank_sensor_level_core::sensor::parse_pressure_sensor_supply_voltage;
+use tank_sensor_level_core::sensor::pressure_sensor_stability_setting;
+use tank_sensor_level_core::sensor::water_density_kg_m3;
+use tank_sensor_level_core::sensor::Error as SamplingError;
+use tank_sensor_level_core::sensor::{VoltageStability, VoltageStabilization};
+
 use thiserror::Error;
 
 use crate::board_components::{
@@ -57,11 +65,16 @@ use crate::board_components::{
     VOLTAGE_DIVIDER_PRESSURE_SENSOR_RESISTOR_AFTER_PROBE,
     VOLTAGE_DIVIDER_PRESSURE_SENSOR_RESISTOR_BEFORE_PROBE,
 };
+use crate::build_env::{parse_bool_or, parse_u64_or, parse_valid_i2c_address};
+use crate::ds18b20::read_water_temperature;
+use crate::persistent_state::LastReading;
+use crate::power::{power_profile, should_skip_pressure_read};
 use crate::sensor_data::Ads1115Data;
 use crate::sensor_data::Bme280Data;
+use crate::sensor_data::Ds18b20Data;
 use crate::sensor_data::Error as DomainError;
 use crate::sensor_data::NUMBER_OF_SAMPLES;
-use crate::sensor_data::TIME_BETWEEN_SAMPLES_IN_SECONDS;
+use crate::sensor_data::TIME_BETWEEN_SAMPLES_IN_MILLISECONDS;
 
 type Adc<'a> = Ads1x1x<I2c<'a, Async>, Ads1115, Resolution16Bit, ads1x1x::mode::OneShot>;
 
@@ -69,10 +82,134 @@ type Adc<'a> = Ads1x1x<I2c<'a, Async>, Ads1115, Resolution16Bit, ads1x1x::mode::
 const WARMUP_INTERVAL_IN_MILLISECONDS: f64 = 10.0;
 
 // Interval to wait between checking if the pressure sensor voltage is stable
-const PRESSURE_SENSOR_VOLTAGE_STABILIZATION_CHECK_INTERVAL_IN_SECONDS: f64 = 0.010;
+const PRESSURE_SENSOR_VOLTAGE_STABILIZATION_CHECK_INTERVAL_IN_MILLISECONDS: u64 = 10;
+
+/// The longest time to wait for the pressure sensor voltage to stabilize
+const PRESSURE_SENSOR_VOLTAGE_STABILIZATION_TIMEOUT_IN_MILLISECONDS: u64 = 5000;
+
+/// The largest standard deviation, in volts, of the pressure sensor voltage readings at which
+/// the voltage is considered stable. Set at build time with the
+/// `PRESSURE_SENSOR_STABILITY_EPSILON_V` environment variable.
+const PRESSURE_SENSOR_STABILITY_EPSILON: Option<&str> =
+    option_env!("PRESSURE_SENSOR_STABILITY_EPSILON_V");
+
+/// The pressure sensor stability epsilon if nothing is configured, for a sensor supplied with
+/// `DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS`. Scaled with the supply voltage.
+const DEFAULT_PRESSURE_SENSOR_STABILITY_EPSILON_IN_VOLTS: f32 = 0.2;
+
+/// The voltage that the pressure sensor is supplied with. Set at build time with the
+/// `PRESSURE_SENSOR_SUPPLY_V` environment variable.
+const PRESSURE_SENSOR_SUPPLY_VOLTAGE: Option<&str> = option_env!("PRESSURE_SENSOR_SUPPLY_V");
+
+/// The largest difference, in volts, between the average pressure sensor voltage and the
+/// supply voltage at which the voltage is considered stable. Set at build time with the
+/// `PRESSURE_SENSOR_VOLTAGE_TOLERANCE_V` environment variable.
+const PRESSURE_SENSOR_VOLTAGE_TOLERANCE: Option<&str> =
+    option_env!("PRESSURE_SENSOR_VOLTAGE_TOLERANCE_V");
+
+/// The pressure sensor voltage tolerance if nothing is configured, for a sensor supplied with
+/// `DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS`. Scaled with the supply voltage.
+const DEFAULT_PRESSURE_SENSOR_VOLTAGE_TOLERANCE_IN_VOLTS: f32 = 0.2;
+
+/// Correct the water height for the change in water density with temperature. Disabled by
+/// default so that readings stay comparable with the original sensor calibration. Set at build
+/// time with the `ENABLE_DENSITY_COMPENSATION` environment variable.
+const ENABLE_DENSITY_COMPENSATION: bool =
+    parse_bool_or(option_env!("ENABLE_DENSITY_COMPENSATION"), false);
+
+/// The water density, in kg/m³, that the pressure sensor height range is calibrated for
+const CALIBRATION_WATER_DENSITY_IN_KG_PER_CUBIC_METER: f32 = 1000.0;
+
+/// The pressure sensor output voltage, in volts, at the low calibration point. Set at build
+/// time with the `PRESSURE_CAL_LOW_V` environment variable.
+const PRESSURE_CALIBRATION_LOW_VOLTAGE: Option<&str> = option_env!("PRESSURE_CAL_LOW_V");
+
+/// The water height, in meters, at the low calibration point. Set at build time with the
+/// `PRESSURE_CAL_LOW_M` environment variable.
+const PRESSURE_CALIBRATION_LOW_HEIGHT: Option<&str> = option_env!("PRESSURE_CAL_LOW_M");
+
+/// The pressure sensor output voltage, in volts, at the high calibration point. Set at build
+/// time with the `PRESSURE_CAL_HIGH_V` environment variable.
+const PRESSURE_CALIBRATION_HIGH_VOLTAGE: Option<&str> = option_env!("PRESSURE_CAL_HIGH_V");
+
+/// The water height, in meters, at the high calibration point. Set at build time with the
+/// `PRESSURE_CAL_HIGH_M` environment variable.
+const PRESSURE_CALIBRATION_HIGH_HEIGHT: Option<&str> = option_env!("PRESSURE_CAL_HIGH_M");
+
+/// The LDR voltage, in volts, when the enclosure is dark. Set at build time with the
+/// `LDR_DARK_V` environment variable.
+const LDR_DARK_VOLTAGE: Option<&str> = option_env!("LDR_DARK_V");
+
+/// The LDR voltage, in volts, when the enclosure is brightly lit. Set at build time with the
+/// `LDR_BRIGHT_V` environment variable.
+const LDR_BRIGHT_VOLTAGE: Option<&str> = option_env!("LDR_BRIGHT_V");
+
+/// The LDR voltage, in volts, in the dark if nothing is configured
+const DEFAULT_LDR_DARK_VOLTAGE_IN_VOLTS: f32 = 0.1;
+
+/// The LDR voltage, in volts, in bright light if nothing is configured
+const DEFAULT_LDR_BRIGHT_VOLTAGE_IN_VOLTS: f32 = MPU_OUTPUT_VOLTAGE;
+
+/// The frequency, in kHz, of the I2C bus. Set at build time with the `I2C_FREQUENCY_KHZ`
+/// environment variable.
+const I2C_FREQUENCY_IN_KILOHERTZ: u64 = parse_u64_or(option_env!("I2C_FREQUENCY_KHZ"), 25);
+
+/// Return fixed sensor values instead of reading the sensors, so that the full wake up cycle can
+/// run on a board without the sensors attached. Set at build time with the `SIMULATE_SENSORS`
+/// environment variable.
+const SIMULATE_SENSORS: bool = parse_bool_or(option_env!("SIMULATE_SENSORS"), false);
+
+/// What the device does when the sensors can't be read, either `send_last_reading` or `sleep`.
+/// Set at build time with the `SENSOR_FAILURE_POLICY` environment variable.
+const SENSOR_FAILURE_POLICY: Option<&str> = option_env!("SENSOR_FAILURE_POLICY");
+
+/// The lowest I2C bus frequency in kHz
+const MIN_I2C_FREQUENCY_IN_KILOHERTZ: u64 = 10;
+
+/// The highest I2C bus frequency in kHz. The I2C fast mode limit.
+const MAX_I2C_FREQUENCY_IN_KILOHERTZ: u64 = 400;
+
+/// The I2C address of the ADS1115, e.g. `0x49`. Set at build time with the `ADS1115_ADDR`
+/// environment variable.
+const ADS1115_ADDRESS: Option<&str> = option_env!("ADS1115_ADDR");
+
+/// The I2C address of the BME280, e.g. `0x77`. Set at build time with the `BME280_ADDR`
+/// environment variable.
+const BME280_ADDRESS: Option<&str> = option_env!("BME280_ADDR");
+
+/// The full scale range of the ADS1115 in volts, e.g. `4.096`. Set at build time with the
+/// `ADS1115_FSR` environment variable.
+const ADS1115_FULL_SCALE_RANGE: Option<&str> = option_env!("ADS1115_FSR");
+
+/// The data rate of the ADS1115 in samples per second, e.g. `64`. Set at build time with the
+/// `ADS1115_DATA_RATE` environment variable.
+const ADS1115_DATA_RATE: Option<&str> = option_env!("ADS1115_DATA_RATE");
+
+/// The full scale range of the ADS1115, in millivolts, if nothing is configured. The lowest
+/// signal is 3V so the ADC uses the 2V range and voltage dividers.
+const DEFAULT_ADS1115_FULL_SCALE_RANGE_IN_MILLIVOLTS: u32 = 2048;
+
+/// The data rate of the ADS1115, in samples per second, if nothing is configured. Generally we
+/// try to get 10 measurements per second, so 16 measurements per second is enough.
+const DEFAULT_ADS1115_DATA_RATE_IN_SAMPLES_PER_SECOND: u32 = 16;
 
-// The voltage for the pressure sensor
-const EXPECTED_PRESSURE_SENSOR_VOLTAGE: f32 = 24.0;
+/// The I2C address of the ADS1115 with the ADDR pin connected to ground
+const ADS1115_GND_ADDRESS: u8 = 0x48;
+
+/// The I2C address of the ADS1115 with the ADDR pin connected to VDD
+const ADS1115_VDD_ADDRESS: u8 = 0x49;
+
+/// The I2C address of the ADS1115 with the ADDR pin connected to SDA
+const ADS1115_SDA_ADDRESS: u8 = 0x4A;
+
+/// The I2C address of the ADS1115 with the ADDR pin connected to SCL
+const ADS1115_SCL_ADDRESS: u8 = 0x4B;
+
+/// The I2C address of the BME280 with the SDO pin connected to ground
+const BME280_PRIMARY_ADDRESS: u8 = 0x76;
+
+/// The I2C address of the BME280 with the SDO pin connected to VDDIO
+const BME280_SECONDARY_ADDRESS: u8 = 0x77;
 
 /// Error within sensor sampling
 #[derive(Debug, Error)]
@@ -93,6 +230,15 @@ pub enum SensorError {
 
     #[error("Failed to initialize I2C")]
     I2cInitializationFailed,
+
+    #[error("The voltage divider resistors are not positive.")]
+    InvalidVoltageDivider,
+
+    #[error("None of the samples could be read.")]
+    NoValidSamples,
+
+    #[error("The ADC could not be read.")]
+    AdcReadFailed,
 }
 
 impl From<DomainError> for SensorError {
@@ -101,6 +247,41 @@ impl From<DomainError> for SensorError {
     }
 }
 
+/// What the device does when the sensors can't be read
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SensorFailurePolicy {
+    /// Send the metrics with the last good reading and the sensors marked as failed, so that the
+    /// service can tell a broken sensor from a device that is offline
+    SendLastReading,
+
+    /// Go back to sleep without sending the metrics
+    Sleep,
+}
+
+impl SensorFailurePolicy {
+    /// Get the configured policy. Sends the last reading if nothing or an unknown policy is
+    /// configured.
+    pub fn configured() -> Self {
+        match SENSOR_FAILURE_POLICY {
+            Some("send_last_reading") | None => Self::SendLastReading,
+            Some("sleep") => Self::Sleep,
+            Some(other) => {
+                warn!("{other} is not a known sensor failure policy. Using send_last_reading.");
+                Self::SendLastReading
+            }
+        }
+    }
+}
+
+impl From<SamplingError> for SensorError {
+    fn from(error: SamplingError) -> Self {
+        match error {
+            SamplingError::NoValidSamples => Self::NoValidSamples,
+            SamplingError::InvalidVoltageDivider => Self::InvalidVoltageDivider,
+        }
+    }
+}
+
 impl From<I2cError> for SensorError {
     fn from(error: I2cError) -> Self {
         Self::I2c(error)
@@ -117,6 +298,9 @@ pub struct SensorPeripherals {
     // The pin that enables or disables the pressure sensor
     pub pressure_sensor_enable: GpioPin<18>,
 
+    /// 1-Wire data pin for the DS18B20 water temperature sensor
+    pub water_temperature_sensor: GpioPin<19>,
+
     /// I²C interface
     pub i2c0: I2C0,
 
@@ -124,17 +308,13 @@ pub struct SensorPeripherals {
     pub rng: Rng,
 }
 
-async fn calculate_ads1115_voltage(measured_value: i16) -> f32 {
-    // Convert to voltage (ADS1115 is 16-bit, ±2.048V full scale)
-    (measured_value as f32 * 2.048) / 32768.0
-}
+/// Determine if the pressure sensor is disconnected. A 4-20mA sensor never draws less than 4mA
+/// while it is working, so a lower current means the loop is broken.
+fn is_pressure_sensor_disconnected(voltage: f32, resistor: f32) -> bool {
+    // Leave some margin below the 4mA live zero for measurement noise
+    const DISCONNECTED_CURRENT: f32 = 0.0035; // 3.5mA
 
-fn calculate_input_voltage_for_voltage_divider(
-    output_voltage: f32,
-    resistor_before_probe: f32,
-    resistor_after_probe: f32,
-) -> f32 {
-    output_voltage * (resistor_before_probe + resistor_after_probe) / resistor_after_probe
+    voltage / resistor < DISCONNECTED_CURRENT
 }
 
 fn calculate_water_height_from_pressure_sensor_voltage(
@@ -157,6 +337,157 @@ fn calculate_water_height_from_pressure_sensor_voltage(
     (voltage - min_voltage) * sensor_maximum_height / voltage_range
 }
 
+/// Two measured points that map the pressure sensor output voltage to the water height. The
+/// points correct the offset and gain errors of the sensor, which the theoretical 4-20mA
+/// conversion doesn't know about.
+#[derive(Clone, Copy, Debug)]
+struct PressureSensorCalibration {
+    low_voltage_in_volts: f32,
+    low_height_in_meters: f32,
+    high_voltage_in_volts: f32,
+    high_height_in_meters: f32,
+}
+
+impl PressureSensorCalibration {
+    /// Get the configured calibration points, if any. Warns if the points are only partly
+    /// configured or are invalid.
+    fn configured() -> Option<Self> {
+        let calibration = Self::parse(
+            PRESSURE_CALIBRATION_LOW_VOLTAGE,
+            PRESSURE_CALIBRATION_LOW_HEIGHT,
+            PRESSURE_CALIBRATION_HIGH_VOLTAGE,
+            PRESSURE_CALIBRATION_HIGH_HEIGHT,
+        );
+
+        let is_configured = PRESSURE_CALIBRATION_LOW_VOLTAGE.is_some()
+            || PRESSURE_CALIBRATION_LOW_HEIGHT.is_some()
+            || PRESSURE_CALIBRATION_HIGH_VOLTAGE.is_some()
+            || PRESSURE_CALIBRATION_HIGH_HEIGHT.is_some();
+        if calibration.is_none() && is_configured {
+            warn!("The pressure sensor calibration points are incomplete or invalid. Using the theoretical conversion.");
+        }
+
+        calibration
+    }
+
+    /// Parse the calibration points. Returns `None` if any of the values is missing or invalid,
+    /// or if both points have the same voltage.
+    fn parse(
+        low_voltage: Option<&str>,
+        low_height: Option<&str>,
+        high_voltage: Option<&str>,
+        high_height: Option<&str>,
+    ) -> Option<Self> {
+        let calibration = Self {
+            low_voltage_in_volts: parse_calibration_value(low_voltage)?,
+            low_height_in_meters: parse_calibration_value(low_height)?,
+            high_voltage_in_volts: parse_calibration_value(high_voltage)?,
+            high_height_in_meters: parse_calibration_value(high_height)?,
+        };
+
+        if calibration.low_voltage_in_volts == calibration.high_voltage_in_volts {
+            return None;
+        }
+
+        Some(calibration)
+    }
+
+    /// The water height, in meters, for the given pressure sensor output voltage. Voltages
+    /// outside the calibration points are extrapolated along the same line.
+    fn water_height(&self, voltage: f32) -> f32 {
+        let slope = (self.high_height_in_meters - self.low_height_in_meters)
+            / (self.high_voltage_in_volts - self.low_voltage_in_volts);
+        self.low_height_in_meters + (voltage - self.low_voltage_in_volts) * slope
+    }
+}
+
+/// Parse a calibration value. Returns `None` if the value is missing or isn't a finite number.
+fn parse_calibration_value(value: Option<&str>) -> Option<f32> {
+    let number = value?.trim().parse::<f32>().ok()?;
+    if number.is_finite() {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+/// The LDR voltages, in volts, in the dark and in bright light. Uses the defaults if the
+/// voltages are not configured or are invalid.
+fn ldr_calibration() -> (f32, f32) {
+    let defaults = (
+        DEFAULT_LDR_DARK_VOLTAGE_IN_VOLTS,
+        DEFAULT_LDR_BRIGHT_VOLTAGE_IN_VOLTS,
+    );
+    if LDR_DARK_VOLTAGE.is_none() && LDR_BRIGHT_VOLTAGE.is_none() {
+        return defaults;
+    }
+
+    match (
+        parse_calibration_value(LDR_DARK_VOLTAGE),
+        parse_calibration_value(LDR_BRIGHT_VOLTAGE),
+    ) {
+        (Some(dark), Some(bright)) if dark > 0.0 && bright > 0.0 && dark != bright => {
+            (dark, bright)
+        }
+        _ => {
+            warn!("The LDR calibration voltages are incomplete or invalid. Using the defaults.");
+            defaults
+        }
+    }
+}
+
+/// The water height, in meters, for the given pressure sensor output voltage. Uses the
+/// calibration points if they are configured and the theoretical 4-20mA conversion otherwise.
+fn water_height_from_pressure_sensor_voltage(
+    voltage: f32,
+    calibration: Option<&PressureSensorCalibration>,
+) -> f32 {
+    match calibration {
+        Some(calibration) => calibration.water_height(voltage),
+        None => calculate_water_height_from_pressure_sensor_voltage(
+            voltage,
+            PRESSURE_SENSOR_OUTPUT_RESISTOR_AFTER_PROBE,
+            PRESSURE_SENSOR_MAXIMUM_HEIGHT,
+        ),
+    }
+}
+
+/// The calibration of the ADS1115 channels. It is read from the configuration once per reading
+/// rather than for every sample, so that invalid values are only reported once.
+#[derive(Clone, Copy, Debug)]
+struct ChannelCalibration {
+    /// The calibration points of the pressure sensor, if they are configured
+    pressure_sensor: Option<PressureSensorCalibration>,
+
+    /// The LDR voltage, in volts, in the dark
+    ldr_dark_voltage: f32,
+
+    /// The LDR voltage, in volts, in bright light
+    ldr_bright_voltage: f32,
+}
+
+impl ChannelCalibration {
+    fn configured() -> Self {
+        let (ldr_dark_voltage, ldr_bright_voltage) = ldr_calibration();
+        Self {
+            pressure_sensor: PressureSensorCalibration::configured(),
+            ldr_dark_voltage,
+            ldr_bright_voltage,
+        }
+    }
+}
+
+/// Scale the water height measured by the pressure sensor to account for the density of the
+/// water at the given temperature.
+fn compensate_height_for_water_density(height: Length, water_temperature: Temperature) -> Length {
+    if !ENABLE_DENSITY_COMPENSATION {
+        return height;
+    }
+
+    let density = water_density_kg_m3(water_temperature.get::<degree_celsius>());
+    height * (CALIBRATION_WATER_DENSITY_IN_KG_PER_CUBIC_METER / density)
+}
+
 async fn initialize_bme280(
     bme280: &mut AsyncBme280<I2c<'static, Async>, Delay>,
 ) -> Result<(), I2cError> {
@@ -176,93 +507,131 @@ async fn initialize_bme280(
     Ok(())
 }
 
-async fn read_ads1115(adc: &mut Adc<'_>) -> Result<Ads1115Data, SensorError> {
+/// Set the data rate and the full scale range of the ADS1115. Returns the full scale range in
+/// volts.
+fn configure_ads1115(adc: &mut Adc<'_>) -> Result<f32, SensorError> {
     info!("Initialize ADS1115 analog-digital converter ...");
 
-    // Generally we try to get 10 measurments per second, so having the converter run at 16 measurements per second is enough
-    match adc.set_data_rate(ads1x1x::DataRate16Bit::Sps16) {
+    let data_rate = ads1115_data_rate_in_samples_per_second();
+    match adc.set_data_rate(
+        data_rate_for_samples_per_second(data_rate).unwrap_or(ads1x1x::DataRate16Bit::Sps16),
+    ) {
         Ok(_) => {
             // Everything is fine. Moving on
-            debug!("Set ADS1115 data rate to 16/s.");
+            debug!("Set ADS1115 data rate to {data_rate}/s.");
         }
         Err(_) => {
-            warn!("Failed to set ADS1115 data rate to 16/s. Remains at default.");
+            warn!("Failed to set ADS1115 data rate to {data_rate}/s. Remains at default.");
         }
     };
 
-    // Our lowest signal is 3V so we drop the ADC back to 2V and use voltage dividers
-    match adc.set_full_scale_range(ads1x1x::FullScaleRange::Within2_048V) {
+    let full_scale_range = ads1115_full_scale_range_in_millivolts();
+    let full_scale_range_in_volts = full_scale_range as f32 / 1000.0;
+    match adc.set_full_scale_range(
+        full_scale_range_for_millivolts(full_scale_range)
+            .unwrap_or(ads1x1x::FullScaleRange::Within2_048V),
+    ) {
         Ok(_) => {
             // Everything is fine. Moving on
-            debug!("Set ADS1115 scale range to 2V.");
+            debug!("Set ADS1115 scale range to {full_scale_range_in_volts:.3}V.");
         }
         Err(_) => {
-            warn!("Failed to set ADS1115 scale range to 2V.");
+            warn!("Failed to set ADS1115 scale range to {full_scale_range_in_volts:.3}V.");
             return Err(SensorError::FailedToSetAdcRange);
         }
     };
 
+    Ok(full_scale_range_in_volts)
+}
+
+/// Take a single reading of the battery voltage. `None` if the ADS1115 could not be read.
+fn read_battery_voltage(adc: &mut Adc<'_>, full_scale_range_in_volts: f32) -> Option<f32> {
+    let channel_a3_voltage = calculate_ads1115_voltage(
+        block!(adc.read(channel::SingleA3)).ok()?,
+        full_scale_range_in_volts,
+    );
+    calculate_input_voltage_for_voltage_divider(
+        channel_a3_voltage,
+        VOLTAGE_DIVIDER_BATTERY_RESISTOR_BEFORE_PROBE,
+        VOLTAGE_DIVIDER_BATTERY_RESISTOR_AFTER_PROBE,
+    )
+    .ok()
+}
+
+/// The ADS1115 data for a reading in which the pressure sensor wasn't powered because the
+/// battery is too low. Only the battery voltage is measured. The water level is marked as not
+/// valid.
+fn unpowered_pressure_sensor_data(battery_voltage: f32) -> Ads1115Data {
+    let mut data = Ads1115Data::from((
+        Ratio::new::<percent>(0.0),
+        Voltage::new::<volt>(battery_voltage),
+        Voltage::new::<volt>(0.0),
+        Length::new::<meter>(0.0),
+    ));
+    data.pressure_sensor_fault = true;
+    data
+}
+
+async fn read_ads1115(
+    adc: &mut Adc<'_>,
+    full_scale_range_in_volts: f32,
+) -> Result<Ads1115Data, SensorError> {
     // Loop around measuring A2 until it stabilizes
     info!("Wait for voltage on ADS1115 A2 to stabilize ...");
-    let stabilization_result = wait_for_pressure_sensor_voltage_to_stabilize(adc).await;
+    let stabilization_result =
+        wait_for_pressure_sensor_voltage_to_stabilize(adc, full_scale_range_in_volts).await;
     match stabilization_result {
         Ok(_) => info!("Pressure sensor voltage is stable."),
-        Err(_) => {
-            error!("Pressure sensor voltage is unstable.");
-            return Err(SensorError::PressureSensorVoltageNotStable);
+        Err(e) => {
+            error!("Pressure sensor voltage could not be stabilized: {e:?}");
+            return Err(e);
         }
     }
 
     // Then collect data
     info!("Collecting samples from the ADS1115 ...");
+    let calibration = ChannelCalibration::configured();
     let mut collected_data = Vec::<Ads1115Data, NUMBER_OF_SAMPLES>::new();
-    for _n in 0..NUMBER_OF_SAMPLES {
-        let sample_result = sample_voltage_data(adc).await;
+    let mut sample_count = NUMBER_OF_SAMPLES;
+    for n in 0..NUMBER_OF_SAMPLES {
+        if n >= sample_count {
+            break;
+        }
+
+        let sample_result = sample_voltage_data(adc, full_scale_range_in_volts, &calibration).await;
         match sample_result {
-            Ok(r) => drop(collected_data.push(r)),
+            Ok(r) => {
+                if collected_data.is_empty() {
+                    // Take fewer samples when the battery is low to save power
+                    sample_count = power_profile(r.battery_voltage.get::<volt>()).sample_count;
+                    if sample_count < NUMBER_OF_SAMPLES {
+                        warn!("Battery is low. Taking {} samples.", sample_count);
+                    }
+                }
+
+                drop(collected_data.push(r))
+            }
             Err(error) => error!("Could not sample sensor: {error:?}"),
         }
 
-        let wait_interval = hifitime::Duration::from_seconds(TIME_BETWEEN_SAMPLES_IN_SECONDS);
-        info!("Wait {}s for next sample", wait_interval.to_seconds());
-        Timer::after(embassy_time::Duration::from_secs(
-            wait_interval.to_seconds() as u64,
+        info!(
+            "Wait {}ms for next sample",
+            TIME_BETWEEN_SAMPLES_IN_MILLISECONDS
+        );
+        Timer::after(embassy_time::Duration::from_millis(
+            TIME_BETWEEN_SAMPLES_IN_MILLISECONDS,
         ))
         .await;
     }
 
-    // Average the readings. Ideally throw out outliers
-    let mut sum_of_brightness: f32 = 0.0;
-    let mut sum_of_battery_voltage: f32 = 0.0;
-    let mut sum_of_sensor_voltage: f32 = 0.0;
-    let mut sum_of_height: f32 = 0.0;
-    for n in 0..collected_data.len() {
-        let data = &collected_data[n];
-        sum_of_brightness += data.enclosure_relative_brightness.get::<percent>();
-        sum_of_battery_voltage += data.battery_voltage.get::<volt>();
-        sum_of_sensor_voltage += data.pressure_sensor_voltage.get::<volt>();
-        sum_of_height += data.height_above_sensor.get::<meter>();
-    }
-
-    let number_of_measurements = collected_data.len() as f32;
-    let final_brightness = Ratio::new::<percent>(sum_of_brightness / number_of_measurements);
-    let final_battery_voltage =
-        Voltage::new::<volt>(sum_of_battery_voltage / number_of_measurements);
-    let final_sensor_voltage = Voltage::new::<volt>(sum_of_sensor_voltage / number_of_measurements);
-    let final_height = Length::new::<meter>(sum_of_height / number_of_measurements);
-    let final_data = Ads1115Data::from((
-        final_brightness,
-        final_battery_voltage,
-        final_sensor_voltage,
-        final_height,
-    ));
-
-    Ok(final_data)
+    // Average the readings, ignoring any outliers
+    Ok(average_ads1115_samples(&collected_data)?)
 }
 
 async fn read_bme280(
     sensor: &mut AsyncBme280<I2c<'static, Async>, Delay>,
     rng: &mut Rng,
+    sample_count: usize,
 ) -> Result<Bme280Data, SensorError> {
     info!("Initialize BME280 environmental sensor ...");
 
@@ -294,21 +663,38 @@ async fn read_bme280(
     .await;
 
     let mut collected_data = Vec::<Bme280Data, NUMBER_OF_SAMPLES>::new();
-    for _n in 0..NUMBER_OF_SAMPLES {
-        let sample_result = sample_environmental_data(sensor, rng).await;
+    for _n in 0..sample_count.min(NUMBER_OF_SAMPLES) {
+        let sample_result = sample_environmental_data(sensor).await;
         match sample_result {
             Ok(r) => drop(collected_data.push(r)),
             Err(error) => error!("Could not sample sensor: {error:?}"),
         }
 
-        let wait_interval = hifitime::Duration::from_seconds(TIME_BETWEEN_SAMPLES_IN_SECONDS);
-        info!("Wait {}s for next sample", wait_interval.to_seconds());
-        Timer::after(embassy_time::Duration::from_secs(
-            wait_interval.to_seconds() as u64,
+        info!(
+            "Wait {}ms for next sample",
+            TIME_BETWEEN_SAMPLES_IN_MILLISECONDS
+        );
+        Timer::after(embassy_time::Duration::from_millis(
+            TIME_BETWEEN_SAMPLES_IN_MILLISECONDS,
         ))
         .await;
     }
 
+    // Only fall back to a random sample if none of the reads succeeded. Mixing random samples
+    // with real ones would make the average meaningless.
+    if collected_data.is_empty() {
+        warn!("Could not read any BME280 samples. Use a random sample");
+        return Ok(Bme280Data::random(rng));
+    }
+
+    if collected_data.len() < sample_count {
+        warn!(
+            "Only {} of {} BME280 samples could be read",
+            collected_data.len(),
+            sample_count
+        );
+    }
+
     // Average the readings. Ideally throw out outliers
     let mut sum_of_temperature: f32 = 0.0;
     let mut sum_of_pressure: f32 = 0.0;
@@ -321,22 +707,212 @@ async fn read_bme280(
     }
 
     let number_of_measurements = collected_data.len() as f32;
-    let final_temperature =
-        Temperature::new::<degree_celsius>(sum_of_temperature / number_of_measurements);
-    let final_pressure = Pressure::new::<hectopascal>(sum_of_pressure / number_of_measurements);
-    let final_humidity = Ratio::new::<percent>(sum_of_humidity / number_of_measurements);
-    let final_data = Bme280Data::from((final_temperature, final_humidity, final_pressure));
+    let final_data = Bme280Data {
+        temperature: Temperature::new::<degree_celsius>(
+            sum_of_temperature / number_of_measurements,
+        ),
+        humidity: Ratio::new::<percent>(sum_of_humidity / number_of_measurements),
+        pressure: Pressure::new::<hectopascal>(sum_of_pressure / number_of_measurements),
+        real_sample_count: collected_data.len(),
+    };
 
     Ok(final_data)
 }
 
+/// Get the configured I2C address. Returns the default if nothing is configured or if the
+/// configured address isn't one of the valid addresses.
+fn configured_i2c_address(
+    device: &str,
+    value: Option<&str>,
+    valid_addresses: &[u8],
+    default: u8,
+) -> u8 {
+    match value {
+        Some(v) => match parse_valid_i2c_address(v, valid_addresses) {
+            Some(address) => address,
+            None => {
+                warn!("{v} is not a valid {device} address. Using 0x{default:02X}.");
+                default
+            }
+        },
+        None => default,
+    }
+}
+
+/// The address of the ADS1115
+fn ads1115_address() -> TargetAddr {
+    let address = configured_i2c_address(
+        "ADS1115",
+        ADS1115_ADDRESS,
+        &[
+            ADS1115_GND_ADDRESS,
+            ADS1115_VDD_ADDRESS,
+            ADS1115_SDA_ADDRESS,
+            ADS1115_SCL_ADDRESS,
+        ],
+        ADS1115_GND_ADDRESS,
+    );
+
+    match address {
+        ADS1115_VDD_ADDRESS => TargetAddr::Vdd,
+        ADS1115_SDA_ADDRESS => TargetAddr::Sda,
+        ADS1115_SCL_ADDRESS => TargetAddr::Scl,
+        _ => TargetAddr::Gnd,
+    }
+}
+
+/// The ADS1115 full scale range setting for a range in millivolts. `None` if the ADS1115
+/// doesn't support the range.
+fn full_scale_range_for_millivolts(millivolts: u32) -> Option<ads1x1x::FullScaleRange> {
+    match millivolts {
+        6144 => Some(ads1x1x::FullScaleRange::Within6_144V),
+        4096 => Some(ads1x1x::FullScaleRange::Within4_096V),
+        2048 => Some(ads1x1x::FullScaleRange::Within2_048V),
+        1024 => Some(ads1x1x::FullScaleRange::Within1_024V),
+        512 => Some(ads1x1x::FullScaleRange::Within0_512V),
+        256 => Some(ads1x1x::FullScaleRange::Within0_256V),
+        _ => None,
+    }
+}
+
+/// The ADS1115 data rate setting for a rate in samples per second. `None` if the ADS1115
+/// doesn't support the rate.
+fn data_rate_for_samples_per_second(samples_per_second: u32) -> Option<ads1x1x::DataRate16Bit> {
+    match samples_per_second {
+        8 => Some(ads1x1x::DataRate16Bit::Sps8),
+        16 => Some(ads1x1x::DataRate16Bit::Sps16),
+        32 => Some(ads1x1x::DataRate16Bit::Sps32),
+        64 => Some(ads1x1x::DataRate16Bit::Sps64),
+        128 => Some(ads1x1x::DataRate16Bit::Sps128),
+        250 => Some(ads1x1x::DataRate16Bit::Sps250),
+        475 => Some(ads1x1x::DataRate16Bit::Sps475),
+        860 => Some(ads1x1x::DataRate16Bit::Sps860),
+        _ => None,
+    }
+}
+
+/// Parse a full scale range in volts, e.g. `4.096`, into millivolts. Returns `None` if the
+/// value isn't a range that the ADS1115 supports.
+fn parse_full_scale_range_in_millivolts(value: &str) -> Option<u32> {
+    let volts = value.trim().parse::<f32>().ok()?;
+    let millivolts = (volts * 1000.0 + 0.5) as u32;
+    full_scale_range_for_millivolts(millivolts).map(|_| millivolts)
+}
+
+/// The full scale range of the ADS1115 in millivolts. Returns the default if nothing is
+/// configured or if the configured range isn't supported.
+fn ads1115_full_scale_range_in_millivolts() -> u32 {
+    match ADS1115_FULL_SCALE_RANGE {
+        Some(v) => match parse_full_scale_range_in_millivolts(v) {
+            Some(millivolts) => millivolts,
+            None => {
+                warn!(
+                    "{v} is not a valid ADS1115 full scale range. Using {}mV.",
+                    DEFAULT_ADS1115_FULL_SCALE_RANGE_IN_MILLIVOLTS
+                );
+                DEFAULT_ADS1115_FULL_SCALE_RANGE_IN_MILLIVOLTS
+            }
+        },
+        None => DEFAULT_ADS1115_FULL_SCALE_RANGE_IN_MILLIVOLTS,
+    }
+}
+
+/// Parse a data rate in samples per second, e.g. `64`. Returns `None` if the value isn't a rate
+/// that the ADS1115 supports.
+fn parse_data_rate_in_samples_per_second(value: &str) -> Option<u32> {
+    let samples_per_second = value.trim().parse::<u32>().ok()?;
+    data_rate_for_samples_per_second(samples_per_second).map(|_| samples_per_second)
+}
+
+/// The data rate of the ADS1115 in samples per second. Returns the default if nothing is
+/// configured or if the configured rate isn't supported.
+fn ads1115_data_rate_in_samples_per_second() -> u32 {
+    match ADS1115_DATA_RATE {
+        Some(v) => match parse_data_rate_in_samples_per_second(v) {
+            Some(samples_per_second) => samples_per_second,
+            None => {
+                warn!(
+                    "{v} is not a valid ADS1115 data rate. Using {}/s.",
+                    DEFAULT_ADS1115_DATA_RATE_IN_SAMPLES_PER_SECOND
+                );
+                DEFAULT_ADS1115_DATA_RATE_IN_SAMPLES_PER_SECOND
+            }
+        },
+        None => DEFAULT_ADS1115_DATA_RATE_IN_SAMPLES_PER_SECOND,
+    }
+}
+
+/// The address of the BME280
+fn bme280_address() -> u8 {
+    configured_i2c_address(
+        "BME280",
+        BME280_ADDRESS,
+        &[BME280_PRIMARY_ADDRESS, BME280_SECONDARY_ADDRESS],
+        BME280_PRIMARY_ADDRESS,
+    )
+}
+
+/// The frequency of the I2C bus in kHz, limited to the frequencies the sensors support
+fn i2c_frequency_in_kilohertz() -> u32 {
+    I2C_FREQUENCY_IN_KILOHERTZ.clamp(
+        MIN_I2C_FREQUENCY_IN_KILOHERTZ,
+        MAX_I2C_FREQUENCY_IN_KILOHERTZ,
+    ) as u32
+}
+
+/// Fixed sensor values that are used instead of the sensors when `SIMULATE_SENSORS` is set. The
+/// environmental data is marked as synthetic, so the sample quality is reported as zero.
+fn simulated_sensor_data() -> (Bme280Data, Ads1115Data, Option<Ds18b20Data>) {
+    let bme280_data = Bme280Data::from((
+        Temperature::new::<degree_celsius>(20.0),
+        Ratio::new::<percent>(50.0),
+        Pressure::new::<hectopascal>(1013.25),
+    ));
+    let ads1115_data = Ads1115Data::from((
+        Ratio::new::<percent>(50.0),
+        Voltage::new::<volt>(12.6),
+        Voltage::new::<volt>(24.0),
+        Length::new::<meter>(1.0),
+    ));
+    let ds18b20_data = Ds18b20Data::from(Temperature::new::<degree_celsius>(15.0));
+
+    (bme280_data, ads1115_data, Some(ds18b20_data))
+}
+
+/// The sensor values that are sent when the sensors can't be read. The water level and the
+/// battery voltage are taken from the last good reading. Without a last reading the pressure
+/// sensor is marked as faulty so that the water level isn't used. The environmental data is a
+/// standard atmosphere that is marked as synthetic, so the sample quality is reported as zero.
+pub fn fallback_sensor_data(last_reading: Option<LastReading>) -> (Bme280Data, Ads1115Data) {
+    let bme280_data = Bme280Data::from((
+        Temperature::new::<degree_celsius>(20.0),
+        Ratio::new::<percent>(50.0),
+        Pressure::new::<hectopascal>(1013.25),
+    ));
+
+    let mut ads1115_data = Ads1115Data::from((
+        Ratio::new::<percent>(0.0),
+        Voltage::new::<volt>(last_reading.map_or(0.0, |reading| reading.battery_voltage)),
+        Voltage::new::<volt>(0.0),
+        Length::new::<meter>(last_reading.map_or(0.0, |reading| reading.tank_level_in_meters)),
+    ));
+    ads1115_data.pressure_sensor_fault = last_reading.is_none();
+
+    (bme280_data, ads1115_data)
+}
+
 pub async fn read_sensor_data(
     peripherals: SensorPeripherals,
-) -> Result<(Bme280Data, Ads1115Data), SensorError> {
+) -> Result<(Bme280Data, Ads1115Data, Option<Ds18b20Data>), SensorError> {
+    if SIMULATE_SENSORS {
+        warn!("Sensors are simulated. The sensor data is not real.");
+        return Ok(simulated_sensor_data());
+    }
+
     info!("Reading data from sensors ...");
 
-    info!("Create I²C bus for the BME280");
-    let i2c_config = I2cConfig::default().with_frequency(25_u32.kHz());
+    info!("Create I²C bus for the ADS1115 and the BME280");
+    let i2c_config = I2cConfig::default().with_frequency(i2c_frequency_in_kilohertz().kHz());
     let i2c_result = I2c::new(peripherals.i2c0, i2c_config);
 
     let i2c_blocking = match i2c_result {
@@ -352,83 +928,161 @@ pub async fn read_sensor_data(
         .with_scl(peripherals.scl)
         .into_async();
 
-    // Read from the BME280
-    let mut rng = peripherals.rng;
-    let mut bme280_sensor = AsyncBme280::new(i2c, Delay);
-    let bme280_data = match read_bme280(&mut bme280_sensor, &mut rng).await {
-        Ok(data) => data,
+    // Read from the ADS1115 first, so that the number of BME280 samples can be chosen for the
+    // battery voltage
+    let mut ads1115_sensor = Ads1x1x::new_ads1115(i2c, ads1115_address());
+    let full_scale_range_in_volts = match configure_ads1115(&mut ads1115_sensor) {
+        Ok(range) => range,
         Err(e) => {
-            let _ = bme280_sensor.release();
-            error!("Failed to read BME280 sensor: {e:?}");
+            error!("Failed to configure ADS1115 sensor: {e:?}");
             return Err(e);
         }
     };
 
-    i2c = bme280_sensor.release();
+    // Check the battery before the pressure sensor is powered up
+    let battery_voltage = read_battery_voltage(&mut ads1115_sensor, full_scale_range_in_volts);
+    let mut ads1115_data = match battery_voltage {
+        Some(voltage) if should_skip_pressure_read(voltage) => {
+            warn!("Battery voltage of {voltage:.2}V is too low to power the pressure sensor. Skipping the water level.");
+            unpowered_pressure_sensor_data(voltage)
+        }
+        _ => {
+            // power up the pressure sensor
+            let mut driver = Output::new(peripherals.pressure_sensor_enable, Level::High);
+
+            let result = read_ads1115(&mut ads1115_sensor, full_scale_range_in_volts).await;
+
+            // shut down the pressure sensor, also when the reading failed
+            driver.set_low();
+
+            match result {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("Failed to read ADS1115 sensor: {e:?}");
+                    return Err(e);
+                }
+            }
+        }
+    };
 
-    // power up the pressure sensor
-    let mut driver = Output::new(peripherals.pressure_sensor_enable, Level::High);
+    i2c = ads1115_sensor.destroy_ads1115();
 
-    // Read from the ADS1115
-    let mut ads1115_sensor = Ads1x1x::new_ads1115(i2c, TargetAddr::default());
-    let ads1115_data = match read_ads1115(&mut ads1115_sensor).await {
+    // Read from the BME280. Take fewer samples if the battery is low.
+    let sample_count = power_profile(ads1115_data.battery_voltage.get::<volt>()).sample_count;
+    let mut rng = peripherals.rng;
+    let mut bme280_sensor = AsyncBme280::new_with_address(i2c, bme280_address(), Delay);
+    let bme280_data = match read_bme280(&mut bme280_sensor, &mut rng, sample_count).await {
         Ok(data) => data,
         Err(e) => {
-            error!("Failed to read ADS1115 sensor: {e:?}");
-            // Ensure we shut down the pressure sensor even on error
-            driver.set_low();
+            let _ = bme280_sensor.release();
+            error!("Failed to read BME280 sensor: {e:?}");
             return Err(e);
         }
     };
 
-    // shut down the pressure sensor
-    driver.set_low();
+    let _ = bme280_sensor.release();
+
+    // Read from the DS18B20. The water temperature is optional so a missing probe doesn't
+    // stop the other measurements from being sent
+    let ds18b20_data = match read_water_temperature(peripherals.water_temperature_sensor).await {
+        Ok(temperature) => {
+            debug!(
+                "Water temperature: {:.2} C",
+                temperature.get::<degree_celsius>()
+            );
+            Some(Ds18b20Data::from(temperature))
+        }
+        Err(e) => {
+            warn!("Failed to read DS18B20 sensor: {e:?}");
+            None
+        }
+    };
 
-    let _ = ads1115_sensor.destroy_ads1115();
+    if let Some(water) = &ds18b20_data {
+        ads1115_data.height_above_sensor = compensate_height_for_water_density(
+            ads1115_data.height_above_sensor,
+            water.temperature,
+        );
+    }
 
-    // Only send data if both sensors read successfully
-    Ok((bme280_data, ads1115_data))
+    // Only send data if both the BME280 and the ADS1115 read successfully
+    Ok((bme280_data, ads1115_data, ds18b20_data))
 }
 
-async fn sample_voltage_data(adc: &mut Adc<'_>) -> Result<Ads1115Data, SensorError> {
+async fn sample_voltage_data(
+    adc: &mut Adc<'_>,
+    full_scale_range_in_volts: f32,
+    calibration: &ChannelCalibration,
+) -> Result<Ads1115Data, SensorError> {
     info!("Reading voltages from ADS1115 ...");
 
     // Status of the LDR
-    let ldr_voltage = calculate_ads1115_voltage(block!(adc.read(channel::SingleA0)).unwrap()).await;
-    let relative_brightness = ldr_voltage / MPU_OUTPUT_VOLTAGE;
+    let ldr_voltage = calculate_ads1115_voltage(
+        block!(adc.read(channel::SingleA0)).map_err(|_| SensorError::AdcReadFailed)?,
+        full_scale_range_in_volts,
+    );
+    let relative_brightness = ldr_to_brightness_percent(
+        ldr_voltage,
+        calibration.ldr_dark_voltage,
+        calibration.ldr_bright_voltage,
+    );
 
     // Status of the battery
-    let channel_a3_voltage =
-        calculate_ads1115_voltage(block!(adc.read(channel::SingleA3)).unwrap()).await;
+    let channel_a3_voltage = calculate_ads1115_voltage(
+        block!(adc.read(channel::SingleA3)).map_err(|_| SensorError::AdcReadFailed)?,
+        full_scale_range_in_volts,
+    );
     let battery_voltage = calculate_input_voltage_for_voltage_divider(
         channel_a3_voltage,
         VOLTAGE_DIVIDER_BATTERY_RESISTOR_BEFORE_PROBE,
         VOLTAGE_DIVIDER_BATTERY_RESISTOR_AFTER_PROBE,
-    );
+    )?;
 
     // Status of the pressure sensor voltage
-    let channel_a2_voltage =
-        calculate_ads1115_voltage(block!(adc.read(channel::SingleA2)).unwrap()).await;
+    let channel_a2_voltage = calculate_ads1115_voltage(
+        block!(adc.read(channel::SingleA2)).map_err(|_| SensorError::AdcReadFailed)?,
+        full_scale_range_in_volts,
+    );
     let pressure_sensor_voltage = calculate_input_voltage_for_voltage_divider(
         channel_a2_voltage,
         VOLTAGE_DIVIDER_PRESSURE_SENSOR_RESISTOR_BEFORE_PROBE,
         VOLTAGE_DIVIDER_PRESSURE_SENSOR_RESISTOR_AFTER_PROBE,
-    );
+    )?;
 
     // Pressure sensor output
-    let channel_a1_voltage =
-        calculate_ads1115_voltage(block!(adc.read(channel::SingleA1)).unwrap()).await;
-    let pressure_height = calculate_water_height_from_pressure_sensor_voltage(
+    let channel_a1_voltage = calculate_ads1115_voltage(
+        block!(adc.read(channel::SingleA1)).map_err(|_| SensorError::AdcReadFailed)?,
+        full_scale_range_in_volts,
+    );
+    let pressure_sensor_fault = is_pressure_sensor_disconnected(
         channel_a1_voltage,
         PRESSURE_SENSOR_OUTPUT_RESISTOR_AFTER_PROBE,
-        PRESSURE_SENSOR_MAXIMUM_HEIGHT,
     );
+    let pressure_height = if pressure_sensor_fault {
+        warn!(
+            "Pressure sensor output of {:.3}V is below the live zero. The sensor is disconnected.",
+            channel_a1_voltage
+        );
+        0.0
+    } else {
+        water_height_from_pressure_sensor_voltage(
+            channel_a1_voltage,
+            calibration.pressure_sensor.as_ref(),
+        )
+    };
 
     let sample = Ads1115Data {
         enclosure_relative_brightness: Ratio::new::<percent>(relative_brightness),
         battery_voltage: Voltage::new::<volt>(battery_voltage),
         pressure_sensor_voltage: Voltage::new::<volt>(pressure_sensor_voltage),
         height_above_sensor: Length::new::<meter>(pressure_height),
+        pressure_sensor_fault,
+        raw_channel_voltages: [
+            Voltage::new::<volt>(ldr_voltage),
+            Voltage::new::<volt>(channel_a1_voltage),
+            Voltage::new::<volt>(channel_a2_voltage),
+            Voltage::new::<volt>(channel_a3_voltage),
+        ],
     };
 
     debug!(
@@ -455,21 +1109,14 @@ async fn sample_voltage_data(adc: &mut Adc<'_>) -> Result<Ads1115Data, SensorErr
 /// Sample sensor and send reading to receiver
 async fn sample_environmental_data(
     sensor: &mut AsyncBme280<I2c<'static, Async>, Delay>,
-    rng: &mut Rng,
 ) -> Result<Bme280Data, SensorError> {
     info!("Reading sample ...");
 
-    let sample_result = sensor
+    let sample = sensor
         .read_sample()
         .await
         .map_err(SensorError::I2c)
-        .and_then(|sample: Bme280Sample| Ok(Bme280Data::try_from(sample)?));
-    let sample = sample_result.unwrap_or_else(|error| {
-        error!("Cannot read sample: {error:?}");
-        warn!("Use a random sample");
-
-        Bme280Data::random(rng)
-    });
+        .and_then(|sample: Bme280Sample| Ok(Bme280Data::try_from(sample)?))?;
 
     debug!(
         " ┣ Temperature: {:.2} C",
@@ -484,44 +1131,65 @@ async fn sample_environmental_data(
     Ok(sample)
 }
 
+/// Get the configured pressure sensor supply voltage
+fn pressure_sensor_supply_voltage() -> f32 {
+    parse_pressure_sensor_supply_voltage(PRESSURE_SENSOR_SUPPLY_VOLTAGE)
+}
+
+/// Wait until the most recent pressure sensor voltage readings are stable
+///
+/// Returns `PressureSensorVoltageNotStable` if the voltage doesn't stabilize within
+/// `PRESSURE_SENSOR_VOLTAGE_STABILIZATION_TIMEOUT_IN_MILLISECONDS`.
 async fn wait_for_pressure_sensor_voltage_to_stabilize(
     adc: &mut Adc<'_>,
+    full_scale_range_in_volts: f32,
 ) -> Result<(), SensorError> {
-    let mut stable_count = 0;
+    let supply_voltage = pressure_sensor_supply_voltage();
+    let mut stabilization = VoltageStabilization::new(
+        supply_voltage,
+        pressure_sensor_stability_setting(
+            PRESSURE_SENSOR_STABILITY_EPSILON,
+            DEFAULT_PRESSURE_SENSOR_STABILITY_EPSILON_IN_VOLTS,
+            supply_voltage,
+        ),
+        pressure_sensor_stability_setting(
+            PRESSURE_SENSOR_VOLTAGE_TOLERANCE,
+            DEFAULT_PRESSURE_SENSOR_VOLTAGE_TOLERANCE_IN_VOLTS,
+            supply_voltage,
+        ),
+        PRESSURE_SENSOR_VOLTAGE_STABILIZATION_TIMEOUT_IN_MILLISECONDS,
+    );
+    let start = embassy_time::Instant::now();
     loop {
         debug!("Measuring the pressure sensor voltage ...");
 
         // Status of the pressure sensor voltage
-        let channel_a2_voltage =
-            calculate_ads1115_voltage(block!(adc.read(channel::SingleA2)).unwrap()).await;
+        let channel_a2_voltage = calculate_ads1115_voltage(
+            block!(adc.read(channel::SingleA2)).map_err(|_| SensorError::AdcReadFailed)?,
+            full_scale_range_in_volts,
+        );
         let pressure_sensor_voltage = calculate_input_voltage_for_voltage_divider(
             channel_a2_voltage,
             VOLTAGE_DIVIDER_PRESSURE_SENSOR_RESISTOR_BEFORE_PROBE,
             VOLTAGE_DIVIDER_PRESSURE_SENSOR_RESISTOR_AFTER_PROBE,
-        );
+        )?;
 
         debug!("Pressure sensor voltage: {:.2} V", pressure_sensor_voltage);
 
-        let diff = fabsf(EXPECTED_PRESSURE_SENSOR_VOLTAGE - pressure_sensor_voltage);
-        if diff < 1.0 {
-            stable_count += 1;
-        } else {
-            stable_count = 0;
-        }
-
-        debug!(
-            "Pressure sensor voltage has been stable for {} loops",
-            stable_count
-        );
-        if stable_count == 10 {
-            break;
+        match stabilization.add_reading(pressure_sensor_voltage, start.elapsed().as_millis()) {
+            VoltageStability::Stable => break,
+            VoltageStability::Unstable => {}
+            VoltageStability::TimedOut => {
+                warn!(
+                    "Pressure sensor voltage did not stabilize within {}ms",
+                    PRESSURE_SENSOR_VOLTAGE_STABILIZATION_TIMEOUT_IN_MILLISECONDS
+                );
+                return Err(SensorError::PressureSensorVoltageNotStable);
+            }
         }
 
-        let wait_interval = hifitime::Duration::from_seconds(
-            PRESSURE_SENSOR_VOLTAGE_STABILIZATION_CHECK_INTERVAL_IN_SECONDS,
-        );
-        Timer::after(embassy_time::Duration::from_secs(
-            wait_interval.to_seconds() as u64,
+        Timer::after(Duration::from_millis(
+            PRESSURE_SENSOR_VOLTAGE_STABILIZATION_CHECK_INTERVAL_IN_MILLISECONDS,
         ))
         .await;
     }
@@ -4,8 +4,6 @@
 
 use esp_hal::rng::Rng;
 
-use uom::si::f32::ElectricPotential as Voltage;
-use uom::si::f32::Length;
 use uom::si::f32::Pressure;
 use uom::si::f32::Ratio;
 use uom::si::f32::ThermodynamicTemperature as Temperature;
@@ -15,40 +13,42 @@ use uom::si::thermodynamic_temperature::degree_celsius;
 
 use bme280_rs::Sample as Bme280Sample;
 
-/// The number of samples that each measurement should take
-pub const NUMBER_OF_SAMPLES: usize = 5;
-
-/// Period to wait between readings (100 milliseconds, aka 0.1 seconds)
-pub const TIME_BETWEEN_SAMPLES_IN_SECONDS: f64 = 0.1;
+use crate::build_env::parse_u64_or;
 
-#[derive(Clone, Debug, Default)]
-pub struct Ads1115Data {
-    pub enclosure_relative_brightness: Ratio,
+pub use tank_sensor_level_core::sensor::{Ads1115Data, MAX_NUMBER_OF_SAMPLES};
 
-    pub battery_voltage: Voltage,
+/// The number of samples that each measurement should take if nothing is configured
+const DEFAULT_NUMBER_OF_SAMPLES: u64 = 5;
 
-    pub pressure_sensor_voltage: Voltage,
-
-    pub height_above_sensor: Length,
-}
+/// Period to wait between readings if nothing is configured (100 milliseconds, aka 0.1 seconds)
+const DEFAULT_TIME_BETWEEN_SAMPLES_IN_MILLISECONDS: u64 = 100;
 
-impl From<(Ratio, Voltage, Voltage, Length)> for Ads1115Data {
-    fn from(
-        (
-            enclosure_relative_brightness,
-            battery_voltage,
-            pressure_sensor_voltage,
-            height_above_sensor,
-        ): (Ratio, Voltage, Voltage, Length),
-    ) -> Self {
-        Self {
-            enclosure_relative_brightness,
-            battery_voltage,
-            pressure_sensor_voltage,
-            height_above_sensor,
-        }
+/// The number of samples that each measurement should take
+///
+/// Set at build time with the `SENSOR_SAMPLE_COUNT` environment variable. Values larger
+/// than `MAX_NUMBER_OF_SAMPLES` are truncated to `MAX_NUMBER_OF_SAMPLES`. Zero would leave
+/// nothing to average, so the default is used instead.
+pub const NUMBER_OF_SAMPLES: usize = {
+    let count = parse_u64_or(
+        option_env!("SENSOR_SAMPLE_COUNT"),
+        DEFAULT_NUMBER_OF_SAMPLES,
+    );
+    if count == 0 {
+        DEFAULT_NUMBER_OF_SAMPLES as usize
+    } else if count > MAX_NUMBER_OF_SAMPLES as u64 {
+        MAX_NUMBER_OF_SAMPLES
+    } else {
+        count as usize
     }
-}
+};
+
+/// Period to wait between readings
+///
+/// Set at build time with the `SENSOR_SAMPLE_INTERVAL_MS` environment variable.
+pub const TIME_BETWEEN_SAMPLES_IN_MILLISECONDS: u64 = parse_u64_or(
+    option_env!("SENSOR_SAMPLE_INTERVAL_MS"),
+    DEFAULT_TIME_BETWEEN_SAMPLES_IN_MILLISECONDS,
+);
 
 /// The data recorded from the BME280. It provides the environmental data (temperature, pressure, humidity)
 /// for the enclosure.
@@ -62,6 +62,10 @@ pub struct Bme280Data {
 
     /// Air Pressure
     pub pressure: Pressure,
+
+    /// The number of genuine sensor readings this data is based on. Zero if the data is
+    /// synthetic.
+    pub real_sample_count: usize,
 }
 
 impl Bme280Data {
@@ -90,6 +94,7 @@ impl From<(Temperature, Ratio, Pressure)> for Bme280Data {
             temperature,
             humidity,
             pressure,
+            real_sample_count: 0,
         }
     }
 }
@@ -105,10 +110,24 @@ impl TryFrom<Bme280Sample> for Bme280Data {
             temperature,
             humidity,
             pressure,
+            real_sample_count: 1,
         })
     }
 }
 
+/// The data recorded from the DS18B20. It provides the temperature of the water in the tank.
+#[derive(Clone, Debug, Default)]
+pub struct Ds18b20Data {
+    /// Water temperature
+    pub temperature: Temperature,
+}
+
+impl From<Temperature> for Ds18b20Data {
+    fn from(temperature: Temperature) -> Self {
+        Self { temperature }
+    }
+}
+
 // AD converter data
 
 /// An error
@@ -1,23 +1,99 @@
 // Based on code from here: https://github.com/claudiomattera/esp32c3-embassy/
 
 //! Functions for module sleep
+//!
+//! The device always wakes from deep sleep when the sleep timer expires. It can also wake as soon
+//! as a GPIO changes level, e.g. when a high level float switch closes because the tank is about
+//! to overflow. The wake GPIO is set at build time with the following environment variables:
+//!
+//! * `WAKE_GPIO` - The number of the GPIO. Not set means the device only wakes on the timer.
+//! * `WAKE_GPIO_LEVEL` - The level that wakes the device, `high` or `low`. Defaults to `high`.
+//!
+//! Only the low power GPIOs can wake the ESP32-C6 from deep sleep, which are GPIO0 to GPIO7.
+//! GPIO4 to GPIO7 are also the JTAG pins, so GPIO0 to GPIO3 are the better choice. On the
+//! ESP32-C3 the RTC GPIOs are GPIO0 to GPIO5.
 
 use log::info;
 
-use esp_hal::peripherals::LPWR;
-use esp_hal::rtc_cntl::sleep::TimerWakeupSource;
+use esp_hal::gpio::RtcPinWithResistors;
+use esp_hal::peripherals::{Peripherals, LPWR};
+use esp_hal::rtc_cntl::sleep::{Ext1WakeupSource, TimerWakeupSource, WakeupLevel};
 use esp_hal::rtc_cntl::Rtc;
+use tank_sensor_level_core::sleep::{parse_wake_pin, WakePin};
 
-/// Enter deep sleep for the specified interval
+/// The GPIO that wakes the device from deep sleep
+const WAKE_GPIO: Option<&str> = option_env!("WAKE_GPIO");
+
+/// The level of the wake GPIO that wakes the device
+const WAKE_GPIO_LEVEL: Option<&str> = option_env!("WAKE_GPIO_LEVEL");
+
+/// The level of the wake GPIO that wakes the device
+fn wakeup_level(wake_pin: &WakePin) -> WakeupLevel {
+    if wake_pin.active_high {
+        WakeupLevel::High
+    } else {
+        WakeupLevel::Low
+    }
+}
+
+/// Get the configured wake GPIO, if any
+fn wake_pin() -> Option<WakePin> {
+    parse_wake_pin(WAKE_GPIO, WAKE_GPIO_LEVEL)
+}
+
+/// Enter deep sleep for the specified interval. The device wakes early if the wake GPIO is
+/// configured and goes to its wake level.
 ///
 /// **NOTE**: WiFi must be turned off before entering deep sleep, otherwise
 /// it will block indefinitely.
 pub fn enter_deep(rtc_cntl: LPWR, interval: hifitime::Duration) -> ! {
-    let wakeup_source =
+    let timer_wakeup_source =
         TimerWakeupSource::new(core::time::Duration::from_secs(interval.to_seconds() as u64));
 
     let mut rtc = Rtc::new(rtc_cntl);
 
-    info!("Entering deep sleep for {interval:?}");
-    rtc.sleep_deep(&[&wakeup_source]);
+    let wake_pin = match wake_pin() {
+        Some(wake_pin) => wake_pin,
+        None => {
+            info!("Entering deep sleep for {interval:?}");
+            rtc.sleep_deep(&[&timer_wakeup_source]);
+        }
+    };
+
+    info!(
+        "Entering deep sleep for {interval:?} or until GPIO{} goes {}",
+        wake_pin.gpio,
+        wake_pin.level_name()
+    );
+
+    // SAFETY:
+    // The low power GPIOs aren't used by anything else on the board and nothing else runs once
+    // the device goes to sleep
+    let peripherals = unsafe { Peripherals::steal() };
+    let level = wakeup_level(&wake_pin);
+    match wake_pin.gpio {
+        0 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO0, level),
+        1 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO1, level),
+        2 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO2, level),
+        3 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO3, level),
+        4 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO4, level),
+        5 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO5, level),
+        6 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO6, level),
+        7 => sleep_deep_until_pin(&mut rtc, &timer_wakeup_source, peripherals.GPIO7, level),
+        // The GPIO number is checked when it is parsed
+        _ => rtc.sleep_deep(&[&timer_wakeup_source]),
+    }
+}
+
+/// Enter deep sleep until either the timer expires or the pin goes to the given level
+fn sleep_deep_until_pin<P: RtcPinWithResistors>(
+    rtc: &mut Rtc<'_>,
+    timer_wakeup_source: &TimerWakeupSource,
+    mut pin: P,
+    level: WakeupLevel,
+) -> ! {
+    let mut wake_pins: [(&mut dyn RtcPinWithResistors, WakeupLevel); 1] = [(&mut pin, level)];
+    let pin_wakeup_source = Ext1WakeupSource::new(&mut wake_pins);
+
+    rtc.sleep_deep(&[timer_wakeup_source, &pin_wakeup_source]);
 }
@@ -0,0 +1,83 @@
+//! Tank geometry
+//!
+//! The shape and size of the tank are set at build time with the following environment
+//! variables:
+//!
+//! * `TANK_SHAPE` - Either `cylinder` or `rectangular`
+//! * `TANK_RADIUS_M` - The radius of a cylindrical tank in meters
+//! * `TANK_WIDTH_M` and `TANK_LENGTH_M` - The width and length of a rectangular tank in meters
+//! * `TANK_MAX_HEIGHT_M` - The height of the water, in meters, when the tank is full
+
+use log::warn;
+use tank_sensor_level_core::tank::{self, TankShape};
+
+/// The shape of the tank
+const TANK_SHAPE: Option<&str> = option_env!("TANK_SHAPE");
+
+/// The radius of a cylindrical tank in meters
+const TANK_RADIUS_IN_METERS: Option<&str> = option_env!("TANK_RADIUS_M");
+
+/// The width of a rectangular tank in meters
+const TANK_WIDTH_IN_METERS: Option<&str> = option_env!("TANK_WIDTH_M");
+
+/// The length of a rectangular tank in meters
+const TANK_LENGTH_IN_METERS: Option<&str> = option_env!("TANK_LENGTH_M");
+
+/// The height of the water in a full tank in meters
+const TANK_MAX_HEIGHT_IN_METERS: Option<&str> = option_env!("TANK_MAX_HEIGHT_M");
+
+/// Get the configured tank shape, if any
+fn configured_tank_shape() -> Option<TankShape> {
+    match TANK_SHAPE {
+        Some("cylinder") => Some(TankShape::Cylinder {
+            radius_in_meters: parse_dimension(TANK_RADIUS_IN_METERS)?,
+        }),
+        Some("rectangular") => Some(TankShape::Rectangular {
+            width_in_meters: parse_dimension(TANK_WIDTH_IN_METERS)?,
+            length_in_meters: parse_dimension(TANK_LENGTH_IN_METERS)?,
+        }),
+        _ => None,
+    }
+}
+
+/// Parse a tank dimension. Returns `None` if the value is missing, invalid or negative.
+fn parse_dimension(value: Option<&str>) -> Option<f32> {
+    let dimension = value?.trim().parse::<f32>().ok()?;
+    if dimension.is_finite() && dimension >= 0.0 {
+        Some(dimension)
+    } else {
+        None
+    }
+}
+
+/// Calculate the volume of water in the tank, in liters, for the given water height
+///
+/// Negative heights are treated as an empty tank. If no valid tank geometry is configured
+/// the volume is reported as zero.
+pub fn tank_volume_liters(height_m: f32) -> f32 {
+    let shape = match configured_tank_shape() {
+        Some(s) => s,
+        None => {
+            warn!("No valid tank geometry configured. Reporting a tank volume of 0 L");
+            return 0.0;
+        }
+    };
+
+    tank::tank_volume_liters(shape, height_m)
+}
+
+/// Calculate how full the tank is, in percent, for the given water height
+///
+/// The result is clamped to the range 0% to 100% so that sensor noise or a slightly overfilled
+/// tank doesn't report more than 100%. Returns `None` if no valid maximum height is configured.
+pub fn tank_fill_percent(height_m: f32) -> Option<f32> {
+    let max_height = match parse_dimension(TANK_MAX_HEIGHT_IN_METERS) {
+        Some(h) if h > 0.0 => h,
+        _ => {
+            warn!("No valid maximum tank height configured. Not reporting the fill percentage");
+            return None;
+        }
+    };
+
+    Some((height_m / max_height * 100.0).clamp(0.0, 100.0))
+}
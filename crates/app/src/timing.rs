@@ -1,21 +1,29 @@
 use core::fmt::Write;
 
-use embassy_net::tcp::client::TcpClientState;
 use embassy_net::Stack;
-use embassy_net::{dns::DnsSocket, tcp::client::TcpClient};
-use embassy_time::Duration;
 use esp_hal::time::now;
 use heapless::String;
-use log::{debug, error};
-use reqwless::client::HttpClient;
-use reqwless::{headers::ContentType, request::RequestBuilder};
+use log::{debug, error, warn};
+use reqwless::headers::ContentType;
+use serde::Deserialize;
+use tank_sensor_level_core::metrics::metrics_urls;
 use thiserror::Error;
 
+use crate::api_path::api_path;
+use crate::clock::{set_ntp_resync_interval, set_unix_time, unix_time_in_seconds};
 use crate::device_meta::DEVICE_LOCATION;
-use crate::wifi::DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS;
+use crate::retry::{with_retry, Retryable};
+use crate::tls::{tls_read_buffer_size, tls_write_buffer_size};
+use crate::upload::{post, upload_timeout_in_milliseconds, Upload, UploadError};
 
+/// The URLs of the servers that receive the metrics, separated by commas. The timing data is
+/// only sent to the first one, which is the service.
 const METRICS_URL: &str = env!("METRICS_URL");
 
+/// The TCP timeout, in milliseconds, of the timing data request. Set at build time with the
+/// `TIMING_TIMEOUT_MS` environment variable.
+const TIMING_TIMEOUT_MS: u64 = upload_timeout_in_milliseconds(option_env!("TIMING_TIMEOUT_MS"));
+
 /// Errors that can occur when sending timing data
 #[derive(Error, Debug)]
 pub enum Error {
@@ -24,16 +32,54 @@ pub enum Error {
 
     #[error("The request failed to send.")]
     RequestFailed,
+
+    #[error("No server URL is configured.")]
+    NoServerConfigured,
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        // A rejected request will be rejected again
+        matches!(self, Error::RequestFailed)
+    }
+}
+
+impl From<UploadError> for Error {
+    fn from(error: UploadError) -> Self {
+        match error {
+            UploadError::NonSuccessResponseCode(_) => Error::NonSuccessResponseCode,
+            UploadError::ConnectionFailed(_) | UploadError::RequestFailed(_) => {
+                Error::RequestFailed
+            }
+        }
+    }
 }
 
-fn format_timing_data(boot_count: u32, ticks_in_micro_seconds: u64) -> String<256> {
+/// The part of the server response to the timing data that the device uses
+#[derive(Deserialize)]
+struct TimingResponse {
+    /// The unix time of the server, in seconds
+    #[serde(default)]
+    server_time_in_seconds: Option<u64>,
+
+    /// The time, in seconds, the device should wait between two NTP syncs
+    #[serde(default)]
+    ntp_resync_seconds: Option<u32>,
+}
+
+fn format_timing_data(
+    boot_count: u32,
+    session_id: u32,
+    ticks_in_micro_seconds: u64,
+) -> String<256> {
     let mut buffer: String<256> = String::new();
 
     writeln!(
         buffer,
-        "{{\"device_id\":\"{device_id}\",\"boot_count\":{boot_count},\"timestamp\":{ticks}}}",
+        "{{\"device_id\":\"{device_id}\",\"boot_count\":{boot_count},\"session_id\":{session_id},\"timestamp\":{ticks}}}",
         device_id = DEVICE_LOCATION,
         boot_count = boot_count,
+        session_id = session_id,
         ticks = ticks_in_micro_seconds,
     )
     .unwrap();
@@ -42,47 +88,87 @@ fn format_timing_data(boot_count: u32, ticks_in_micro_seconds: u64) -> String<25
 }
 
 /// Send timing data to the server immediately after WiFi connection
-pub async fn send_timing_data(stack: Stack<'_>, boot_count: u32) -> Result<(), Error> {
+pub async fn send_timing_data(
+    stack: Stack<'_>,
+    boot_count: u32,
+    session_id: u32,
+) -> Result<(), Error> {
+    with_retry("timing data", || {
+        send_timing_request(stack, boot_count, session_id)
+    })
+    .await
+}
+
+async fn send_timing_request(
+    stack: Stack<'_>,
+    boot_count: u32,
+    session_id: u32,
+) -> Result<(), Error> {
     debug!("Sending timing data...");
 
-    let timing_data = format_timing_data(boot_count, now().ticks());
-    let bytes = timing_data.as_bytes();
-
-    let dns_socket = DnsSocket::new(stack);
-    let tcp_client_state = TcpClientState::<1, 4096, 4096>::new();
-    let mut tcp_client = TcpClient::new(stack, &tcp_client_state);
-    tcp_client.set_timeout(Some(Duration::from_millis(
-        DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS,
-    )));
-
-    debug!("Creating HTTP client...");
-    let mut client = HttpClient::new(&tcp_client, &dns_socket);
-
-    debug!("Creating request...");
-    let mut rx_buf = [0; 4096];
-    let mut resource = client.resource(METRICS_URL).await.unwrap();
-    let response = resource
-        .post("/api/v1/timing")
-        .content_type(ContentType::ApplicationJson)
-        .body(bytes);
-
-    debug!("Sending request...");
-    let response = response.send(&mut rx_buf).await;
-
-    debug!("Processing response...");
-    match response {
-        Ok(r) => {
-            if r.status.is_successful() {
-                debug!("Sent timing data. Status code: {:?}", r.status);
-                Ok(())
-            } else {
-                error!("Failed to send timing data: Status code {:?}", r.status);
-                Err(Error::NonSuccessResponseCode)
-            }
+    // The timestamp is taken for every attempt so that it matches the time the data was sent
+    let timing_data = format_timing_data(boot_count, session_id, now().ticks());
+
+    let url = match metrics_urls(METRICS_URL).next() {
+        Some(url) => url,
+        None => {
+            error!("No metrics server is configured to send the timing data to");
+            return Err(Error::NoServerConfigured);
+        }
+    };
+
+    let mut tls_read_buffer = [0u8; tls_read_buffer_size(METRICS_URL)];
+    let mut tls_write_buffer = [0u8; tls_write_buffer_size(METRICS_URL)];
+    let path = api_path("/api/v1/timing");
+    let upload = Upload {
+        url,
+        path: &path,
+        content_type: ContentType::ApplicationJson,
+        headers: &[],
+        body: timing_data.as_bytes(),
+        timeout_in_milliseconds: TIMING_TIMEOUT_MS,
+    };
+
+    let result = post(
+        stack,
+        upload,
+        &mut tls_read_buffer,
+        &mut tls_write_buffer,
+        |body| match body {
+            Ok(body) => store_timing_response(body),
+            Err(e) => warn!("Failed to read the timing response: {:?}", e),
+        },
+    )
+    .await;
+    match result {
+        Ok(()) => {
+            debug!("Sent timing data");
+            Ok(())
         }
         Err(e) => {
-            error!("Failed to send timing data: error {:?}", e);
-            Err(Error::RequestFailed)
+            error!("Failed to send timing data: {e}");
+            Err(e.into())
+        }
+    }
+}
+
+/// Store the server time from the response to the timing data so that payloads can be
+/// timestamped, and the NTP resync interval the server asked for
+fn store_timing_response(body: &[u8]) {
+    match serde_json_core::from_slice::<TimingResponse>(body) {
+        Ok((response, _)) => {
+            match response.server_time_in_seconds {
+                // The time from NTP is more accurate so it is kept if it is available
+                Some(_) if unix_time_in_seconds().is_some() => {
+                    debug!("The time was already set from NTP. Ignoring the server time.")
+                }
+                Some(server_time) => set_unix_time(server_time),
+                None => warn!("The timing response did not contain the server time"),
+            }
+
+            // Without a hint from the server the device falls back to the default interval
+            set_ntp_resync_interval(response.ntp_resync_seconds);
         }
+        Err(e) => warn!("Failed to parse the timing response: {:?}", e),
     }
 }
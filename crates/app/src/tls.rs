@@ -0,0 +1,230 @@
+//! TLS for the connections to the service
+//!
+//! A connection uses TLS when its URL starts with `https://`. The TLS handshake is done by
+//! `reqwless` with `embedded-tls`, which can't verify certificates. The server is authenticated
+//! with a pre-shared key instead, which is set at build time with the `TLS_PSK_IDENTITY` and
+//! `TLS_PSK` environment variables. The key is written as hex. Without a key the identity of
+//! the server could not be checked and the API key and the data could be sent to anyone, so the
+//! build fails if one of the URLs uses `https` and no key is configured.
+//!
+//! A TLS connection needs a read buffer that can hold a full TLS record and a write buffer. The
+//! buffers are only reserved for URLs that use `https`. They are part of the future that sends
+//! the request, which lives in the executor task arena and not in the 72 KiB heap.
+//! `embedded-tls` doesn't allocate from the heap either. Only one connection is open at a time,
+//! so TLS adds about 21 KiB of RAM use while a request is being sent.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+
+use embassy_net::dns::DnsSocket;
+use embassy_net::tcp::client::TcpClient;
+
+use esp_hal::rng::Rng;
+
+use rand_core::RngCore;
+
+use reqwless::client::{HttpClient, TlsConfig, TlsVerify};
+
+use crate::random::RngWrapper;
+
+/// The size of the TLS read buffer. A TLS record holds up to 16 KiB of data plus the record
+/// overhead.
+const TLS_READ_BUFFER_SIZE: usize = 16640;
+
+/// The size of the TLS write buffer. The requests are small so they don't need a full record.
+const TLS_WRITE_BUFFER_SIZE: usize = 4096;
+
+/// The maximum length, in bytes, of the pre-shared key
+const MAX_PSK_LENGTH: usize = 64;
+
+/// The identity of the pre-shared key. Set at build time with the `TLS_PSK_IDENTITY`
+/// environment variable.
+const TLS_PSK_IDENTITY: Option<&str> = option_env!("TLS_PSK_IDENTITY");
+
+/// The pre-shared key, decoded from the hex in the `TLS_PSK` build time environment variable,
+/// and the length of the key. The length is zero if no valid key is configured.
+static TLS_PSK: ([u8; MAX_PSK_LENGTH], usize) = parse_hex_key(option_env!("TLS_PSK"));
+
+/// `true` if both the identity and a valid pre-shared key are configured
+const PRE_SHARED_KEY_CONFIGURED: bool = match TLS_PSK_IDENTITY {
+    Some(identity) => !identity.is_empty() && parse_hex_key(option_env!("TLS_PSK")).1 > 0,
+    None => false,
+};
+
+/// The random number generator that seeds the TLS connections. Set once at start up.
+static TLS_RNG: Mutex<Cell<Option<Rng>>> = Mutex::new(Cell::new(None));
+
+/// Store the random number generator that is used to seed the TLS connections
+pub fn set_tls_rng(rng: Rng) {
+    critical_section::with(|cs| TLS_RNG.borrow(cs).set(Some(rng)));
+}
+
+/// The size of the TLS read buffer for the given URL, or for the given URLs separated by commas.
+/// Zero if none of the URLs use TLS.
+pub const fn tls_read_buffer_size(url: &str) -> usize {
+    if requires_tls(url) {
+        TLS_READ_BUFFER_SIZE
+    } else {
+        0
+    }
+}
+
+/// The size of the TLS write buffer for the given URL, or for the given URLs separated by
+/// commas. Zero if none of the URLs use TLS.
+pub const fn tls_write_buffer_size(url: &str) -> usize {
+    if requires_tls(url) {
+        TLS_WRITE_BUFFER_SIZE
+    } else {
+        0
+    }
+}
+
+/// Create an HTTP client for the given URL. The client uses TLS if the URL starts with
+/// `https://`, in which case the buffers must be at least as large as `tls_read_buffer_size`
+/// and `tls_write_buffer_size`.
+pub fn http_client<'a, 'd>(
+    tcp_client: &'a TcpClient<'d, 1, 4096, 4096>,
+    dns_socket: &'a DnsSocket<'d>,
+    url: &str,
+    read_buffer: &'a mut [u8],
+    write_buffer: &'a mut [u8],
+) -> HttpClient<'a, TcpClient<'d, 1, 4096, 4096>, DnsSocket<'d>>
+where
+    'd: 'a,
+{
+    if !is_https(url) {
+        return HttpClient::new(tcp_client, dns_socket);
+    }
+
+    // The buffer sizes are only known if the pre-shared key is configured, see `requires_tls`
+    let (Some(identity), Some(psk)) = (TLS_PSK_IDENTITY, pre_shared_key()) else {
+        unreachable!("An https URL requires a pre-shared key");
+    };
+    let verify = TlsVerify::Psk {
+        identity: identity.as_bytes(),
+        psk,
+    };
+
+    let tls_config = TlsConfig::new(tls_seed(), read_buffer, write_buffer, verify);
+    HttpClient::new_with_tls(tcp_client, dns_socket, tls_config)
+}
+
+/// The pre-shared key, if one is configured
+fn pre_shared_key() -> Option<&'static [u8]> {
+    let (key, length) = &TLS_PSK;
+    if *length == 0 {
+        None
+    } else {
+        Some(&key[..*length])
+    }
+}
+
+/// A new random seed for a TLS connection
+fn tls_seed() -> u64 {
+    let rng = critical_section::with(|cs| TLS_RNG.borrow(cs).get())
+        .expect("The TLS random number generator should be set at start up");
+    RngWrapper::from(rng).next_u64()
+}
+
+/// Determine if any of the URLs, separated by commas, uses TLS. Fails the build if one does and
+/// no pre-shared key is configured, because the server can't be authenticated without it.
+const fn requires_tls(urls: &str) -> bool {
+    let https = any_https(urls);
+    assert!(
+        !https || PRE_SHARED_KEY_CONFIGURED,
+        "https URLs require TLS_PSK_IDENTITY and TLS_PSK so that the server can be authenticated"
+    );
+
+    https
+}
+
+/// Determine if the URL uses TLS
+const fn is_https(url: &str) -> bool {
+    starts_with_https(url.as_bytes(), 0)
+}
+
+/// Determine if any of the URLs, separated by commas, uses TLS
+const fn any_https(urls: &str) -> bool {
+    let bytes = urls.as_bytes();
+    let mut start = 0;
+    let mut index = 0;
+    while index <= bytes.len() {
+        if index == bytes.len() || bytes[index] == b',' {
+            if starts_with_https(bytes, start) {
+                return true;
+            }
+
+            start = index + 1;
+        }
+
+        index += 1;
+    }
+
+    false
+}
+
+/// Determine if the URL that starts at `start` uses TLS. Leading spaces are skipped.
+const fn starts_with_https(bytes: &[u8], start: usize) -> bool {
+    let scheme = b"https://";
+    let mut start = start;
+    while start < bytes.len() && bytes[start] == b' ' {
+        start += 1;
+    }
+
+    if bytes.len() < start + scheme.len() {
+        return false;
+    }
+
+    let mut index = 0;
+    while index < scheme.len() {
+        if bytes[start + index].to_ascii_lowercase() != scheme[index] {
+            return false;
+        }
+
+        index += 1;
+    }
+
+    true
+}
+
+/// Decode a key written as hex. Returns a length of zero if the value is not set, is empty, is
+/// too long or is not valid hex.
+const fn parse_hex_key(value: Option<&str>) -> ([u8; MAX_PSK_LENGTH], usize) {
+    let mut key = [0u8; MAX_PSK_LENGTH];
+    let bytes = match value {
+        Some(v) => v.as_bytes(),
+        None => return (key, 0),
+    };
+
+    if bytes.is_empty() || bytes.len() % 2 != 0 || bytes.len() / 2 > MAX_PSK_LENGTH {
+        return (key, 0);
+    }
+
+    let mut index = 0;
+    while index < bytes.len() / 2 {
+        let high = match hex_digit(bytes[2 * index]) {
+            Some(d) => d,
+            None => return ([0u8; MAX_PSK_LENGTH], 0),
+        };
+        let low = match hex_digit(bytes[2 * index + 1]) {
+            Some(d) => d,
+            None => return ([0u8; MAX_PSK_LENGTH], 0),
+        };
+        key[index] = (high << 4) | low;
+
+        index += 1;
+    }
+
+    (key, bytes.len() / 2)
+}
+
+/// The value of a single hex digit
+const fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
@@ -0,0 +1,150 @@
+//! Posting payloads to the servers
+//!
+//! The timing data, the logs and the metrics are all posted the same way. Every request opens
+//! a new connection with the TCP timeout of the uploader, uses TLS for `https` URLs and
+//! carries the authorization header if an ingest API key is configured and the device token
+//! header once the device is provisioned. The body of a successful response is handed back to
+//! the caller.
+//!
+//! Each uploader can set its own TCP timeout at build time, e.g. a longer one for the larger
+//! log payloads, with the following environment variables. Each defaults to the TCP timeout of
+//! the WiFi module.
+//!
+//! * `TIMING_TIMEOUT_MS` - The timeout for the timing data
+//! * `METRICS_TIMEOUT_MS` - The timeout for the metrics
+//! * `LOGS_TIMEOUT_MS` - The timeout for the logs
+//!
+//! Nothing is logged here because the log uploader posts its payloads through this module too.
+//! The errors carry the details so that the callers can log them.
+
+use embassy_net::dns::DnsSocket;
+use embassy_net::tcp::client::{TcpClient, TcpClientState};
+use embassy_net::Stack;
+use embassy_time::Duration;
+use heapless::Vec;
+use reqwless::headers::ContentType;
+use reqwless::request::RequestBuilder;
+use tank_sensor_level_core::upload::{is_success_status, parse_upload_timeout_in_milliseconds};
+use thiserror::Error;
+
+use crate::auth::{ingest_authorization, AUTHORIZATION_HEADER_NAME};
+use crate::provisioning::{device_token_header_value, DEVICE_TOKEN_HEADER_NAME};
+use crate::retry::Retryable;
+use crate::tls::http_client;
+use crate::wifi::DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS;
+
+/// The maximum number of headers that a payload can add to the request, besides the
+/// authorization and device token headers
+const MAX_PAYLOAD_HEADERS: usize = 4;
+
+/// Errors that can occur when a payload is posted
+#[derive(Error, Debug)]
+pub enum UploadError {
+    #[error("Failed to connect to the server: {0:?}")]
+    ConnectionFailed(reqwless::Error),
+
+    #[error("The request failed to send: {0:?}")]
+    RequestFailed(reqwless::Error),
+
+    #[error("The response code {0} does not indicate success.")]
+    NonSuccessResponseCode(u16),
+}
+
+impl Retryable for UploadError {
+    fn is_retryable(&self) -> bool {
+        // A rejected payload will be rejected again
+        !matches!(self, UploadError::NonSuccessResponseCode(_))
+    }
+}
+
+/// A payload and where it is posted to
+pub struct Upload<'a> {
+    /// The URL of the server, e.g. `https://metrics.example.com`
+    pub url: &'a str,
+
+    /// The path of the endpoint, including the base path
+    pub path: &'a str,
+
+    pub content_type: ContentType,
+
+    /// The headers of the payload, e.g. the content encoding. At most `MAX_PAYLOAD_HEADERS`.
+    pub headers: &'a [(&'a str, &'a str)],
+
+    pub body: &'a [u8],
+
+    /// The TCP timeout of the connection in milliseconds
+    pub timeout_in_milliseconds: u64,
+}
+
+/// Parse the TCP timeout, in milliseconds, of an uploader from a build time environment
+/// variable. Returns the TCP timeout of the WiFi module if the value is not set, is zero or is
+/// longer than `MAX_UPLOAD_TIMEOUT_IN_MILLISECONDS`.
+pub const fn upload_timeout_in_milliseconds(value: Option<&str>) -> u64 {
+    parse_upload_timeout_in_milliseconds(value, DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS)
+}
+
+/// Check the status code of the response
+fn status_result(status_code: u16) -> Result<(), UploadError> {
+    if is_success_status(status_code) {
+        Ok(())
+    } else {
+        Err(UploadError::NonSuccessResponseCode(status_code))
+    }
+}
+
+/// Post the payload and pass the body of the response to `read_response` if the server accepted
+/// the payload. The TLS buffers must be at least as large as `tls_read_buffer_size` and
+/// `tls_write_buffer_size` for the URL.
+pub async fn post<T>(
+    stack: Stack<'_>,
+    upload: Upload<'_>,
+    tls_read_buffer: &mut [u8],
+    tls_write_buffer: &mut [u8],
+    read_response: impl FnOnce(Result<&[u8], reqwless::Error>) -> T,
+) -> Result<T, UploadError> {
+    let dns_socket = DnsSocket::new(stack);
+    let tcp_client_state = TcpClientState::<1, 4096, 4096>::new();
+    let mut tcp_client = TcpClient::new(stack, &tcp_client_state);
+    tcp_client.set_timeout(Some(Duration::from_millis(upload.timeout_in_milliseconds)));
+
+    let mut client = http_client(
+        &tcp_client,
+        &dns_socket,
+        upload.url,
+        tls_read_buffer,
+        tls_write_buffer,
+    );
+
+    let authorization = ingest_authorization();
+    let device_token = device_token_header_value();
+    let mut headers = Vec::<(&str, &str), { MAX_PAYLOAD_HEADERS + 2 }>::new();
+    if let Some(a) = &authorization {
+        let _ = headers.push((AUTHORIZATION_HEADER_NAME, a.as_str()));
+    }
+    if let Some(t) = &device_token {
+        let _ = headers.push((DEVICE_TOKEN_HEADER_NAME, t.as_str()));
+    }
+    for header in upload.headers {
+        let _ = headers.push(*header);
+    }
+
+    let mut rx_buf = [0; 4096];
+    // Connecting includes the TLS handshake for https URLs
+    let mut resource = client
+        .resource(upload.url)
+        .await
+        .map_err(UploadError::ConnectionFailed)?;
+    let response = resource
+        .post(upload.path)
+        .headers(&headers)
+        .content_type(upload.content_type)
+        .body(upload.body)
+        .send(&mut rx_buf)
+        .await
+        .map_err(UploadError::RequestFailed)?;
+
+    status_result(response.status.0)?;
+
+    let body = response.body().read_to_end().await;
+    Ok(read_response(body.map(|body| &*body)))
+}
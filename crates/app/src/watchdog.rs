@@ -0,0 +1,97 @@
+//! Safety net that limits how long the device stays awake
+//!
+//! If an operation hangs, e.g. on a half-open TCP connection, the device would never reach
+//! deep sleep and would drain the battery. The watchdog resets the device once it has been awake
+//! for `MAX_AWAKE_SECONDS`, which is set at build time with the `MAX_AWAKE_SECONDS` environment
+//! variable. It must be between 30 and 3600 seconds.
+//!
+//! Two mechanisms are used:
+//!
+//! * An embassy task that performs a software reset after `MAX_AWAKE_SECONDS`. This covers
+//!   awaits that never complete.
+//! * The hardware watchdog of timer group 1, which resets the device a few seconds later. This
+//!   covers code that blocks the executor so that the task can't run.
+//!
+//! A reset brings the device straight back into the wake cycle, which would keep it awake for as
+//! long as the server or the WiFi network is down and drain the battery. The watchdog task
+//! therefore leaves a flag in RTC memory before it resets the device, and the next boot goes
+//! back to deep sleep right away if the flag is set or if the hardware watchdog reset the device.
+//!
+//! The watchdog is independent of the WiFi monitor task. The WiFi monitor reports connection
+//! problems through `WIFI_MONITOR_RESULT_CHANNEL` and the main task only reads that channel
+//! between operations, so a hung operation never sees the result. The watchdog doesn't use the
+//! channel and doesn't disconnect from WiFi before the reset, because the hang may be inside the
+//! WiFi stack. A reset, unlike deep sleep, doesn't require WiFi to be turned off first.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+
+use embassy_executor::{SpawnError, Spawner};
+use embassy_time::{Duration, Timer};
+
+use esp_hal::peripherals::TIMG1;
+use esp_hal::ram;
+use esp_hal::reset::software_reset;
+use esp_hal::timer::timg::{MwdtStage, TimerGroup, Wdt};
+
+use log::{error, info};
+
+use crate::boot_reason::BootReason;
+use crate::build_env::parse_u64_in_range_or;
+
+/// The longest time, in seconds, that the device may stay awake. Less than 30 seconds doesn't
+/// leave enough time to connect to WiFi and send the data.
+const MAX_AWAKE_SECONDS: u64 =
+    parse_u64_in_range_or(option_env!("MAX_AWAKE_SECONDS"), 120, 30, 3600);
+
+/// The extra time, in seconds, the hardware watchdog allows before it resets the device. Gives
+/// the watchdog task the chance to log the reset first.
+const HARDWARE_WATCHDOG_GRACE_PERIOD_IN_SECONDS: u64 = 5;
+
+/// The value of `SLEEP_AFTER_RESET` that asks the next boot to go back to sleep. Any other
+/// value, e.g. the random content of the memory after a power loss, doesn't.
+const SLEEP_AFTER_RESET_MARKER: u32 = u32::from_le_bytes(*b"SLEP");
+
+/// Set by the watchdog task before it resets the device, so that the next boot goes back to
+/// sleep instead of starting another wake cycle
+///
+/// This is placed in the RTC Fast memory and isn't initialized on boot, so that it survives a
+/// software reset.
+#[ram(rtc_fast, persistent)]
+static SLEEP_AFTER_RESET: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Determine if the device should go back to sleep right away because one of the watchdogs
+/// ended the previous wake cycle. Clears the flag that the watchdog task leaves.
+pub fn sleep_after_watchdog_reset(boot_reason: BootReason) -> bool {
+    let flag_set = critical_section::with(|cs| SLEEP_AFTER_RESET.borrow(cs).replace(0))
+        == SLEEP_AFTER_RESET_MARKER;
+
+    flag_set || boot_reason == BootReason::Watchdog
+}
+
+/// Start the watchdog. The device is reset if it is still awake after `MAX_AWAKE_SECONDS`.
+pub fn start_awake_watchdog(spawner: Spawner, timg1: TIMG1) -> Result<(), SpawnError> {
+    let mut wdt = TimerGroup::new(timg1).wdt;
+    wdt.set_timeout(
+        MwdtStage::Stage0,
+        esp_hal::time::Duration::secs(
+            MAX_AWAKE_SECONDS + HARDWARE_WATCHDOG_GRACE_PERIOD_IN_SECONDS,
+        ),
+    );
+    wdt.enable();
+
+    info!("Device will be reset if it is still awake after {MAX_AWAKE_SECONDS}s");
+    spawner.spawn(awake_watchdog_task(wdt))
+}
+
+/// Reset the device once it has been awake for too long. Owns the hardware watchdog so that it
+/// stays enabled.
+#[embassy_executor::task]
+async fn awake_watchdog_task(_wdt: Wdt<TIMG1>) {
+    Timer::after(Duration::from_secs(MAX_AWAKE_SECONDS)).await;
+
+    error!("Device has been awake for more than {MAX_AWAKE_SECONDS}s. Resetting.");
+    critical_section::with(|cs| SLEEP_AFTER_RESET.borrow(cs).set(SLEEP_AFTER_RESET_MARKER));
+    software_reset();
+}
@@ -8,6 +8,7 @@ use esp_wifi::config::PowerSaveMode;
 use log::debug;
 use log::error;
 use log::info;
+use log::warn;
 
 use embassy_executor::Spawner;
 
@@ -17,6 +18,7 @@ use esp_wifi::init as initialize_wifi;
 use esp_wifi::wifi::new_with_mode as new_wifi_with_mode;
 use esp_wifi::wifi::ClientConfiguration;
 use esp_wifi::wifi::Configuration;
+use esp_wifi::wifi::ScanConfig;
 use esp_wifi::wifi::WifiController;
 use esp_wifi::wifi::WifiDevice;
 use esp_wifi::wifi::WifiError as EspWifiError;
@@ -47,21 +49,57 @@ use static_cell::StaticCell;
 
 use rand_core::RngCore as _;
 
+use tank_sensor_level_core::backoff::{backoff_delay_ms, jitter_ms};
+use tank_sensor_level_core::wifi::{parse_power_save_mode, DisconnectSchedule, WifiPowerSave};
+
+use crate::build_env::{parse_u64_in_range_or, parse_u64_or};
 use crate::RngWrapper;
 
+pub use tank_sensor_level_core::wifi::{parse_wifi_networks, WifiNetworks};
+
 // Constants
-/// Maximum number of retry attempts when disconnecting
-const MAX_DISCONNECT_RETRIES: u8 = 3;
-/// Delay between disconnect retry attempts in milliseconds
-const DISCONNECT_RETRY_DELAY_MS: u64 = 100;
-/// Maximum number of WiFi reconnection attempts
-const WIFI_RECONNECT_ATTEMPTS: u8 = 3;
-/// Delay between reconnection attempts in milliseconds
+/// Maximum number of retry attempts when disconnecting. Set at build time with the
+/// `WIFI_MAX_DISCONNECT_RETRIES` environment variable.
+const MAX_DISCONNECT_RETRIES: u8 =
+    parse_u64_in_range_or(option_env!("WIFI_MAX_DISCONNECT_RETRIES"), 3, 1, 10) as u8;
+/// Delay between disconnect retry attempts in milliseconds, excluding jitter. Also the time that
+/// the first disconnect is given to complete. Set at build time with the
+/// `WIFI_DISCONNECT_RETRY_DELAY_MS` environment variable.
+const DISCONNECT_RETRY_DELAY_MS: u64 =
+    parse_u64_in_range_or(option_env!("WIFI_DISCONNECT_RETRY_DELAY_MS"), 100, 10, 2000);
+/// Maximum time in milliseconds that a disconnect is given to complete, excluding jitter
+const DISCONNECT_MAX_VERIFICATION_DELAY_MS: u64 = 2000;
+/// Maximum random delay in milliseconds that is added to the delays while disconnecting
+const DISCONNECT_MAX_JITTER_MS: u32 = 20;
+/// The delays while disconnecting
+const DISCONNECT_SCHEDULE: DisconnectSchedule = DisconnectSchedule {
+    base_delay_ms: DISCONNECT_RETRY_DELAY_MS,
+    max_verification_delay_ms: DISCONNECT_MAX_VERIFICATION_DELAY_MS,
+    max_jitter_ms: DISCONNECT_MAX_JITTER_MS,
+};
+/// Maximum number of WiFi reconnection attempts. Set at build time with the
+/// `WIFI_RECONNECT_ATTEMPTS` environment variable.
+const WIFI_RECONNECT_ATTEMPTS: u8 =
+    parse_u64_in_range_or(option_env!("WIFI_RECONNECT_ATTEMPTS"), 3, 1, 10) as u8;
+/// Delay between reconnection attempts in milliseconds. Doubles with every failed attempt.
 const WIFI_RECONNECT_DELAY_MS: u64 = 100;
-/// Interval for checking WiFi connection status in milliseconds
-const WIFI_CHECK_INTERVAL_MS: u64 = 50;
-/// Maximum number of consecutive connection failures before giving up
-const MAX_CONSECUTIVE_FAILURES: u8 = 2;
+/// Maximum delay between reconnection attempts in milliseconds, excluding jitter. Set at build
+/// time with the `WIFI_RECONNECT_MAX_DELAY_MS` environment variable.
+const WIFI_RECONNECT_MAX_DELAY_MS: u64 =
+    parse_u64_or(option_env!("WIFI_RECONNECT_MAX_DELAY_MS"), 2000);
+/// Maximum random delay in milliseconds that is added to the delay between reconnection attempts
+const WIFI_RECONNECT_MAX_JITTER_MS: u32 = 50;
+/// Interval for checking WiFi connection status in milliseconds. Set at build time with the
+/// `WIFI_CHECK_INTERVAL_MS` environment variable.
+const WIFI_CHECK_INTERVAL_MS: u64 =
+    parse_u64_in_range_or(option_env!("WIFI_CHECK_INTERVAL_MS"), 50, 1, 10_000);
+/// The power save mode of the WiFi radio, either `none`, `minimum` or `maximum`. Set at build
+/// time with the `WIFI_POWER_SAVE` environment variable.
+const WIFI_POWER_SAVE: Option<&str> = option_env!("WIFI_POWER_SAVE");
+/// Maximum number of consecutive connection failures before giving up. Set at build time with
+/// the `WIFI_MAX_CONSECUTIVE_FAILURES` environment variable.
+const MAX_CONSECUTIVE_FAILURES: u8 =
+    parse_u64_in_range_or(option_env!("WIFI_MAX_CONSECUTIVE_FAILURES"), 2, 1, 10) as u8;
 
 pub const DEFAULT_TCP_TIMEOUT_IN_MILLISECONDS: u64 = 5000;
 
@@ -144,9 +182,8 @@ pub async fn connect_to_wifi<'a>(
     wifi: WIFI,
     radio_clk: RADIO_CLK,
     rng: Rng,
-    ssid: String<32>,
-    password: String<64>,
-) -> Result<(WifiController<'a>, Stack<'a>), WifiConnectionError> {
+    networks: &WifiNetworks,
+) -> Result<(WifiController<'a>, Stack<'a>, Option<i8>), WifiConnectionError> {
     info!("Connecting to WiFi");
     let timg0 = TimerGroup::new(timg0);
 
@@ -161,69 +198,128 @@ pub async fn connect_to_wifi<'a>(
         return Err(WifiConnectionError::NetworkTaskSpawnFailed);
     }
 
-    let mut attempts = 0;
-    while attempts < WIFI_RECONNECT_ATTEMPTS {
-        debug!("Connecting to network ...");
-        let connect_result =
-            connect_to_network(&mut controller, ssid.clone(), password.clone()).await;
-        if connect_result.is_err() {
-            let e = connect_result.err().unwrap();
-            error!(
-                "WiFi connection attempt {}/{} failed: {e:?}",
-                attempts + 1,
-                WIFI_RECONNECT_ATTEMPTS
-            );
-        } else {
-            debug!("Wait for network link");
-            loop {
-                if stack.is_link_up() {
-                    break;
+    let mut jitter_rng = rng;
+    for (ssid, password) in networks.iter() {
+        info!("Trying WiFi network {ssid}");
+
+        let mut attempts = 0;
+        while attempts < WIFI_RECONNECT_ATTEMPTS {
+            debug!("Connecting to network ...");
+            let connect_result =
+                connect_to_network(&mut controller, ssid.clone(), password.clone()).await;
+            if connect_result.is_err() {
+                let e = connect_result.err().unwrap();
+                error!(
+                    "WiFi connection attempt {}/{} failed: {e:?}",
+                    attempts + 1,
+                    WIFI_RECONNECT_ATTEMPTS
+                );
+            } else {
+                debug!("Wait for network link");
+                loop {
+                    if stack.is_link_up() {
+                        break;
+                    }
+                    Timer::after(Duration::from_millis(500)).await;
                 }
-                Timer::after(Duration::from_millis(500)).await;
-            }
 
-            debug!("Wait for IP address");
-            loop {
-                if let Some(config) = stack.config_v4() {
-                    info!("Connected to WiFi with IP address {}", config.address);
-                    break;
+                debug!("Wait for IP address");
+                loop {
+                    if let Some(config) = stack.config_v4() {
+                        info!("Connected to WiFi with IP address {}", config.address);
+                        break;
+                    }
+                    Timer::after(Duration::from_millis(500)).await;
                 }
-                Timer::after(Duration::from_millis(500)).await;
-            }
 
-            // Verify connection is stable
-            Timer::after(Duration::from_millis(WIFI_RECONNECT_DELAY_MS)).await;
-            match controller.is_connected() {
-                Ok(true) => {
-                    info!("WiFi connection established and stable");
-                    return Ok((controller, stack));
-                }
-                Ok(false) => {
-                    error!(
-                        "WiFi connection attempt {}/{} failed. Failed to establish a stable connection.",
-                        attempts + 1,
-                        WIFI_RECONNECT_ATTEMPTS
-                    );
-                }
-                Err(e) => {
-                    error!(
-                        "WiFi connection attempt {}/{} failed: {e:?}",
-                        attempts + 1,
-                        WIFI_RECONNECT_ATTEMPTS
-                    );
+                // Verify connection is stable
+                Timer::after(Duration::from_millis(WIFI_RECONNECT_DELAY_MS)).await;
+                match controller.is_connected() {
+                    Ok(true) => {
+                        info!("WiFi connection to {ssid} established and stable");
+                        let signal_strength = read_signal_strength(&mut controller, ssid).await;
+                        return Ok((controller, stack, signal_strength));
+                    }
+                    Ok(false) => {
+                        error!(
+                            "WiFi connection attempt {}/{} failed. Failed to establish a stable connection.",
+                            attempts + 1,
+                            WIFI_RECONNECT_ATTEMPTS
+                        );
+                    }
+                    Err(e) => {
+                        error!(
+                            "WiFi connection attempt {}/{} failed: {e:?}",
+                            attempts + 1,
+                            WIFI_RECONNECT_ATTEMPTS
+                        );
+                    }
                 }
             }
+
+            if attempts + 1 < WIFI_RECONNECT_ATTEMPTS {
+                let delay = backoff_delay_ms(
+                    attempts,
+                    WIFI_RECONNECT_DELAY_MS,
+                    WIFI_RECONNECT_MAX_DELAY_MS,
+                    jitter_ms(jitter_rng.random(), WIFI_RECONNECT_MAX_JITTER_MS),
+                );
+                debug!("Waiting {delay}ms before the next connection attempt");
+                Timer::after(Duration::from_millis(delay)).await;
+            }
+            attempts += 1;
         }
 
-        attempts += 1;
-        if attempts < WIFI_RECONNECT_ATTEMPTS {
-            Timer::after(Duration::from_millis(WIFI_RECONNECT_DELAY_MS)).await;
+        error!("Failed to connect to WiFi network {ssid}");
+
+        // Stop the controller so that the next network gets configured when connecting
+        if let Err(e) = controller.stop_async().await {
+            error!("Failed to stop the WiFi controller: {e:?}");
         }
     }
 
     Err(WifiConnectionError::WifiConnectionFailed)
 }
 
+/// The power save mode of the WiFi radio for the configured mode
+fn power_save_mode(mode: WifiPowerSave) -> PowerSaveMode {
+    match mode {
+        WifiPowerSave::None => PowerSaveMode::None,
+        WifiPowerSave::Minimum => PowerSaveMode::Minimum,
+        WifiPowerSave::Maximum => PowerSaveMode::Maximum,
+    }
+}
+
+/// Read the signal strength, in dBm, of the access point for the given network
+///
+/// Returns `None` if the access point could not be found.
+async fn read_signal_strength(controller: &mut WifiController<'_>, ssid: &str) -> Option<i8> {
+    let scan_config = ScanConfig {
+        ssid: Some(ssid),
+        ..Default::default()
+    };
+
+    match controller.scan_with_config_async::<1>(scan_config).await {
+        Ok((access_points, _)) => match access_points.first() {
+            Some(access_point) => {
+                info!(
+                    "WiFi signal strength for {ssid}: {} dBm",
+                    access_point.signal_strength
+                );
+                Some(access_point.signal_strength)
+            }
+            None => {
+                warn!("Could not find the access point for {ssid} to read the signal strength");
+                None
+            }
+        },
+        Err(e) => {
+            warn!("Failed to read the WiFi signal strength: {e:?}");
+            None
+        }
+    }
+}
+
 /// Connect to WiFi
 async fn create_controller_and_stack<'a>(
     timg0: TimerGroup<TIMG0>,
@@ -247,7 +343,7 @@ async fn create_controller_and_stack<'a>(
 
     let (wifi_interface, mut controller) =
         new_wifi_with_mode(wifi_controller, wifi, WifiStaDevice)?;
-    controller.set_power_saving(PowerSaveMode::None)?;
+    controller.set_power_saving(power_save_mode(parse_power_save_mode(WIFI_POWER_SAVE)))?;
 
     let config = Config::dhcpv4(DhcpConfig::default());
 
@@ -288,9 +384,15 @@ async fn connect_to_network(
     }
 }
 
+/// Disconnect from the WiFi network and verify that the connection is closed
+///
+/// Some access points are slow to acknowledge the disconnect, so every retry gives the disconnect
+/// twice as long to complete as the one before it.
 pub async fn disconnect_from_wifi(
     wifi_controller: &mut WifiController<'_>,
+    rng: Rng,
 ) -> Result<(), WifiDisconnectError> {
+    let mut jitter_rng = rng;
     let mut retries = 0;
     while retries < MAX_DISCONNECT_RETRIES {
         match wifi_controller.is_connected() {
@@ -303,8 +405,11 @@ pub async fn disconnect_from_wifi(
                 debug!("Disconnecting from Wifi ...");
                 match wifi_controller.disconnect() {
                     Ok(_) => {
-                        // Wait briefly to ensure disconnection completes
-                        Timer::after(Duration::from_millis(DISCONNECT_RETRY_DELAY_MS)).await;
+                        // Wait for the disconnection to complete
+                        let delay =
+                            DISCONNECT_SCHEDULE.verification_delay_ms(retries, jitter_rng.random());
+                        debug!("Waiting {delay}ms for the disconnect to complete");
+                        Timer::after(Duration::from_millis(delay)).await;
 
                         // Verify disconnection
                         match wifi_controller.is_connected() {
@@ -348,7 +453,10 @@ pub async fn disconnect_from_wifi(
 
         retries += 1;
         if retries < MAX_DISCONNECT_RETRIES {
-            Timer::after(Duration::from_millis(DISCONNECT_RETRY_DELAY_MS)).await;
+            Timer::after(Duration::from_millis(
+                DISCONNECT_SCHEDULE.retry_delay_ms(jitter_rng.random()),
+            ))
+            .await;
         }
     }
 
@@ -0,0 +1,61 @@
+//! Signing the payloads that are sent to the ingestion endpoints of the service
+//!
+//! The signature is the hex encoded HMAC-SHA256 of `<timestamp>.<payload>` with a secret that the
+//! device and the service share.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use hmac::{Hmac, Mac};
+
+use sha2::Sha256;
+
+/// The length of a hex encoded HMAC-SHA256 signature
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// The maximum length of a unix timestamp in seconds
+pub const TIMESTAMP_LENGTH: usize = 20;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The signature of a payload and the time at which it was signed
+pub struct PayloadSignature {
+    /// The unix time, in seconds, at which the payload was signed
+    pub timestamp: String<TIMESTAMP_LENGTH>,
+
+    /// The hex encoded HMAC-SHA256 of `<timestamp>.<payload>`
+    pub signature: String<SIGNATURE_LENGTH>,
+}
+
+/// Sign a payload with the shared secret at the given unix time, in seconds
+///
+/// The signature covers the timestamp as well as the payload so that the service can reject
+/// requests that are replayed later. Returns `None` if the secret is empty.
+pub fn sign_payload_at(secret: &str, unix_time: u64, payload: &[u8]) -> Option<PayloadSignature> {
+    if secret.is_empty() {
+        return None;
+    }
+
+    let mut timestamp = String::<TIMESTAMP_LENGTH>::new();
+    write!(timestamp, "{unix_time}").ok()?;
+
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+
+    let mut signature = String::<SIGNATURE_LENGTH>::new();
+    for byte in mac.finalize().into_bytes() {
+        write!(signature, "{byte:02x}").ok()?;
+    }
+
+    Some(PayloadSignature {
+        timestamp,
+        signature,
+    })
+}
+
+#[cfg(test)]
+#[path = "auth_tests.rs"]
+mod auth_tests;
@@ -0,0 +1,36 @@
+use super::*;
+
+/// A payload signed with a known secret at a known time. The service tests check that the
+/// service accepts the same signature.
+const SECRET: &str = "test-secret";
+const UNIX_TIME: u64 = 1_700_000_000;
+const PAYLOAD: &[u8] = br#"{"device_id":"tank"}"#;
+const SIGNATURE: &str = "b04351e03a6ea4f58fc2a7307c9c623465ff36bbeade3905451f19e6576dbc1f";
+
+#[test]
+fn test_payload_signature_matches_the_known_signature() {
+    let signature = sign_payload_at(SECRET, UNIX_TIME, PAYLOAD).unwrap();
+
+    assert_eq!(signature.timestamp.as_str(), "1700000000");
+    assert_eq!(signature.signature.as_str(), SIGNATURE);
+}
+
+#[test]
+fn test_signature_changes_with_the_timestamp() {
+    let signature = sign_payload_at(SECRET, UNIX_TIME + 1, PAYLOAD).unwrap();
+
+    assert_eq!(signature.timestamp.as_str(), "1700000001");
+    assert_ne!(signature.signature.as_str(), SIGNATURE);
+}
+
+#[test]
+fn test_signature_changes_with_the_secret() {
+    let signature = sign_payload_at("other-secret", UNIX_TIME, PAYLOAD).unwrap();
+
+    assert_ne!(signature.signature.as_str(), SIGNATURE);
+}
+
+#[test]
+fn test_payload_is_not_signed_without_a_secret() {
+    assert!(sign_payload_at("", UNIX_TIME, PAYLOAD).is_none());
+}
@@ -0,0 +1,21 @@
+//! The delays between the attempts of an operation that is retried
+
+/// Calculate the delay before the next attempt
+///
+/// The delay doubles with every attempt, starting at `base` for the first attempt (`attempt` is
+/// zero based), and is limited to `cap`. The `jitter` is added to the delay to prevent devices
+/// from retrying in lock-step.
+pub fn backoff_delay_ms(attempt: u8, base: u64, cap: u64, jitter: u32) -> u64 {
+    let multiplier = 1_u64.checked_shl(u32::from(attempt)).unwrap_or(u64::MAX);
+    let delay = base.saturating_mul(multiplier).min(cap);
+    delay.saturating_add(u64::from(jitter))
+}
+
+/// Turn a random number into a jitter between zero and `max_jitter`, inclusive
+pub fn jitter_ms(random: u32, max_jitter: u32) -> u32 {
+    random % max_jitter.saturating_add(1)
+}
+
+#[cfg(test)]
+#[path = "backoff_tests.rs"]
+mod backoff_tests;
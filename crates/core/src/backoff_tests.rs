@@ -0,0 +1,57 @@
+use super::*;
+
+#[test]
+fn test_delay_doubles_with_every_attempt() {
+    let delays: Vec<u64> = (0..5)
+        .map(|attempt| backoff_delay_ms(attempt, 100, 10_000, 0))
+        .collect();
+
+    assert_eq!(delays, [100, 200, 400, 800, 1600]);
+}
+
+#[test]
+fn test_delay_is_capped() {
+    assert_eq!(backoff_delay_ms(4, 100, 1000, 0), 1000);
+    assert_eq!(backoff_delay_ms(10, 100, 2000, 0), 2000);
+}
+
+#[test]
+fn test_delay_does_not_overflow_for_many_attempts() {
+    assert_eq!(backoff_delay_ms(63, 100, 2000, 0), 2000);
+    assert_eq!(backoff_delay_ms(64, 100, 2000, 0), 2000);
+    assert_eq!(backoff_delay_ms(u8::MAX, u64::MAX, u64::MAX, 0), u64::MAX);
+}
+
+#[test]
+fn test_jitter_is_added_after_the_cap() {
+    assert_eq!(backoff_delay_ms(0, 100, 2000, 50), 150);
+    assert_eq!(backoff_delay_ms(10, 100, 2000, 50), 2050);
+    assert_eq!(backoff_delay_ms(0, u64::MAX, u64::MAX, 50), u64::MAX);
+}
+
+#[test]
+fn test_jitter_is_within_bounds() {
+    for random in [0, 1, 49, 50, 51, 12_345, u32::MAX - 1, u32::MAX] {
+        let jitter = jitter_ms(random, 50);
+        assert!(jitter <= 50, "{random} gives a jitter of {jitter}");
+
+        let delay = backoff_delay_ms(2, 100, 2000, jitter);
+        assert!(
+            (400..=450).contains(&delay),
+            "{random} gives a delay of {delay}"
+        );
+    }
+}
+
+#[test]
+fn test_jitter_covers_the_whole_range() {
+    assert_eq!(jitter_ms(0, 50), 0);
+    assert_eq!(jitter_ms(50, 50), 50);
+    assert_eq!(jitter_ms(51, 50), 0);
+}
+
+#[test]
+fn test_no_jitter() {
+    assert_eq!(jitter_ms(12_345, 0), 0);
+    assert!(jitter_ms(u32::MAX, u32::MAX) < u32::MAX);
+}
@@ -0,0 +1,64 @@
+//! The reason the device booted
+
+/// The reason the device booted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BootReason {
+    /// The device was powered on
+    PowerOn,
+
+    /// The deep sleep timer woke the device
+    TimerWake,
+
+    /// The wake GPIO woke the device from deep sleep
+    GpioWake,
+
+    /// The supply voltage dropped too low
+    Brownout,
+
+    /// The firmware reset the device
+    SoftwareReset,
+
+    /// One of the watchdogs reset the device
+    Watchdog,
+
+    /// A reset that is not expected during normal operation, e.g. from the debugger
+    Other,
+
+    /// The reset reason could not be read
+    Unknown,
+}
+
+impl BootReason {
+    /// The boot reason for a wake up from deep sleep. The device was woken by the wake GPIO if
+    /// `woke_from_gpio` is `true` and by the deep sleep timer otherwise.
+    pub fn deep_sleep_wake(woke_from_gpio: bool) -> Self {
+        if woke_from_gpio {
+            BootReason::GpioWake
+        } else {
+            BootReason::TimerWake
+        }
+    }
+
+    /// The name of the boot reason as it is sent to the service
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootReason::PowerOn => "power_on",
+            BootReason::TimerWake => "timer_wake",
+            BootReason::GpioWake => "gpio_wake",
+            BootReason::Brownout => "brownout",
+            BootReason::SoftwareReset => "software_reset",
+            BootReason::Watchdog => "watchdog",
+            BootReason::Other => "other",
+            BootReason::Unknown => "unknown",
+        }
+    }
+
+    /// `true` if the device woke from deep sleep, in which case the RTC memory was kept
+    pub fn is_deep_sleep_wake(&self) -> bool {
+        matches!(self, BootReason::TimerWake | BootReason::GpioWake)
+    }
+}
+
+#[cfg(test)]
+#[path = "boot_reason_tests.rs"]
+mod boot_reason_tests;
@@ -0,0 +1,48 @@
+use super::*;
+
+const ALL_BOOT_REASONS: [BootReason; 8] = [
+    BootReason::PowerOn,
+    BootReason::TimerWake,
+    BootReason::GpioWake,
+    BootReason::Brownout,
+    BootReason::SoftwareReset,
+    BootReason::Watchdog,
+    BootReason::Other,
+    BootReason::Unknown,
+];
+
+#[test]
+fn test_boot_reason_names() {
+    let names: Vec<&str> = ALL_BOOT_REASONS.iter().map(BootReason::as_str).collect();
+
+    assert_eq!(
+        names,
+        [
+            "power_on",
+            "timer_wake",
+            "gpio_wake",
+            "brownout",
+            "software_reset",
+            "watchdog",
+            "other",
+            "unknown",
+        ]
+    );
+}
+
+#[test]
+fn test_deep_sleep_wake_source() {
+    assert_eq!(BootReason::deep_sleep_wake(true), BootReason::GpioWake);
+    assert_eq!(BootReason::deep_sleep_wake(false), BootReason::TimerWake);
+}
+
+#[test]
+fn test_only_wakes_from_deep_sleep_keep_the_rtc_memory() {
+    for reason in ALL_BOOT_REASONS {
+        assert_eq!(
+            reason.is_deep_sleep_wake(),
+            matches!(reason, BootReason::TimerWake | BootReason::GpioWake),
+            "{reason:?}"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Helpers for reading configuration values from build time environment variables
+
+/// Parse an unsigned integer from a build time environment variable
+///
+/// Returns the `default` value if the variable is not set, is empty or is not a valid
+/// unsigned integer.
+pub const fn parse_u64_or(value: Option<&str>, default: u64) -> u64 {
+    let bytes = match value {
+        Some(v) => v.as_bytes(),
+        None => return default,
+    };
+
+    if bytes.is_empty() {
+        return default;
+    }
+
+    let mut result: u64 = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        if !byte.is_ascii_digit() {
+            return default;
+        }
+
+        result = match result.checked_mul(10) {
+            Some(r) => r,
+            None => return default,
+        };
+        result = match result.checked_add((byte - b'0') as u64) {
+            Some(r) => r,
+            None => return default,
+        };
+
+        index += 1;
+    }
+
+    result
+}
+
+/// Parse an unsigned integer within a range from a build time environment variable
+///
+/// Returns the `default` value if the variable is not set, is not a valid unsigned integer or
+/// is outside the range `min..=max`. A `min` of 1 rejects zero.
+pub const fn parse_u64_in_range_or(value: Option<&str>, default: u64, min: u64, max: u64) -> u64 {
+    let result = parse_u64_or(value, default);
+    if result < min || result > max {
+        default
+    } else {
+        result
+    }
+}
+
+/// Parse a boolean from a build time environment variable
+///
+/// Accepts `true` or `1` and `false` or `0`. Returns the `default` value if the variable is not
+/// set or has any other value.
+pub const fn parse_bool_or(value: Option<&str>, default: bool) -> bool {
+    match value {
+        Some(v) if bytes_equal(v.as_bytes(), b"true") || bytes_equal(v.as_bytes(), b"1") => true,
+        Some(v) if bytes_equal(v.as_bytes(), b"false") || bytes_equal(v.as_bytes(), b"0") => false,
+        _ => default,
+    }
+}
+
+/// Parse an I2C address, either as hexadecimal with a `0x` prefix or as decimal
+pub fn parse_i2c_address(value: &str) -> Option<u8> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => value.parse::<u8>().ok(),
+    }
+}
+
+/// Parse an I2C address and check that it is one of the given addresses
+pub fn parse_valid_i2c_address(value: &str, valid_addresses: &[u8]) -> Option<u8> {
+    parse_i2c_address(value).filter(|address| valid_addresses.contains(address))
+}
+
+/// Compare two byte strings. `PartialEq` can't be used in a `const fn`.
+const fn bytes_equal(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+
+    let mut index = 0;
+    while index < left.len() {
+        if left[index] != right[index] {
+            return false;
+        }
+
+        index += 1;
+    }
+
+    true
+}
+
+#[cfg(test)]
+#[path = "build_env_tests.rs"]
+mod build_env_tests;
@@ -0,0 +1,85 @@
+use super::*;
+
+#[test]
+fn test_u64_is_parsed() {
+    assert_eq!(parse_u64_or(Some("0"), 7), 0);
+    assert_eq!(parse_u64_or(Some("42"), 7), 42);
+    assert_eq!(parse_u64_or(Some("18446744073709551615"), 7), u64::MAX);
+}
+
+#[test]
+fn test_invalid_u64_uses_the_default() {
+    for value in [
+        None,
+        Some(""),
+        Some(" 42"),
+        Some("-1"),
+        Some("4.2"),
+        Some("0x10"),
+    ] {
+        assert_eq!(parse_u64_or(value, 7), 7, "{value:?}");
+    }
+}
+
+#[test]
+fn test_u64_that_overflows_uses_the_default() {
+    assert_eq!(parse_u64_or(Some("18446744073709551616"), 7), 7);
+    assert_eq!(parse_u64_or(Some("99999999999999999999"), 7), 7);
+}
+
+#[test]
+fn test_u64_in_range_is_parsed() {
+    assert_eq!(parse_u64_in_range_or(Some("1"), 7, 1, 10), 1);
+    assert_eq!(parse_u64_in_range_or(Some("10"), 7, 1, 10), 10);
+}
+
+#[test]
+fn test_u64_outside_the_range_uses_the_default() {
+    assert_eq!(parse_u64_in_range_or(Some("0"), 7, 1, 10), 7);
+    assert_eq!(parse_u64_in_range_or(Some("11"), 7, 1, 10), 7);
+    assert_eq!(parse_u64_in_range_or(Some("abc"), 7, 1, 10), 7);
+}
+
+#[test]
+fn test_bool_is_parsed() {
+    assert!(parse_bool_or(Some("true"), false));
+    assert!(parse_bool_or(Some("1"), false));
+    assert!(!parse_bool_or(Some("false"), true));
+    assert!(!parse_bool_or(Some("0"), true));
+}
+
+#[test]
+fn test_invalid_bool_uses_the_default() {
+    for value in [None, Some(""), Some("TRUE"), Some("yes"), Some("2")] {
+        assert!(parse_bool_or(value, true), "{value:?}");
+        assert!(!parse_bool_or(value, false), "{value:?}");
+    }
+}
+
+#[test]
+fn test_i2c_address_is_parsed() {
+    assert_eq!(parse_i2c_address("0x48"), Some(0x48));
+    assert_eq!(parse_i2c_address("0X4a"), Some(0x4A));
+    assert_eq!(parse_i2c_address("118"), Some(0x76));
+    assert_eq!(parse_i2c_address(" 0x77 "), Some(0x77));
+}
+
+#[test]
+fn test_invalid_i2c_address_is_not_parsed() {
+    for value in ["", "0x", "0x100", "256", "-1", "0xZZ", "x48", "forty-eight"] {
+        assert_eq!(parse_i2c_address(value), None, "{value:?}");
+    }
+}
+
+#[test]
+fn test_valid_i2c_address_is_accepted() {
+    assert_eq!(parse_valid_i2c_address("0x77", &[0x76, 0x77]), Some(0x77));
+    assert_eq!(parse_valid_i2c_address("118", &[0x76, 0x77]), Some(0x76));
+}
+
+#[test]
+fn test_i2c_address_that_is_not_valid_for_the_device_is_rejected() {
+    assert_eq!(parse_valid_i2c_address("0x48", &[0x76, 0x77]), None);
+    assert_eq!(parse_valid_i2c_address("0x76", &[]), None);
+    assert_eq!(parse_valid_i2c_address("invalid", &[0x76, 0x77]), None);
+}
@@ -0,0 +1,34 @@
+//! Wall clock time from the time since boot
+//!
+//! The device has no real time clock. The unix time is stored as the time at which the device
+//! booted, so that the current time follows from the time since boot. The time since boot is
+//! read through a `TimeSource` so that the calculations don't depend on the system timer of the
+//! device.
+
+/// A source of the time since boot
+pub trait TimeSource {
+    /// The time since boot in micro seconds
+    fn now_micros(&self) -> u64;
+}
+
+/// The unix time, in micro seconds, at which the device booted given the current unix time
+pub fn unix_time_at_boot_in_micro_seconds(
+    time_source: &impl TimeSource,
+    unix_time_in_seconds: u64,
+) -> u64 {
+    unix_time_in_seconds
+        .saturating_mul(1_000_000)
+        .saturating_sub(time_source.now_micros())
+}
+
+/// The current unix time, in seconds, given the unix time at which the device booted
+pub fn current_unix_time_in_seconds(
+    time_source: &impl TimeSource,
+    unix_time_at_boot_in_micro_seconds: u64,
+) -> u64 {
+    unix_time_at_boot_in_micro_seconds.saturating_add(time_source.now_micros()) / 1_000_000
+}
+
+#[cfg(test)]
+#[path = "clock_tests.rs"]
+mod clock_tests;
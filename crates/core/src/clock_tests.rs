@@ -0,0 +1,80 @@
+use core::cell::Cell;
+
+use super::*;
+
+/// A time source that returns a time that the test sets
+#[derive(Default)]
+struct ManualTimeSource {
+    now_micros: Cell<u64>,
+}
+
+impl ManualTimeSource {
+    fn at(now_micros: u64) -> Self {
+        Self {
+            now_micros: Cell::new(now_micros),
+        }
+    }
+
+    fn advance(&self, micros: u64) {
+        self.now_micros.set(self.now_micros.get() + micros);
+    }
+}
+
+impl TimeSource for ManualTimeSource {
+    fn now_micros(&self) -> u64 {
+        self.now_micros.get()
+    }
+}
+
+#[test]
+fn test_boot_time_is_the_unix_time_minus_the_time_since_boot() {
+    let time_source = ManualTimeSource::at(2_500_000);
+
+    assert_eq!(
+        unix_time_at_boot_in_micro_seconds(&time_source, 1_700_000_000),
+        1_699_999_997_500_000
+    );
+}
+
+#[test]
+fn test_unix_time_follows_the_time_since_boot() {
+    let time_source = ManualTimeSource::at(2_500_000);
+    let boot_time = unix_time_at_boot_in_micro_seconds(&time_source, 1_700_000_000);
+    assert_eq!(
+        current_unix_time_in_seconds(&time_source, boot_time),
+        1_700_000_000
+    );
+
+    time_source.advance(10_400_000);
+    assert_eq!(
+        current_unix_time_in_seconds(&time_source, boot_time),
+        1_700_000_010
+    );
+
+    time_source.advance(600_000);
+    assert_eq!(
+        current_unix_time_in_seconds(&time_source, boot_time),
+        1_700_000_011
+    );
+}
+
+#[test]
+fn test_unix_time_before_the_time_since_boot_saturates() {
+    let time_source = ManualTimeSource::at(5_000_000);
+
+    assert_eq!(unix_time_at_boot_in_micro_seconds(&time_source, 2), 0);
+}
+
+#[test]
+fn test_unix_time_does_not_overflow() {
+    let time_source = ManualTimeSource::at(u64::MAX);
+
+    assert_eq!(
+        unix_time_at_boot_in_micro_seconds(&time_source, u64::MAX),
+        0
+    );
+    assert_eq!(
+        current_unix_time_in_seconds(&time_source, u64::MAX),
+        u64::MAX / 1_000_000
+    );
+}
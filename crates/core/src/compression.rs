@@ -0,0 +1,231 @@
+//! Gzip compression of payloads
+//!
+//! The payloads are compressed with a small deflate encoder that uses the fixed Huffman codes
+//! and a short look-back window. It doesn't compress as well as a full deflate implementation,
+//! but it doesn't need any heap memory, which is scarce on the device.
+
+/// The number of bytes before the current position that are searched for a match
+const WINDOW_SIZE: usize = 512;
+
+/// The shortest match that deflate can encode
+const MIN_MATCH_LENGTH: usize = 3;
+
+/// The longest match that deflate can encode
+const MAX_MATCH_LENGTH: usize = 258;
+
+/// The gzip header: magic number, deflate compression, no flags, no modification time, no
+/// extra flags and an unknown operating system
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff];
+
+/// The shortest length for each deflate length code, starting at code 257
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// The number of extra bits for each deflate length code, starting at code 257
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The shortest distance for each deflate distance code
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// The number of extra bits for each deflate distance code
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The size of the buffer that is always large enough for the compressed payload
+///
+/// A literal takes at most 9 bits with the fixed Huffman codes, so incompressible data grows by
+/// an eighth, plus the gzip header and trailer.
+pub const fn max_compressed_length(length: usize) -> usize {
+    length + length / 8 + 32
+}
+
+/// Writes bits to a byte buffer, least significant bit first, as required by deflate
+struct BitWriter<'a> {
+    output: &'a mut [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+    overflow: bool,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(output: &'a mut [u8]) -> Self {
+        Self {
+            output,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+            overflow: false,
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        match self.output.get_mut(self.position) {
+            Some(b) => {
+                *b = byte;
+                self.position += 1;
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_byte(*byte);
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.write_byte(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Huffman codes are stored most significant bit first
+    fn write_huffman_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Write any remaining bits, padding the last byte with zeros
+    fn flush(&mut self) {
+        if self.bit_count > 0 {
+            self.write_byte(self.bit_buffer as u8);
+            self.bit_buffer = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn write_literal(&mut self, literal: u8) {
+        match literal {
+            0..=143 => self.write_huffman_code(0x30 + u32::from(literal), 8),
+            _ => self.write_huffman_code(0x190 + u32::from(literal) - 144, 9),
+        }
+    }
+
+    fn write_length_symbol(&mut self, symbol: u32) {
+        match symbol {
+            256..=279 => self.write_huffman_code(symbol - 256, 7),
+            _ => self.write_huffman_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let length_code = LENGTH_BASE
+            .iter()
+            .rposition(|base| usize::from(*base) <= length)
+            .unwrap_or(0);
+        self.write_length_symbol(257 + length_code as u32);
+        self.write_bits(
+            (length - usize::from(LENGTH_BASE[length_code])) as u32,
+            u32::from(LENGTH_EXTRA_BITS[length_code]),
+        );
+
+        let distance_code = DISTANCE_BASE
+            .iter()
+            .rposition(|base| usize::from(*base) <= distance)
+            .unwrap_or(0);
+        self.write_huffman_code(distance_code as u32, 5);
+        self.write_bits(
+            (distance - usize::from(DISTANCE_BASE[distance_code])) as u32,
+            u32::from(DISTANCE_EXTRA_BITS[distance_code]),
+        );
+    }
+}
+
+/// Find the longest earlier occurrence of the bytes at `position`. Returns the length and the
+/// distance of the match.
+fn longest_match(input: &[u8], position: usize) -> (usize, usize) {
+    let max_length = MAX_MATCH_LENGTH.min(input.len() - position);
+    let window_start = position.saturating_sub(WINDOW_SIZE);
+
+    let mut best_length = 0;
+    let mut best_distance = 0;
+    for start in (window_start..position).rev() {
+        let length = input[start..]
+            .iter()
+            .zip(&input[position..position + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best_length {
+            best_length = length;
+            best_distance = position - start;
+            if length == max_length {
+                break;
+            }
+        }
+    }
+
+    (best_length, best_distance)
+}
+
+/// Calculate the CRC-32 checksum that gzip uses
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Compress the input with gzip
+///
+/// Returns the number of bytes written to the output, or `None` if the output is too small. An
+/// output of `max_compressed_length(input.len())` bytes is always large enough.
+pub fn gzip(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut writer = BitWriter::new(output);
+    writer.write_bytes(&GZIP_HEADER);
+
+    // A single final block with the fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut position = 0;
+    while position < input.len() {
+        let (length, distance) = longest_match(input, position);
+        if length >= MIN_MATCH_LENGTH {
+            writer.write_match(length, distance);
+            position += length;
+        } else {
+            writer.write_literal(input[position]);
+            position += 1;
+        }
+    }
+
+    // End of block
+    writer.write_length_symbol(256);
+    writer.flush();
+
+    writer.write_bytes(&crc32(input).to_le_bytes());
+    writer.write_bytes(&(input.len() as u32).to_le_bytes());
+
+    if writer.overflow {
+        None
+    } else {
+        Some(writer.position)
+    }
+}
+
+#[cfg(test)]
+#[path = "compression_tests.rs"]
+mod compression_tests;
@@ -0,0 +1,98 @@
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+
+use super::*;
+
+fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = vec![0u8; max_compressed_length(input.len())];
+    let length = gzip(input, &mut output).expect("The output should be large enough");
+    output.truncate(length);
+    output
+}
+
+fn decompress(compressed: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    GzDecoder::new(compressed)
+        .read_to_end(&mut output)
+        .expect("The payload should be valid gzip");
+    output
+}
+
+#[test]
+fn test_crc32_matches_the_check_value() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn test_gzip_round_trips_an_empty_payload() {
+    assert_eq!(decompress(&compress(b"")), b"");
+}
+
+#[test]
+fn test_gzip_round_trips_a_metrics_payload() {
+    let payload = br#"{"device_id":"tank_1","boot_count":42,"temperature_in_celcius":18.25,"humidity_in_percent":64.50,"battery_voltage":12.612,"tank_level_in_meters":1.234,"tank_volume_in_liters":8712.3}"#;
+
+    let compressed = compress(payload);
+
+    assert_eq!(decompress(&compressed), payload);
+}
+
+#[test]
+fn test_gzip_compresses_repeated_data() {
+    let payload = br#"{"level":"INFO","message":"Sending metrics to server ..."},"#.repeat(20);
+
+    let compressed = compress(&payload);
+
+    assert!(compressed.len() < payload.len() / 4);
+    assert_eq!(decompress(&compressed), payload);
+}
+
+#[test]
+fn test_gzip_round_trips_the_longest_match() {
+    let payload = [b'a'; 1000];
+
+    assert_eq!(decompress(&compress(&payload)), payload);
+}
+
+#[test]
+fn test_gzip_round_trips_matches_at_the_end_of_the_window() {
+    let block: Vec<u8> = (0..WINDOW_SIZE as u32)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect();
+    let payload = [block.as_slice(), block.as_slice(), block.as_slice()].concat();
+
+    assert_eq!(decompress(&compress(&payload)), payload);
+}
+
+#[test]
+fn test_gzip_round_trips_incompressible_data() {
+    // A simple linear congruential generator, so that the data has no repeats to match
+    let mut state = 12345u32;
+    let payload: Vec<u8> = (0..2048)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+
+    let compressed = compress(&payload);
+
+    assert!(compressed.len() <= max_compressed_length(payload.len()));
+    assert_eq!(decompress(&compressed), payload);
+}
+
+#[test]
+fn test_gzip_round_trips_every_byte_value() {
+    let payload: Vec<u8> = (0..=255u8).chain((0..=255u8).rev()).collect();
+
+    assert_eq!(decompress(&compress(&payload)), payload);
+}
+
+#[test]
+fn test_gzip_reports_an_output_that_is_too_small() {
+    let mut output = [0u8; 16];
+
+    assert_eq!(gzip(b"a payload that does not fit", &mut output), None);
+}
@@ -0,0 +1,27 @@
+//! The hardware independent logic of the embedded app
+//!
+//! The crate is `no_std` so that the embedded app can use it, but it doesn't depend on the
+//! ESP32 crates so that the logic can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod auth;
+pub mod backoff;
+pub mod boot_reason;
+pub mod build_env;
+pub mod clock;
+pub mod compression;
+pub mod logging;
+pub mod metrics;
+pub mod partition_table;
+pub mod payload_queue;
+pub mod persistent_state;
+pub mod power;
+pub mod provisioning;
+pub mod radio_budget;
+pub mod recovery;
+pub mod sensor;
+pub mod sleep;
+pub mod tank;
+pub mod upload;
+pub mod wifi;
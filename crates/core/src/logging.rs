@@ -0,0 +1,64 @@
+//! Buffering the log entries and sending them to the service in chunks
+
+use heapless::Deque;
+
+/// A log entry that is counted, rather than stored again, when it is logged several times in a row
+pub trait RepeatableEntry {
+    /// `true` if the other entry is a repeat of this entry
+    fn is_repeated_by(&self, other: &Self) -> bool;
+
+    /// Count another repeat of the entry
+    fn count_repeat(&mut self);
+}
+
+/// Store the entry in the buffer, removing the oldest entry if the buffer is full. An entry that
+/// repeats the newest entry in the buffer only increments the count of the newest entry, so that
+/// e.g. a retry loop doesn't push out the messages that came before it.
+pub fn push_log_entry<T: RepeatableEntry, const N: usize>(buffer: &mut Deque<T, N>, entry: T) {
+    if let Some(newest) = buffer.back_mut() {
+        if newest.is_repeated_by(&entry) {
+            newest.count_repeat();
+            return;
+        }
+    }
+
+    if buffer.is_full() {
+        let _ = buffer.pop_front();
+    }
+
+    // There is always room after the oldest entry was removed
+    let _ = buffer.push_back(entry);
+}
+
+/// The number of entries at the start of the logs that go in the next chunk, given the size of
+/// each entry formatted as JSON. An entry has no size if it doesn't fit in the buffer by itself.
+///
+/// A chunk holds at most `max_entries` entries and, formatted as a JSON array, fits in
+/// `buffer_size` bytes. The first entry is always part of the chunk, even if it doesn't fit, so
+/// that it is reported and skipped instead of blocking the entries after it.
+pub fn chunk_length<I: IntoIterator<Item = Option<usize>>>(
+    entry_sizes: I,
+    max_entries: usize,
+    buffer_size: usize,
+) -> usize {
+    // The brackets around the array
+    let mut chunk_size = 2;
+    let mut length = 0;
+    for entry_size in entry_sizes.into_iter().take(max_entries) {
+        // The comma between the entries
+        let separator_size = if length == 0 { 0 } else { 1 };
+        match entry_size {
+            Some(entry_size) if chunk_size + separator_size + entry_size <= buffer_size => {
+                chunk_size += separator_size + entry_size;
+                length += 1;
+            }
+            _ => break,
+        }
+    }
+
+    length.max(1)
+}
+
+#[cfg(test)]
+#[path = "logging_tests.rs"]
+mod logging_tests;
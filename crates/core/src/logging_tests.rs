@@ -0,0 +1,126 @@
+use super::*;
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    message: &'static str,
+    count: u32,
+}
+
+impl RepeatableEntry for Entry {
+    fn is_repeated_by(&self, other: &Self) -> bool {
+        self.message == other.message
+    }
+
+    fn count_repeat(&mut self) {
+        self.count = self.count.saturating_add(1);
+    }
+}
+
+fn entry(message: &'static str) -> Entry {
+    Entry { message, count: 1 }
+}
+
+fn messages<const N: usize>(buffer: &Deque<Entry, N>) -> Vec<(&'static str, u32)> {
+    buffer.iter().map(|e| (e.message, e.count)).collect()
+}
+
+#[test]
+fn test_consecutive_duplicates_are_coalesced() {
+    let mut buffer = Deque::<Entry, 4>::new();
+    for _ in 0..3 {
+        push_log_entry(&mut buffer, entry("retrying"));
+    }
+
+    assert_eq!(messages(&buffer), [("retrying", 3)]);
+}
+
+#[test]
+fn test_distinct_messages_are_not_coalesced() {
+    let mut buffer = Deque::<Entry, 4>::new();
+    push_log_entry(&mut buffer, entry("first"));
+    push_log_entry(&mut buffer, entry("second"));
+
+    assert_eq!(messages(&buffer), [("first", 1), ("second", 1)]);
+}
+
+#[test]
+fn test_duplicates_that_are_not_consecutive_are_not_coalesced() {
+    let mut buffer = Deque::<Entry, 4>::new();
+    push_log_entry(&mut buffer, entry("retrying"));
+    push_log_entry(&mut buffer, entry("failed"));
+    push_log_entry(&mut buffer, entry("retrying"));
+
+    assert_eq!(
+        messages(&buffer),
+        [("retrying", 1), ("failed", 1), ("retrying", 1)]
+    );
+}
+
+#[test]
+fn test_full_buffer_drops_the_oldest_entry() {
+    let mut buffer = Deque::<Entry, 2>::new();
+    push_log_entry(&mut buffer, entry("first"));
+    push_log_entry(&mut buffer, entry("second"));
+    push_log_entry(&mut buffer, entry("third"));
+
+    assert_eq!(messages(&buffer), [("second", 1), ("third", 1)]);
+}
+
+#[test]
+fn test_duplicate_in_a_full_buffer_does_not_drop_an_entry() {
+    let mut buffer = Deque::<Entry, 2>::new();
+    push_log_entry(&mut buffer, entry("first"));
+    push_log_entry(&mut buffer, entry("second"));
+    push_log_entry(&mut buffer, entry("second"));
+
+    assert_eq!(messages(&buffer), [("first", 1), ("second", 2)]);
+}
+
+#[test]
+fn test_chunk_holds_at_most_the_maximum_number_of_entries() {
+    assert_eq!(chunk_length([Some(10); 5], 3, 1000), 3);
+}
+
+#[test]
+fn test_chunk_holds_all_entries_that_fit() {
+    assert_eq!(chunk_length([Some(10); 3], 10, 1000), 3);
+}
+
+#[test]
+fn test_chunk_fits_in_the_buffer() {
+    // The brackets, three entries and the two commas between them
+    let buffer_size = 2 + 3 * 10 + 2;
+
+    assert_eq!(chunk_length([Some(10); 5], 10, buffer_size), 3);
+    assert_eq!(chunk_length([Some(10); 5], 10, buffer_size - 1), 2);
+}
+
+#[test]
+fn test_no_chunk_exceeds_the_buffer() {
+    let entry_sizes = [30, 5, 60, 1, 40, 40, 2, 70, 10, 25];
+    let buffer_size = 100;
+
+    let mut remaining = &entry_sizes[..];
+    while !remaining.is_empty() {
+        let length = chunk_length(remaining.iter().map(|size| Some(*size)), 4, buffer_size);
+        let (chunk, rest) = remaining.split_at(length);
+
+        let chunk_size = 2 + chunk.iter().sum::<usize>() + chunk.len() - 1;
+        assert!(chunk_size <= buffer_size, "{chunk:?} is {chunk_size} bytes");
+        assert!(chunk.len() <= 4);
+
+        remaining = rest;
+    }
+}
+
+#[test]
+fn test_entry_too_large_for_the_buffer_is_skipped_on_its_own() {
+    assert_eq!(chunk_length([Some(200), Some(10)], 10, 100), 1);
+    assert_eq!(chunk_length([None, Some(10)], 10, 100), 1);
+}
+
+#[test]
+fn test_chunk_stops_before_an_entry_that_does_not_fit() {
+    assert_eq!(chunk_length([Some(10), None, Some(10)], 10, 100), 1);
+    assert_eq!(chunk_length([Some(10), Some(200), Some(10)], 10, 100), 1);
+}
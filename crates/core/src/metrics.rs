@@ -0,0 +1,60 @@
+//! The metrics that are sent to the servers
+
+use core::fmt::Write;
+
+use crate::upload::Retryable;
+
+/// The URLs of the servers that receive the metrics, in the order they should be sent to. Empty
+/// entries are skipped.
+pub fn metrics_urls(urls: &str) -> impl Iterator<Item = &str> {
+    urls.split(',')
+        .map(|url| url.trim())
+        .filter(|url| !url.is_empty())
+}
+
+/// Combine the results of sending the metrics to two servers. The metrics are sent if either
+/// server accepted them, in which case the first response is used. If both failed, the metrics
+/// are only worth queueing if one of the servers may accept them later, so a retryable error
+/// takes precedence.
+pub fn combine_send_results<T, E: Retryable>(
+    first: Result<Option<T>, E>,
+    second: Result<Option<T>, E>,
+) -> Result<Option<T>, E> {
+    match (first, second) {
+        (Ok(first), Ok(second)) => Ok(first.or(second)),
+        (Ok(response), Err(_)) | (Err(_), Ok(response)) => Ok(response),
+        (Err(first), Err(second)) if !first.is_retryable() && second.is_retryable() => Err(second),
+        (Err(first), Err(_)) => Err(first),
+    }
+}
+
+/// Write a tag key or a tag value for the InfluxDB line protocol. Commas, equals signs and
+/// spaces are escaped with a backslash.
+pub fn write_influx_tag<W: Write>(buffer: &mut W, value: &str) -> core::fmt::Result {
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            buffer.write_char('\\')?;
+        }
+        buffer.write_char(c)?;
+    }
+
+    Ok(())
+}
+
+/// Write a string field value, including the quotes, for the InfluxDB line protocol. Double
+/// quotes and backslashes are escaped with a backslash.
+pub fn write_influx_string_field<W: Write>(buffer: &mut W, value: &str) -> core::fmt::Result {
+    buffer.write_char('"')?;
+    for c in value.chars() {
+        if matches!(c, '"' | '\\') {
+            buffer.write_char('\\')?;
+        }
+        buffer.write_char(c)?;
+    }
+
+    buffer.write_char('"')
+}
+
+#[cfg(test)]
+#[path = "metrics_tests.rs"]
+mod metrics_tests;
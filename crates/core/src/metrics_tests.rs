@@ -0,0 +1,130 @@
+use super::*;
+
+use heapless::String;
+
+#[derive(Debug, PartialEq)]
+enum SendError {
+    Rejected,
+    RequestFailed,
+}
+
+impl Retryable for SendError {
+    fn is_retryable(&self) -> bool {
+        matches!(self, SendError::RequestFailed)
+    }
+}
+
+#[test]
+fn test_metrics_urls_are_split_on_commas() {
+    let urls: Vec<&str> = metrics_urls("https://service,http://influx:8086").collect();
+
+    assert_eq!(urls, ["https://service", "http://influx:8086"]);
+}
+
+#[test]
+fn test_metrics_urls_are_trimmed_and_empty_entries_are_skipped() {
+    let urls: Vec<&str> = metrics_urls(" https://service , ,http://influx:8086,").collect();
+
+    assert_eq!(urls, ["https://service", "http://influx:8086"]);
+}
+
+#[test]
+fn test_no_metrics_urls() {
+    assert_eq!(metrics_urls("").count(), 0);
+    assert_eq!(metrics_urls(" , ").count(), 0);
+}
+
+#[test]
+fn test_first_response_is_used_if_both_servers_accept_the_metrics() {
+    assert_eq!(
+        combine_send_results::<_, SendError>(Ok(Some(1)), Ok(Some(2))),
+        Ok(Some(1))
+    );
+    assert_eq!(
+        combine_send_results::<_, SendError>(Ok(None), Ok(Some(2))),
+        Ok(Some(2))
+    );
+}
+
+#[test]
+fn test_metrics_are_sent_if_one_server_accepts_them() {
+    assert_eq!(
+        combine_send_results(Ok(Some(1)), Err(SendError::RequestFailed)),
+        Ok(Some(1))
+    );
+    assert_eq!(
+        combine_send_results(Err(SendError::Rejected), Ok(Some(2))),
+        Ok(Some(2))
+    );
+}
+
+#[test]
+fn test_retryable_error_is_kept_if_both_servers_fail() {
+    assert_eq!(
+        combine_send_results::<u32, _>(Err(SendError::Rejected), Err(SendError::RequestFailed)),
+        Err(SendError::RequestFailed)
+    );
+    assert_eq!(
+        combine_send_results::<u32, _>(Err(SendError::RequestFailed), Err(SendError::Rejected)),
+        Err(SendError::RequestFailed)
+    );
+}
+
+#[test]
+fn test_first_error_is_kept_if_neither_server_may_accept_the_metrics_later() {
+    assert_eq!(
+        combine_send_results::<u32, _>(Err(SendError::Rejected), Err(SendError::Rejected)),
+        Err(SendError::Rejected)
+    );
+}
+
+fn influx_tag(value: &str) -> String<64> {
+    let mut buffer = String::new();
+    write_influx_tag(&mut buffer, value).unwrap();
+    buffer
+}
+
+fn influx_string_field(value: &str) -> String<64> {
+    let mut buffer = String::new();
+    write_influx_string_field(&mut buffer, value).unwrap();
+    buffer
+}
+
+#[test]
+fn test_plain_influx_tag_is_not_escaped() {
+    assert_eq!(influx_tag("garden-tank_1").as_str(), "garden-tank_1");
+}
+
+#[test]
+fn test_influx_tag_escapes_commas_equals_signs_and_spaces() {
+    assert_eq!(influx_tag("a,b=c d").as_str(), "a\\,b\\=c\\ d");
+}
+
+#[test]
+fn test_influx_tag_does_not_escape_quotes() {
+    assert_eq!(influx_tag("\"tank\"").as_str(), "\"tank\"");
+}
+
+#[test]
+fn test_influx_string_field_is_quoted() {
+    assert_eq!(influx_string_field("0.1.0").as_str(), "\"0.1.0\"");
+}
+
+#[test]
+fn test_influx_string_field_escapes_quotes_and_backslashes() {
+    assert_eq!(
+        influx_string_field("say \"hi\" \\o/").as_str(),
+        "\"say \\\"hi\\\" \\\\o/\""
+    );
+}
+
+#[test]
+fn test_influx_string_field_does_not_escape_commas_or_spaces() {
+    assert_eq!(influx_string_field("a, b=c").as_str(), "\"a, b=c\"");
+}
+
+#[test]
+fn test_influx_escaping_fails_if_the_buffer_is_full() {
+    assert!(write_influx_tag(&mut String::<4>::new(), "a b c").is_err());
+    assert!(write_influx_string_field(&mut String::<4>::new(), "abcd").is_err());
+}
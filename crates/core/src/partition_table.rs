@@ -0,0 +1,92 @@
+//! The partition table of the flash
+//!
+//! The bootloader finds the partitions of the flash through the partition table, which is a list
+//! of 32 byte entries at a fixed offset. Each entry is, in little endian, the magic, the type,
+//! the subtype, the offset, the size, a 16 byte label that is padded with zeros and the flags.
+//! The list ends at an erased entry or at the MD5 checksum of the table.
+
+/// The offset of the partition table in the flash
+pub const PARTITION_TABLE_OFFSET: u32 = 0x8000;
+
+/// The maximum size of the partition table
+pub const PARTITION_TABLE_SIZE: u32 = 0xC00;
+
+/// The size of an entry in the partition table
+pub const PARTITION_ENTRY_SIZE: usize = 32;
+
+/// The type of the partitions that contain data, as opposed to apps
+pub const DATA_PARTITION_TYPE: u8 = 0x01;
+
+/// The marker at the start of every partition entry
+const PARTITION_MAGIC: [u8; 2] = [0xAA, 0x50];
+
+/// The maximum length of a partition label
+const MAX_LABEL_LENGTH: usize = 16;
+
+/// A partition from the partition table
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Partition {
+    /// The type, either an app or data
+    pub partition_type: u8,
+
+    /// The subtype, e.g. NVS for a data partition
+    pub subtype: u8,
+
+    /// The offset of the partition in the flash
+    pub offset: u32,
+
+    /// The size of the partition in bytes
+    pub size: u32,
+
+    /// The label, padded with zeros
+    label: [u8; MAX_LABEL_LENGTH],
+}
+
+impl Partition {
+    /// Read a partition from an entry of the partition table. `None` if the entry marks the end
+    /// of the table.
+    pub fn from_entry(entry: &[u8; PARTITION_ENTRY_SIZE]) -> Option<Self> {
+        if entry[0..2] != PARTITION_MAGIC {
+            return None;
+        }
+
+        let word = |index: usize| {
+            u32::from_le_bytes([
+                entry[index],
+                entry[index + 1],
+                entry[index + 2],
+                entry[index + 3],
+            ])
+        };
+
+        let mut label = [0u8; MAX_LABEL_LENGTH];
+        label.copy_from_slice(&entry[12..12 + MAX_LABEL_LENGTH]);
+
+        Some(Self {
+            partition_type: entry[2],
+            subtype: entry[3],
+            offset: word(4),
+            size: word(8),
+            label,
+        })
+    }
+
+    /// The label without the padding
+    pub fn label(&self) -> &[u8] {
+        let length = self
+            .label
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(MAX_LABEL_LENGTH);
+        &self.label[..length]
+    }
+
+    /// `true` if this is a data partition with the given label
+    pub fn is_data_partition(&self, label: &str) -> bool {
+        self.partition_type == DATA_PARTITION_TYPE && self.label() == label.as_bytes()
+    }
+}
+
+#[cfg(test)]
+#[path = "partition_table_tests.rs"]
+mod partition_table_tests;
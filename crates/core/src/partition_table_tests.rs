@@ -0,0 +1,63 @@
+use super::*;
+
+fn entry(partition_type: u8, subtype: u8, offset: u32, size: u32, label: &str) -> [u8; 32] {
+    let mut entry = [0u8; PARTITION_ENTRY_SIZE];
+    entry[0..2].copy_from_slice(&PARTITION_MAGIC);
+    entry[2] = partition_type;
+    entry[3] = subtype;
+    entry[4..8].copy_from_slice(&offset.to_le_bytes());
+    entry[8..12].copy_from_slice(&size.to_le_bytes());
+    entry[12..12 + label.len()].copy_from_slice(label.as_bytes());
+    entry
+}
+
+#[test]
+fn test_data_partition_is_read_from_an_entry() {
+    let partition = Partition::from_entry(&entry(0x01, 0x06, 0x3F_0000, 0x1_0000, "tank_state"))
+        .expect("The entry should contain a partition");
+
+    assert_eq!(partition.partition_type, DATA_PARTITION_TYPE);
+    assert_eq!(partition.subtype, 0x06);
+    assert_eq!(partition.offset, 0x3F_0000);
+    assert_eq!(partition.size, 0x1_0000);
+    assert_eq!(partition.label(), b"tank_state");
+    assert!(partition.is_data_partition("tank_state"));
+}
+
+#[test]
+fn test_label_may_use_all_sixteen_bytes() {
+    let partition =
+        Partition::from_entry(&entry(0x01, 0x02, 0x9000, 0x6000, "0123456789abcdef")).unwrap();
+
+    assert_eq!(partition.label(), b"0123456789abcdef");
+}
+
+#[test]
+fn test_partition_with_another_label_does_not_match() {
+    let partition = Partition::from_entry(&entry(0x01, 0x02, 0x9000, 0x6000, "nvs")).unwrap();
+
+    assert!(!partition.is_data_partition("tank_state"));
+    assert!(!partition.is_data_partition("nv"));
+}
+
+#[test]
+fn test_app_partition_is_not_a_data_partition() {
+    let partition =
+        Partition::from_entry(&entry(0x00, 0x00, 0x1_0000, 0x10_0000, "tank_state")).unwrap();
+
+    assert!(!partition.is_data_partition("tank_state"));
+}
+
+#[test]
+fn test_erased_entry_ends_the_table() {
+    assert_eq!(Partition::from_entry(&[0xFF; PARTITION_ENTRY_SIZE]), None);
+}
+
+#[test]
+fn test_checksum_entry_ends_the_table() {
+    let mut entry = [0xFF; PARTITION_ENTRY_SIZE];
+    entry[0] = 0xEB;
+    entry[1] = 0xEB;
+
+    assert_eq!(Partition::from_entry(&entry), None);
+}
@@ -0,0 +1,125 @@
+//! A ring buffer of payloads that failed to send
+//!
+//! The buffer is a fixed size so that it can be placed in the RTC memory of the device, which
+//! survives deep sleep. When the buffer is full the oldest payload is overwritten.
+
+/// The error when a payload is larger than an entry of the queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PayloadTooLarge {
+    /// The number of bytes in the payload
+    pub length: usize,
+}
+
+/// A payload that failed to send
+#[derive(Clone, Copy)]
+struct QueuedPayload<const LENGTH: usize> {
+    /// The number of bytes in the payload
+    length: usize,
+
+    /// The payload
+    payload: [u8; LENGTH],
+}
+
+impl<const LENGTH: usize> QueuedPayload<LENGTH> {
+    const fn empty() -> Self {
+        Self {
+            length: 0,
+            payload: [0; LENGTH],
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.payload[..self.length]
+    }
+}
+
+/// A ring buffer of at most `CAPACITY` payloads of at most `LENGTH` bytes each
+pub struct PayloadQueue<const CAPACITY: usize, const LENGTH: usize> {
+    /// The index of the oldest payload
+    first: usize,
+
+    /// The number of stored payloads
+    count: usize,
+
+    /// The stored payloads
+    entries: [QueuedPayload<LENGTH>; CAPACITY],
+}
+
+impl<const CAPACITY: usize, const LENGTH: usize> PayloadQueue<CAPACITY, LENGTH> {
+    pub const fn new() -> Self {
+        Self {
+            first: 0,
+            count: 0,
+            entries: [QueuedPayload::empty(); CAPACITY],
+        }
+    }
+
+    /// Check that the queue is consistent. RTC memory is not guaranteed to hold valid
+    /// data, e.g. after a firmware update.
+    pub fn is_valid(&self) -> bool {
+        self.first < CAPACITY
+            && self.count <= CAPACITY
+            && self.entries.iter().all(|entry| entry.length <= LENGTH)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Add a payload to the back of the queue. Returns `true` if the oldest payload
+    /// had to be removed to make space.
+    pub fn push_back(&mut self, payload: &[u8]) -> Result<bool, PayloadTooLarge> {
+        if payload.len() > LENGTH {
+            return Err(PayloadTooLarge {
+                length: payload.len(),
+            });
+        }
+
+        let is_full = self.count == CAPACITY;
+        if is_full {
+            self.pop_front();
+        }
+
+        let index = (self.first + self.count) % CAPACITY;
+        let entry = &mut self.entries[index];
+        entry.payload[..payload.len()].copy_from_slice(payload);
+        entry.length = payload.len();
+        self.count += 1;
+
+        Ok(is_full)
+    }
+
+    /// The oldest payload in the queue
+    pub fn front(&self) -> Option<&[u8]> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some(self.entries[self.first].as_bytes())
+    }
+
+    /// Remove the oldest payload from the queue
+    pub fn pop_front(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+
+        self.entries[self.first].length = 0;
+        self.first = (self.first + 1) % CAPACITY;
+        self.count -= 1;
+    }
+}
+
+impl<const CAPACITY: usize, const LENGTH: usize> Default for PayloadQueue<CAPACITY, LENGTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[path = "payload_queue_tests.rs"]
+mod payload_queue_tests;
@@ -0,0 +1,112 @@
+use super::*;
+
+type TestQueue = PayloadQueue<3, 8>;
+
+#[test]
+fn test_new_queue_is_empty() {
+    let queue = TestQueue::new();
+
+    assert!(queue.is_valid());
+    assert!(queue.is_empty());
+    assert_eq!(queue.len(), 0);
+    assert_eq!(queue.front(), None);
+}
+
+#[test]
+fn test_payloads_come_out_oldest_first() {
+    let mut queue = TestQueue::new();
+    assert_eq!(queue.push_back(b"first"), Ok(false));
+    assert_eq!(queue.push_back(b"second"), Ok(false));
+
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue.front(), Some(&b"first"[..]));
+    queue.pop_front();
+    assert_eq!(queue.front(), Some(&b"second"[..]));
+    queue.pop_front();
+    assert_eq!(queue.front(), None);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_full_queue_drops_the_oldest_payload() {
+    let mut queue = TestQueue::new();
+    assert_eq!(queue.push_back(b"1"), Ok(false));
+    assert_eq!(queue.push_back(b"2"), Ok(false));
+    assert_eq!(queue.push_back(b"3"), Ok(false));
+
+    assert_eq!(queue.push_back(b"4"), Ok(true));
+    assert_eq!(queue.len(), 3);
+
+    let mut payloads = Vec::new();
+    while let Some(payload) = queue.front() {
+        payloads.push(payload.to_vec());
+        queue.pop_front();
+    }
+    assert_eq!(payloads, vec![b"2".to_vec(), b"3".to_vec(), b"4".to_vec()]);
+}
+
+#[test]
+fn test_queue_wraps_around() {
+    let mut queue = TestQueue::new();
+    for round in 0..10u8 {
+        assert_eq!(queue.push_back(&[round]), Ok(false));
+        assert_eq!(queue.front(), Some(&[round][..]));
+        queue.pop_front();
+        assert!(queue.is_valid());
+    }
+
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_payload_of_the_entry_length_is_queued() {
+    let mut queue = TestQueue::new();
+
+    assert_eq!(queue.push_back(b"12345678"), Ok(false));
+    assert_eq!(queue.front(), Some(&b"12345678"[..]));
+}
+
+#[test]
+fn test_payload_larger_than_an_entry_is_rejected() {
+    let mut queue = TestQueue::new();
+
+    assert_eq!(
+        queue.push_back(b"123456789"),
+        Err(PayloadTooLarge { length: 9 })
+    );
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_shorter_payload_does_not_keep_the_end_of_an_earlier_one() {
+    let mut queue = PayloadQueue::<1, 8>::new();
+    assert_eq!(queue.push_back(b"longer"), Ok(false));
+    queue.pop_front();
+
+    assert_eq!(queue.push_back(b"ab"), Ok(false));
+    assert_eq!(queue.front(), Some(&b"ab"[..]));
+}
+
+#[test]
+fn test_pop_front_on_an_empty_queue_does_nothing() {
+    let mut queue = TestQueue::new();
+    queue.pop_front();
+
+    assert!(queue.is_valid());
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_corrupt_queue_is_not_valid() {
+    let mut queue = TestQueue::new();
+    queue.first = 3;
+    assert!(!queue.is_valid());
+
+    let mut queue = TestQueue::new();
+    queue.count = 4;
+    assert!(!queue.is_valid());
+
+    let mut queue = TestQueue::new();
+    queue.entries[1].length = 9;
+    assert!(!queue.is_valid());
+}
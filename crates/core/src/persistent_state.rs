@@ -0,0 +1,182 @@
+//! The state that the device keeps in flash and its record format
+//!
+//! Each write of the state is a fixed size record. The record starts with a magic so that erased
+//! flash, which reads as `0xFF`, never looks like a record, and ends with a CRC-32 so that a
+//! record that was only partly written is ignored.
+//!
+//! Flash sectors can only be erased a limited number of times, so the state only changes when it
+//! has to. The boot count is reserved in blocks, and the last reading is only replaced when it
+//! differs noticeably from the stored one.
+
+use crate::compression::crc32;
+use crate::provisioning::{DeviceToken, DEVICE_TOKEN_LENGTH};
+
+/// The size of a single record
+pub const RECORD_SIZE: usize = 64;
+
+/// The marker at the start of every record
+const RECORD_MAGIC: u32 = u32::from_le_bytes(*b"TSL1");
+
+/// The flag that is set if the record contains a reading
+const HAS_LAST_READING_FLAG: u32 = 0x1;
+
+/// The flag that is set if the record contains a device token
+const HAS_DEVICE_TOKEN_FLAG: u32 = 0x2;
+
+/// The number of boot counts that are reserved with each write. After a power loss the boot
+/// count continues after the reserved boot counts, so it never repeats one that was sent.
+pub const BOOT_COUNT_RESERVATION: u32 = 16;
+
+/// The change in the tank level, in meters, after which the last reading is replaced
+const LAST_READING_LEVEL_TOLERANCE_IN_METERS: f32 = 0.01;
+
+/// The change in the battery voltage, in volts, after which the last reading is replaced
+const LAST_READING_VOLTAGE_TOLERANCE_IN_VOLTS: f32 = 0.05;
+
+/// The last reading that the device took without a sensor fault
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LastReading {
+    /// The boot count at the time the reading was stored
+    pub boot_count: u32,
+
+    /// The height of the water above the pressure sensor
+    pub tank_level_in_meters: f32,
+
+    /// The battery voltage
+    pub battery_voltage: f32,
+}
+
+impl LastReading {
+    /// `true` if the tank level or the battery voltage changed by more than the tolerance
+    fn differs_from(&self, other: &LastReading) -> bool {
+        (self.tank_level_in_meters - other.tank_level_in_meters).abs()
+            > LAST_READING_LEVEL_TOLERANCE_IN_METERS
+            || (self.battery_voltage - other.battery_voltage).abs()
+                > LAST_READING_VOLTAGE_TOLERANCE_IN_VOLTS
+    }
+}
+
+/// The state that is kept in flash
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PersistentState {
+    /// The highest boot count that has been reserved
+    pub boot_count: u32,
+
+    /// The last reading that the device took without a sensor fault, if any
+    pub last_reading: Option<LastReading>,
+
+    /// The token the service issued when the device provisioned itself, if any
+    pub device_token: Option<DeviceToken>,
+}
+
+impl PersistentState {
+    /// Reserve the next block of boot counts if the given boot count is past the reserved ones.
+    /// Returns `true` if the state changed and has to be stored.
+    pub fn reserve_boot_count(&mut self, boot_count: u32) -> bool {
+        if boot_count <= self.boot_count {
+            return false;
+        }
+
+        self.boot_count = boot_count.saturating_add(BOOT_COUNT_RESERVATION - 1);
+        true
+    }
+
+    /// Replace the last reading if there is none yet or if the new one differs noticeably.
+    /// Returns `true` if the state changed and has to be stored.
+    pub fn record_reading(&mut self, reading: LastReading) -> bool {
+        if self
+            .last_reading
+            .is_some_and(|last_reading| !reading.differs_from(&last_reading))
+        {
+            return false;
+        }
+
+        self.last_reading = Some(reading);
+        true
+    }
+
+    /// Write the state to a record with the given sequence number. The layout, in little endian,
+    /// is the magic, the sequence number, the boot count, the flags, the last reading, the
+    /// device token and a CRC-32 of the preceding bytes.
+    pub fn to_record(self, sequence: u32) -> [u8; RECORD_SIZE] {
+        let (mut flags, reading) = match self.last_reading {
+            Some(reading) => (HAS_LAST_READING_FLAG, reading),
+            None => (
+                0,
+                LastReading {
+                    boot_count: 0,
+                    tank_level_in_meters: 0.0,
+                    battery_voltage: 0.0,
+                },
+            ),
+        };
+        let device_token = match self.device_token {
+            Some(device_token) => {
+                flags |= HAS_DEVICE_TOKEN_FLAG;
+                device_token
+            }
+            None => [0; DEVICE_TOKEN_LENGTH],
+        };
+
+        let mut record = [0u8; RECORD_SIZE];
+        record[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        record[4..8].copy_from_slice(&sequence.to_le_bytes());
+        record[8..12].copy_from_slice(&self.boot_count.to_le_bytes());
+        record[12..16].copy_from_slice(&flags.to_le_bytes());
+        record[16..20].copy_from_slice(&reading.boot_count.to_le_bytes());
+        record[20..24].copy_from_slice(&reading.tank_level_in_meters.to_le_bytes());
+        record[24..28].copy_from_slice(&reading.battery_voltage.to_le_bytes());
+        record[28..60].copy_from_slice(&device_token);
+        let checksum = crc32(&record[..RECORD_SIZE - 4]);
+        record[60..64].copy_from_slice(&checksum.to_le_bytes());
+        record
+    }
+
+    /// Read the state and the sequence number from a record. `None` if the slot is empty or the
+    /// record is corrupt, e.g. because the power failed while it was written.
+    pub fn from_record(record: &[u8; RECORD_SIZE]) -> Option<(u32, PersistentState)> {
+        let word = |index: usize| {
+            u32::from_le_bytes([
+                record[index],
+                record[index + 1],
+                record[index + 2],
+                record[index + 3],
+            ])
+        };
+
+        if word(0) != RECORD_MAGIC || word(60) != crc32(&record[..RECORD_SIZE - 4]) {
+            return None;
+        }
+
+        let last_reading = if word(12) & HAS_LAST_READING_FLAG != 0 {
+            Some(LastReading {
+                boot_count: word(16),
+                tank_level_in_meters: f32::from_bits(word(20)),
+                battery_voltage: f32::from_bits(word(24)),
+            })
+        } else {
+            None
+        };
+
+        let device_token = if word(12) & HAS_DEVICE_TOKEN_FLAG != 0 {
+            let mut device_token = [0u8; DEVICE_TOKEN_LENGTH];
+            device_token.copy_from_slice(&record[28..60]);
+            Some(device_token)
+        } else {
+            None
+        };
+
+        Some((
+            word(4),
+            PersistentState {
+                boot_count: word(8),
+                last_reading,
+                device_token,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+#[path = "persistent_state_tests.rs"]
+mod persistent_state_tests;
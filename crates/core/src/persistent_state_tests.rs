@@ -0,0 +1,135 @@
+use super::*;
+
+fn reading(boot_count: u32, tank_level_in_meters: f32, battery_voltage: f32) -> LastReading {
+    LastReading {
+        boot_count,
+        tank_level_in_meters,
+        battery_voltage,
+    }
+}
+
+#[test]
+fn test_state_with_a_reading_round_trips() {
+    let state = PersistentState {
+        boot_count: 1234,
+        last_reading: Some(reading(1230, 1.875, 12.61)),
+        device_token: None,
+    };
+
+    assert_eq!(
+        PersistentState::from_record(&state.to_record(42)),
+        Some((42, state))
+    );
+}
+
+#[test]
+fn test_state_without_a_reading_round_trips() {
+    let state = PersistentState {
+        boot_count: 7,
+        last_reading: None,
+        device_token: None,
+    };
+
+    assert_eq!(
+        PersistentState::from_record(&state.to_record(u32::MAX)),
+        Some((u32::MAX, state))
+    );
+}
+
+#[test]
+fn test_state_with_a_device_token_round_trips() {
+    let mut device_token = [0u8; DEVICE_TOKEN_LENGTH];
+    for (index, byte) in device_token.iter_mut().enumerate() {
+        *byte = index as u8;
+    }
+
+    let state = PersistentState {
+        boot_count: 16,
+        last_reading: None,
+        device_token: Some(device_token),
+    };
+
+    assert_eq!(
+        PersistentState::from_record(&state.to_record(3)),
+        Some((3, state))
+    );
+}
+
+#[test]
+fn test_erased_slot_is_not_a_record() {
+    assert_eq!(PersistentState::from_record(&[0xFF; RECORD_SIZE]), None);
+}
+
+#[test]
+fn test_corrupt_record_is_ignored() {
+    let state = PersistentState {
+        boot_count: 1234,
+        last_reading: Some(reading(1230, 1.875, 12.61)),
+        device_token: Some([0xA5; DEVICE_TOKEN_LENGTH]),
+    };
+
+    for index in 0..RECORD_SIZE {
+        let mut record = state.to_record(1);
+        record[index] ^= 0x10;
+        assert_eq!(PersistentState::from_record(&record), None, "byte {index}");
+    }
+}
+
+#[test]
+fn test_boot_count_is_reserved_in_blocks() {
+    let mut state = PersistentState::default();
+
+    assert!(state.reserve_boot_count(1));
+    assert_eq!(state.boot_count, BOOT_COUNT_RESERVATION);
+
+    for boot_count in 2..=BOOT_COUNT_RESERVATION {
+        assert!(!state.reserve_boot_count(boot_count));
+    }
+
+    assert!(state.reserve_boot_count(BOOT_COUNT_RESERVATION + 1));
+    assert_eq!(state.boot_count, 2 * BOOT_COUNT_RESERVATION);
+}
+
+#[test]
+fn test_boot_count_reservation_saturates() {
+    let mut state = PersistentState::default();
+
+    assert!(state.reserve_boot_count(u32::MAX - 1));
+    assert_eq!(state.boot_count, u32::MAX);
+    assert!(!state.reserve_boot_count(u32::MAX));
+}
+
+#[test]
+fn test_first_reading_is_recorded() {
+    let mut state = PersistentState::default();
+
+    assert!(state.record_reading(reading(1, 1.0, 12.5)));
+    assert_eq!(state.last_reading, Some(reading(1, 1.0, 12.5)));
+}
+
+#[test]
+fn test_similar_reading_is_not_recorded() {
+    let mut state = PersistentState::default();
+    state.record_reading(reading(1, 1.0, 12.5));
+
+    assert!(!state.record_reading(reading(2, 1.005, 12.52)));
+    assert_eq!(state.last_reading, Some(reading(1, 1.0, 12.5)));
+}
+
+#[test]
+fn test_changed_tank_level_is_recorded() {
+    let mut state = PersistentState::default();
+    state.record_reading(reading(1, 1.0, 12.5));
+
+    assert!(state.record_reading(reading(2, 0.95, 12.5)));
+    assert_eq!(state.last_reading, Some(reading(2, 0.95, 12.5)));
+}
+
+#[test]
+fn test_changed_battery_voltage_is_recorded() {
+    let mut state = PersistentState::default();
+    state.record_reading(reading(1, 1.0, 12.5));
+
+    assert!(state.record_reading(reading(2, 1.0, 12.3)));
+    assert_eq!(state.last_reading, Some(reading(2, 1.0, 12.3)));
+}
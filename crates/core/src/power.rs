@@ -0,0 +1,174 @@
+//! How hard the device works for the battery voltage
+//!
+//! When the battery runs low the device takes fewer samples and sleeps longer so that the
+//! battery lasts until it can be recharged. The charge of the battery is estimated from the
+//! voltage with the discharge curve of the battery chemistry.
+
+/// The shortest deep sleep when the battery is critically low
+pub const CRITICAL_BATTERY_MINIMUM_SLEEP_DURATION_IN_SECONDS: u32 = 900;
+
+/// The shortest deep sleep when the battery is below the minimum operating voltage
+pub const LOW_VOLTAGE_SHUTDOWN_MINIMUM_SLEEP_DURATION_IN_SECONDS: u32 = 3600;
+
+/// The discharge curve of a 12V lead-acid battery at rest, as pairs of the battery voltage and
+/// the charge in percent, ordered by voltage
+const LEAD_ACID_DISCHARGE_CURVE: [(f32, f32); 11] = [
+    (10.50, 0.0),
+    (11.51, 10.0),
+    (11.66, 20.0),
+    (11.81, 30.0),
+    (11.96, 40.0),
+    (12.10, 50.0),
+    (12.24, 60.0),
+    (12.37, 70.0),
+    (12.50, 80.0),
+    (12.62, 90.0),
+    (12.73, 100.0),
+];
+
+/// The discharge curve of a 3 cell Li-ion battery, from the 3.0V per cell cutoff to the 4.2V per
+/// cell full charge
+const LI_ION_DISCHARGE_CURVE: [(f32, f32); 10] = [
+    (9.0, 0.0),
+    (9.9, 5.0),
+    (10.5, 10.0),
+    (10.8, 20.0),
+    (11.1, 40.0),
+    (11.4, 60.0),
+    (11.7, 75.0),
+    (12.0, 85.0),
+    (12.3, 95.0),
+    (12.6, 100.0),
+];
+
+/// The discharge curve of a 4 cell LiFePO4 battery at rest, from the 2.5V per cell cutoff to
+/// the 3.4V per cell full charge. The curve is very flat between 20% and 90%.
+const LIFEPO4_DISCHARGE_CURVE: [(f32, f32); 11] = [
+    (10.0, 0.0),
+    (12.0, 10.0),
+    (12.8, 15.0),
+    (13.0, 20.0),
+    (13.04, 30.0),
+    (13.08, 40.0),
+    (13.12, 50.0),
+    (13.2, 70.0),
+    (13.28, 90.0),
+    (13.4, 99.0),
+    (13.6, 100.0),
+];
+
+/// How hard the device should work for the current battery voltage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerProfile {
+    /// The number of samples to take for each measurement
+    pub sample_count: usize,
+
+    /// The shortest time the device should sleep for. Zero if the device can sleep for the
+    /// normal duration.
+    pub minimum_sleep_duration_in_seconds: u32,
+}
+
+/// The battery voltages below which the device works less hard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryThresholds {
+    /// Below this voltage the device takes half the number of samples
+    pub low_voltage: f32,
+
+    /// Below this voltage the device takes a single sample and sleeps for at least
+    /// `CRITICAL_BATTERY_MINIMUM_SLEEP_DURATION_IN_SECONDS`
+    pub critical_voltage: f32,
+
+    /// Below this voltage the pressure sensor isn't powered and the device sleeps for at least
+    /// `LOW_VOLTAGE_SHUTDOWN_MINIMUM_SLEEP_DURATION_IN_SECONDS`
+    pub min_operating_voltage: f32,
+}
+
+impl BatteryThresholds {
+    /// Determine if the battery is too low to power the pressure sensor. Powering the sensor
+    /// from an almost empty battery can cause a brownout in the middle of the reading.
+    pub fn should_skip_pressure_read(&self, battery_v: f32) -> bool {
+        battery_v < self.min_operating_voltage
+    }
+
+    /// Determine the power profile for the given battery voltage. `number_of_samples` is the
+    /// number of samples that the device takes when the battery isn't low.
+    pub fn power_profile(&self, battery_v: f32, number_of_samples: usize) -> PowerProfile {
+        if self.should_skip_pressure_read(battery_v) {
+            PowerProfile {
+                sample_count: 1,
+                minimum_sleep_duration_in_seconds:
+                    LOW_VOLTAGE_SHUTDOWN_MINIMUM_SLEEP_DURATION_IN_SECONDS,
+            }
+        } else if battery_v < self.critical_voltage {
+            PowerProfile {
+                sample_count: 1,
+                minimum_sleep_duration_in_seconds:
+                    CRITICAL_BATTERY_MINIMUM_SLEEP_DURATION_IN_SECONDS,
+            }
+        } else if battery_v < self.low_voltage {
+            PowerProfile {
+                sample_count: (number_of_samples / 2).max(1),
+                minimum_sleep_duration_in_seconds: 0,
+            }
+        } else {
+            PowerProfile {
+                sample_count: number_of_samples,
+                minimum_sleep_duration_in_seconds: 0,
+            }
+        }
+    }
+}
+
+/// The chemistry of the battery, which determines how the voltage relates to the charge
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatteryChemistry {
+    /// A 12V lead-acid battery
+    LeadAcid,
+
+    /// A 3 cell lithium-ion or lithium polymer battery
+    LiIon,
+
+    /// A 4 cell lithium iron phosphate battery
+    LiFePo4,
+}
+
+impl BatteryChemistry {
+    /// The battery voltage and the matching charge in percent, ordered by voltage
+    fn discharge_curve(&self) -> &'static [(f32, f32)] {
+        match self {
+            Self::LeadAcid => &LEAD_ACID_DISCHARGE_CURVE,
+            Self::LiIon => &LI_ION_DISCHARGE_CURVE,
+            Self::LiFePo4 => &LIFEPO4_DISCHARGE_CURVE,
+        }
+    }
+}
+
+/// Estimate the charge of the battery, in percent, from the voltage. Interpolates linearly
+/// between the points of the discharge curve and clamps to 0% and 100%.
+pub fn battery_percent_for_chemistry(chemistry: BatteryChemistry, voltage_v: f32) -> f32 {
+    let curve = chemistry.discharge_curve();
+    let (empty_voltage, empty_percent) = curve[0];
+    let (full_voltage, full_percent) = curve[curve.len() - 1];
+    if voltage_v.is_nan() || voltage_v <= empty_voltage {
+        return empty_percent;
+    }
+
+    if voltage_v >= full_voltage {
+        return full_percent;
+    }
+
+    for pair in curve.windows(2) {
+        let (lower_voltage, lower_percent) = pair[0];
+        let (upper_voltage, upper_percent) = pair[1];
+        if voltage_v <= upper_voltage {
+            let fraction = (voltage_v - lower_voltage) / (upper_voltage - lower_voltage);
+            return lower_percent + fraction * (upper_percent - lower_percent);
+        }
+    }
+
+    full_percent
+}
+
+#[cfg(test)]
+#[path = "power_tests.rs"]
+mod power_tests;
@@ -0,0 +1,137 @@
+use super::*;
+
+const THRESHOLDS: BatteryThresholds = BatteryThresholds {
+    low_voltage: 12.2,
+    critical_voltage: 11.9,
+    min_operating_voltage: 11.5,
+};
+
+const NUMBER_OF_SAMPLES: usize = 5;
+
+#[test]
+fn test_pressure_read_is_skipped_below_the_minimum_operating_voltage() {
+    assert!(THRESHOLDS.should_skip_pressure_read(11.49));
+    assert!(THRESHOLDS.should_skip_pressure_read(0.0));
+}
+
+#[test]
+fn test_pressure_read_is_not_skipped_at_the_minimum_operating_voltage() {
+    assert!(!THRESHOLDS.should_skip_pressure_read(11.5));
+    assert!(!THRESHOLDS.should_skip_pressure_read(12.7));
+}
+
+#[test]
+fn test_full_battery_takes_all_samples() {
+    assert_eq!(
+        THRESHOLDS.power_profile(12.7, NUMBER_OF_SAMPLES),
+        PowerProfile {
+            sample_count: NUMBER_OF_SAMPLES,
+            minimum_sleep_duration_in_seconds: 0,
+        }
+    );
+}
+
+#[test]
+fn test_battery_at_the_low_threshold_is_not_low() {
+    assert_eq!(
+        THRESHOLDS
+            .power_profile(12.2, NUMBER_OF_SAMPLES)
+            .sample_count,
+        NUMBER_OF_SAMPLES
+    );
+}
+
+#[test]
+fn test_battery_below_the_low_threshold_takes_half_the_samples() {
+    assert_eq!(
+        THRESHOLDS.power_profile(12.19, NUMBER_OF_SAMPLES),
+        PowerProfile {
+            sample_count: 2,
+            minimum_sleep_duration_in_seconds: 0,
+        }
+    );
+}
+
+#[test]
+fn test_low_battery_takes_at_least_one_sample() {
+    assert_eq!(THRESHOLDS.power_profile(12.0, 1).sample_count, 1);
+}
+
+#[test]
+fn test_battery_at_the_critical_threshold_is_only_low() {
+    assert_eq!(
+        THRESHOLDS.power_profile(11.9, NUMBER_OF_SAMPLES),
+        PowerProfile {
+            sample_count: 2,
+            minimum_sleep_duration_in_seconds: 0,
+        }
+    );
+}
+
+#[test]
+fn test_battery_below_the_critical_threshold_takes_one_sample_and_sleeps_longer() {
+    assert_eq!(
+        THRESHOLDS.power_profile(11.89, NUMBER_OF_SAMPLES),
+        PowerProfile {
+            sample_count: 1,
+            minimum_sleep_duration_in_seconds: CRITICAL_BATTERY_MINIMUM_SLEEP_DURATION_IN_SECONDS,
+        }
+    );
+}
+
+#[test]
+fn test_battery_below_the_minimum_operating_voltage_sleeps_longest() {
+    assert_eq!(
+        THRESHOLDS.power_profile(11.49, NUMBER_OF_SAMPLES),
+        PowerProfile {
+            sample_count: 1,
+            minimum_sleep_duration_in_seconds:
+                LOW_VOLTAGE_SHUTDOWN_MINIMUM_SLEEP_DURATION_IN_SECONDS,
+        }
+    );
+}
+
+fn assert_percent(chemistry: BatteryChemistry, voltage: f32, expected: f32) {
+    let percent = battery_percent_for_chemistry(chemistry, voltage);
+    assert!(
+        (percent - expected).abs() < 0.01,
+        "{chemistry:?} at {voltage}V is {percent}% instead of {expected}%"
+    );
+}
+
+#[test]
+fn test_full_battery_is_at_100_percent() {
+    assert_percent(BatteryChemistry::LeadAcid, 12.73, 100.0);
+    assert_percent(BatteryChemistry::LiIon, 12.6, 100.0);
+    assert_percent(BatteryChemistry::LiFePo4, 13.6, 100.0);
+}
+
+#[test]
+fn test_battery_at_the_nominal_voltage() {
+    // 12.0V lies between the 40% and 50% points of the lead-acid curve
+    assert_percent(BatteryChemistry::LeadAcid, 12.0, 40.0 + 10.0 * 0.04 / 0.14);
+    // 3.7V per cell
+    assert_percent(BatteryChemistry::LiIon, 11.1, 40.0);
+    // 3.2V per cell
+    assert_percent(BatteryChemistry::LiFePo4, 12.8, 15.0);
+}
+
+#[test]
+fn test_battery_at_the_cutoff_voltage_is_empty() {
+    assert_percent(BatteryChemistry::LeadAcid, 10.5, 0.0);
+    assert_percent(BatteryChemistry::LiIon, 9.0, 0.0);
+    assert_percent(BatteryChemistry::LiFePo4, 10.0, 0.0);
+}
+
+#[test]
+fn test_battery_percent_is_clamped() {
+    for chemistry in [
+        BatteryChemistry::LeadAcid,
+        BatteryChemistry::LiIon,
+        BatteryChemistry::LiFePo4,
+    ] {
+        assert_percent(chemistry, 5.0, 0.0);
+        assert_percent(chemistry, 15.0, 100.0);
+        assert_percent(chemistry, f32::NAN, 0.0);
+    }
+}
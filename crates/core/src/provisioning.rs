@@ -0,0 +1,73 @@
+//! The device token that the service hands out when a device provisions itself
+//!
+//! The service sends the token as hex. The device keeps the raw bytes in flash and sends the
+//! token as hex again with every request.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+/// The number of bytes in a device token
+pub const DEVICE_TOKEN_LENGTH: usize = 32;
+
+/// The length of a device token written as hex
+pub const DEVICE_TOKEN_HEX_LENGTH: usize = 2 * DEVICE_TOKEN_LENGTH;
+
+/// The length of a hardware ID written as a MAC address, e.g. `AA:BB:CC:DD:EE:FF`
+pub const HARDWARE_ID_LENGTH: usize = 17;
+
+/// The token that the service issued to the device
+pub type DeviceToken = [u8; DEVICE_TOKEN_LENGTH];
+
+/// Parse the hex encoded device token from the service. `None` if the token has the wrong length
+/// or is not hex.
+pub fn parse_device_token(hex: &str) -> Option<DeviceToken> {
+    let hex = hex.as_bytes();
+    if hex.len() != DEVICE_TOKEN_HEX_LENGTH {
+        return None;
+    }
+
+    let mut token = [0u8; DEVICE_TOKEN_LENGTH];
+    for (byte, pair) in token.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+
+    Some(token)
+}
+
+/// Write the device token as lower case hex
+pub fn format_device_token(token: &DeviceToken) -> String<DEVICE_TOKEN_HEX_LENGTH> {
+    let mut hex = String::new();
+    for byte in token {
+        // The string is exactly long enough for the token
+        let _ = write!(hex, "{byte:02x}");
+    }
+
+    hex
+}
+
+/// Write the MAC address of the device as the hardware ID that the device provisions itself with
+pub fn format_hardware_id(mac_address: &[u8; 6]) -> String<HARDWARE_ID_LENGTH> {
+    let mut hardware_id = String::new();
+    for (index, byte) in mac_address.iter().enumerate() {
+        let separator = if index == 0 { "" } else { ":" };
+        // The string is exactly long enough for the MAC address
+        let _ = write!(hardware_id, "{separator}{byte:02X}");
+    }
+
+    hardware_id
+}
+
+/// The value of a single hex digit
+fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[path = "provisioning_tests.rs"]
+mod provisioning_tests;
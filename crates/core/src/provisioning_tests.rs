@@ -0,0 +1,43 @@
+use super::*;
+
+const TOKEN_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+#[test]
+fn test_device_token_is_parsed() {
+    let token = parse_device_token(TOKEN_HEX).unwrap();
+
+    for (index, byte) in token.iter().enumerate() {
+        assert_eq!(*byte as usize, index);
+    }
+}
+
+#[test]
+fn test_upper_case_device_token_is_parsed() {
+    assert_eq!(
+        parse_device_token(&TOKEN_HEX.to_uppercase()),
+        parse_device_token(TOKEN_HEX)
+    );
+}
+
+#[test]
+fn test_invalid_device_token_is_rejected() {
+    assert_eq!(parse_device_token(""), None);
+    assert_eq!(parse_device_token(&TOKEN_HEX[..62]), None);
+    assert_eq!(parse_device_token(&format!("{TOKEN_HEX}00")), None);
+    assert_eq!(parse_device_token(&TOKEN_HEX.replace('a', "g")), None);
+    assert_eq!(parse_device_token(&TOKEN_HEX.replace("0a", " a")), None);
+}
+
+#[test]
+fn test_device_token_is_formatted_as_hex() {
+    let token = parse_device_token(&TOKEN_HEX.to_uppercase()).unwrap();
+
+    assert_eq!(format_device_token(&token).as_str(), TOKEN_HEX);
+}
+
+#[test]
+fn test_hardware_id_is_the_mac_address() {
+    let hardware_id = format_hardware_id(&[0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0xff]);
+
+    assert_eq!(hardware_id.as_str(), "00:1A:2B:3C:4D:FF");
+}
@@ -0,0 +1,37 @@
+//! Limit on the time the radio is used for uploads during a wake cycle
+
+/// The time that is left for uploads in this wake cycle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadioBudget {
+    remaining_in_micro_seconds: u64,
+}
+
+impl RadioBudget {
+    /// Create a budget with the given time for uploads
+    pub fn new(budget_in_micro_seconds: u64) -> Self {
+        Self {
+            remaining_in_micro_seconds: budget_in_micro_seconds,
+        }
+    }
+
+    /// `true` if there is no time left for uploads
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining_in_micro_seconds == 0
+    }
+
+    /// The time that is left for uploads, in seconds
+    pub fn remaining_in_seconds(&self) -> f32 {
+        self.remaining_in_micro_seconds as f32 * 1e-6
+    }
+
+    /// Take the time spent on an upload from the budget. The budget doesn't go below zero.
+    pub fn spend(&mut self, duration_in_micro_seconds: u64) {
+        self.remaining_in_micro_seconds = self
+            .remaining_in_micro_seconds
+            .saturating_sub(duration_in_micro_seconds);
+    }
+}
+
+#[cfg(test)]
+#[path = "radio_budget_tests.rs"]
+mod radio_budget_tests;
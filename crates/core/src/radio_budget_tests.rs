@@ -0,0 +1,40 @@
+use super::*;
+
+#[test]
+fn test_new_budget_is_not_exhausted() {
+    let budget = RadioBudget::new(60_000_000);
+
+    assert!(!budget.is_exhausted());
+    assert!((budget.remaining_in_seconds() - 60.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_uploads_are_taken_from_the_budget() {
+    let mut budget = RadioBudget::new(60_000_000);
+
+    budget.spend(15_000_000);
+    budget.spend(20_500_000);
+
+    assert!(!budget.is_exhausted());
+    assert!((budget.remaining_in_seconds() - 24.5).abs() < 1e-3);
+}
+
+#[test]
+fn test_budget_is_exhausted_once_all_time_is_spent() {
+    let mut budget = RadioBudget::new(60_000_000);
+
+    budget.spend(60_000_000);
+
+    assert!(budget.is_exhausted());
+}
+
+#[test]
+fn test_overspent_budget_does_not_go_below_zero() {
+    let mut budget = RadioBudget::new(60_000_000);
+
+    budget.spend(50_000_000);
+    budget.spend(50_000_000);
+
+    assert!(budget.is_exhausted());
+    assert_eq!(budget.remaining_in_seconds(), 0.0);
+}
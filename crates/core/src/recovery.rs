@@ -0,0 +1,119 @@
+//! The policy for errors that end the wake cycle early
+//!
+//! The policy decides whether the device goes back to sleep and tries again on the next wake up,
+//! or resets so that it starts from a clean state. The device always resets if WiFi can't be
+//! disconnected, whatever the policy, because it would never wake up from deep sleep with WiFi
+//! still on.
+
+use log::warn;
+
+/// The reason a wake cycle ended early
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatalReason {
+    /// The logger could not be set up
+    LoggerSetupFailed,
+
+    /// No WiFi networks are configured
+    NoWifiNetworks,
+
+    /// None of the WiFi networks could be connected to
+    WifiConnectFailed,
+
+    /// The task that monitors the WiFi connection could not be started
+    WifiMonitorSpawnFailed,
+
+    /// The WiFi connection was lost
+    NetworkDisconnected,
+
+    /// The timing data could not be sent
+    TimingDataFailed,
+
+    /// The sensors could not be read and the sensor failure policy is to sleep
+    SensorReadFailed,
+
+    /// WiFi could not be disconnected before going to sleep
+    WifiDisconnectFailed,
+}
+
+impl FatalReason {
+    /// The name of the reason for the logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FatalReason::LoggerSetupFailed => "logger_setup_failed",
+            FatalReason::NoWifiNetworks => "no_wifi_networks",
+            FatalReason::WifiConnectFailed => "wifi_connect_failed",
+            FatalReason::WifiMonitorSpawnFailed => "wifi_monitor_spawn_failed",
+            FatalReason::NetworkDisconnected => "network_disconnected",
+            FatalReason::TimingDataFailed => "timing_data_failed",
+            FatalReason::SensorReadFailed => "sensor_read_failed",
+            FatalReason::WifiDisconnectFailed => "wifi_disconnect_failed",
+        }
+    }
+
+    /// `true` if the device can't go to sleep after this error
+    fn requires_reset(&self) -> bool {
+        matches!(self, FatalReason::WifiDisconnectFailed)
+    }
+}
+
+/// What the device does after a fatal error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatalErrorPolicy {
+    /// Go back to sleep and try again on the next wake up
+    Sleep,
+
+    /// Reset the device
+    Reset,
+
+    /// Go back to sleep, unless this many wake cycles in a row ended with a fatal error, in
+    /// which case the device resets
+    ResetAfterFailures(u32),
+}
+
+/// Parse the fatal error policy. Unknown policies are logged and fall back to `Sleep`.
+pub fn parse_fatal_error_policy(policy: Option<&str>, reset_threshold: u32) -> FatalErrorPolicy {
+    match policy.map(|policy| policy.trim()) {
+        Some("sleep") | Some("") | None => FatalErrorPolicy::Sleep,
+        Some("reset") => FatalErrorPolicy::Reset,
+        Some("reset_after_failures") => FatalErrorPolicy::ResetAfterFailures(reset_threshold),
+        Some(other) => {
+            warn!("{other} is not a known fatal error policy. Using sleep.");
+            FatalErrorPolicy::Sleep
+        }
+    }
+}
+
+/// The way the device recovers from a fatal error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecoveryAction {
+    /// Go to sleep until the next wake up
+    Sleep,
+
+    /// Reset the device
+    Reset,
+}
+
+/// Decide how to recover from a fatal error. `failure_count` is the number of wake cycles in a
+/// row that ended with a fatal error, including this one.
+pub fn recovery_action(
+    policy: FatalErrorPolicy,
+    reason: FatalReason,
+    failure_count: u32,
+) -> RecoveryAction {
+    if reason.requires_reset() {
+        return RecoveryAction::Reset;
+    }
+
+    match policy {
+        FatalErrorPolicy::Sleep => RecoveryAction::Sleep,
+        FatalErrorPolicy::Reset => RecoveryAction::Reset,
+        FatalErrorPolicy::ResetAfterFailures(threshold) if failure_count >= threshold => {
+            RecoveryAction::Reset
+        }
+        FatalErrorPolicy::ResetAfterFailures(_) => RecoveryAction::Sleep,
+    }
+}
+
+#[cfg(test)]
+#[path = "recovery_tests.rs"]
+mod recovery_tests;
@@ -0,0 +1,113 @@
+use super::*;
+
+#[test]
+fn test_missing_or_empty_policy_is_sleep() {
+    assert_eq!(parse_fatal_error_policy(None, 3), FatalErrorPolicy::Sleep);
+    assert_eq!(
+        parse_fatal_error_policy(Some(""), 3),
+        FatalErrorPolicy::Sleep
+    );
+    assert_eq!(
+        parse_fatal_error_policy(Some("  "), 3),
+        FatalErrorPolicy::Sleep
+    );
+}
+
+#[test]
+fn test_known_policies_are_parsed() {
+    assert_eq!(
+        parse_fatal_error_policy(Some("sleep"), 3),
+        FatalErrorPolicy::Sleep
+    );
+    assert_eq!(
+        parse_fatal_error_policy(Some(" reset "), 3),
+        FatalErrorPolicy::Reset
+    );
+    assert_eq!(
+        parse_fatal_error_policy(Some("reset_after_failures"), 5),
+        FatalErrorPolicy::ResetAfterFailures(5)
+    );
+}
+
+#[test]
+fn test_unknown_policy_is_sleep() {
+    assert_eq!(
+        parse_fatal_error_policy(Some("Reset"), 3),
+        FatalErrorPolicy::Sleep
+    );
+    assert_eq!(
+        parse_fatal_error_policy(Some("reboot"), 3),
+        FatalErrorPolicy::Sleep
+    );
+}
+
+#[test]
+fn test_sleep_policy_sleeps() {
+    assert_eq!(
+        recovery_action(FatalErrorPolicy::Sleep, FatalReason::WifiConnectFailed, 100),
+        RecoveryAction::Sleep
+    );
+}
+
+#[test]
+fn test_reset_policy_resets() {
+    assert_eq!(
+        recovery_action(FatalErrorPolicy::Reset, FatalReason::WifiConnectFailed, 1),
+        RecoveryAction::Reset
+    );
+}
+
+#[test]
+fn test_reset_after_failures_resets_at_the_threshold() {
+    let policy = FatalErrorPolicy::ResetAfterFailures(3);
+
+    assert_eq!(
+        recovery_action(policy, FatalReason::TimingDataFailed, 1),
+        RecoveryAction::Sleep
+    );
+    assert_eq!(
+        recovery_action(policy, FatalReason::TimingDataFailed, 2),
+        RecoveryAction::Sleep
+    );
+    assert_eq!(
+        recovery_action(policy, FatalReason::TimingDataFailed, 3),
+        RecoveryAction::Reset
+    );
+    assert_eq!(
+        recovery_action(policy, FatalReason::TimingDataFailed, 4),
+        RecoveryAction::Reset
+    );
+}
+
+#[test]
+fn test_wifi_disconnect_failure_always_resets() {
+    for policy in [
+        FatalErrorPolicy::Sleep,
+        FatalErrorPolicy::Reset,
+        FatalErrorPolicy::ResetAfterFailures(3),
+    ] {
+        assert_eq!(
+            recovery_action(policy, FatalReason::WifiDisconnectFailed, 1),
+            RecoveryAction::Reset
+        );
+    }
+}
+
+#[test]
+fn test_reason_names_are_unique() {
+    let reasons = [
+        FatalReason::LoggerSetupFailed,
+        FatalReason::NoWifiNetworks,
+        FatalReason::WifiConnectFailed,
+        FatalReason::WifiMonitorSpawnFailed,
+        FatalReason::NetworkDisconnected,
+        FatalReason::TimingDataFailed,
+        FatalReason::SensorReadFailed,
+        FatalReason::WifiDisconnectFailed,
+    ];
+
+    let mut names: Vec<&str> = reasons.iter().map(FatalReason::as_str).collect();
+    names.sort_unstable();
+    names.dedup();
+    assert_eq!(names.len(), reasons.len());
+}
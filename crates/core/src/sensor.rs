@@ -0,0 +1,428 @@
+//! The sensor data and the calculations on the sensor readings
+//!
+//! Each measurement takes several samples of the sensors. The samples are averaged per channel,
+//! ignoring outliers, into the value that is sent. Before the samples are taken the device waits
+//! for the supply voltage of the pressure sensor to stabilize.
+
+use core::cmp::Ordering;
+
+use heapless::{Deque, Vec};
+
+use libm::logf;
+
+use log::debug;
+use log::error;
+use log::warn;
+
+use uom::si::electric_potential::volt;
+use uom::si::f32::ElectricPotential as Voltage;
+use uom::si::f32::Length;
+use uom::si::f32::Ratio;
+use uom::si::length::meter;
+use uom::si::ratio::percent;
+
+/// The maximum number of samples that each measurement can take. Limits the amount of memory
+/// that is used to store the samples.
+pub const MAX_NUMBER_OF_SAMPLES: usize = 64;
+
+/// Samples that are more than this many median absolute deviations away from the median
+/// are considered outliers
+const OUTLIER_REJECTION_MAD_FACTOR: f32 = 3.0;
+
+/// The minimum number of samples that should remain after rejecting outliers. If fewer
+/// samples remain then all samples are averaged.
+const MINIMUM_SAMPLES_AFTER_OUTLIER_REJECTION: usize = 3;
+
+/// The percentage of the samples in which the pressure sensor has to be connected. If fewer
+/// samples remain after dropping the disconnected ones, the pressure sensor is reported as
+/// faulty.
+const MINIMUM_CONNECTED_SAMPLES_IN_PERCENT: usize = 50;
+
+/// The number of most recent pressure sensor voltage readings that are checked for stability
+pub const PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW: usize = 10;
+
+/// The pressure sensor supply voltage if nothing is configured
+pub const DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS: f32 = 24.0;
+
+/// The lowest pressure sensor supply voltage that can be configured
+pub const MIN_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS: f32 = 5.0;
+
+/// The highest pressure sensor supply voltage that can be configured
+pub const MAX_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS: f32 = 36.0;
+
+/// The lowest LDR voltage that is converted to a brightness. Avoids the logarithm of zero.
+const MIN_LDR_VOLTAGE_IN_VOLTS: f32 = 0.001;
+
+/// Errors that can occur in the calculations on the sensor readings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// None of the samples could be read, so there is nothing to average
+    NoValidSamples,
+
+    /// A resistor of the voltage divider is not positive, so the input voltage can't be
+    /// calculated
+    InvalidVoltageDivider,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Ads1115Data {
+    pub enclosure_relative_brightness: Ratio,
+
+    pub battery_voltage: Voltage,
+
+    pub pressure_sensor_voltage: Voltage,
+
+    pub height_above_sensor: Length,
+
+    /// Set when the current in the pressure sensor loop is below the live zero, which means the
+    /// sensor is disconnected or the loop is broken. The height is not valid in that case.
+    pub pressure_sensor_fault: bool,
+
+    /// The voltages measured on the ADS1115 channels A0 to A3, before any voltage divider
+    /// calculations. Used to calibrate the voltage dividers.
+    pub raw_channel_voltages: [Voltage; 4],
+}
+
+impl From<(Ratio, Voltage, Voltage, Length)> for Ads1115Data {
+    fn from(
+        (
+            enclosure_relative_brightness,
+            battery_voltage,
+            pressure_sensor_voltage,
+            height_above_sensor,
+        ): (Ratio, Voltage, Voltage, Length),
+    ) -> Self {
+        Self {
+            enclosure_relative_brightness,
+            battery_voltage,
+            pressure_sensor_voltage,
+            height_above_sensor,
+            pressure_sensor_fault: false,
+            raw_channel_voltages: Default::default(),
+        }
+    }
+}
+
+/// Average the ADS1115 samples per channel, discarding the samples that are outliers
+///
+/// The samples in which the pressure sensor was disconnected are dropped, e.g. because of a
+/// loose contact. The pressure sensor is only reported as faulty if fewer than
+/// `MINIMUM_CONNECTED_SAMPLES_IN_PERCENT` of the samples remain, in which case the height is
+/// reported as 0m. At most `MAX_NUMBER_OF_SAMPLES` samples are used.
+///
+/// Returns `NoValidSamples` if there are no samples, because there is nothing to average.
+pub fn average_ads1115_samples(samples: &[Ads1115Data]) -> Result<Ads1115Data, Error> {
+    let samples = &samples[..samples.len().min(MAX_NUMBER_OF_SAMPLES)];
+    if samples.is_empty() {
+        return Err(Error::NoValidSamples);
+    }
+
+    let number_of_connected_samples = samples.iter().filter(|s| !s.pressure_sensor_fault).count();
+    let pressure_sensor_fault =
+        number_of_connected_samples * 100 < samples.len() * MINIMUM_CONNECTED_SAMPLES_IN_PERCENT;
+    if pressure_sensor_fault {
+        warn!(
+            "The pressure sensor was disconnected in {} of {} samples.",
+            samples.len() - number_of_connected_samples,
+            samples.len()
+        );
+    } else if number_of_connected_samples < samples.len() {
+        debug!(
+            "Dropping {} samples in which the pressure sensor was disconnected.",
+            samples.len() - number_of_connected_samples
+        );
+    }
+
+    // With a faulty sensor there may be no connected samples at all, so the other channels are
+    // averaged over all samples
+    let averaged_samples: Vec<&Ads1115Data, MAX_NUMBER_OF_SAMPLES> = samples
+        .iter()
+        .filter(|s| pressure_sensor_fault || !s.pressure_sensor_fault)
+        .collect();
+
+    let final_brightness =
+        Ratio::new::<percent>(average_without_outliers(&averaged_samples, |s| {
+            s.enclosure_relative_brightness.get::<percent>()
+        }));
+    let final_battery_voltage =
+        Voltage::new::<volt>(average_without_outliers(&averaged_samples, |s| {
+            s.battery_voltage.get::<volt>()
+        }));
+    let final_sensor_voltage =
+        Voltage::new::<volt>(average_without_outliers(&averaged_samples, |s| {
+            s.pressure_sensor_voltage.get::<volt>()
+        }));
+    let final_height = if pressure_sensor_fault {
+        Length::new::<meter>(0.0)
+    } else {
+        Length::new::<meter>(average_without_outliers(&averaged_samples, |s| {
+            s.height_above_sensor.get::<meter>()
+        }))
+    };
+
+    let mut final_raw_channel_voltages: [Voltage; 4] = Default::default();
+    for (channel, voltage) in final_raw_channel_voltages.iter_mut().enumerate() {
+        *voltage = Voltage::new::<volt>(average_without_outliers(&averaged_samples, |s| {
+            s.raw_channel_voltages[channel].get::<volt>()
+        }));
+    }
+
+    Ok(Ads1115Data {
+        pressure_sensor_fault,
+        raw_channel_voltages: final_raw_channel_voltages,
+        ..Ads1115Data::from((
+            final_brightness,
+            final_battery_voltage,
+            final_sensor_voltage,
+            final_height,
+        ))
+    })
+}
+
+/// Average the values, ignoring the values that are more than `OUTLIER_REJECTION_MAD_FACTOR`
+/// median absolute deviations away from the median.
+///
+/// If too few values remain after rejecting the outliers the plain average of all values
+/// is returned. At most `MAX_NUMBER_OF_SAMPLES` values are used.
+pub fn average_without_outliers<T>(samples: &[T], value_of: impl Fn(&T) -> f32) -> f32 {
+    let values: Vec<f32, MAX_NUMBER_OF_SAMPLES> = samples
+        .iter()
+        .take(MAX_NUMBER_OF_SAMPLES)
+        .map(value_of)
+        .collect();
+
+    let number_of_values = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / number_of_values;
+    if values.len() < MINIMUM_SAMPLES_AFTER_OUTLIER_REJECTION {
+        return mean;
+    }
+
+    let mut sorted_values = values.clone();
+    let median = median_of(&mut sorted_values);
+
+    let mut deviations: Vec<f32, MAX_NUMBER_OF_SAMPLES> =
+        values.iter().map(|v| (v - median).abs()).collect();
+    let median_absolute_deviation = median_of(&mut deviations);
+    let threshold = OUTLIER_REJECTION_MAD_FACTOR * median_absolute_deviation;
+
+    let mut sum_of_values: f32 = 0.0;
+    let mut number_of_accepted_values: usize = 0;
+    for value in values.iter() {
+        if (value - median).abs() <= threshold {
+            sum_of_values += value;
+            number_of_accepted_values += 1;
+        } else {
+            debug!("Rejecting outlier {value:.3} (median {median:.3}, MAD {median_absolute_deviation:.3})");
+        }
+    }
+
+    if number_of_accepted_values < MINIMUM_SAMPLES_AFTER_OUTLIER_REJECTION {
+        warn!("Too few samples left after outlier rejection. Using all samples.");
+        return mean;
+    }
+
+    sum_of_values / number_of_accepted_values as f32
+}
+
+/// Calculate the median of the values. Sorts the values in place.
+fn median_of(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// The state of the pressure sensor voltage while waiting for it to stabilize
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoltageStability {
+    /// The most recent readings are stable
+    Stable,
+
+    /// The most recent readings are not stable yet
+    Unstable,
+
+    /// The readings did not stabilize before the timeout
+    TimedOut,
+}
+
+/// Tracks the most recent pressure sensor voltage readings to decide when the voltage is stable
+pub struct VoltageStabilization {
+    /// The most recent readings, oldest first
+    readings: Deque<f32, PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW>,
+
+    /// The voltage that the readings should average to
+    expected: f32,
+
+    /// The largest standard deviation of the readings at which they are stable
+    epsilon: f32,
+
+    /// The largest difference between the average of the readings and the expected voltage
+    tolerance: f32,
+
+    /// The time after which the voltage is considered not to stabilize
+    timeout_in_milliseconds: u64,
+}
+
+impl VoltageStabilization {
+    pub fn new(expected: f32, epsilon: f32, tolerance: f32, timeout_in_milliseconds: u64) -> Self {
+        Self {
+            readings: Deque::new(),
+            expected,
+            epsilon,
+            tolerance,
+            timeout_in_milliseconds,
+        }
+    }
+
+    /// Add a reading that was taken `elapsed_in_milliseconds` after the wait started. Only the
+    /// most recent `PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW` readings are checked.
+    pub fn add_reading(&mut self, voltage: f32, elapsed_in_milliseconds: u64) -> VoltageStability {
+        if self.readings.is_full() {
+            self.readings.pop_front();
+        }
+        // There is always room after the oldest reading was removed
+        let _ = self.readings.push_back(voltage);
+
+        let readings: Vec<f32, PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW> =
+            self.readings.iter().copied().collect();
+        if is_voltage_stable(
+            &readings,
+            PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW,
+            self.expected,
+            self.epsilon,
+            self.tolerance,
+        ) {
+            VoltageStability::Stable
+        } else if elapsed_in_milliseconds >= self.timeout_in_milliseconds {
+            VoltageStability::TimedOut
+        } else {
+            VoltageStability::Unstable
+        }
+    }
+}
+
+/// Determine if the voltage readings are stable
+///
+/// The readings are stable if there are at least `window_size` readings, their standard
+/// deviation is below `epsilon` and their average is within `tolerance` of the `expected`
+/// voltage.
+pub fn is_voltage_stable(
+    readings: &[f32],
+    window_size: usize,
+    expected: f32,
+    epsilon: f32,
+    tolerance: f32,
+) -> bool {
+    let count = readings.len();
+    if count == 0 || count < window_size {
+        return false;
+    }
+
+    let mean = readings.iter().sum::<f32>() / count as f32;
+    let variance = readings
+        .iter()
+        .map(|v| (v - mean) * (v - mean))
+        .sum::<f32>()
+        / count as f32;
+
+    // The variance is compared with the square of epsilon, which avoids a square root
+    variance < epsilon * epsilon && (expected - mean).abs() < tolerance
+}
+
+/// Convert an ADS1115 reading to a voltage. The ADS1115 is 16-bit, so the full scale range is
+/// divided over 32768 steps on either side of zero.
+pub fn calculate_ads1115_voltage(measured_value: i16, full_scale_range_in_volts: f32) -> f32 {
+    (measured_value as f32 * full_scale_range_in_volts) / 32768.0
+}
+
+/// Calculate the voltage at the input of a voltage divider from the voltage at the probe.
+///
+/// Returns `InvalidVoltageDivider` if either resistor is not a positive value, because the
+/// voltage would be infinite or NaN.
+pub fn calculate_input_voltage_for_voltage_divider(
+    output_voltage: f32,
+    resistor_before_probe: f32,
+    resistor_after_probe: f32,
+) -> Result<f32, Error> {
+    let is_valid_resistor = |resistor: f32| resistor.is_finite() && resistor > 0.0;
+    if !is_valid_resistor(resistor_before_probe) || !is_valid_resistor(resistor_after_probe) {
+        error!(
+            "Voltage divider resistors of {resistor_before_probe} and {resistor_after_probe} ohm are invalid. Both must be positive."
+        );
+        return Err(Error::InvalidVoltageDivider);
+    }
+
+    Ok(output_voltage * (resistor_before_probe + resistor_after_probe) / resistor_after_probe)
+}
+
+/// Scale a voltage that applies to the default pressure sensor supply voltage to the given
+/// supply voltage
+pub fn scale_to_supply_voltage(voltage_at_default_supply: f32, supply_voltage: f32) -> f32 {
+    voltage_at_default_supply * supply_voltage / DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS
+}
+
+/// Parse a pressure sensor stability setting in volts, e.g. the stability epsilon. Returns the
+/// default, scaled to the supply voltage, if the value is missing or invalid.
+pub fn pressure_sensor_stability_setting(
+    value: Option<&str>,
+    default_at_default_supply: f32,
+    supply_voltage: f32,
+) -> f32 {
+    match value.and_then(|v| v.trim().parse::<f32>().ok()) {
+        Some(volts) if volts.is_finite() && volts > 0.0 => volts,
+        _ => scale_to_supply_voltage(default_at_default_supply, supply_voltage),
+    }
+}
+
+/// Parse the pressure sensor supply voltage. Returns the default if the value is missing, isn't
+/// a number or is outside the range that can be configured.
+pub fn parse_pressure_sensor_supply_voltage(value: Option<&str>) -> f32 {
+    let Some(value) = value.map(|v| v.trim()).filter(|v| !v.is_empty()) else {
+        return DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS;
+    };
+
+    match value.parse::<f32>() {
+        Ok(voltage)
+            if (MIN_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS
+                ..=MAX_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS)
+                .contains(&voltage) =>
+        {
+            voltage
+        }
+        _ => {
+            warn!(
+                "PRESSURE_SENSOR_SUPPLY_V {value} is not between {MIN_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS}V and {MAX_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS}V. Using {DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS}V."
+            );
+            DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS
+        }
+    }
+}
+
+/// Convert the LDR voltage to the enclosure brightness in percent. The resistance of an LDR
+/// changes with the logarithm of the light level, so the brightness is interpolated on a log
+/// scale between the dark and the bright voltage. The result is clamped to 0-100%.
+pub fn ldr_to_brightness_percent(v: f32, dark_v: f32, bright_v: f32) -> f32 {
+    let v = v.max(MIN_LDR_VOLTAGE_IN_VOLTS);
+    let brightness = 100.0 * (logf(v) - logf(dark_v)) / (logf(bright_v) - logf(dark_v));
+    brightness.clamp(0.0, 100.0)
+}
+
+/// Density of water at the given temperature, in kg/m³.
+///
+/// Uses the polynomial fit from Jones & Harris (1992), which is accurate to within 0.01 kg/m³
+/// between 5°C and 40°C.
+pub fn water_density_kg_m3(temp_c: f32) -> f32 {
+    let t2 = temp_c * temp_c;
+    let t3 = t2 * temp_c;
+    let t4 = t3 * temp_c;
+
+    999.853_1 + 6.326_93e-2 * temp_c - 8.523_829e-3 * t2 + 6.943_248e-5 * t3 - 3.821_216e-7 * t4
+}
+
+#[cfg(test)]
+#[path = "sensor_tests.rs"]
+mod sensor_tests;
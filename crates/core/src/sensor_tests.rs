@@ -0,0 +1,419 @@
+use super::*;
+
+fn sample(battery_voltage: f32, height: f32) -> Ads1115Data {
+    Ads1115Data::from((
+        Ratio::new::<percent>(50.0),
+        Voltage::new::<volt>(battery_voltage),
+        Voltage::new::<volt>(24.0),
+        Length::new::<meter>(height),
+    ))
+}
+
+#[test]
+fn test_no_samples_cannot_be_averaged() {
+    assert_eq!(
+        average_ads1115_samples(&[]).unwrap_err(),
+        Error::NoValidSamples
+    );
+}
+
+#[test]
+fn test_samples_are_averaged() {
+    let samples = [sample(12.0, 1.0), sample(12.2, 1.2), sample(12.4, 1.4)];
+
+    let average = average_ads1115_samples(&samples).unwrap();
+
+    assert!((average.battery_voltage.get::<volt>() - 12.2).abs() < 1e-4);
+    assert!((average.height_above_sensor.get::<meter>() - 1.2).abs() < 1e-4);
+    assert!(!average.pressure_sensor_fault);
+}
+
+#[test]
+fn test_outlier_is_excluded_from_the_average() {
+    let values = [1.0, 1.1, 0.9, 1.0, 50.0];
+
+    let average = average_without_outliers(&values, |v| *v);
+
+    assert!((average - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_values_without_outliers_are_all_averaged() {
+    let values = [1.0, 2.0, 3.0, 4.0];
+
+    assert!((average_without_outliers(&values, |v| *v) - 2.5).abs() < 1e-4);
+}
+
+#[test]
+fn test_too_few_values_for_outlier_rejection_are_all_averaged() {
+    let values = [1.0, 50.0];
+
+    assert!((average_without_outliers(&values, |v| *v) - 25.5).abs() < 1e-4);
+}
+
+#[test]
+fn test_outlier_sample_is_excluded_per_channel() {
+    let samples = [
+        sample(12.0, 1.0),
+        sample(12.0, 1.0),
+        sample(12.0, 1.0),
+        sample(12.0, 1.0),
+        sample(30.0, 1.0),
+    ];
+
+    let average = average_ads1115_samples(&samples).unwrap();
+
+    assert!((average.battery_voltage.get::<volt>() - 12.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_disconnected_samples_are_dropped() {
+    let mut disconnected = sample(12.0, 0.0);
+    disconnected.pressure_sensor_fault = true;
+    let samples = [sample(12.0, 1.0), disconnected, sample(12.0, 1.0)];
+
+    let average = average_ads1115_samples(&samples).unwrap();
+
+    assert!(!average.pressure_sensor_fault);
+    assert!((average.height_above_sensor.get::<meter>() - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_mostly_disconnected_sensor_is_faulty() {
+    let mut disconnected = sample(12.0, 0.5);
+    disconnected.pressure_sensor_fault = true;
+    let samples = [sample(12.0, 1.0), disconnected.clone(), disconnected];
+
+    let average = average_ads1115_samples(&samples).unwrap();
+
+    assert!(average.pressure_sensor_fault);
+    assert_eq!(average.height_above_sensor.get::<meter>(), 0.0);
+}
+
+fn stabilization() -> VoltageStabilization {
+    VoltageStabilization::new(24.0, 0.2, 0.2, 5000)
+}
+
+#[test]
+fn test_voltage_is_stable_after_a_full_window_of_steady_readings() {
+    let mut stabilization = stabilization();
+    for n in 0..PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW - 1 {
+        assert_eq!(
+            stabilization.add_reading(24.0, n as u64 * 10),
+            VoltageStability::Unstable
+        );
+    }
+
+    assert_eq!(
+        stabilization.add_reading(24.05, 100),
+        VoltageStability::Stable
+    );
+}
+
+#[test]
+fn test_noisy_voltage_is_unstable() {
+    let mut stabilization = stabilization();
+    let mut stability = VoltageStability::Unstable;
+    for n in 0..2 * PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW {
+        let voltage = if n % 2 == 0 { 23.6 } else { 24.4 };
+        stability = stabilization.add_reading(voltage, n as u64 * 10);
+    }
+
+    assert_eq!(stability, VoltageStability::Unstable);
+}
+
+#[test]
+fn test_steady_voltage_away_from_the_supply_voltage_is_unstable() {
+    let mut stabilization = stabilization();
+    let mut stability = VoltageStability::Unstable;
+    for n in 0..PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW {
+        stability = stabilization.add_reading(23.5, n as u64 * 10);
+    }
+
+    assert_eq!(stability, VoltageStability::Unstable);
+}
+
+#[test]
+fn test_voltage_becomes_stable_once_the_old_readings_leave_the_window() {
+    let mut stabilization = stabilization();
+    for n in 0..PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW {
+        stabilization.add_reading(20.0, n as u64 * 10);
+    }
+
+    let mut stability = VoltageStability::Unstable;
+    for n in 0..PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW {
+        stability = stabilization.add_reading(24.0, 100 + n as u64 * 10);
+    }
+
+    assert_eq!(stability, VoltageStability::Stable);
+}
+
+#[test]
+fn test_unstable_voltage_times_out() {
+    let mut stabilization = stabilization();
+
+    assert_eq!(
+        stabilization.add_reading(12.0, 4999),
+        VoltageStability::Unstable
+    );
+    assert_eq!(
+        stabilization.add_reading(12.0, 5000),
+        VoltageStability::TimedOut
+    );
+}
+
+#[test]
+fn test_stable_voltage_at_the_timeout_is_stable() {
+    let mut stabilization = stabilization();
+    for _ in 0..PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW - 1 {
+        stabilization.add_reading(24.0, 0);
+    }
+
+    assert_eq!(
+        stabilization.add_reading(24.0, 6000),
+        VoltageStability::Stable
+    );
+}
+
+#[test]
+fn test_water_density_at_known_temperatures() {
+    // Reference densities of air free water at standard pressure
+    for (temperature, density) in [
+        (4.0, 999.972),
+        (10.0, 999.700),
+        (20.0, 998.207),
+        (25.0, 997.047),
+        (30.0, 995.649),
+        (40.0, 992.22),
+    ] {
+        let calculated = water_density_kg_m3(temperature);
+        assert!(
+            (calculated - density).abs() < 0.01,
+            "{calculated} kg/m³ at {temperature}°C"
+        );
+    }
+}
+
+#[test]
+fn test_water_is_densest_near_four_degrees() {
+    assert!(water_density_kg_m3(4.0) > water_density_kg_m3(1.0));
+    assert!(water_density_kg_m3(4.0) > water_density_kg_m3(8.0));
+}
+
+#[test]
+fn test_supply_voltage_is_parsed() {
+    assert_eq!(parse_pressure_sensor_supply_voltage(Some("12")), 12.0);
+    assert_eq!(parse_pressure_sensor_supply_voltage(Some(" 24.0 ")), 24.0);
+}
+
+#[test]
+fn test_missing_or_invalid_supply_voltage_uses_the_default() {
+    for value in [None, Some(""), Some("twelve"), Some("4.9"), Some("36.1")] {
+        assert_eq!(
+            parse_pressure_sensor_supply_voltage(value),
+            DEFAULT_PRESSURE_SENSOR_SUPPLY_VOLTAGE_IN_VOLTS
+        );
+    }
+}
+
+#[test]
+fn test_stability_settings_scale_with_the_supply_voltage() {
+    assert!((pressure_sensor_stability_setting(None, 0.2, 24.0) - 0.2).abs() < 1e-6);
+    assert!((pressure_sensor_stability_setting(None, 0.2, 12.0) - 0.1).abs() < 1e-6);
+    assert!((scale_to_supply_voltage(0.2, 12.0) - 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn test_configured_stability_setting_is_not_scaled() {
+    assert_eq!(
+        pressure_sensor_stability_setting(Some("0.3"), 0.2, 12.0),
+        0.3
+    );
+    assert!((pressure_sensor_stability_setting(Some("-0.3"), 0.2, 12.0) - 0.1).abs() < 1e-6);
+}
+
+/// The stabilization for the given configured supply voltage with the default settings
+fn stabilization_for_supply(supply_voltage: &str) -> VoltageStabilization {
+    let supply_voltage = parse_pressure_sensor_supply_voltage(Some(supply_voltage));
+    VoltageStabilization::new(
+        supply_voltage,
+        pressure_sensor_stability_setting(None, 0.2, supply_voltage),
+        pressure_sensor_stability_setting(None, 0.2, supply_voltage),
+        5000,
+    )
+}
+
+fn stability_of_steady_voltage(
+    stabilization: &mut VoltageStabilization,
+    voltage: f32,
+) -> VoltageStability {
+    let mut stability = VoltageStability::Unstable;
+    for n in 0..PRESSURE_SENSOR_VOLTAGE_STABILIZATION_WINDOW {
+        stability = stabilization.add_reading(voltage, n as u64 * 10);
+    }
+
+    stability
+}
+
+#[test]
+fn test_stabilization_at_a_12v_supply() {
+    assert_eq!(
+        stability_of_steady_voltage(&mut stabilization_for_supply("12"), 12.05),
+        VoltageStability::Stable
+    );
+    assert_eq!(
+        stability_of_steady_voltage(&mut stabilization_for_supply("12"), 12.15),
+        VoltageStability::Unstable
+    );
+    assert_eq!(
+        stability_of_steady_voltage(&mut stabilization_for_supply("12"), 24.0),
+        VoltageStability::Unstable
+    );
+}
+
+#[test]
+fn test_stabilization_at_a_24v_supply() {
+    assert_eq!(
+        stability_of_steady_voltage(&mut stabilization_for_supply("24"), 24.15),
+        VoltageStability::Stable
+    );
+    assert_eq!(
+        stability_of_steady_voltage(&mut stabilization_for_supply("24"), 24.25),
+        VoltageStability::Unstable
+    );
+    assert_eq!(
+        stability_of_steady_voltage(&mut stabilization_for_supply("24"), 12.0),
+        VoltageStability::Unstable
+    );
+}
+
+const LDR_DARK_VOLTAGE: f32 = 0.1;
+const LDR_BRIGHT_VOLTAGE: f32 = 3.3;
+
+#[test]
+fn test_dark_ldr_is_0_percent() {
+    let brightness =
+        ldr_to_brightness_percent(LDR_DARK_VOLTAGE, LDR_DARK_VOLTAGE, LDR_BRIGHT_VOLTAGE);
+
+    assert!(brightness.abs() < 1e-3);
+}
+
+#[test]
+fn test_ldr_halfway_on_the_log_scale_is_50_percent() {
+    // The geometric mean of the dark and bright voltages
+    let voltage = 0.574_456_3;
+
+    let brightness = ldr_to_brightness_percent(voltage, LDR_DARK_VOLTAGE, LDR_BRIGHT_VOLTAGE);
+
+    assert!((brightness - 50.0).abs() < 1e-2);
+}
+
+#[test]
+fn test_bright_ldr_is_100_percent() {
+    let brightness =
+        ldr_to_brightness_percent(LDR_BRIGHT_VOLTAGE, LDR_DARK_VOLTAGE, LDR_BRIGHT_VOLTAGE);
+
+    assert!((brightness - 100.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_ldr_brightness_is_clamped() {
+    assert_eq!(
+        ldr_to_brightness_percent(0.0, LDR_DARK_VOLTAGE, LDR_BRIGHT_VOLTAGE),
+        0.0
+    );
+    assert_eq!(
+        ldr_to_brightness_percent(5.0, LDR_DARK_VOLTAGE, LDR_BRIGHT_VOLTAGE),
+        100.0
+    );
+}
+
+#[test]
+fn test_input_voltage_of_a_voltage_divider() {
+    // 10k and 2.2k ohm divide 12.2V down to 2.2V
+    let voltage = calculate_input_voltage_for_voltage_divider(2.2, 10_000.0, 2_200.0).unwrap();
+
+    assert!((voltage - 12.2).abs() < 1e-4);
+}
+
+#[test]
+fn test_voltage_divider_with_a_zero_resistor_is_invalid() {
+    assert_eq!(
+        calculate_input_voltage_for_voltage_divider(2.2, 0.0, 2_200.0),
+        Err(Error::InvalidVoltageDivider)
+    );
+    assert_eq!(
+        calculate_input_voltage_for_voltage_divider(2.2, 10_000.0, 0.0),
+        Err(Error::InvalidVoltageDivider)
+    );
+}
+
+#[test]
+fn test_voltage_divider_with_a_negative_resistor_is_invalid() {
+    assert_eq!(
+        calculate_input_voltage_for_voltage_divider(2.2, -10_000.0, 2_200.0),
+        Err(Error::InvalidVoltageDivider)
+    );
+    assert_eq!(
+        calculate_input_voltage_for_voltage_divider(2.2, 10_000.0, -2_200.0),
+        Err(Error::InvalidVoltageDivider)
+    );
+}
+
+#[test]
+fn test_voltage_divider_with_an_infinite_resistor_is_invalid() {
+    assert_eq!(
+        calculate_input_voltage_for_voltage_divider(2.2, f32::INFINITY, 2_200.0),
+        Err(Error::InvalidVoltageDivider)
+    );
+}
+
+/// The full scale ranges, in volts, that the ADS1115 supports
+const ADS1115_FULL_SCALE_RANGES: [f32; 6] = [6.144, 4.096, 2.048, 1.024, 0.512, 0.256];
+
+#[test]
+fn test_ads1115_voltage_at_known_codes_for_each_full_scale_range() {
+    for full_scale_range in ADS1115_FULL_SCALE_RANGES {
+        for (code, fraction) in [
+            (0, 0.0),
+            (8192, 0.25),
+            (16384, 0.5),
+            (-16384, -0.5),
+            (-32768, -1.0),
+        ] {
+            let voltage = calculate_ads1115_voltage(code, full_scale_range);
+            assert!(
+                (voltage - fraction * full_scale_range).abs() < 1e-6,
+                "{code} at ±{full_scale_range}V is {voltage}V"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_ads1115_voltage_of_the_largest_code_is_one_step_below_the_full_scale_range() {
+    for full_scale_range in ADS1115_FULL_SCALE_RANGES {
+        let step = full_scale_range / 32768.0;
+
+        let voltage = calculate_ads1115_voltage(i16::MAX, full_scale_range);
+
+        assert!((voltage - (full_scale_range - step)).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_ads1115_step_size() {
+    // The step sizes from the ADS1115 data sheet
+    for (full_scale_range, step_in_micro_volts) in [
+        (6.144, 187.5),
+        (4.096, 125.0),
+        (2.048, 62.5),
+        (1.024, 31.25),
+        (0.512, 15.625),
+        (0.256, 7.8125),
+    ] {
+        let voltage = calculate_ads1115_voltage(1, full_scale_range);
+
+        assert!((voltage * 1e6 - step_in_micro_volts).abs() < 1e-3);
+    }
+}
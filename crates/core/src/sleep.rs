@@ -0,0 +1,61 @@
+//! The GPIO that wakes the device from deep sleep
+//!
+//! Only the low power GPIOs can wake the ESP32-C6 from deep sleep, which are GPIO0 to GPIO7.
+
+use log::warn;
+
+/// The highest numbered GPIO that can wake the device from deep sleep
+pub const MAX_WAKE_GPIO: u8 = 7;
+
+/// A GPIO that wakes the device from deep sleep
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WakePin {
+    /// The number of the GPIO
+    pub gpio: u8,
+
+    /// `true` if the device wakes when the GPIO goes high, `false` if it wakes when it goes low
+    pub active_high: bool,
+}
+
+impl WakePin {
+    /// The name of the level that wakes the device
+    pub fn level_name(&self) -> &'static str {
+        if self.active_high {
+            "high"
+        } else {
+            "low"
+        }
+    }
+}
+
+/// Parse the wake GPIO and its level. `None` if the GPIO is not set or isn't one of the GPIOs
+/// that can wake the device, in which case the device only wakes on the timer.
+pub fn parse_wake_pin(gpio: Option<&str>, level: Option<&str>) -> Option<WakePin> {
+    let gpio = gpio
+        .map(|gpio| gpio.trim())
+        .filter(|gpio| !gpio.is_empty())?;
+    let gpio = match gpio.parse::<u8>() {
+        Ok(number) if number <= MAX_WAKE_GPIO => number,
+        _ => {
+            warn!(
+                "WAKE_GPIO {gpio} is not one of GPIO0 to GPIO{MAX_WAKE_GPIO}. Waking on the timer only."
+            );
+            return None;
+        }
+    };
+
+    let active_high = match level.map(|level| level.trim()) {
+        Some("high") | Some("") | None => true,
+        Some("low") => false,
+        Some(other) => {
+            warn!("WAKE_GPIO_LEVEL {other} is not high or low. Using high.");
+            true
+        }
+    };
+
+    Some(WakePin { gpio, active_high })
+}
+
+#[cfg(test)]
+#[path = "sleep_tests.rs"]
+mod sleep_tests;
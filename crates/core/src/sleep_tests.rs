@@ -0,0 +1,59 @@
+use super::*;
+
+#[test]
+fn test_no_wake_gpio_wakes_on_the_timer_only() {
+    assert_eq!(parse_wake_pin(None, None), None);
+    assert_eq!(parse_wake_pin(Some(""), Some("low")), None);
+    assert_eq!(parse_wake_pin(Some("  "), None), None);
+}
+
+#[test]
+fn test_wake_gpio_is_selected() {
+    assert_eq!(
+        parse_wake_pin(Some("2"), Some("low")),
+        Some(WakePin {
+            gpio: 2,
+            active_high: false,
+        })
+    );
+    assert_eq!(
+        parse_wake_pin(Some(" 0 "), Some("high")),
+        Some(WakePin {
+            gpio: 0,
+            active_high: true,
+        })
+    );
+}
+
+#[test]
+fn test_wake_gpio_that_cannot_wake_the_device_wakes_on_the_timer_only() {
+    for gpio in ["8", "18", "-1", "GPIO2"] {
+        assert_eq!(parse_wake_pin(Some(gpio), None), None, "{gpio}");
+    }
+}
+
+#[test]
+fn test_highest_low_power_gpio_can_wake_the_device() {
+    assert_eq!(
+        parse_wake_pin(Some("7"), None).map(|pin| pin.gpio),
+        Some(MAX_WAKE_GPIO)
+    );
+}
+
+#[test]
+fn test_wake_level_defaults_to_high() {
+    for level in [None, Some(""), Some("rising")] {
+        let pin = parse_wake_pin(Some("3"), level).unwrap();
+
+        assert!(pin.active_high, "{level:?}");
+        assert_eq!(pin.level_name(), "high");
+    }
+}
+
+#[test]
+fn test_low_wake_level() {
+    let pin = parse_wake_pin(Some("3"), Some(" low ")).unwrap();
+
+    assert!(!pin.active_high);
+    assert_eq!(pin.level_name(), "low");
+}
@@ -0,0 +1,43 @@
+//! Tank geometry
+
+/// The number of liters in a cubic meter
+const LITERS_PER_CUBIC_METER: f32 = 1000.0;
+
+/// The cross-section of the tank
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TankShape {
+    /// A vertical cylinder
+    Cylinder { radius_in_meters: f32 },
+
+    /// A rectangular box
+    Rectangular {
+        width_in_meters: f32,
+        length_in_meters: f32,
+    },
+}
+
+impl TankShape {
+    /// The horizontal cross-section area of the tank in square meters
+    pub fn cross_section_in_square_meters(&self) -> f32 {
+        match *self {
+            Self::Cylinder { radius_in_meters } => {
+                core::f32::consts::PI * radius_in_meters * radius_in_meters
+            }
+            Self::Rectangular {
+                width_in_meters,
+                length_in_meters,
+            } => width_in_meters * length_in_meters,
+        }
+    }
+}
+
+/// Calculate the volume of water in a tank of the given shape, in liters, for the given water
+/// height. Negative heights are treated as an empty tank.
+pub fn tank_volume_liters(shape: TankShape, height_m: f32) -> f32 {
+    let height = if height_m > 0.0 { height_m } else { 0.0 };
+    shape.cross_section_in_square_meters() * height * LITERS_PER_CUBIC_METER
+}
+
+#[cfg(test)]
+#[path = "tank_tests.rs"]
+mod tank_tests;
@@ -0,0 +1,32 @@
+use super::*;
+
+#[test]
+fn test_volume_of_a_full_2m_cylinder() {
+    // A cylinder with a diameter of 2m, filled to 2m, holds 2π cubic meters
+    let shape = TankShape::Cylinder {
+        radius_in_meters: 1.0,
+    };
+
+    let volume = tank_volume_liters(shape, 2.0);
+
+    assert!((volume - 2000.0 * core::f32::consts::PI).abs() < 0.1);
+}
+
+#[test]
+fn test_volume_of_a_rectangular_tank() {
+    let shape = TankShape::Rectangular {
+        width_in_meters: 2.0,
+        length_in_meters: 3.0,
+    };
+
+    assert!((tank_volume_liters(shape, 0.5) - 3000.0).abs() < 0.1);
+}
+
+#[test]
+fn test_negative_height_is_an_empty_tank() {
+    let shape = TankShape::Cylinder {
+        radius_in_meters: 1.0,
+    };
+
+    assert_eq!(tank_volume_liters(shape, -0.1), 0.0);
+}
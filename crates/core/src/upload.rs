@@ -0,0 +1,28 @@
+//! The rules for posting payloads to the servers
+
+use crate::build_env::parse_u64_in_range_or;
+
+/// The longest TCP timeout, in milliseconds, that can be configured for an uploader
+pub const MAX_UPLOAD_TIMEOUT_IN_MILLISECONDS: u64 = 60_000;
+
+/// Parse the TCP timeout, in milliseconds, of an uploader from a build time environment
+/// variable. Returns the `default` timeout if the value is not set, is zero or is longer than
+/// `MAX_UPLOAD_TIMEOUT_IN_MILLISECONDS`.
+pub const fn parse_upload_timeout_in_milliseconds(value: Option<&str>, default: u64) -> u64 {
+    parse_u64_in_range_or(value, default, 1, MAX_UPLOAD_TIMEOUT_IN_MILLISECONDS)
+}
+
+/// An error that may go away if the request is sent again
+pub trait Retryable {
+    /// Returns `true` if sending the request again may succeed
+    fn is_retryable(&self) -> bool;
+}
+
+/// `true` if the status code of the response indicates that the server accepted the payload
+pub fn is_success_status(status_code: u16) -> bool {
+    (200..300).contains(&status_code)
+}
+
+#[cfg(test)]
+#[path = "upload_tests.rs"]
+mod upload_tests;
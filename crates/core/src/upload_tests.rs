@@ -0,0 +1,46 @@
+use super::*;
+
+#[test]
+fn test_2xx_status_codes_are_a_success() {
+    for status_code in [200, 201, 202, 204, 299] {
+        assert!(is_success_status(status_code), "{status_code}");
+    }
+}
+
+#[test]
+fn test_other_status_codes_are_not_a_success() {
+    for status_code in [0, 100, 199, 300, 301, 400, 401, 413, 429, 500, 503] {
+        assert!(!is_success_status(status_code), "{status_code}");
+    }
+}
+
+#[test]
+fn test_upload_timeout_is_parsed() {
+    assert_eq!(parse_upload_timeout_in_milliseconds(Some("1"), 5000), 1);
+    assert_eq!(
+        parse_upload_timeout_in_milliseconds(Some("15000"), 5000),
+        15000
+    );
+    assert_eq!(
+        parse_upload_timeout_in_milliseconds(Some("60000"), 5000),
+        MAX_UPLOAD_TIMEOUT_IN_MILLISECONDS
+    );
+}
+
+#[test]
+fn test_invalid_upload_timeout_uses_the_default() {
+    for value in [
+        None,
+        Some(""),
+        Some("0"),
+        Some("60001"),
+        Some("-1"),
+        Some("5s"),
+    ] {
+        assert_eq!(
+            parse_upload_timeout_in_milliseconds(value, 5000),
+            5000,
+            "{value:?}"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! The WiFi settings of the device
+
+use heapless::String;
+use heapless::Vec;
+
+use log::debug;
+use log::error;
+use log::warn;
+
+use crate::backoff::{backoff_delay_ms, jitter_ms};
+
+/// Maximum number of WiFi networks that can be configured
+pub const MAX_WIFI_NETWORKS: usize = 4;
+
+/// The SSID and password of each WiFi network, in the order they should be tried
+pub type WifiNetworks = Vec<(String<32>, String<64>), MAX_WIFI_NETWORKS>;
+
+/// Parse the WiFi networks from the SSIDs and passwords
+///
+/// If `ssids` doesn't contain a comma it is a single network and `passwords` is its password,
+/// even if the password contains a comma. Otherwise both are comma-separated lists where the n-th
+/// password belongs to the n-th SSID. If only a single password is provided for a list of SSIDs it
+/// is used for all networks. Empty SSIDs are skipped, as are networks whose SSID or password is
+/// too long. At most `MAX_WIFI_NETWORKS` networks are returned.
+pub fn parse_wifi_networks(ssids: &str, passwords: &str) -> WifiNetworks {
+    let mut networks = WifiNetworks::new();
+    if !ssids.contains(',') {
+        if ssids.is_empty() {
+            debug!("Skipping empty WiFi SSID");
+        } else {
+            push_network(&mut networks, 0, ssids, passwords);
+        }
+
+        return networks;
+    }
+
+    let has_single_password = !passwords.contains(',');
+    for (index, ssid) in ssids.split(',').enumerate() {
+        if ssid.is_empty() {
+            debug!("Skipping empty WiFi SSID at position {index}");
+            continue;
+        }
+
+        let password = if has_single_password {
+            passwords
+        } else {
+            passwords.split(',').nth(index).unwrap_or("")
+        };
+
+        if !push_network(&mut networks, index, ssid, password) {
+            warn!("More than {MAX_WIFI_NETWORKS} WiFi networks provided. Ignoring the remainder.");
+            break;
+        }
+    }
+
+    networks
+}
+
+/// Add the network to the list. Returns `false` if the list is full.
+fn push_network(networks: &mut WifiNetworks, index: usize, ssid: &str, password: &str) -> bool {
+    let (Ok(ssid), Ok(password)) = (
+        String::<32>::try_from(ssid),
+        String::<64>::try_from(password),
+    ) else {
+        error!("WiFi SSID or password at position {index} is too long. Skipping it.");
+        return true;
+    };
+
+    networks.push((ssid, password)).is_ok()
+}
+
+/// The power save mode of the WiFi radio
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WifiPowerSave {
+    /// Power saving is off
+    None,
+
+    /// The radio sleeps between the beacons of the access point
+    Minimum,
+
+    /// The radio sleeps for several beacon intervals at a time
+    Maximum,
+}
+
+/// Parse the power save mode of the WiFi radio. Power saving is off if nothing or an unknown mode
+/// is configured, which gives the fastest connection. Devices that stay awake for longer, e.g.
+/// to monitor a GPIO, use less power with power saving on.
+pub fn parse_power_save_mode(mode: Option<&str>) -> WifiPowerSave {
+    match mode.map(|mode| mode.trim()) {
+        Some("none") | Some("") | None => WifiPowerSave::None,
+        Some("minimum") => WifiPowerSave::Minimum,
+        Some("maximum") => WifiPowerSave::Maximum,
+        Some(other) => {
+            warn!("WIFI_POWER_SAVE {other} is not none, minimum or maximum. Using none.");
+            WifiPowerSave::None
+        }
+    }
+}
+
+/// The delays while the device disconnects from WiFi
+///
+/// Some access points are slow to acknowledge the disconnect, so every retry gives the disconnect
+/// twice as long to complete as the one before it. A random jitter is added to every delay.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisconnectSchedule {
+    /// The delay between the retries and the time that the first disconnect is given to
+    /// complete, in milliseconds, excluding jitter
+    pub base_delay_ms: u64,
+
+    /// The longest time, in milliseconds, that a disconnect is given to complete, excluding
+    /// jitter
+    pub max_verification_delay_ms: u64,
+
+    /// The largest random delay, in milliseconds, that is added to the delays
+    pub max_jitter_ms: u32,
+}
+
+impl DisconnectSchedule {
+    /// The time, in milliseconds, that the disconnect of the given retry (zero based) is given to
+    /// complete. `random` is a random number that determines the jitter.
+    pub fn verification_delay_ms(&self, retry: u8, random: u32) -> u64 {
+        backoff_delay_ms(
+            retry,
+            self.base_delay_ms,
+            self.max_verification_delay_ms,
+            jitter_ms(random, self.max_jitter_ms),
+        )
+    }
+
+    /// The time, in milliseconds, to wait before the next retry. `random` is a random number
+    /// that determines the jitter.
+    pub fn retry_delay_ms(&self, random: u32) -> u64 {
+        self.base_delay_ms
+            .saturating_add(u64::from(jitter_ms(random, self.max_jitter_ms)))
+    }
+}
+
+#[cfg(test)]
+#[path = "wifi_tests.rs"]
+mod wifi_tests;
@@ -0,0 +1,178 @@
+use super::*;
+
+fn network(ssid: &str, password: &str) -> (String<32>, String<64>) {
+    (
+        String::try_from(ssid).unwrap(),
+        String::try_from(password).unwrap(),
+    )
+}
+
+#[test]
+fn test_single_network_is_parsed() {
+    let networks = parse_wifi_networks("home", "secret");
+
+    assert_eq!(networks.as_slice(), &[network("home", "secret")]);
+}
+
+#[test]
+fn test_single_network_keeps_a_password_with_a_comma() {
+    let networks = parse_wifi_networks("home", "se,cr,et");
+
+    assert_eq!(networks.as_slice(), &[network("home", "se,cr,et")]);
+}
+
+#[test]
+fn test_single_network_keeps_the_ssid_unchanged() {
+    let networks = parse_wifi_networks(" home ", "secret");
+
+    assert_eq!(networks.as_slice(), &[network(" home ", "secret")]);
+}
+
+#[test]
+fn test_empty_ssid_is_not_a_network() {
+    assert!(parse_wifi_networks("", "secret").is_empty());
+}
+
+#[test]
+fn test_networks_are_parsed_in_order() {
+    let networks = parse_wifi_networks("home,hotspot", "secret,other");
+
+    assert_eq!(
+        networks.as_slice(),
+        &[network("home", "secret"), network("hotspot", "other")]
+    );
+}
+
+#[test]
+fn test_single_password_is_used_for_all_networks() {
+    let networks = parse_wifi_networks("home,hotspot", "secret");
+
+    assert_eq!(
+        networks.as_slice(),
+        &[network("home", "secret"), network("hotspot", "secret")]
+    );
+}
+
+#[test]
+fn test_empty_ssids_in_a_list_are_skipped() {
+    let networks = parse_wifi_networks("home,,hotspot,", "secret,unused,other,");
+
+    assert_eq!(
+        networks.as_slice(),
+        &[network("home", "secret"), network("hotspot", "other")]
+    );
+}
+
+#[test]
+fn test_missing_password_is_empty() {
+    let networks = parse_wifi_networks("home,hotspot,open", "secret,other");
+
+    assert_eq!(
+        networks.as_slice(),
+        &[
+            network("home", "secret"),
+            network("hotspot", "other"),
+            network("open", "")
+        ]
+    );
+}
+
+#[test]
+fn test_too_long_ssid_is_skipped() {
+    let long_ssid = "s".repeat(33);
+    let ssids = format!("home,{long_ssid},hotspot");
+    let networks = parse_wifi_networks(&ssids, "secret");
+
+    assert_eq!(
+        networks.as_slice(),
+        &[network("home", "secret"), network("hotspot", "secret")]
+    );
+}
+
+#[test]
+fn test_too_long_single_password_is_not_a_network() {
+    let long_password = "p".repeat(65);
+
+    assert!(parse_wifi_networks("home", &long_password).is_empty());
+}
+
+#[test]
+fn test_at_most_the_maximum_number_of_networks_is_returned() {
+    let networks = parse_wifi_networks("a,b,c,d,e", "secret");
+
+    assert_eq!(networks.len(), MAX_WIFI_NETWORKS);
+    assert_eq!(networks[MAX_WIFI_NETWORKS - 1], network("d", "secret"));
+}
+
+const DISCONNECT_SCHEDULE: DisconnectSchedule = DisconnectSchedule {
+    base_delay_ms: 100,
+    max_verification_delay_ms: 2000,
+    max_jitter_ms: 20,
+};
+
+#[test]
+fn test_disconnect_verification_delay_doubles_with_every_retry() {
+    let delays: Vec<u64, 3> = (0..3)
+        .map(|retry| DISCONNECT_SCHEDULE.verification_delay_ms(retry, 0))
+        .collect();
+
+    assert_eq!(delays, [100, 200, 400]);
+}
+
+#[test]
+fn test_disconnect_verification_delay_is_capped() {
+    assert_eq!(DISCONNECT_SCHEDULE.verification_delay_ms(5, 0), 2000);
+    assert_eq!(DISCONNECT_SCHEDULE.verification_delay_ms(9, 0), 2000);
+}
+
+#[test]
+fn test_disconnect_verification_delay_includes_the_jitter() {
+    assert_eq!(DISCONNECT_SCHEDULE.verification_delay_ms(1, 15), 215);
+    assert_eq!(DISCONNECT_SCHEDULE.verification_delay_ms(1, 20), 220);
+    assert_eq!(DISCONNECT_SCHEDULE.verification_delay_ms(1, 21), 200);
+    assert_eq!(DISCONNECT_SCHEDULE.verification_delay_ms(9, 20), 2020);
+}
+
+#[test]
+fn test_disconnect_retry_delay_includes_the_jitter() {
+    assert_eq!(DISCONNECT_SCHEDULE.retry_delay_ms(0), 100);
+    assert_eq!(DISCONNECT_SCHEDULE.retry_delay_ms(7), 107);
+    assert_eq!(DISCONNECT_SCHEDULE.retry_delay_ms(41), 120);
+}
+
+#[test]
+fn test_disconnect_delays_stay_within_the_jitter_bounds() {
+    for random in [0, 1, 20, 21, 9999, u32::MAX] {
+        let retry_delay = DISCONNECT_SCHEDULE.retry_delay_ms(random);
+        assert!((100..=120).contains(&retry_delay), "{random}");
+
+        for retry in 0..3 {
+            let minimum = 100 << retry;
+            let delay = DISCONNECT_SCHEDULE.verification_delay_ms(retry, random);
+            assert!(
+                (minimum..=minimum + 20).contains(&delay),
+                "{random} {retry}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_power_save_mode_is_parsed() {
+    assert_eq!(parse_power_save_mode(Some("none")), WifiPowerSave::None);
+    assert_eq!(
+        parse_power_save_mode(Some("minimum")),
+        WifiPowerSave::Minimum
+    );
+    assert_eq!(
+        parse_power_save_mode(Some(" maximum ")),
+        WifiPowerSave::Maximum
+    );
+}
+
+#[test]
+fn test_missing_or_unknown_power_save_mode_is_none() {
+    for mode in [None, Some(""), Some("max"), Some("Minimum")] {
+        assert_eq!(parse_power_save_mode(mode), WifiPowerSave::None, "{mode:?}");
+    }
+}
@@ -1,18 +1,31 @@
 // time
-use chrono::Utc;
+use chrono::{Timelike, Utc};
 
 // REST
 use axum::{
-    extract::{rejection::JsonRejection, Extension, Json, Path, Query, Request, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{
+        rejection::JsonRejection, DefaultBodyLimit, Extension, FromRequest, Json, Path, Query,
+        Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
     Router,
 };
 
+use hmac::{Hmac, Mac};
 use once_cell::sync::Lazy;
+use sha2::Sha256;
 
 // HTTP
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, WatchStream};
+use tokio_stream::{Stream, StreamExt};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
 use tower_http::trace::TraceLayer;
 
 // JSON
@@ -21,15 +34,23 @@ use serde::{Deserialize, Serialize};
 // Observability
 use opentelemetry::KeyValue;
 use opentelemetry::{global, InstrumentationScope};
-use opentelemetry::{metrics::Meter, trace::TraceError};
+use opentelemetry::{
+    metrics::{Counter, Gauge, Histogram, Meter},
+    trace::{Span, TraceError, Tracer},
+};
 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
 use opentelemetry_otlp::{LogExporter, MetricExporter, SpanExporter, WithExportConfig};
-use opentelemetry_sdk::metrics::{MetricError, PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::metrics::data::ResourceMetrics;
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use opentelemetry_sdk::metrics::{MetricError, MetricResult, PeriodicReader, SdkMeterProvider};
 use opentelemetry_sdk::{
     logs::{LogError, LoggerProvider},
     metrics::Temporality,
 };
 use opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
+use prometheus::{
+    GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
 use tracing::{debug, error, info, instrument};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{prelude::*, EnvFilter};
@@ -48,254 +69,3900 @@ static RESOURCE: Lazy<Resource> = Lazy::new(|| {
     )])
 });
 
+static PROMETHEUS_METRICS: Lazy<PrometheusMetrics> = Lazy::new(PrometheusMetrics::new);
+
+/// Create a counter and register it with the Prometheus registry. Returns `None` if the counter
+/// can't be created, in which case the count is not kept.
+fn register_counter_vec(name: &str, help: &str, labels: &[&str]) -> Option<IntCounterVec> {
+    let counter = match IntCounterVec::new(Opts::new(name, help), labels) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed to create the {} counter: {:?}", name, e);
+            return None;
+        }
+    };
+
+    if let Err(e) = PROMETHEUS_METRICS
+        .registry
+        .register(Box::new(counter.clone()))
+    {
+        error!("Failed to register the {} counter: {:?}", name, e);
+    }
+
+    Some(counter)
+}
+
+static BOOT_REASONS: Lazy<Option<IntCounterVec>> = Lazy::new(|| {
+    register_counter_vec(
+        "device_boots_total",
+        "The number of times the device has booted, by boot reason",
+        &["device_id", "boot_reason"],
+    )
+});
+
+static COLD_BOOTS: Lazy<Option<IntCounterVec>> = Lazy::new(|| {
+    register_counter_vec(
+        "cold_boots_total",
+        "The number of times the device booted after it lost power",
+        &["device_id"],
+    )
+});
+
+static SENSOR_READ_FAILURES: Lazy<Option<IntCounterVec>> = Lazy::new(|| {
+    register_counter_vec(
+        "sensor_read_failures_total",
+        "The number of times the device could not read its sensors",
+        &["device_id"],
+    )
+});
+
+/// The number of payloads that didn't match their checksum, by endpoint. The device isn't
+/// known because the payload can't be trusted.
+static CORRUPT_PAYLOADS: Lazy<Option<IntCounterVec>> = Lazy::new(|| {
+    register_counter_vec(
+        "corrupt_payloads_total",
+        "The number of payloads that did not match their checksum",
+        &["endpoint"],
+    )
+});
+
+static CONDENSATION_RISKS: Lazy<Option<IntCounterVec>> = Lazy::new(|| {
+    register_counter_vec(
+        "device_condensation_risk_total",
+        "The number of readings for which condensation in the enclosure was likely",
+        &["device_id"],
+    )
+});
+
+static EXPORT_FAILURES: Lazy<ExportFailures> = Lazy::new(|| {
+    ExportFailures::new(
+        register_counter_vec(
+            "export_failures_total",
+            "The number of times the telemetry could not be exported",
+            &["signal"],
+        ),
+        EXPORT_FAILURE_LOG_INTERVAL,
+    )
+});
+
+/// The telemetry signals, in the order in which the self test reports them
+const TELEMETRY_SIGNALS: [&str; 3] = ["metrics", "logs", "traces"];
+
+/// The shortest time between two log messages about export failures of the same signal
+const EXPORT_FAILURE_LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Keeps track of the failures to export the telemetry
+///
+/// Every failure is counted, but failures of the same signal are only logged once per interval
+/// so that an unreachable collector doesn't flood the logs.
+struct ExportFailures {
+    counter: Option<IntCounterVec>,
+    /// The OpenTelemetry counter, which is created once the meter provider is set
+    otel_counter: std::sync::OnceLock<Counter<u64>>,
+    log_interval: std::time::Duration,
+    /// When a failure was last logged, by signal
+    last_logged: std::sync::Mutex<std::collections::HashMap<String, std::time::Instant>>,
+}
+
+impl ExportFailures {
+    fn new(counter: Option<IntCounterVec>, log_interval: std::time::Duration) -> Self {
+        Self {
+            counter,
+            otel_counter: std::sync::OnceLock::new(),
+            log_interval,
+            last_logged: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+
+    /// Create the OpenTelemetry counter from the global meter provider. This has to be called
+    /// after the meter provider is set, because the counter is bound to the provider it was
+    /// created with. Failures that happen before that are only counted for Prometheus.
+    fn init_otel_counter(&self) {
+        self.otel_counter.get_or_init(|| {
+            global::meter("tank-sensor-service")
+                .u64_counter("export_failures_total")
+                .with_description("The number of times the telemetry could not be exported")
+                .build()
+        });
+    }
+
+    /// Record a failure to export the given signal, e.g. `metrics`, `traces` or `logs`
+    fn record(&self, signal: &str, message: &str) {
+        self.count_failure(signal);
+
+        if self.should_log(signal, std::time::Instant::now()) {
+            error!("Failed to export the {}: {}", signal, message);
+        }
+    }
+
+    /// Count a failure of the given signal without logging it
+    fn count_failure(&self, signal: &str) {
+        if let Some(counter) = &self.counter {
+            counter.with_label_values(&[signal]).inc();
+        }
+
+        if let Some(counter) = self.otel_counter.get() {
+            counter.add(1, &[KeyValue::new("signal", signal.to_string())]);
+        }
+    }
+
+    /// The number of failures recorded for the given signal
+    fn count(&self, signal: &str) -> u64 {
+        self.counter
+            .as_ref()
+            .map(|counter| counter.with_label_values(&[signal]).get())
+            .unwrap_or_default()
+    }
+
+    /// Determine if a failure should be logged. The failures of a signal are logged once per
+    /// interval, whatever the error was, so that the number of entries is bound by the number
+    /// of signals.
+    fn should_log(&self, signal: &str, now: std::time::Instant) -> bool {
+        let mut last_logged = self
+            .last_logged
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match last_logged.get(signal) {
+            Some(previous) if now.duration_since(*previous) < self.log_interval => false,
+            _ => {
+                last_logged.insert(signal.to_string(), now);
+                true
+            }
+        }
+    }
+}
+
+/// Counts the errors that the OpenTelemetry SDK reports about itself, e.g. spans or logs that
+/// were dropped because the queue was full. The SDK reports these as tracing events instead of
+/// through an error handler. Failed exports are skipped because the exporters count those.
+/// The events are already logged by the fmt layer, so they are only counted here.
+struct SdkErrorCounter;
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SdkErrorCounter {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let name = event.metadata().name();
+        if let Some(signal) = sdk_error_signal(name) {
+            EXPORT_FAILURES.count_failure(signal);
+        }
+    }
+}
+
+/// The telemetry signal that an error event of the OpenTelemetry SDK is about, or `None` if
+/// the event is a failed export, which the exporters count themselves
+fn sdk_error_signal(event_name: &str) -> Option<&'static str> {
+    if event_name.contains("Export") {
+        return None;
+    }
+
+    let signal = if event_name.starts_with("BatchSpanProcessor")
+        || event_name.starts_with("TracerProvider")
+    {
+        "traces"
+    } else if event_name.starts_with("BatchLogProcessor")
+        || event_name.starts_with("SimpleLogProcessor")
+        || event_name.starts_with("LoggerProvider")
+    {
+        "logs"
+    } else if event_name.starts_with("PeriodicReader")
+        || event_name.starts_with("PeriodReader")
+        || event_name.starts_with("MeterProvider")
+        || event_name.starts_with("InstrumentCreationFailed")
+        || event_name.starts_with("ValueMap")
+    {
+        "metrics"
+    } else {
+        "other"
+    };
+
+    Some(signal)
+}
+
+/// The latest value of each sensor metric, per device, in a form that can be scraped by
+/// Prometheus.
+struct PrometheusMetrics {
+    registry: Registry,
+    gauges: std::sync::Mutex<std::collections::HashMap<String, GaugeVec>>,
+    histograms: std::sync::Mutex<std::collections::HashMap<String, HistogramVec>>,
+}
+
+impl PrometheusMetrics {
+    fn new() -> Self {
+        Self {
+            registry: Registry::new(),
+            gauges: std::sync::Mutex::new(std::collections::HashMap::new()),
+            histograms: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+
+    fn record(&self, name: &str, description: &str, device_id: &str, value: f64) {
+        let mut gauges = self
+            .gauges
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if !gauges.contains_key(name) {
+            let gauge = match GaugeVec::new(Opts::new(name, description), &["device_id"]) {
+                Ok(g) => g,
+                Err(e) => {
+                    error!("Failed to create the Prometheus gauge {}: {:?}", name, e);
+                    return;
+                }
+            };
+
+            if let Err(e) = self.registry.register(Box::new(gauge.clone())) {
+                error!("Failed to register the Prometheus gauge {}: {:?}", name, e);
+                return;
+            }
+
+            gauges.insert(name.to_string(), gauge);
+        }
+
+        if let Some(gauge) = gauges.get(name) {
+            gauge.with_label_values(&[device_id]).set(value);
+        }
+    }
+
+    fn observe(&self, name: &str, description: &str, buckets: &[f64], device_id: &str, value: f64) {
+        let mut histograms = self
+            .histograms
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if !histograms.contains_key(name) {
+            let histogram = match HistogramVec::new(
+                HistogramOpts::new(name, description).buckets(buckets.to_vec()),
+                &["device_id"],
+            ) {
+                Ok(h) => h,
+                Err(e) => {
+                    error!(
+                        "Failed to create the Prometheus histogram {}: {:?}",
+                        name, e
+                    );
+                    return;
+                }
+            };
+
+            if let Err(e) = self.registry.register(Box::new(histogram.clone())) {
+                error!(
+                    "Failed to register the Prometheus histogram {}: {:?}",
+                    name, e
+                );
+                return;
+            }
+
+            histograms.insert(name.to_string(), histogram);
+        }
+
+        if let Some(histogram) = histograms.get(name) {
+            histogram.with_label_values(&[device_id]).observe(value);
+        }
+    }
+
+    /// Remove the series of the device from the gauges and the histograms
+    fn remove_device(&self, device_id: &str) {
+        // Removing fails if the device has no series for the metric, which is fine
+        for gauge in self
+            .gauges
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values()
+        {
+            let _ = gauge.remove_label_values(&[device_id]);
+        }
+        for histogram in self
+            .histograms
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values()
+        {
+            let _ = histogram.remove_label_values(&[device_id]);
+        }
+    }
+
+    fn render(&self) -> Result<String, prometheus::Error> {
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
+
+/// The maximum length of a device ID
+const MAX_DEVICE_ID_LENGTH: usize = 64;
+
+/// The maximum length of the firmware version reported by a device
+const MAX_FIRMWARE_VERSION_LENGTH: usize = 32;
+
+/// The maximum number of tags a device may send
+const MAX_DEVICE_TAGS: usize = 8;
+
+/// The maximum length of a device tag key
+const MAX_TAG_KEY_LENGTH: usize = 32;
+
+/// The maximum length of a device tag value
+const MAX_TAG_VALUE_LENGTH: usize = 64;
+
+/// Check that the firmware version looks like `MAJOR.MINOR.PATCH` with an optional pre-release
+/// suffix, e.g. `1.2.3` or `1.2.3-beta.1`.
+fn is_valid_firmware_version(version: &str) -> bool {
+    let (core, pre_release) = match version.split_once('-') {
+        Some((core, pre_release)) => (core, Some(pre_release)),
+        None => (version, None),
+    };
+
+    let mut parts = 0;
+    for part in core.split('.') {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return false;
+        }
+        parts += 1;
+    }
+
+    if parts != 3 {
+        return false;
+    }
+
+    match pre_release {
+        Some(pre_release) => pre_release.split('.').all(|identifier| {
+            !identifier.is_empty()
+                && identifier
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        }),
+        None => true,
+    }
+}
+
+/// The reasons a device reports for booting
+const KNOWN_BOOT_REASONS: [&str; 8] = [
+    "power_on",
+    "timer_wake",
+    "gpio_wake",
+    "brownout",
+    "software_reset",
+    "watchdog",
+    "other",
+    "unknown",
+];
+
+/// The voltages measured on the ADC channels before any voltage divider calculations. Only sent
+/// by devices that are being calibrated.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct RawVoltages {
+    a0: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 struct SensorData {
     device_id: String,
     firmware_version: String,
     boot_count: u32,
+    /// The sequence number of the reading within the boot. Older firmware doesn't send it.
+    #[serde(default)]
+    reading_seq: Option<u32>,
     run_time_in_seconds: f64,
     wifi_start_time_in_seconds: f64,
+    #[serde(default)]
+    wifi_rssi_in_dbm: Option<i8>,
+    /// False if the device could not read its sensors and sent its last good reading instead.
+    /// Older firmware doesn't send it.
+    #[serde(default)]
+    sensor_ok: Option<bool>,
     temperature_in_celcius: f32,
     humidity_in_percent: f32,
     pressure_in_pascal: f32,
     brightness_in_percent: f32,
     battery_voltage: f32,
+    /// The charge of the battery estimated from the voltage. Older firmware doesn't send it.
+    #[serde(default)]
+    battery_in_percent: Option<f32>,
     pressure_sensor_voltage: f32,
+    #[serde(default)]
+    pressure_sensor_fault: Option<bool>,
     tank_level_in_meters: f32,
-    tank_temperature_in_celcius: f32,
+    tank_volume_in_liters: f32,
+    #[serde(default)]
+    tank_temperature_in_celcius: Option<f32>,
+    #[serde(default)]
+    sample_quality: Option<f32>,
+    #[serde(default)]
+    tank_fill_in_percent: Option<f32>,
+    #[serde(default)]
+    boot_reason: Option<String>,
+    /// True if the device lost power since the previous reading
+    #[serde(default)]
+    cold_boot: Option<bool>,
+    /// The random ID of the wake cycle, which the device also sends with its timing data and its
+    /// logs. Older firmware doesn't send it.
+    #[serde(default)]
+    session_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_voltages: Option<RawVoltages>,
+    /// Site or zone metadata that is configured on the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<std::collections::HashMap<String, String>>,
+    /// The quality of the reading as judged by the service. Set when the reading is stored, any
+    /// value sent by the device is replaced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data_quality: Option<DataQuality>,
 }
 
-impl SensorData {
-    fn validate(&self) -> Result<(), String> {
-        if self.boot_count < 1 {
-            return Err("The device boot count should at least be 1.".to_string());
-        }
+/// The quality of a reading as judged by the service
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DataQuality {
+    Ok,
+    /// The water level changed faster since the previous reading than is physically possible
+    ImplausibleLevelChange,
+}
+
+/// The reason a sensor data field was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum ValidationErrorCode {
+    /// A numeric value is outside the accepted range
+    OutOfRange,
+    /// A required value is empty
+    Empty,
+    /// A value is longer than allowed
+    TooLong,
+    /// A value doesn't have the expected format
+    InvalidFormat,
+    /// A value is not one of the known values
+    UnknownValue,
+}
+
+/// The reason sensor data was rejected
+#[derive(Debug, Clone, PartialEq)]
+struct ValidationError {
+    /// The name of the field as it is sent by the devices
+    field: &'static str,
+    code: ValidationErrorCode,
+    message: String,
+}
 
-        if self.run_time_in_seconds < 0.0 {
-            return Err("Run time out of reasonable range (> 0.0)".to_string());
+impl ValidationError {
+    fn new(field: &'static str, code: ValidationErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            code,
+            message: message.into(),
         }
+    }
+}
 
-        if self.wifi_start_time_in_seconds < 0.0 {
-            return Err("Wifi start time out of reasonable range (> 0.0)".to_string());
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The range of values that is accepted for a numeric sensor data field
+#[derive(Debug, Clone, Copy)]
+struct ValidationRange {
+    /// The name of the field as it is sent by the devices
+    field: &'static str,
+    /// The name of the field in the error message
+    label: &'static str,
+    /// The unit of the field in the error message
+    unit: &'static str,
+    /// The factor that converts the value of the field into the unit of the error message
+    unit_factor: f64,
+    /// The smallest accepted value
+    min: f64,
+    /// The largest accepted value. Infinite if the field has no upper limit.
+    max: f64,
+    /// The value of the field. `None` if the device didn't send the field.
+    value: fn(&SensorData) -> Option<f64>,
+}
+
+impl ValidationRange {
+    fn check(&self, sensor_data: &SensorData) -> Result<(), ValidationError> {
+        match (self.value)(sensor_data) {
+            Some(value) if !(self.min..=self.max).contains(&value) => Err(ValidationError::new(
+                self.field,
+                ValidationErrorCode::OutOfRange,
+                format!(
+                    "{} out of reasonable range ({})",
+                    self.label,
+                    self.describe()
+                ),
+            )),
+            _ => Ok(()),
         }
+    }
 
-        if self.temperature_in_celcius < -50.0 || self.temperature_in_celcius > 100.0 {
-            return Err("Temperature out of reasonable range (-50°C to 100°C)".to_string());
+    fn describe(&self) -> String {
+        if self.max.is_infinite() {
+            format!("{}{} or more", self.min * self.unit_factor, self.unit)
+        } else {
+            format!(
+                "{}{unit} to {}{unit}",
+                self.min * self.unit_factor,
+                self.max * self.unit_factor,
+                unit = self.unit
+            )
         }
+    }
+}
+
+/// The number of pascal in a hectopascal
+const PASCAL_PER_HECTOPASCAL: f64 = 100.0;
+
+/// The lowest accepted air pressure. Well below the pressure at the top of a high mountain.
+const MIN_AIR_PRESSURE_IN_PASCAL: f64 = 50.0e3;
+
+/// The highest accepted air pressure. Well above the standard atmospheric pressure of
+/// 101.325 kPa.
+const MAX_AIR_PRESSURE_IN_PASCAL: f64 = 150.0e3;
 
-        if self.humidity_in_percent < 0.0 || self.humidity_in_percent > 100.0 {
-            return Err("Humidity must be between 0% and 100%".to_string());
+/// The accepted ranges of the numeric sensor data fields, in the order in which they are checked
+const SENSOR_DATA_RANGES: [ValidationRange; 15] = [
+    ValidationRange {
+        field: "run_time_in_seconds",
+        label: "Run time",
+        unit: "s",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: f64::INFINITY,
+        value: |data| Some(data.run_time_in_seconds),
+    },
+    ValidationRange {
+        field: "wifi_start_time_in_seconds",
+        label: "Wifi start time",
+        unit: "s",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: f64::INFINITY,
+        value: |data| Some(data.wifi_start_time_in_seconds),
+    },
+    ValidationRange {
+        field: "wifi_rssi_in_dbm",
+        label: "Wifi signal strength",
+        unit: "dBm",
+        unit_factor: 1.0,
+        min: -100.0,
+        max: 0.0,
+        value: |data| data.wifi_rssi_in_dbm.map(f64::from),
+    },
+    ValidationRange {
+        field: "temperature_in_celcius",
+        label: "Temperature",
+        unit: "°C",
+        unit_factor: 1.0,
+        min: -50.0,
+        max: 100.0,
+        value: |data| Some(data.temperature_in_celcius.into()),
+    },
+    ValidationRange {
+        field: "humidity_in_percent",
+        label: "Humidity",
+        unit: "%",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 100.0,
+        value: |data| Some(data.humidity_in_percent.into()),
+    },
+    ValidationRange {
+        field: "pressure_in_pascal",
+        label: "Pressure",
+        unit: "hPa",
+        unit_factor: 1.0 / PASCAL_PER_HECTOPASCAL,
+        min: MIN_AIR_PRESSURE_IN_PASCAL,
+        max: MAX_AIR_PRESSURE_IN_PASCAL,
+        value: |data| Some(data.pressure_in_pascal.into()),
+    },
+    ValidationRange {
+        field: "brightness_in_percent",
+        label: "Enclosure brightness",
+        unit: "%",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 100.0,
+        value: |data| Some(data.brightness_in_percent.into()),
+    },
+    ValidationRange {
+        field: "battery_voltage",
+        label: "Battery voltage",
+        unit: "V",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 15.0,
+        value: |data| Some(data.battery_voltage.into()),
+    },
+    ValidationRange {
+        field: "battery_in_percent",
+        label: "Battery charge",
+        unit: "%",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 100.0,
+        value: |data| data.battery_in_percent.map(f64::from),
+    },
+    ValidationRange {
+        field: "pressure_sensor_voltage",
+        label: "Pressure sensor voltage",
+        unit: "V",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 32.0,
+        value: |data| Some(data.pressure_sensor_voltage.into()),
+    },
+    ValidationRange {
+        field: "tank_level_in_meters",
+        label: "Tank water level",
+        unit: "m",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 5.0,
+        value: |data| Some(data.tank_level_in_meters.into()),
+    },
+    ValidationRange {
+        field: "tank_volume_in_liters",
+        label: "Tank water volume",
+        unit: "L",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 100.0e3,
+        value: |data| Some(data.tank_volume_in_liters.into()),
+    },
+    ValidationRange {
+        field: "tank_temperature_in_celcius",
+        label: "Tank water temperature",
+        unit: "°C",
+        unit_factor: 1.0,
+        min: -50.0,
+        max: 100.0,
+        value: |data| data.tank_temperature_in_celcius.map(f64::from),
+    },
+    ValidationRange {
+        field: "sample_quality",
+        label: "Sample quality",
+        unit: "",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 1.0,
+        value: |data| data.sample_quality.map(f64::from),
+    },
+    ValidationRange {
+        field: "tank_fill_in_percent",
+        label: "Tank fill",
+        unit: "%",
+        unit_factor: 1.0,
+        min: 0.0,
+        max: 100.0,
+        value: |data| data.tank_fill_in_percent.map(f64::from),
+    },
+];
+
+/// Change the accepted ranges of the given fields. The overrides are formatted as
+/// `<field>=<min>:<max>`, separated by commas, e.g. `battery_voltage=0:30`.
+fn override_validation_ranges(
+    ranges: &[ValidationRange],
+    overrides: &str,
+) -> Result<Vec<ValidationRange>, String> {
+    let mut ranges = ranges.to_vec();
+    for range_override in overrides
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+    {
+        let (field, limits) = range_override
+            .split_once('=')
+            .ok_or_else(|| format!("The range override {} has no limits.", range_override))?;
+        let (min, max) = limits
+            .split_once(':')
+            .and_then(|(min, max)| Some((min.trim().parse().ok()?, max.trim().parse().ok()?)))
+            .ok_or_else(|| format!("The limits of the range override {} are invalid.", field))?;
+        if min > max {
+            return Err(format!(
+                "The minimum of the range override {} is larger than the maximum.",
+                field
+            ));
         }
 
-        if self.pressure_in_pascal < 50.0e3 || self.pressure_in_pascal > 150.0e3 {
-            return Err("Pressure out of reasonable range (500-1500 hPa)".to_string());
+        let range = ranges
+            .iter_mut()
+            .find(|range| range.field == field.trim())
+            .ok_or_else(|| format!("The field {} has no range.", field))?;
+        range.min = min;
+        range.max = max;
+    }
+
+    Ok(ranges)
+}
+
+impl SensorData {
+    /// Validate the sensor data with the default ranges for the numeric fields
+    #[cfg(test)]
+    fn validate(&self) -> Result<(), ValidationError> {
+        self.validate_with(&SENSOR_DATA_RANGES)
+    }
+
+    /// Validate the sensor data with the given ranges for the numeric fields
+    fn validate_with(&self, ranges: &[ValidationRange]) -> Result<(), ValidationError> {
+        validate_device_id(&self.device_id)?;
+
+        if self.boot_count < 1 {
+            return Err(ValidationError::new(
+                "boot_count",
+                ValidationErrorCode::OutOfRange,
+                "The device boot count should at least be 1.",
+            ));
         }
 
-        if self.brightness_in_percent < 0.0 || self.brightness_in_percent > 100.0 {
-            return Err("Enclosure brightness must be bewteen 0% and 100%".to_string());
+        if self.firmware_version.is_empty() {
+            return Err(ValidationError::new(
+                "firmware_version",
+                ValidationErrorCode::Empty,
+                "The firmware version should not be empty.",
+            ));
         }
 
-        if self.battery_voltage < 0.0 || self.battery_voltage > 15.0 {
-            return Err("Battery voltage out of reasonable range (0.0V to 15.0V)".to_string());
+        if self.firmware_version.len() > MAX_FIRMWARE_VERSION_LENGTH {
+            return Err(ValidationError::new(
+                "firmware_version",
+                ValidationErrorCode::TooLong,
+                format!(
+                    "The firmware version should be at most {} characters long.",
+                    MAX_FIRMWARE_VERSION_LENGTH
+                ),
+            ));
         }
 
-        if self.pressure_sensor_voltage < 0.0 || self.pressure_sensor_voltage > 32.0 {
-            return Err(
-                "Pressure sensor voltage out of reasonable range (0.0V to 32.0V)".to_string(),
-            );
+        if !is_valid_firmware_version(&self.firmware_version) {
+            return Err(ValidationError::new(
+                "firmware_version",
+                ValidationErrorCode::InvalidFormat,
+                "The firmware version should be formatted as MAJOR.MINOR.PATCH with an optional pre-release suffix.",
+            ));
         }
 
-        if self.tank_level_in_meters < 0.0 || self.tank_level_in_meters > 5.0 {
-            return Err("Tank water level out of reasonable range (0.0m to 5.0m)".to_string());
+        ranges.iter().try_for_each(|range| range.check(self))?;
+
+        if let Some(tags) = &self.tags {
+            validate_tags(tags)?;
         }
 
-        if self.tank_temperature_in_celcius < -50.0 || self.tank_temperature_in_celcius > 100.0 {
-            return Err(
-                "Tank water temperature out of reasonable range (-50°C to 100°C)".to_string(),
-            );
+        if let Some(boot_reason) = &self.boot_reason {
+            if !KNOWN_BOOT_REASONS.contains(&boot_reason.as_str()) {
+                return Err(ValidationError::new(
+                    "boot_reason",
+                    ValidationErrorCode::UnknownValue,
+                    format!(
+                        "The boot reason should be one of: {}.",
+                        KNOWN_BOOT_REASONS.join(", ")
+                    ),
+                ));
+            }
         }
 
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ApiResponse {
-    status: String,
-    timestamp: String,
-    message: String,
+/// Check that the device ID is not empty, not too long and only contains letters, digits, `.`,
+/// `-` and `_`. The device ID is used as a metric attribute and as a key for the device state.
+fn validate_device_id(device_id: &str) -> Result<(), ValidationError> {
+    if device_id.is_empty() {
+        return Err(ValidationError::new(
+            "device_id",
+            ValidationErrorCode::Empty,
+            "The device ID should not be empty.",
+        ));
+    }
+
+    if device_id.len() > MAX_DEVICE_ID_LENGTH {
+        return Err(ValidationError::new(
+            "device_id",
+            ValidationErrorCode::TooLong,
+            format!(
+                "The device ID should be at most {} characters long.",
+                MAX_DEVICE_ID_LENGTH
+            ),
+        ));
+    }
+
+    if !device_id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-' || b == b'_')
+    {
+        return Err(ValidationError::new(
+            "device_id",
+            ValidationErrorCode::InvalidFormat,
+            "The device ID should only contain letters, digits, '.', '-' and '_'.",
+        ));
+    }
+
+    Ok(())
 }
 
-impl ApiResponse {
-    fn success(message: impl Into<String>) -> Self {
-        Self {
-            status: "success".to_string(),
-            timestamp: Utc::now().to_rfc3339(),
-            message: message.into(),
+/// Check the number of device tags and the length of their keys and values
+fn validate_tags(tags: &std::collections::HashMap<String, String>) -> Result<(), ValidationError> {
+    if tags.len() > MAX_DEVICE_TAGS {
+        return Err(ValidationError::new(
+            "tags",
+            ValidationErrorCode::TooLong,
+            format!("A device should send at most {} tags.", MAX_DEVICE_TAGS),
+        ));
+    }
+
+    for (key, value) in tags {
+        if key.is_empty() {
+            return Err(ValidationError::new(
+                "tags",
+                ValidationErrorCode::Empty,
+                "The device tag keys should not be empty.",
+            ));
+        }
+
+        if key.len() > MAX_TAG_KEY_LENGTH {
+            return Err(ValidationError::new(
+                "tags",
+                ValidationErrorCode::TooLong,
+                format!(
+                    "The device tag keys should be at most {} characters long.",
+                    MAX_TAG_KEY_LENGTH
+                ),
+            ));
+        }
+
+        if value.len() > MAX_TAG_VALUE_LENGTH {
+            return Err(ValidationError::new(
+                "tags",
+                ValidationErrorCode::TooLong,
+                format!(
+                    "The device tag values should be at most {} characters long.",
+                    MAX_TAG_VALUE_LENGTH
+                ),
+            ));
         }
     }
 
-    fn error(message: impl Into<String>) -> Self {
-        Self {
-            status: "error".to_string(),
-            timestamp: Utc::now().to_rfc3339(),
-            message: message.into(),
+    Ok(())
+}
+
+/// The number of liters in a cubic meter
+const LITERS_PER_CUBIC_METER: f32 = 1000.0;
+
+/// The number of meters in a foot
+const METERS_PER_FOOT: f32 = 0.3048;
+
+/// The number of liters in a US gallon
+const LITERS_PER_GALLON: f32 = 3.785_411_8;
+
+fn meters_to_feet(meters: f32) -> f32 {
+    meters / METERS_PER_FOOT
+}
+
+/// Convert liters to US gallons
+fn liters_to_gallons(liters: f32) -> f32 {
+    liters / LITERS_PER_GALLON
+}
+
+/// The units in which the read endpoints return the water level and the volume. The readings
+/// are always stored in SI units.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Units {
+    /// Meters and liters
+    Metric,
+    /// Feet and US gallons
+    Imperial,
+}
+
+impl Units {
+    /// Parse the name of the units. Returns `None` if the units aren't known.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "metric" => Some(Self::Metric),
+            "imperial" => Some(Self::Imperial),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct LogData {
-    device_id: String,
-    level: String,
-    message: String,
-    boot_count: u32,
-    timestamp: u64,
+/// The query parameter that selects the units of a read endpoint
+#[derive(Debug, Deserialize)]
+struct UnitsQuery {
+    /// `metric` or `imperial`, `metric` if not set
+    units: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct DeviceTimingData {
-    device_id: String,
-    boot_count: u32,
-    timestamp: u64,
+/// Replace the water level and the volume of a serialized reading with the value in feet and in
+/// US gallons. The names of the fields carry the unit.
+fn convert_reading_to_imperial(reading: &mut serde_json::Map<String, serde_json::Value>) {
+    if let Some(level) = reading
+        .remove("tank_level_in_meters")
+        .and_then(|level| level.as_f64())
+    {
+        reading.insert(
+            "tank_level_in_feet".to_string(),
+            serde_json::json!(meters_to_feet(level as f32)),
+        );
+    }
+
+    if let Some(volume) = reading
+        .remove("tank_volume_in_liters")
+        .and_then(|volume| volume.as_f64())
+    {
+        reading.insert(
+            "tank_volume_in_gallons".to_string(),
+            serde_json::json!(liters_to_gallons(volume as f32)),
+        );
+    }
 }
 
-#[derive(Debug, Clone)]
-struct DeviceTimeMapping {
-    boot_count: u32,
-    first_tick: u64,
-    first_timestamp: chrono::DateTime<chrono::Utc>,
+/// The cross-section of a tank
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TankShape {
+    /// A vertical cylinder
+    Cylinder { radius_in_meters: f32 },
+
+    /// A rectangular box
+    Rectangular {
+        width_in_meters: f32,
+        length_in_meters: f32,
+    },
 }
 
-#[derive(Clone)]
-struct ObservabilityConfig {
-    metrics_push_url: String,
-    trace_push_url: String,
-    logs_push_url: String,
+impl TankShape {
+    /// The horizontal cross-section area of the tank in square meters
+    fn cross_section_in_square_meters(&self) -> f32 {
+        match *self {
+            Self::Cylinder { radius_in_meters } => {
+                std::f32::consts::PI * radius_in_meters * radius_in_meters
+            }
+            Self::Rectangular {
+                width_in_meters,
+                length_in_meters,
+            } => width_in_meters * length_in_meters,
+        }
+    }
 }
 
-#[derive(Clone)]
-struct AppState {
-    device_time_mappings:
-        std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, DeviceTimeMapping>>>,
+/// The water levels, as a percentage of a full tank, and the enclosure humidity at which an
+/// alert should be raised
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+struct AlertThresholds {
+    #[serde(default)]
+    low_level_in_percent: Option<f32>,
+    #[serde(default)]
+    high_level_in_percent: Option<f32>,
+    /// The enclosure humidity above which condensation is likely. Uses
+    /// `DEFAULT_CONDENSATION_HUMIDITY_THRESHOLD_IN_PERCENT` if not set.
+    #[serde(default)]
+    condensation_humidity_in_percent: Option<f32>,
 }
 
-impl AppState {
-    fn new() -> Self {
-        Self {
-            device_time_mappings: std::sync::Arc::new(tokio::sync::RwLock::new(
-                std::collections::HashMap::new(),
-            )),
+/// The configuration of the tank that a device is measuring
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct TankConfig {
+    shape: TankShape,
+    max_height_in_meters: f32,
+    #[serde(default)]
+    alert_thresholds: AlertThresholds,
+    /// The unix time, in seconds, until which the tank is in maintenance. No alerts are raised
+    /// and the device is asked to skip the uploads it can do without until then.
+    #[serde(default)]
+    quiet_until_in_seconds: Option<i64>,
+}
+
+impl TankConfig {
+    fn validate(&self) -> Result<(), String> {
+        let is_valid_dimension = |value: f32| value.is_finite() && value > 0.0;
+
+        let dimensions_are_valid = match self.shape {
+            TankShape::Cylinder { radius_in_meters } => is_valid_dimension(radius_in_meters),
+            TankShape::Rectangular {
+                width_in_meters,
+                length_in_meters,
+            } => is_valid_dimension(width_in_meters) && is_valid_dimension(length_in_meters),
+        };
+        if !dimensions_are_valid {
+            return Err("The tank dimensions should be larger than 0 m.".to_string());
+        }
+
+        if !is_valid_dimension(self.max_height_in_meters) {
+            return Err("The maximum tank height should be larger than 0 m.".to_string());
+        }
+
+        let thresholds = &self.alert_thresholds;
+        for threshold in [
+            thresholds.low_level_in_percent,
+            thresholds.high_level_in_percent,
+            thresholds.condensation_humidity_in_percent,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !(0.0..=100.0).contains(&threshold) {
+                return Err("Alert threshold out of reasonable range (0% to 100%)".to_string());
+            }
+        }
+
+        if let (Some(low), Some(high)) = (
+            thresholds.low_level_in_percent,
+            thresholds.high_level_in_percent,
+        ) {
+            if low >= high {
+                return Err(
+                    "The low level alert threshold should be below the high level alert threshold."
+                        .to_string(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Determine if the tank is in maintenance at the given time
+    fn is_quiet(&self, now: chrono::DateTime<Utc>) -> bool {
+        self.quiet_until_in_seconds
+            .is_some_and(|quiet_until| now.timestamp() < quiet_until)
+    }
+
+    /// Calculate the fill percentage and volume of the tank from the measured water level
+    fn apply(&self, sensor_data: &mut SensorData) {
+        let level = sensor_data.tank_level_in_meters.max(0.0);
+        sensor_data.tank_fill_in_percent =
+            Some((level / self.max_height_in_meters * 100.0).clamp(0.0, 100.0));
+        sensor_data.tank_volume_in_liters =
+            self.shape.cross_section_in_square_meters() * level * LITERS_PER_CUBIC_METER;
+    }
+}
+
+/// The comparison of an alert rule
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+enum AlertOperator {
+    #[serde(rename = ">")]
+    GreaterThan,
+    #[serde(rename = ">=")]
+    GreaterThanOrEqual,
+    #[serde(rename = "<")]
+    LessThan,
+    #[serde(rename = "<=")]
+    LessThanOrEqual,
+    #[serde(rename = "==")]
+    Equal,
+    #[serde(rename = "!=")]
+    NotEqual,
+}
+
+/// A rule that raises an alert while a metric of a device crosses a threshold
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct AlertRule {
+    /// The name of the metric, as used by the history endpoint. The threshold is in the SI unit
+    /// of the metric.
+    metric: String,
+    operator: AlertOperator,
+    threshold: f32,
+}
+
+/// The alert rules of a device
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+struct AlertRules {
+    rules: Vec<AlertRule>,
+}
+
+impl AlertRules {
+    fn validate(&self) -> Result<(), String> {
+        if self.rules.len() > MAX_ALERT_RULES {
+            return Err(format!(
+                "Too many alert rules. At most {MAX_ALERT_RULES} rules are allowed."
+            ));
+        }
+
+        for rule in &self.rules {
+            if HistoryMetric::parse(&rule.metric).is_none() {
+                return Err(format!("Unknown metric in alert rule: {}", rule.metric));
+            }
+
+            if !rule.threshold.is_finite() {
+                return Err(format!(
+                    "The threshold of the alert rule for {} should be a finite number.",
+                    rule.metric
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The rules that the reading triggers. Rules for a metric that the device didn't report
+    /// are not triggered.
+    fn evaluate(&self, point: &HistoryPoint) -> Vec<TriggeredAlert> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let value = HistoryMetric::parse(&rule.metric)?.value(point)?;
+                eval_rule(value, rule.operator, rule.threshold).then(|| TriggeredAlert {
+                    metric: rule.metric.clone(),
+                    operator: rule.operator,
+                    threshold: rule.threshold,
+                    value,
+                })
+            })
+            .collect()
+    }
+}
+
+/// An alert rule that the latest reading of a device triggered
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct TriggeredAlert {
+    metric: String,
+    operator: AlertOperator,
+    threshold: f32,
+    /// The value of the metric in the reading
+    value: f32,
+}
+
+/// Identifies an alert rule by its metric, operator and threshold
+type AlertRuleKey = (String, AlertOperator, u32);
+
+impl TriggeredAlert {
+    /// Identifies the rule that triggered the alert
+    fn rule_key(&self) -> AlertRuleKey {
+        (self.metric.clone(), self.operator, self.threshold.to_bits())
+    }
+}
+
+/// The alerts that are triggered now but weren't triggered by the previous reading
+fn newly_triggered_alerts(
+    previous: &[TriggeredAlert],
+    current: &[TriggeredAlert],
+) -> Vec<TriggeredAlert> {
+    current
+        .iter()
+        .filter(|alert| {
+            !previous
+                .iter()
+                .any(|previous| previous.rule_key() == alert.rule_key())
+        })
+        .cloned()
+        .collect()
+}
+
+/// The notification that is posted to the alert webhook
+#[derive(Debug, Serialize)]
+struct AlertNotification {
+    device_id: String,
+    #[serde(flatten)]
+    alert: TriggeredAlert,
+    /// The time, in RFC 3339 format, at which the alert triggered
+    triggered_at: String,
+}
+
+/// Posts a notification to a webhook when an alert rule of a device triggers
+///
+/// Only the transition from not triggered to triggered is notified. An alert that triggers
+/// again within the minimum interval of its last notification, e.g. because the value flaps
+/// around the threshold, isn't notified again. The notifications are posted in the background
+/// so that a slow or failing webhook doesn't hold up the sensor data.
+#[derive(Debug, Clone)]
+struct AlertWebhook {
+    url: String,
+    client: reqwest::Client,
+    min_interval: std::time::Duration,
+    /// The time of the last notification for each device and rule
+    last_notified: std::sync::Arc<
+        std::sync::Mutex<std::collections::HashMap<(String, AlertRuleKey), std::time::Instant>>,
+    >,
+}
+
+impl AlertWebhook {
+    fn new(url: String) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(ALERT_WEBHOOK_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            url,
+            client,
+            min_interval: DEFAULT_ALERT_WEBHOOK_MIN_INTERVAL,
+            last_notified: std::sync::Arc::new(std::sync::Mutex::new(
+                std::collections::HashMap::new(),
+            )),
+        })
+    }
+
+    /// Don't notify an alert again until the given duration has passed since its last
+    /// notification
+    fn with_min_interval(mut self, min_interval: std::time::Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    /// Record a notification for the alert. Returns `false` if the alert was notified less than
+    /// the minimum interval ago.
+    fn try_notify(&self, device_id: &str, alert: &TriggeredAlert, now: std::time::Instant) -> bool {
+        let mut last_notified = self
+            .last_notified
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        // Notifications older than the minimum interval don't hold back a new one
+        last_notified.retain(|_, notified_at| now.duration_since(*notified_at) < self.min_interval);
+
+        let key = (device_id.to_string(), alert.rule_key());
+        if last_notified.contains_key(&key) {
+            return false;
+        }
+
+        last_notified.insert(key, now);
+        true
+    }
+
+    /// Post a notification for each alert that the device triggered, in the background
+    fn notify(&self, device_id: &str, alerts: Vec<TriggeredAlert>) {
+        let now = std::time::Instant::now();
+        for alert in alerts {
+            if !self.try_notify(device_id, &alert, now) {
+                debug!(
+                    device_id = %device_id,
+                    metric = %alert.metric,
+                    "Alert triggered again within the minimum interval. Not notifying."
+                );
+                continue;
+            }
+
+            let notification = AlertNotification {
+                device_id: device_id.to_string(),
+                alert,
+                triggered_at: Utc::now().to_rfc3339(),
+            };
+            let body = match serde_json::to_vec(&notification) {
+                Ok(body) => body,
+                Err(e) => {
+                    error!(error = %e, "Failed to serialize the alert notification");
+                    continue;
+                }
+            };
+
+            let request = self
+                .client
+                .post(&self.url)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body);
+            let device_id = device_id.to_string();
+            tokio::spawn(async move {
+                match request.send().await {
+                    Ok(response) if response.status().is_success() => {
+                        debug!(device_id = %device_id, "Alert notification sent");
+                    }
+                    Ok(response) => {
+                        tracing::warn!(
+                            device_id = %device_id,
+                            status = %response.status(),
+                            "The alert webhook rejected the notification"
+                        );
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            device_id = %device_id,
+                            error = %e,
+                            "Failed to send the alert notification"
+                        );
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// The alert rules of a device and the alerts that its latest reading triggered
+#[derive(Debug, Serialize)]
+struct DeviceAlerts {
+    rules: Vec<AlertRule>,
+    triggered_alerts: Vec<TriggeredAlert>,
+}
+
+/// A sensor reading together with the time at which it was received
+#[derive(Debug, Clone)]
+struct SensorReading {
+    received_at: chrono::DateTime<Utc>,
+    data: SensorData,
+}
+
+/// Whether a device has sent data recently
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DeviceStatus {
+    Online,
+    Offline,
+}
+
+impl DeviceStatus {
+    /// The status of a device that was last seen at the given time. The device is offline if
+    /// it hasn't sent data for more than `offline_after_in_seconds`.
+    fn from_last_seen(
+        last_seen: &chrono::DateTime<Utc>,
+        now: &chrono::DateTime<Utc>,
+        offline_after_in_seconds: i64,
+    ) -> Self {
+        if (*now - *last_seen).num_seconds() > offline_after_in_seconds {
+            DeviceStatus::Offline
+        } else {
+            DeviceStatus::Online
+        }
+    }
+}
+
+/// A device the service has received data from, as returned by the devices endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct DeviceSummary {
+    device_id: String,
+    /// The time the latest sensor data of the device was received as an RFC 3339 timestamp
+    last_seen: String,
+    boot_count: u32,
+    status: DeviceStatus,
+}
+
+/// The devices the service has received data from
+#[derive(Debug, Serialize, Deserialize)]
+struct DeviceList {
+    devices: Vec<DeviceSummary>,
+}
+
+/// The latest sensor data for a device, as returned by the sensor data endpoint
+#[derive(Debug, Serialize)]
+struct LatestSensorData {
+    #[serde(flatten)]
+    data: SensorData,
+    leak_suspected: bool,
+    condensation_risk: bool,
+    /// The alert rules that the reading triggered
+    triggered_alerts: Vec<TriggeredAlert>,
+    /// The units of the water level and the volume
+    units: Units,
+}
+
+/// Settings for the leak detection heuristic
+#[derive(Debug, Clone)]
+struct LeakDetectionSettings {
+    /// The number of consecutive readings that must show the level dropping
+    readings: usize,
+
+    /// The slowest average drop in water level that is considered a leak
+    minimum_drop_rate_in_meters_per_hour: f32,
+
+    /// The largest drop between two readings that is still considered a leak. Larger drops are
+    /// assumed to be water being used.
+    maximum_drop_between_readings_in_meters: f32,
+
+    /// The start of the night, in UTC hours. Little water is expected to be used at night.
+    night_start_hour: u32,
+
+    /// The end of the night, in UTC hours
+    night_end_hour: u32,
+}
+
+impl Default for LeakDetectionSettings {
+    fn default() -> Self {
+        Self {
+            readings: 6,
+            minimum_drop_rate_in_meters_per_hour: 0.005,
+            maximum_drop_between_readings_in_meters: 0.02,
+            night_start_hour: 0,
+            night_end_hour: 5,
+        }
+    }
+}
+
+/// Settings for the check that the water level doesn't change faster than is physically possible
+#[derive(Debug, Clone)]
+struct LevelPlausibilitySettings {
+    /// The fastest change in water level, in either direction, that is plausible. `None` turns
+    /// the check off.
+    maximum_change_rate_in_meters_per_hour: Option<f32>,
+
+    /// The change in water level between two readings that is always plausible, however little
+    /// time passed between them. Covers the noise of the pressure sensor.
+    tolerance_in_meters: f32,
+}
+
+impl Default for LevelPlausibilitySettings {
+    fn default() -> Self {
+        Self {
+            maximum_change_rate_in_meters_per_hour: Some(1.0),
+            tolerance_in_meters: 0.05,
+        }
+    }
+}
+
+impl LeakDetectionSettings {
+    fn is_night(&self, time: &chrono::DateTime<Utc>) -> bool {
+        let hour = time.hour();
+        if self.night_start_hour <= self.night_end_hour {
+            hour >= self.night_start_hour && hour < self.night_end_hour
+        } else {
+            hour >= self.night_start_hour || hour < self.night_end_hour
+        }
+    }
+}
+
+/// The tokens available to a single device
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: std::time::Instant,
+}
+
+/// Limits the number of requests each device can make with a token bucket per device
+///
+/// Every request takes a token from the bucket of the device. The bucket is refilled at a fixed
+/// rate up to the burst size. Buckets of devices that haven't made a request for a while are
+/// removed so that the number of buckets doesn't grow without bound.
+#[derive(Debug, Clone)]
+struct DeviceRateLimiter {
+    requests_per_minute: u32,
+    burst: u32,
+    idle_timeout: std::time::Duration,
+    buckets: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, TokenBucket>>>,
+}
+
+impl DeviceRateLimiter {
+    fn new(requests_per_minute: u32, burst: u32) -> Self {
+        Self {
+            requests_per_minute,
+            burst: burst.max(1),
+            idle_timeout: DEFAULT_RATE_LIMIT_IDLE_TIMEOUT,
+            buckets: std::sync::Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
+        }
+    }
+
+    /// Remove the bucket of a device once it hasn't made a request for the given duration
+    fn with_idle_timeout(mut self, idle_timeout: std::time::Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Take a token for the device. Returns `false` if the device has no tokens left.
+    fn try_acquire(&self, device_id: &str, now: std::time::Instant) -> bool {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        buckets.retain(|_, bucket| now.duration_since(bucket.last_refill) < self.idle_timeout);
+
+        let burst = self.burst as f64;
+        let bucket = buckets.entry(device_id.to_string()).or_insert(TokenBucket {
+            tokens: burst,
+            last_refill: now,
+        });
+
+        let elapsed_minutes = now.duration_since(bucket.last_refill).as_secs_f64() / 60.0;
+        bucket.tokens =
+            (bucket.tokens + elapsed_minutes * self.requests_per_minute as f64).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The number of devices that are being tracked
+    #[cfg(test)]
+    fn tracked_devices(&self) -> usize {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .len()
+    }
+}
+
+/// Determine if the tank is likely leaking based on the most recent readings, oldest first
+///
+/// A leak shows up as a slow but steady drop in the water level at night, when no water is
+/// expected to be used. A sharp drop between two readings is treated as water being used.
+fn detect_leak(history: &[SensorReading], settings: &LeakDetectionSettings) -> bool {
+    if settings.readings < 2 || history.len() < settings.readings {
+        return false;
+    }
+
+    let recent = &history[history.len() - settings.readings..];
+    if !recent
+        .iter()
+        .all(|reading| settings.is_night(&reading.received_at))
+    {
+        return false;
+    }
+
+    for pair in recent.windows(2) {
+        let drop = pair[0].data.tank_level_in_meters - pair[1].data.tank_level_in_meters;
+        if drop <= 0.0 || drop > settings.maximum_drop_between_readings_in_meters {
+            return false;
+        }
+    }
+
+    let first = &recent[0];
+    let last = &recent[recent.len() - 1];
+    let elapsed_hours = (last.received_at - first.received_at).num_seconds() as f32 / 3600.0;
+    if elapsed_hours <= 0.0 {
+        return false;
+    }
+
+    let drop_rate =
+        (first.data.tank_level_in_meters - last.data.tank_level_in_meters) / elapsed_hours;
+    drop_rate >= settings.minimum_drop_rate_in_meters_per_hour
+}
+
+/// Determine if the water level could have changed from the previous reading to the current
+/// one in the time between them. The change can't be judged if the pressure sensor was
+/// disconnected for either reading, in which case the change is plausible.
+fn is_plausible_level_change(
+    previous: &SensorReading,
+    current: &SensorReading,
+    settings: &LevelPlausibilitySettings,
+) -> bool {
+    let Some(maximum_rate) = settings.maximum_change_rate_in_meters_per_hour else {
+        return true;
+    };
+
+    if previous.data.pressure_sensor_fault == Some(true)
+        || current.data.pressure_sensor_fault == Some(true)
+    {
+        return true;
+    }
+
+    let elapsed_hours = (current.received_at - previous.received_at)
+        .num_seconds()
+        .max(0) as f32
+        / 3600.0;
+    let change = (current.data.tank_level_in_meters - previous.data.tank_level_in_meters).abs();
+    change <= settings.tolerance_in_meters + maximum_rate * elapsed_hours
+}
+
+/// The dew point, in degrees Celcius, for the given air temperature and relative humidity
+///
+/// Uses the Magnus formula, which is accurate to about 0.35 °C between -45 °C and 60 °C.
+fn dew_point_c(temperature_in_celcius: f32, humidity_in_percent: f32) -> f32 {
+    const A: f32 = 17.62;
+    const B: f32 = 243.12;
+
+    // The formula is undefined for a relative humidity of 0%
+    let humidity = humidity_in_percent.clamp(0.1, 100.0) / 100.0;
+    let gamma = humidity.ln() + A * temperature_in_celcius / (B + temperature_in_celcius);
+    B * gamma / (A - gamma)
+}
+
+/// Determine if condensation is likely in the enclosure. This is the case if the humidity is
+/// above the threshold and the temperature is close to the dew point.
+fn condensation_risk(
+    temperature_in_celcius: f32,
+    humidity_in_percent: f32,
+    threshold_in_percent: f32,
+) -> bool {
+    humidity_in_percent > threshold_in_percent
+        && temperature_in_celcius - dew_point_c(temperature_in_celcius, humidity_in_percent)
+            <= CONDENSATION_DEW_POINT_MARGIN_IN_CELCIUS
+}
+
+/// Determine if a value crosses the threshold of an alert rule. A value that isn't a number
+/// never triggers an alert.
+fn eval_rule(value: f32, operator: AlertOperator, threshold: f32) -> bool {
+    if value.is_nan() {
+        return false;
+    }
+
+    match operator {
+        AlertOperator::GreaterThan => value > threshold,
+        AlertOperator::GreaterThanOrEqual => value >= threshold,
+        AlertOperator::LessThan => value < threshold,
+        AlertOperator::LessThanOrEqual => value <= threshold,
+        AlertOperator::Equal => value == threshold,
+        AlertOperator::NotEqual => value != threshold,
+    }
+}
+
+/// The values of a sensor reading that are kept for the history endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HistoryPoint {
+    received_at: chrono::DateTime<Utc>,
+    tank_level_in_meters: f32,
+    tank_fill_in_percent: Option<f32>,
+    tank_volume_in_liters: f32,
+    tank_temperature_in_celcius: Option<f32>,
+    temperature_in_celcius: f32,
+    battery_voltage: f32,
+}
+
+impl HistoryPoint {
+    fn new(received_at: chrono::DateTime<Utc>, sensor_data: &SensorData) -> Self {
+        Self {
+            received_at,
+            tank_level_in_meters: sensor_data.tank_level_in_meters,
+            tank_fill_in_percent: sensor_data.tank_fill_in_percent,
+            tank_volume_in_liters: sensor_data.tank_volume_in_liters,
+            tank_temperature_in_celcius: sensor_data.tank_temperature_in_celcius,
+            temperature_in_celcius: sensor_data.temperature_in_celcius,
+            battery_voltage: sensor_data.battery_voltage,
+        }
+    }
+}
+
+/// The metrics that can be read from the history endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HistoryMetric {
+    WaterLevel,
+    TankFill,
+    TankVolume,
+    TankTemperature,
+    Temperature,
+    BatteryVoltage,
+}
+
+impl HistoryMetric {
+    /// Parse the name of a metric. Returns `None` if the metric isn't known.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "water_level" => Some(Self::WaterLevel),
+            "tank_fill" => Some(Self::TankFill),
+            "tank_volume" => Some(Self::TankVolume),
+            "tank_temperature" => Some(Self::TankTemperature),
+            "temperature" => Some(Self::Temperature),
+            "battery_voltage" => Some(Self::BatteryVoltage),
+            _ => None,
+        }
+    }
+
+    /// The unit of the values of the metric
+    fn unit(&self, units: Units) -> &'static str {
+        match (self, units) {
+            (Self::WaterLevel, Units::Metric) => "m",
+            (Self::WaterLevel, Units::Imperial) => "ft",
+            (Self::TankFill, _) => "%",
+            (Self::TankVolume, Units::Metric) => "L",
+            (Self::TankVolume, Units::Imperial) => "gal",
+            (Self::TankTemperature, _) | (Self::Temperature, _) => "C",
+            (Self::BatteryVoltage, _) => "V",
+        }
+    }
+
+    /// Convert a value of the metric from the SI unit in which it is stored
+    fn convert(&self, value: f32, units: Units) -> f32 {
+        match (self, units) {
+            (Self::WaterLevel, Units::Imperial) => meters_to_feet(value),
+            (Self::TankVolume, Units::Imperial) => liters_to_gallons(value),
+            _ => value,
+        }
+    }
+
+    /// The value of the metric in the given point, if the device reported it
+    fn value(&self, point: &HistoryPoint) -> Option<f32> {
+        match self {
+            Self::WaterLevel => Some(point.tank_level_in_meters),
+            Self::TankFill => point.tank_fill_in_percent,
+            Self::TankVolume => Some(point.tank_volume_in_liters),
+            Self::TankTemperature => point.tank_temperature_in_celcius,
+            Self::Temperature => Some(point.temperature_in_celcius),
+            Self::BatteryVoltage => Some(point.battery_voltage),
+        }
+    }
+}
+
+/// Keeps the recent readings of each device in memory so that short term trends can be shown
+/// without a time series database
+///
+/// Readings older than the retention period are removed. Each device keeps at most
+/// `points_per_device` readings and all devices together keep at most `max_points` readings.
+/// Once a limit is reached the oldest readings are removed first.
+#[derive(Debug, Clone)]
+struct MetricHistory {
+    points_per_device: usize,
+    max_points: usize,
+    points: std::sync::Arc<
+        std::sync::Mutex<
+            std::collections::HashMap<String, std::collections::VecDeque<HistoryPoint>>,
+        >,
+    >,
+}
+
+impl MetricHistory {
+    fn new(points_per_device: usize, max_points: usize) -> Self {
+        Self {
+            points_per_device,
+            max_points,
+            points: std::sync::Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
+        }
+    }
+
+    /// Add a reading for the device and remove the readings that no longer fit
+    fn record(&self, device_id: &str, point: HistoryPoint) {
+        let mut points = self
+            .points
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let oldest_kept =
+            point.received_at - chrono::Duration::seconds(HISTORY_RETENTION_IN_SECONDS);
+        for device_points in points.values_mut() {
+            while device_points
+                .front()
+                .is_some_and(|p| p.received_at < oldest_kept)
+            {
+                device_points.pop_front();
+            }
+        }
+
+        let device_points = points.entry(device_id.to_string()).or_default();
+        device_points.push_back(point);
+        while device_points.len() > self.points_per_device {
+            device_points.pop_front();
+        }
+
+        let mut total: usize = points.values().map(|p| p.len()).sum();
+        while total > self.max_points {
+            let oldest_device = points
+                .iter()
+                .filter_map(|(id, p)| p.front().map(|point| (id, point.received_at)))
+                .min_by_key(|(_, received_at)| *received_at)
+                .map(|(id, _)| id.clone());
+            match oldest_device.and_then(|id| points.get_mut(&id)) {
+                Some(device_points) => {
+                    device_points.pop_front();
+                    total -= 1;
+                }
+                None => break,
+            }
+        }
+
+        points.retain(|_, p| !p.is_empty());
+    }
+
+    /// Remove the readings of the given devices
+    fn remove_devices(&self, device_ids: &std::collections::HashSet<String>) {
+        self.points
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|device_id, _| !device_ids.contains(device_id));
+    }
+
+    /// The readings of the device, oldest first. `None` if there are no readings for the device.
+    fn points(&self, device_id: &str) -> Option<Vec<HistoryPoint>> {
+        self.points
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(device_id)
+            .map(|p| p.iter().copied().collect())
+    }
+
+    /// The number of readings that are kept for all devices together
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.points
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values()
+            .map(|p| p.len())
+            .sum()
+    }
+}
+
+/// The query parameters for reading the history of a device
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    /// The metric to return, `water_level` if not set
+    metric: Option<String>,
+    /// The width of the buckets, e.g. `15m` or `1h`. One hour if not set.
+    resolution: Option<String>,
+    /// `metric` or `imperial`, `metric` if not set
+    units: Option<String>,
+}
+
+/// The summary of the values of a metric within a single time bucket
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct HistoryBucket {
+    /// The start of the bucket as an RFC 3339 timestamp
+    start: String,
+    min: f32,
+    avg: f32,
+    max: f32,
+    /// The number of readings in the bucket
+    count: usize,
+}
+
+/// The downsampled history of a metric for a device
+#[derive(Debug, Serialize, Deserialize)]
+struct DeviceHistory {
+    device_id: String,
+    metric: String,
+    /// The unit of the values in the buckets
+    unit: String,
+    resolution_in_seconds: i64,
+    buckets: Vec<HistoryBucket>,
+}
+
+/// Parse a bucket width such as `30s`, `15m` or `1h` into seconds. Returns `None` if the width
+/// is not valid, is zero or is longer than the retention period.
+fn parse_resolution(resolution: &str) -> Option<i64> {
+    let (amount, unit) = resolution.split_at(resolution.len().checked_sub(1)?);
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => return None,
+    };
+
+    let seconds = amount.parse::<i64>().ok()?.checked_mul(multiplier)?;
+    if seconds <= 0 || seconds > HISTORY_RETENTION_IN_SECONDS {
+        None
+    } else {
+        Some(seconds)
+    }
+}
+
+/// Group the readings, oldest first, into buckets of the given width and summarize the metric in
+/// each bucket. Buckets are aligned to the unix epoch. Buckets without values are left out.
+fn downsample(
+    points: &[HistoryPoint],
+    metric: HistoryMetric,
+    resolution_in_seconds: i64,
+) -> Vec<HistoryBucket> {
+    let mut buckets: Vec<(i64, HistoryBucket, f64)> = Vec::new();
+    for point in points {
+        let value = match metric.value(point) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let start = point
+            .received_at
+            .timestamp()
+            .div_euclid(resolution_in_seconds)
+            * resolution_in_seconds;
+        match buckets.last_mut() {
+            Some((bucket_start, bucket, sum)) if *bucket_start == start => {
+                bucket.min = bucket.min.min(value);
+                bucket.max = bucket.max.max(value);
+                bucket.count += 1;
+                *sum += value as f64;
+            }
+            _ => buckets.push((
+                start,
+                HistoryBucket {
+                    start: chrono::DateTime::from_timestamp(start, 0)
+                        .unwrap_or_default()
+                        .to_rfc3339(),
+                    min: value,
+                    avg: value,
+                    max: value,
+                    count: 1,
+                },
+                value as f64,
+            )),
+        }
+    }
+
+    buckets
+        .into_iter()
+        .map(|(_, mut bucket, sum)| {
+            bucket.avg = (sum / bucket.count as f64) as f32;
+            bucket
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct RejectedSensorData {
+    index: usize,
+    message: String,
+}
+
+/// The values that the service calculated from a reading, so that a device can show them without
+/// calculating them itself
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct DerivedValues {
+    /// How full the tank is, if the height of a full tank is known
+    tank_fill_in_percent: Option<f32>,
+    tank_volume_in_liters: f32,
+    leak_suspected: bool,
+    data_quality: DataQuality,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ApiResponse {
+    status: String,
+    timestamp: String,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accepted: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rejected: Vec<RejectedSensorData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_sleep_seconds: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    server_time_in_seconds: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ntp_resync_seconds: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device_token: Option<String>,
+    /// The sensor data field that was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    /// The reason the field was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<ValidationErrorCode>,
+    /// The values that the service calculated from the reading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    derived: Option<DerivedValues>,
+    /// Tells the device that its tank is in maintenance. Left out otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quiet: Option<bool>,
+}
+
+impl ApiResponse {
+    fn success(message: impl Into<String>) -> Self {
+        Self {
+            status: "success".to_string(),
+            timestamp: Utc::now().to_rfc3339(),
+            message: message.into(),
+            accepted: None,
+            rejected: Vec::new(),
+            next_sleep_seconds: None,
+            server_time_in_seconds: None,
+            ntp_resync_seconds: None,
+            device_id: None,
+            device_token: None,
+            field: None,
+            code: None,
+            derived: None,
+            quiet: None,
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            status: "error".to_string(),
+            timestamp: Utc::now().to_rfc3339(),
+            message: message.into(),
+            accepted: None,
+            rejected: Vec::new(),
+            next_sleep_seconds: None,
+            server_time_in_seconds: None,
+            ntp_resync_seconds: None,
+            device_id: None,
+            device_token: None,
+            field: None,
+            code: None,
+            derived: None,
+            quiet: None,
+        }
+    }
+
+    /// An error response that names the sensor data field that was rejected and the reason
+    fn validation_error(error: ValidationError) -> Self {
+        Self {
+            field: Some(error.field.to_string()),
+            code: Some(error.code),
+            ..Self::error(error.message)
+        }
+    }
+
+    /// A response for a reading that was already received. The reading is not recorded again.
+    fn duplicate(message: impl Into<String>) -> Self {
+        Self {
+            status: "duplicate".to_string(),
+            ..Self::success(message)
+        }
+    }
+
+    fn partial(message: impl Into<String>) -> Self {
+        Self {
+            status: "partial".to_string(),
+            timestamp: Utc::now().to_rfc3339(),
+            message: message.into(),
+            accepted: None,
+            rejected: Vec::new(),
+            next_sleep_seconds: None,
+            server_time_in_seconds: None,
+            ntp_resync_seconds: None,
+            device_id: None,
+            device_token: None,
+            field: None,
+            code: None,
+            derived: None,
+            quiet: None,
+        }
+    }
+
+    fn with_batch_result(mut self, accepted: usize, rejected: Vec<RejectedSensorData>) -> Self {
+        self.accepted = Some(accepted);
+        self.rejected = rejected;
+        self
+    }
+
+    fn with_next_sleep_seconds(mut self, next_sleep_seconds: Option<u32>) -> Self {
+        self.next_sleep_seconds = next_sleep_seconds;
+        self
+    }
+
+    fn with_derived(mut self, derived: DerivedValues) -> Self {
+        self.derived = Some(derived);
+        self
+    }
+
+    /// Tell the device that its tank is in maintenance so that it can sleep longer and skip the
+    /// uploads it can do without
+    fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet.then_some(true);
+        self
+    }
+
+    fn with_server_time(mut self) -> Self {
+        self.server_time_in_seconds = Some(Utc::now().timestamp());
+        self
+    }
+
+    fn with_ntp_resync_seconds(mut self, ntp_resync_seconds: Option<u32>) -> Self {
+        self.ntp_resync_seconds = ntp_resync_seconds;
+        self
+    }
+
+    fn with_device_registration(mut self, registration: &DeviceRegistration) -> Self {
+        self.device_id = Some(registration.device_id.clone());
+        self.device_token = Some(registration.token.clone());
+        self
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct LogData {
+    device_id: String,
+    level: String,
+    message: String,
+    boot_count: u32,
+    timestamp: u64,
+    /// The wake cycle in which the device logged the message. Older firmware doesn't send it.
+    #[serde(default)]
+    session_id: Option<u32>,
+    /// The number of times the device logged the message in a row. Older firmware doesn't send it.
+    #[serde(default = "default_log_count")]
+    count: u32,
+}
+
+/// The number of times a message was logged if the device doesn't say
+fn default_log_count() -> u32 {
+    1
+}
+
+/// A log message from a device as it is kept in memory
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+struct DeviceLogEntry {
+    level: String,
+    message: String,
+    boot_count: u32,
+    device_ticks: u64,
+    timestamp: String,
+    count: u32,
+}
+
+/// The query parameters for reading the logs of a device
+#[derive(Debug, Deserialize)]
+struct LogQuery {
+    /// The least severe level to return, e.g. `warn` returns warnings and errors
+    level: Option<String>,
+    /// The maximum number of log messages to return. The most recent messages are returned.
+    limit: Option<usize>,
+}
+
+/// Parse a log level. Returns `None` if the level isn't known.
+fn parse_level(level: &str) -> Option<tracing::Level> {
+    match level.to_lowercase().as_str() {
+        "error" => Some(tracing::Level::ERROR),
+        "warn" => Some(tracing::Level::WARN),
+        "info" => Some(tracing::Level::INFO),
+        "debug" => Some(tracing::Level::DEBUG),
+        "trace" => Some(tracing::Level::TRACE),
+        _ => None,
+    }
+}
+
+/// Add a log message to the buffer of a device. The oldest messages are removed once the
+/// buffer holds more than `capacity` messages.
+fn buffer_log_entry(
+    buffer: &mut std::collections::VecDeque<DeviceLogEntry>,
+    entry: DeviceLogEntry,
+    capacity: usize,
+) {
+    buffer.push_back(entry);
+    while buffer.len() > capacity {
+        buffer.pop_front();
+    }
+}
+
+/// The most recent log messages that are at least as severe as the given level, oldest first
+fn filter_log_entries(
+    buffer: &std::collections::VecDeque<DeviceLogEntry>,
+    min_level: tracing::Level,
+    limit: Option<usize>,
+) -> Vec<DeviceLogEntry> {
+    // Less severe levels compare as greater
+    let mut entries: Vec<DeviceLogEntry> = buffer
+        .iter()
+        .filter(|entry| parse_level(&entry.level).is_some_and(|level| level <= min_level))
+        .cloned()
+        .collect();
+
+    if let Some(limit) = limit {
+        let skip = entries.len().saturating_sub(limit);
+        entries.drain(..skip);
+    }
+
+    entries
+}
+
+/// The request a device sends to get its device token
+#[derive(Debug, Deserialize, Serialize)]
+struct ProvisionRequest {
+    /// An identifier that is unique to the hardware of the device, e.g. its MAC address
+    hardware_id: String,
+    /// The device ID the device would like to use. The server picks the device ID if this is
+    /// not set.
+    #[serde(default)]
+    device_id: Option<String>,
+}
+
+/// The device ID and token that the server assigned to the hardware of a device
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct DeviceRegistration {
+    device_id: String,
+    token: String,
+}
+
+/// The device that sent a request, as identified by its device token
+#[derive(Debug, Clone)]
+struct ProvisionedDevice {
+    device_id: String,
+}
+
+/// Check that the hardware ID is not empty, not too long and only contains letters, digits,
+/// `:`, `-` and `_`
+fn validate_hardware_id(hardware_id: &str) -> Result<(), String> {
+    if hardware_id.is_empty() {
+        return Err("The hardware ID should not be empty.".to_string());
+    }
+
+    if hardware_id.len() > MAX_HARDWARE_ID_LENGTH {
+        return Err(format!(
+            "The hardware ID should be at most {} characters long.",
+            MAX_HARDWARE_ID_LENGTH
+        ));
+    }
+
+    if !hardware_id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b':' || b == b'-' || b == b'_')
+    {
+        return Err(
+            "The hardware ID should only contain letters, digits, ':', '-' and '_'.".to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+/// The device ID the server assigns to a device that doesn't ask for a specific device ID. Long
+/// hardware IDs are cut off so that the device ID isn't too long.
+fn default_device_id(hardware_id: &str) -> String {
+    const PREFIX: &str = "device-";
+    let id: String = hardware_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(MAX_DEVICE_ID_LENGTH - PREFIX.len())
+        .collect();
+    format!("{}{}", PREFIX, id.to_lowercase())
+}
+
+/// Create a new random device token
+fn new_device_token() -> String {
+    hex::encode(rand::random::<[u8; DEVICE_TOKEN_LENGTH]>())
+}
+
+/// Check that the data is for the device that sent the request. Data for a provisioned device
+/// is only accepted with its device token. Data for a device that isn't provisioned is accepted
+/// without a token.
+async fn check_device_id(
+    state: &AppState,
+    device: &Option<Extension<ProvisionedDevice>>,
+    device_id: &str,
+) -> Result<(), String> {
+    match device {
+        Some(Extension(device)) if device.device_id != device_id => Err(format!(
+            "The device token is not valid for device {}.",
+            device_id
+        )),
+        Some(_) => Ok(()),
+        None => {
+            let is_provisioned = state
+                .device_registrations
+                .read()
+                .await
+                .values()
+                .any(|registration| registration.device_id == device_id);
+            if is_provisioned {
+                Err(format!(
+                    "Device {} is provisioned and has to send its device token.",
+                    device_id
+                ))
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Read the device registrations from the given file. A file that doesn't exist yet holds no
+/// registrations.
+fn load_device_registrations(
+    path: &std::path::Path,
+) -> Result<std::collections::HashMap<String, DeviceRegistration>> {
+    match std::fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(std::collections::HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write the device registrations to the given file. The registrations are written to a
+/// temporary file first so that a crash doesn't leave a partly written file behind.
+async fn save_device_registrations(
+    path: &std::path::Path,
+    registrations: &std::collections::HashMap<String, DeviceRegistration>,
+) -> std::io::Result<()> {
+    let contents = serde_json::to_vec_pretty(registrations)?;
+    let temporary_path = path.with_extension("tmp");
+    tokio::fs::write(&temporary_path, contents).await?;
+    tokio::fs::rename(&temporary_path, path).await
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct DeviceTimingData {
+    device_id: String,
+    boot_count: u32,
+    timestamp: u64,
+    /// The wake cycle that the device is in. Older firmware doesn't send it.
+    #[serde(default)]
+    session_id: Option<u32>,
+}
+
+/// The server time at which the device timer had a given number of ticks during a boot. It is
+/// anchored on the first timing data of the boot.
+#[derive(Debug, Clone)]
+struct DeviceTimeMapping {
+    boot_count: u32,
+    first_tick: u64,
+    first_timestamp: chrono::DateTime<chrono::Utc>,
+    /// The time between two NTP syncs that the device was asked to use
+    ntp_resync_seconds: Option<u32>,
+}
+
+impl DeviceTimeMapping {
+    /// The server time at which the device timer had the given number of ticks
+    fn timestamp_at(&self, ticks: u64) -> chrono::DateTime<Utc> {
+        // Log messages can have been taken before the timing data was sent
+        let tick_diff = ticks as i64 - self.first_tick as i64;
+        self.first_timestamp + chrono::Duration::milliseconds(tick_diff)
+    }
+}
+
+/// Check that the device ticks of the timing data are a plausible time since boot
+fn validate_device_ticks(ticks: u64) -> Result<(), ValidationError> {
+    if ticks > MAX_DEVICE_TICKS {
+        return Err(ValidationError::new(
+            "timestamp",
+            ValidationErrorCode::OutOfRange,
+            format!(
+                "The timestamp should be at most {} ticks after boot.",
+                MAX_DEVICE_TICKS
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The time mapping of the device after it sent the timing data
+///
+/// Timing data from the same boot, e.g. when the device sends it again because the response
+/// was lost, keeps the existing anchor so that the log timestamps of the boot don't shift. The
+/// mapping is anchored again when the boot count changes. It is also anchored again when the
+/// ticks are before the anchor, because the timer of the device started over, e.g. when the
+/// boot count was restored from flash after a power loss.
+fn anchor_time_mapping(
+    previous: Option<&DeviceTimeMapping>,
+    boot_count: u32,
+    ticks: u64,
+    now: chrono::DateTime<Utc>,
+    ntp_resync_seconds: Option<u32>,
+) -> DeviceTimeMapping {
+    match previous {
+        Some(previous) if previous.boot_count == boot_count && ticks >= previous.first_tick => {
+            DeviceTimeMapping {
+                ntp_resync_seconds,
+                ..previous.clone()
+            }
+        }
+        _ => DeviceTimeMapping {
+            boot_count,
+            first_tick: ticks,
+            first_timestamp: now,
+            ntp_resync_seconds,
+        },
+    }
+}
+
+#[derive(Clone)]
+struct ObservabilityConfig {
+    metrics_push_url: String,
+    trace_push_url: String,
+    logs_push_url: String,
+    metrics_export_interval: std::time::Duration,
+}
+
+/// The time, in seconds, between two exports of the metrics if nothing is configured
+const DEFAULT_METRICS_EXPORT_INTERVAL_IN_SECONDS: u64 = 60;
+
+/// The shortest time, in seconds, between two exports of the metrics
+const MIN_METRICS_EXPORT_INTERVAL_IN_SECONDS: u64 = 1;
+
+/// The longest time, in seconds, between two exports of the metrics
+const MAX_METRICS_EXPORT_INTERVAL_IN_SECONDS: u64 = 600;
+
+/// Parse the time, in seconds, between two exports of the metrics
+fn parse_metrics_export_interval(value: &str) -> Result<std::time::Duration, String> {
+    let seconds = value
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("'{}' is not a valid number of seconds", value))?;
+    if !(MIN_METRICS_EXPORT_INTERVAL_IN_SECONDS..=MAX_METRICS_EXPORT_INTERVAL_IN_SECONDS)
+        .contains(&seconds)
+    {
+        return Err(format!(
+            "The metrics export interval must be between {} and {} seconds",
+            MIN_METRICS_EXPORT_INTERVAL_IN_SECONDS, MAX_METRICS_EXPORT_INTERVAL_IN_SECONDS
+        ));
+    }
+
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// The name of the header that contains the signature of the sensor data
+const SIGNATURE_HEADER_NAME: &str = "x-signature";
+
+/// The name of the header that contains the unix time at which the sensor data was signed
+const SIGNATURE_TIMESTAMP_HEADER_NAME: &str = "x-signature-timestamp";
+
+/// The name of the header that contains the CRC-32 of the uncompressed sensor data, as eight
+/// hexadecimal digits
+const PAYLOAD_CRC_HEADER_NAME: &str = "x-payload-crc32";
+
+/// The name of the header that contains the token the device received when it was provisioned
+const DEVICE_TOKEN_HEADER_NAME: &str = "x-device-token";
+
+/// The number of random bytes in a device token
+const DEVICE_TOKEN_LENGTH: usize = 32;
+
+/// The maximum length of the hardware ID a device uses to provision itself
+const MAX_HARDWARE_ID_LENGTH: usize = 64;
+
+/// How old, in seconds, signed sensor data may be if nothing is configured
+const DEFAULT_SIGNATURE_MAX_AGE_IN_SECONDS: i64 = 300;
+
+/// The number of log messages that are kept for each device if nothing is configured
+const DEFAULT_DEVICE_LOG_BUFFER_SIZE: usize = 100;
+
+/// The enclosure humidity above which condensation is likely if the device has no threshold
+/// configured
+const DEFAULT_CONDENSATION_HUMIDITY_THRESHOLD_IN_PERCENT: f32 = 85.0;
+
+/// How close the temperature has to be to the dew point for condensation to be likely
+const CONDENSATION_DEW_POINT_MARGIN_IN_CELCIUS: f32 = 3.0;
+
+/// How long readings are kept for the history endpoint
+const HISTORY_RETENTION_IN_SECONDS: i64 = 24 * 3600;
+
+/// The number of readings that are kept for each device for the history endpoint. This allows
+/// a device to report once a minute for the full retention period.
+const HISTORY_POINTS_PER_DEVICE: usize = 24 * 60;
+
+/// The number of readings that are kept for all devices together if nothing is configured
+const DEFAULT_HISTORY_MAX_POINTS: usize = 100_000;
+
+/// The width of the history buckets if the request doesn't ask for one
+const DEFAULT_HISTORY_RESOLUTION_IN_SECONDS: i64 = 3600;
+
+/// How long, in seconds, a device may not send data before it is reported as offline if
+/// nothing is configured. This is twice the longest time the firmware sleeps.
+const DEFAULT_DEVICE_OFFLINE_AFTER_IN_SECONDS: i64 = 2 * 3600;
+
+/// The largest number of device ticks that is accepted in the timing data. The ticks count from
+/// the moment the device woke up and the device is only awake for seconds, so this is a day
+/// even if the ticks are micro seconds.
+const MAX_DEVICE_TICKS: u64 = 24 * 3600 * 1_000_000;
+
+/// How long, in seconds, the data of a device is kept after its last contact if nothing is
+/// configured
+const DEFAULT_DEVICE_DATA_TTL_IN_SECONDS: i64 = 7 * 24 * 3600;
+
+/// The time between two sweeps for the data of devices that are no longer in contact
+const DEVICE_DATA_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// How long a device may be idle before its rate limit state is removed
+const DEFAULT_RATE_LIMIT_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// The number of readings that the live stream keeps for clients that fall behind. A client
+/// that falls further behind misses the oldest readings.
+const SENSOR_DATA_STREAM_CAPACITY: usize = 64;
+
+/// The largest sensor data and timing request body, after decompression, if nothing is
+/// configured
+const DEFAULT_SENSOR_BODY_LIMIT_IN_BYTES: usize = 64 * 1024;
+
+/// The largest log data request body, after decompression, if nothing is configured
+const DEFAULT_LOG_BODY_LIMIT_IN_BYTES: usize = 256 * 1024;
+
+/// The number of recent readings per device that are kept to detect readings that are sent again
+const RECENT_READING_KEYS_PER_DEVICE: usize = 16;
+
+/// The boot count and reading sequence number of the most recent readings of a device
+type RecentReadingKeys = std::collections::VecDeque<(u32, u32)>;
+
+/// The message for a request or a reading that exceeded the rate limit of its device
+const RATE_LIMITED_MESSAGE: &str = "Too many requests from this device";
+
+/// The maximum number of alert rules per device
+const MAX_ALERT_RULES: usize = 32;
+
+/// The default time before an alert that triggers again is notified again
+const DEFAULT_ALERT_WEBHOOK_MIN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// The time the alert webhook gets to accept a notification
+const ALERT_WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone)]
+struct AppState {
+    device_time_mappings:
+        std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, DeviceTimeMapping>>>,
+    latest_sensor_data:
+        std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, SensorReading>>>,
+    sensor_history: std::sync::Arc<
+        tokio::sync::RwLock<
+            std::collections::HashMap<String, std::collections::VecDeque<SensorReading>>,
+        >,
+    >,
+    leak_detection: LeakDetectionSettings,
+    level_plausibility: LevelPlausibilitySettings,
+    ingest_api_key: Option<String>,
+    ingest_hmac_secret: Option<String>,
+    signature_max_age_in_seconds: i64,
+    device_sleep_seconds: Option<u32>,
+    ntp_resync_seconds: Option<u32>,
+    telemetry_export_healthy: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    cors_allowed_origins: Vec<String>,
+    rate_limiter: Option<DeviceRateLimiter>,
+    alert_webhook: Option<AlertWebhook>,
+    tank_configs:
+        std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, TankConfig>>>,
+    alert_rules: std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, AlertRules>>>,
+    /// The alerts that the latest reading of each device triggered
+    triggered_alerts:
+        std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, Vec<TriggeredAlert>>>>,
+    device_logs: std::sync::Arc<
+        tokio::sync::RwLock<
+            std::collections::HashMap<String, std::collections::VecDeque<DeviceLogEntry>>,
+        >,
+    >,
+    device_log_buffer_size: usize,
+    validation_ranges: Vec<ValidationRange>,
+    /// The device ID and token for each provisioned device, by hardware ID
+    device_registrations:
+        std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, DeviceRegistration>>>,
+    /// The file that keeps the device registrations when the service restarts
+    device_registrations_file: Option<std::path::PathBuf>,
+    metric_history: MetricHistory,
+    /// The boot count and reading sequence number of the most recent readings of each device
+    recent_reading_keys:
+        std::sync::Arc<tokio::sync::RwLock<std::collections::HashMap<String, RecentReadingKeys>>>,
+    device_offline_after_in_seconds: i64,
+    /// How long the data of a device is kept after its last contact
+    device_data_ttl_in_seconds: i64,
+    /// Publishes each new reading to the clients of the live stream
+    sensor_data_updates: tokio::sync::broadcast::Sender<SensorData>,
+    /// Set to `true` when the service shuts down, which ends the live streams
+    shutting_down: std::sync::Arc<tokio::sync::watch::Sender<bool>>,
+    device_instruments: DeviceInstrumentCache,
+    telemetry_providers: Option<TelemetryProviders>,
+    sensor_body_limit_in_bytes: usize,
+    log_body_limit_in_bytes: usize,
+}
+
+impl AppState {
+    fn new() -> Self {
+        Self {
+            device_time_mappings: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            latest_sensor_data: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            sensor_history: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            leak_detection: LeakDetectionSettings::default(),
+            level_plausibility: LevelPlausibilitySettings::default(),
+            ingest_api_key: None,
+            ingest_hmac_secret: None,
+            signature_max_age_in_seconds: DEFAULT_SIGNATURE_MAX_AGE_IN_SECONDS,
+            device_sleep_seconds: None,
+            ntp_resync_seconds: None,
+            telemetry_export_healthy: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true)),
+            cors_allowed_origins: Vec::new(),
+            rate_limiter: None,
+            alert_webhook: None,
+            tank_configs: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            alert_rules: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            triggered_alerts: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            device_logs: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            device_log_buffer_size: DEFAULT_DEVICE_LOG_BUFFER_SIZE,
+            validation_ranges: SENSOR_DATA_RANGES.to_vec(),
+            device_registrations: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            device_registrations_file: None,
+            metric_history: MetricHistory::new(
+                HISTORY_POINTS_PER_DEVICE,
+                DEFAULT_HISTORY_MAX_POINTS,
+            ),
+            recent_reading_keys: std::sync::Arc::new(tokio::sync::RwLock::new(
+                std::collections::HashMap::new(),
+            )),
+            device_offline_after_in_seconds: DEFAULT_DEVICE_OFFLINE_AFTER_IN_SECONDS,
+            device_data_ttl_in_seconds: DEFAULT_DEVICE_DATA_TTL_IN_SECONDS,
+            sensor_data_updates: tokio::sync::broadcast::channel(SENSOR_DATA_STREAM_CAPACITY).0,
+            shutting_down: std::sync::Arc::new(tokio::sync::watch::channel(false).0),
+            device_instruments: DeviceInstrumentCache::new(),
+            telemetry_providers: None,
+            sensor_body_limit_in_bytes: DEFAULT_SENSOR_BODY_LIMIT_IN_BYTES,
+            log_body_limit_in_bytes: DEFAULT_LOG_BODY_LIMIT_IN_BYTES,
+        }
+    }
+
+    /// Require the given API key on the ingestion endpoints. `None` or an empty key leaves
+    /// the ingestion endpoints open.
+    fn with_ingest_api_key(mut self, api_key: Option<String>) -> Self {
+        self.ingest_api_key = api_key.filter(|key| !key.is_empty());
+        self
+    }
+
+    /// Require the sensor data to be signed with the given secret. `None` or an empty secret
+    /// accepts unsigned sensor data.
+    fn with_ingest_hmac_secret(mut self, secret: Option<String>) -> Self {
+        self.ingest_hmac_secret = secret.filter(|secret| !secret.is_empty());
+        self
+    }
+
+    /// Reject signed sensor data that was signed more than the given number of seconds ago
+    fn with_signature_max_age_in_seconds(mut self, max_age_in_seconds: i64) -> Self {
+        self.signature_max_age_in_seconds = max_age_in_seconds;
+        self
+    }
+
+    fn with_leak_detection(mut self, leak_detection: LeakDetectionSettings) -> Self {
+        self.leak_detection = leak_detection;
+        self
+    }
+
+    fn with_level_plausibility(mut self, level_plausibility: LevelPlausibilitySettings) -> Self {
+        self.level_plausibility = level_plausibility;
+        self
+    }
+
+    /// Tell the devices how long to sleep for after they send their sensor data. `None` lets
+    /// the devices use their own default.
+    fn with_device_sleep_seconds(mut self, device_sleep_seconds: Option<u32>) -> Self {
+        self.device_sleep_seconds = device_sleep_seconds;
+        self
+    }
+
+    /// Tell the devices how long to wait between two NTP syncs. `None` lets the devices use
+    /// their own default.
+    fn with_ntp_resync_seconds(mut self, ntp_resync_seconds: Option<u32>) -> Self {
+        self.ntp_resync_seconds = ntp_resync_seconds;
+        self
+    }
+
+    /// Share the flag that the telemetry pipeline uses to report if the last export succeeded
+    fn with_telemetry_export_healthy(
+        mut self,
+        healthy: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    ) -> Self {
+        self.telemetry_export_healthy = healthy;
+        self
+    }
+
+    /// Share the telemetry providers so that the self test can flush them
+    fn with_telemetry_providers(mut self, providers: TelemetryProviders) -> Self {
+        self.telemetry_providers = Some(providers);
+        self
+    }
+
+    /// Limit the number of sensor data requests per device. `None` doesn't limit the requests.
+    fn with_rate_limiter(mut self, rate_limiter: Option<DeviceRateLimiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Notify the webhook when an alert rule triggers. `None` doesn't send notifications.
+    fn with_alert_webhook(mut self, alert_webhook: Option<AlertWebhook>) -> Self {
+        self.alert_webhook = alert_webhook;
+        self
+    }
+
+    /// Keep the given number of log messages for each device so that they can be read back
+    fn with_device_log_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.device_log_buffer_size = buffer_size;
+        self
+    }
+
+    /// Keep at most the given number of readings, for all devices together, for the history
+    /// endpoint
+    fn with_history_max_points(mut self, max_points: usize) -> Self {
+        self.metric_history = MetricHistory::new(HISTORY_POINTS_PER_DEVICE, max_points);
+        self
+    }
+
+    /// Report a device as offline if it hasn't sent data for more than the given number of
+    /// seconds
+    fn with_device_offline_after_in_seconds(mut self, offline_after_in_seconds: i64) -> Self {
+        self.device_offline_after_in_seconds = offline_after_in_seconds;
+        self
+    }
+
+    /// Remove the data of a device once it hasn't been in contact for the given number of
+    /// seconds
+    fn with_device_data_ttl_in_seconds(mut self, ttl_in_seconds: i64) -> Self {
+        self.device_data_ttl_in_seconds = ttl_in_seconds;
+        self
+    }
+
+    /// Reject sensor data and timing requests with a body larger than the given number of bytes
+    fn with_sensor_body_limit_in_bytes(mut self, limit_in_bytes: usize) -> Self {
+        self.sensor_body_limit_in_bytes = limit_in_bytes;
+        self
+    }
+
+    /// Reject log data requests with a body larger than the given number of bytes
+    fn with_log_body_limit_in_bytes(mut self, limit_in_bytes: usize) -> Self {
+        self.log_body_limit_in_bytes = limit_in_bytes;
+        self
+    }
+
+    /// Accept the sensor data if the numeric fields are inside the given ranges
+    fn with_validation_ranges(mut self, ranges: Vec<ValidationRange>) -> Self {
+        self.validation_ranges = ranges;
+        self
+    }
+
+    /// Start with the given device registrations and write every new registration to the given
+    /// file. Without a file the registrations are lost when the service restarts.
+    fn with_device_registrations(
+        mut self,
+        registrations: std::collections::HashMap<String, DeviceRegistration>,
+        file: Option<std::path::PathBuf>,
+    ) -> Self {
+        self.device_registrations = std::sync::Arc::new(tokio::sync::RwLock::new(registrations));
+        self.device_registrations_file = file;
+        self
+    }
+
+    /// Allow browsers on the given origins to call the read-only endpoints. `*` allows any
+    /// origin. No origins disables CORS.
+    fn with_cors_allowed_origins(mut self, origins: Vec<String>) -> Self {
+        self.cors_allowed_origins = origins;
+        self
+    }
+
+    /// End the live streams so that the graceful shutdown doesn't wait for the clients to
+    /// disconnect
+    fn end_live_streams(&self) {
+        self.shutting_down.send_replace(true);
+    }
+}
+
+/// Compare two keys in constant time so that the comparison doesn't leak how much of the key
+/// matched.
+fn keys_match(provided: &str, expected: &str) -> bool {
+    let provided = provided.as_bytes();
+    let expected = expected.as_bytes();
+    if provided.len() != expected.len() {
+        return false;
+    }
+
+    provided
+        .iter()
+        .zip(expected.iter())
+        .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+async fn require_ingest_api_key(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<ApiResponse>)> {
+    let expected_key = match &state.ingest_api_key {
+        Some(key) => key,
+        None => return Ok(next.run(request).await),
+    };
+
+    let provided_key = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided_key {
+        Some(key) if keys_match(key, expected_key) => Ok(next.run(request).await),
+        Some(_) => {
+            error!("Request to {} has an invalid API key", request.uri());
+            Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::error("Invalid API key")),
+            ))
+        }
+        None => {
+            error!("Request to {} is missing the API key", request.uri());
+            Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::error("Missing API key")),
+            ))
+        }
+    }
+}
+
+/// Tie a request that carries a device token to the device that the token was issued to, so
+/// that the device can only send data for its own device ID. Requests without a token are
+/// passed on so that devices that aren't provisioned keep working. The handlers reject data
+/// for a provisioned device that comes without its token.
+async fn check_device_token(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<ApiResponse>)> {
+    let Some(token) = header_value(request.headers(), DEVICE_TOKEN_HEADER_NAME) else {
+        return Ok(next.run(request).await);
+    };
+
+    let device_id = state
+        .device_registrations
+        .read()
+        .await
+        .values()
+        .find(|registration| keys_match(&token, &registration.token))
+        .map(|registration| registration.device_id.clone());
+
+    match device_id {
+        Some(device_id) => {
+            request
+                .extensions_mut()
+                .insert(ProvisionedDevice { device_id });
+            Ok(next.run(request).await)
+        }
+        None => {
+            error!("Request to {} has an invalid device token", request.uri());
+            Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::error("Invalid device token")),
+            ))
+        }
+    }
+}
+
+/// Check that the signature is the HMAC-SHA256 of `<timestamp>.<body>` with the given secret
+fn signature_is_valid(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    let mut mac = match HmacSha256::new_from_slice(secret.as_bytes()) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    // Verifying through the MAC compares the signatures in constant time
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn header_value(headers: &header::HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+fn signature_error(message: &str) -> (StatusCode, Json<ApiResponse>) {
+    (StatusCode::UNAUTHORIZED, Json(ApiResponse::error(message)))
+}
+
+/// The error response for a request body that is larger than the limit for the endpoint
+fn payload_too_large_error() -> (StatusCode, Json<ApiResponse>) {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(ApiResponse::error("The request body is too large")),
+    )
+}
+
+/// Calculate the CRC-32 checksum that gzip uses, which is the checksum the devices send
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Read the whole request body so that a middleware can check it before the handler reads it.
+/// The body limit of the route applies, so the body limit layer has to be outside the
+/// middleware. The purpose is only used in the logs.
+async fn buffer_request_body(
+    request: Request,
+    purpose: &str,
+) -> Result<(axum::http::request::Parts, axum::body::Bytes), (StatusCode, Json<ApiResponse>)> {
+    let (parts, body) = request.into_parts();
+    match axum::body::Bytes::from_request(Request::from_parts(parts.clone(), body), &()).await {
+        Ok(body) => Ok((parts, body)),
+        Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            error!(
+                "The body of the request to {} is too large to {purpose}. Error was {:?}",
+                parts.uri, e
+            );
+            Err(payload_too_large_error())
+        }
+        Err(e) => {
+            error!(
+                "Could not read the body of the request to {} to {purpose}. Error was {:?}",
+                parts.uri, e
+            );
+            Err((
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ApiResponse::error(
+                    "The request body could not be extracted",
+                )),
+            ))
+        }
+    }
+}
+
+fn corrupt_payload_error(message: &str) -> (StatusCode, Json<ApiResponse>) {
+    (StatusCode::BAD_REQUEST, Json(ApiResponse::error(message)))
+}
+
+/// Reject sensor data that doesn't match its checksum, so that a payload that was corrupted on
+/// the way is reported as such instead of as a payload that can't be read. Older firmware
+/// doesn't send a checksum, so requests without one are passed on.
+async fn verify_payload_checksum(
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<ApiResponse>)> {
+    let checksum = match header_value(request.headers(), PAYLOAD_CRC_HEADER_NAME) {
+        Some(checksum) => checksum,
+        None => return Ok(next.run(request).await),
+    };
+    let expected = match u32::from_str_radix(checksum.trim(), 16) {
+        Ok(expected) => expected,
+        Err(_) => {
+            error!("Request to {} has an invalid checksum", request.uri());
+            return Err(corrupt_payload_error("Invalid payload checksum"));
+        }
+    };
+
+    let (parts, body) = buffer_request_body(request, "verify the checksum").await?;
+    let actual = crc32(&body);
+    if actual != expected {
+        error!(
+            expected = format!("{expected:08x}"),
+            actual = format!("{actual:08x}"),
+            "Request to {} has a corrupt payload",
+            parts.uri
+        );
+        if let Some(counter) = CORRUPT_PAYLOADS.as_ref() {
+            counter.with_label_values(&[parts.uri.path()]).inc();
+        }
+        return Err(corrupt_payload_error(
+            "Corrupt payload: the body doesn't match its checksum",
+        ));
+    }
+
+    Ok(next
+        .run(Request::from_parts(parts, axum::body::Body::from(body)))
+        .await)
+}
+
+async fn require_payload_signature(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<ApiResponse>)> {
+    let secret = match &state.ingest_hmac_secret {
+        Some(secret) => secret,
+        None => return Ok(next.run(request).await),
+    };
+
+    let (signature, timestamp) = match (
+        header_value(request.headers(), SIGNATURE_HEADER_NAME),
+        header_value(request.headers(), SIGNATURE_TIMESTAMP_HEADER_NAME),
+    ) {
+        (Some(signature), Some(timestamp)) => (signature, timestamp),
+        _ => {
+            error!("Request to {} is missing the signature", request.uri());
+            return Err(signature_error("Missing signature"));
+        }
+    };
+
+    // Reject old requests so that a captured request can't be replayed later
+    let signed_at = match timestamp.parse::<i64>() {
+        Ok(t) => t,
+        Err(_) => {
+            error!(
+                "Request to {} has an invalid signature timestamp",
+                request.uri()
+            );
+            return Err(signature_error("Invalid signature timestamp"));
+        }
+    };
+    if (Utc::now().timestamp() - signed_at).abs() > state.signature_max_age_in_seconds {
+        error!("Request to {} has an expired signature", request.uri());
+        return Err(signature_error("Signature has expired"));
+    }
+
+    let (parts, body) = buffer_request_body(request, "verify the signature").await?;
+    if !signature_is_valid(secret, &timestamp, &body, &signature) {
+        error!("Request to {} has an invalid signature", parts.uri);
+        return Err(signature_error("Invalid signature"));
+    }
+
+    Ok(next
+        .run(Request::from_parts(parts, axum::body::Body::from(body)))
+        .await)
+}
+
+/// Convert a rejected sensor data JSON payload into the error response for the device
+fn sensor_data_rejection_response(rejection: JsonRejection) -> (StatusCode, Json<ApiResponse>) {
+    match rejection {
+        JsonRejection::MissingJsonContentType(e) => {
+            error!("The sensor data request did not have the right `Content-Type: application/json` header. Error was {:?}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(
+                    "The data request did not have the right right `Content-Type: application/json` header.",
+                )),
+            )
+        }
+        JsonRejection::JsonDataError(e) => {
+            // Couldn't deserialize the body into the target type
+            error!(
+                "Could not deserialize the sensor data request body. Error was {:?}",
+                e
+            );
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ApiResponse::error(
+                    "Could not deserialize the sensor data request body.",
+                )),
+            )
+        }
+        JsonRejection::JsonSyntaxError(e) => {
+            // Syntax error in the body
+            error!(
+                "The sensor data request body has syntax errors. Error was {:?}",
+                e
+            );
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ApiResponse::error(
+                    "The sensor data request body has syntax errors",
+                )),
+            )
+        }
+        JsonRejection::BytesRejection(e) => {
+            if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                error!(
+                    "The sensor data request body is too large. Error was {:?}",
+                    e
+                );
+                return payload_too_large_error();
+            }
+
+            // Failed to extract the request body
+            error!(
+                "The sensor data request body could not be extracted. Error was {:?}",
+                e
+            );
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ApiResponse::error(
+                    "The sensor data request body could not be extracted",
+                )),
+            )
+        }
+        e => {
+            // `JsonRejection` is marked `#[non_exhaustive]` so match must
+            // include a catch-all case.
+            error!(
+                "Could not process the sensor data request. Error was {:?}",
+                e
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(
+                    "Could not process the sensor data request.",
+                )),
+            )
+        }
+    }
+}
+
+/// Take a token from the rate limiter for the device. Returns `false` if the device exceeded
+/// the rate limit.
+fn within_rate_limit(state: &AppState, device_id: &str) -> bool {
+    let Some(rate_limiter) = &state.rate_limiter else {
+        return true;
+    };
+
+    let within_limit = rate_limiter.try_acquire(device_id, std::time::Instant::now());
+    if !within_limit {
+        tracing::warn!(device_id = %device_id, "Device exceeded the rate limit");
+    }
+
+    within_limit
+}
+
+/// Determine if the reading was already received and remember it as one of the recent readings
+/// of the device
+///
+/// A device sends a reading again if the response to the first attempt was lost, either right
+/// away or on the next wake up. A batch of queued readings is sent again as a whole, so the
+/// reading is compared with the most recent readings of the device rather than only the last
+/// one. Readings without a sequence number are never duplicates.
+async fn is_duplicate_reading(state: &AppState, sensor_data: &SensorData) -> bool {
+    let reading_seq = match sensor_data.reading_seq {
+        Some(reading_seq) => reading_seq,
+        None => return false,
+    };
+
+    let key = (sensor_data.boot_count, reading_seq);
+    let mut recent_reading_keys = state.recent_reading_keys.write().await;
+    let recent = recent_reading_keys
+        .entry(sensor_data.device_id.clone())
+        .or_default();
+    if recent.contains(&key) {
+        return true;
+    }
+
+    if recent.len() == RECENT_READING_KEYS_PER_DEVICE {
+        recent.pop_front();
+    }
+    recent.push_back(key);
+    false
+}
+
+/// The reason a reading was not admitted for storage
+enum ReadingRejection {
+    /// The device exceeded its rate limit
+    RateLimited,
+
+    /// The reading is not valid
+    Invalid(ValidationError),
+}
+
+/// Check that a reading may be stored. Takes a token from the rate limiter of the device,
+/// validates the reading and determines if it was already received. Returns `true` if the
+/// reading is new and `false` if it is a duplicate that should not be stored again.
+///
+/// Both the single reading and the batch endpoints admit their readings through this, so
+/// that neither can be used to get around the rate limit or to store a reading twice.
+async fn admit_reading(
+    state: &AppState,
+    sensor_data: &SensorData,
+) -> Result<bool, ReadingRejection> {
+    if !within_rate_limit(state, &sensor_data.device_id) {
+        return Err(ReadingRejection::RateLimited);
+    }
+
+    sensor_data
+        .validate_with(&state.validation_ranges)
+        .map_err(ReadingRejection::Invalid)?;
+
+    if is_duplicate_reading(state, sensor_data).await {
+        info!(device_id = %sensor_data.device_id, "Duplicate sensor data received. Ignoring it.");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Remove the data of the devices that haven't sent timing or sensor data within the time to
+/// live, so that the maps don't keep every device ID that was ever seen. Returns the number of
+/// devices that were removed.
+async fn evict_stale_devices(state: &AppState, now: chrono::DateTime<Utc>) -> usize {
+    let oldest_kept = now - chrono::Duration::seconds(state.device_data_ttl_in_seconds);
+
+    let stale = {
+        let mut mappings = state.device_time_mappings.write().await;
+        let mut latest = state.latest_sensor_data.write().await;
+
+        let mut last_contact = std::collections::HashMap::<String, chrono::DateTime<Utc>>::new();
+        let contacts = mappings
+            .iter()
+            .map(|(device_id, mapping)| (device_id, mapping.first_timestamp))
+            .chain(
+                latest
+                    .iter()
+                    .map(|(device_id, reading)| (device_id, reading.received_at)),
+            );
+        for (device_id, contact) in contacts {
+            let last = last_contact.entry(device_id.clone()).or_insert(contact);
+            *last = (*last).max(contact);
+        }
+
+        let stale: std::collections::HashSet<String> = last_contact
+            .into_iter()
+            .filter(|(_, contact)| *contact < oldest_kept)
+            .map(|(device_id, _)| device_id)
+            .collect();
+        mappings.retain(|device_id, _| !stale.contains(device_id));
+        latest.retain(|device_id, _| !stale.contains(device_id));
+        stale
+    };
+
+    if stale.is_empty() {
+        return 0;
+    }
+
+    state
+        .sensor_history
+        .write()
+        .await
+        .retain(|device_id, _| !stale.contains(device_id));
+    state
+        .recent_reading_keys
+        .write()
+        .await
+        .retain(|device_id, _| !stale.contains(device_id));
+    state
+        .device_logs
+        .write()
+        .await
+        .retain(|device_id, _| !stale.contains(device_id));
+    state
+        .triggered_alerts
+        .write()
+        .await
+        .retain(|device_id, _| !stale.contains(device_id));
+    state.metric_history.remove_devices(&stale);
+    state.device_instruments.remove_devices(&stale);
+    for device_id in &stale {
+        remove_prometheus_series(device_id);
+    }
+
+    stale.len()
+}
+
+/// Remove the Prometheus series of the device, so that a device that is no longer in contact
+/// isn't scraped with its last values forever
+fn remove_prometheus_series(device_id: &str) {
+    PROMETHEUS_METRICS.remove_device(device_id);
+
+    // Removing fails if the device has no series for the counter, which is fine
+    for counter in [&COLD_BOOTS, &SENSOR_READ_FAILURES, &CONDENSATION_RISKS] {
+        if let Some(counter) = counter.as_ref() {
+            let _ = counter.remove_label_values(&[device_id]);
+        }
+    }
+    if let Some(counter) = BOOT_REASONS.as_ref() {
+        for boot_reason in KNOWN_BOOT_REASONS {
+            let _ = counter.remove_label_values(&[device_id, boot_reason]);
+        }
+    }
+}
+
+/// Periodically remove the data of the devices that are no longer in contact
+async fn sweep_stale_devices(state: AppState) {
+    let mut interval = tokio::time::interval(DEVICE_DATA_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let removed = evict_stale_devices(&state, Utc::now()).await;
+        if removed > 0 {
+            info!(
+                removed,
+                "Removed the data of devices that are no longer in contact"
+            );
+        }
+    }
+}
+
+/// The enclosure humidity above which condensation is likely for the device
+async fn condensation_threshold(state: &AppState, device_id: &str) -> f32 {
+    state
+        .tank_configs
+        .read()
+        .await
+        .get(device_id)
+        .and_then(|config| config.alert_thresholds.condensation_humidity_in_percent)
+        .unwrap_or(DEFAULT_CONDENSATION_HUMIDITY_THRESHOLD_IN_PERCENT)
+}
+
+/// Determine if the tank of the device is in maintenance
+async fn is_quiet(state: &AppState, device_id: &str) -> bool {
+    state
+        .tank_configs
+        .read()
+        .await
+        .get(device_id)
+        .is_some_and(|config| config.is_quiet(Utc::now()))
+}
+
+/// Record the metrics for the sensor data and keep it as the latest reading for the device.
+/// Returns the values that were calculated from the reading.
+async fn store_sensor_data(state: &AppState, mut sensor_data: SensorData) -> DerivedValues {
+    // The tank configuration on the service takes precedence over the firmware configuration
+    if let Some(tank_config) = state.tank_configs.read().await.get(&sensor_data.device_id) {
+        tank_config.apply(&mut sensor_data);
+    }
+
+    let instruments = state
+        .device_instruments
+        .for_device(&sensor_data.device_id, &sensor_data.firmware_version);
+    let attributes = device_attributes(&sensor_data);
+    record_sensor_metrics(&instruments, &sensor_data);
+
+    // The water level and the enclosure change during maintenance, which would raise alerts
+    let quiet = is_quiet(state, &sensor_data.device_id).await;
+
+    let point = HistoryPoint::new(Utc::now(), &sensor_data);
+    state.metric_history.record(&sensor_data.device_id, point);
+
+    let triggered_alerts = if quiet {
+        Vec::new()
+    } else {
+        state
+            .alert_rules
+            .read()
+            .await
+            .get(&sensor_data.device_id)
+            .map(|rules| rules.evaluate(&point))
+            .unwrap_or_default()
+    };
+    for alert in &triggered_alerts {
+        tracing::warn!(
+            device_id = %sensor_data.device_id,
+            metric = %alert.metric,
+            value = alert.value,
+            threshold = alert.threshold,
+            "Alert rule triggered"
+        );
+    }
+
+    let received_at = Utc::now();
+    let (leak_suspected, previous_reading, data_quality) = {
+        let mut history = state.sensor_history.write().await;
+        let device_history = history.entry(sensor_data.device_id.clone()).or_default();
+        let previous_reading = device_history.back().cloned();
+        let mut reading = SensorReading {
+            received_at,
+            data: sensor_data.clone(),
+        };
+        let data_quality = match &previous_reading {
+            Some(previous)
+                if !is_plausible_level_change(previous, &reading, &state.level_plausibility) =>
+            {
+                tracing::warn!(
+                    device_id = %sensor_data.device_id,
+                    previous_level_in_meters = previous.data.tank_level_in_meters,
+                    level_in_meters = sensor_data.tank_level_in_meters,
+                    "Implausible change in water level"
+                );
+                DataQuality::ImplausibleLevelChange
+            }
+            _ => DataQuality::Ok,
+        };
+        sensor_data.data_quality = Some(data_quality);
+        reading.data.data_quality = Some(data_quality);
+        device_history.push_back(reading);
+        while device_history.len() > state.leak_detection.readings {
+            device_history.pop_front();
+        }
+
+        (
+            !quiet && detect_leak(device_history.make_contiguous(), &state.leak_detection),
+            previous_reading,
+            data_quality,
+        )
+    };
+
+    if let Some(previous) = previous_reading {
+        record_level_rate(
+            &instruments,
+            &attributes,
+            &previous,
+            &SensorReading {
+                received_at,
+                data: sensor_data.clone(),
+            },
+        );
+    }
+
+    if leak_suspected {
+        tracing::warn!(device_id = %sensor_data.device_id, "Tank leak suspected");
+    }
+
+    if !quiet
+        && condensation_risk(
+            sensor_data.temperature_in_celcius,
+            sensor_data.humidity_in_percent,
+            condensation_threshold(state, &sensor_data.device_id).await,
+        )
+    {
+        tracing::warn!(device_id = %sensor_data.device_id, "Condensation in the enclosure likely");
+        instruments
+            .u64_counter(
+                "device_condensation_risk_total",
+                "The number of readings for which condensation in the enclosure was likely",
+            )
+            .add(1, &attributes);
+        if let Some(counter) = CONDENSATION_RISKS.as_ref() {
+            counter.with_label_values(&[&sensor_data.device_id]).inc();
+        }
+    }
+
+    record_gauge(
+        &instruments,
+        &sensor_data.device_id,
+        &attributes,
+        "leak_suspected".to_string(),
+        "1 if the water level is dropping in a way that suggests a leak, 0 otherwise.".to_string(),
+        None,
+        if leak_suspected { 1.0 } else { 0.0 },
+    );
+    record_gauge(
+        &instruments,
+        &sensor_data.device_id,
+        &attributes,
+        "alerts_active".to_string(),
+        "The number of alert rules that the latest reading triggered.".to_string(),
+        None,
+        triggered_alerts.len() as f64,
+    );
+    let previous_alerts = state
+        .triggered_alerts
+        .write()
+        .await
+        .insert(sensor_data.device_id.clone(), triggered_alerts.clone())
+        .unwrap_or_default();
+    if let Some(alert_webhook) = &state.alert_webhook {
+        let new_alerts = newly_triggered_alerts(&previous_alerts, &triggered_alerts);
+        if !new_alerts.is_empty() {
+            alert_webhook.notify(&sensor_data.device_id, new_alerts);
+        }
+    }
+
+    // Sending only fails if no client is listening to the live stream
+    let _ = state.sensor_data_updates.send(sensor_data.clone());
+
+    let derived = DerivedValues {
+        tank_fill_in_percent: sensor_data.tank_fill_in_percent,
+        tank_volume_in_liters: sensor_data.tank_volume_in_liters,
+        leak_suspected,
+        data_quality,
+    };
+
+    state.latest_sensor_data.write().await.insert(
+        sensor_data.device_id.clone(),
+        SensorReading {
+            received_at: Utc::now(),
+            data: sensor_data,
+        },
+    );
+
+    derived
+}
+
+#[instrument(
+    skip(state),
+    fields(session_id, device_id, boot_count, firmware_version, validation)
+)]
+async fn handle_sensor_data(
+    State(state): State<AppState>,
+    device: Option<Extension<ProvisionedDevice>>,
+    payload: Result<Json<SensorData>, JsonRejection>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Sensor data received. Processing ...");
+
+    let sensor_data = match payload {
+        Ok(payload) => payload.0,
+        Err(rejection) => return Err(sensor_data_rejection_response(rejection)),
+    };
+    let span = tracing::Span::current();
+    span.record("session_id", sensor_data.session_id);
+    span.record("device_id", sensor_data.device_id.as_str());
+    span.record("boot_count", sensor_data.boot_count);
+    span.record("firmware_version", sensor_data.firmware_version.as_str());
+
+    if let Err(e) = check_device_id(&state, &device, &sensor_data.device_id).await {
+        error!(error = %e, "Sensor data sent without the token of the device");
+        return Err((StatusCode::FORBIDDEN, Json(ApiResponse::error(e))));
+    }
+
+    let is_new = match admit_reading(&state, &sensor_data).await {
+        Ok(is_new) => is_new,
+        Err(ReadingRejection::RateLimited) => {
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiResponse::error(RATE_LIMITED_MESSAGE)),
+            ));
+        }
+        Err(ReadingRejection::Invalid(e)) => {
+            span.record("validation", "failed");
+            error!(error = %e, field = e.field, "Invalid sensor data received");
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::validation_error(e)),
+            ));
+        }
+    };
+    span.record("validation", "passed");
+
+    if !is_new {
+        return Ok((
+            StatusCode::OK,
+            Json(
+                ApiResponse::duplicate("Data was already received")
+                    .with_next_sleep_seconds(state.device_sleep_seconds),
+            ),
+        ));
+    }
+
+    let quiet = is_quiet(&state, &sensor_data.device_id).await;
+    if quiet {
+        info!(device_id = %sensor_data.device_id, "The tank is in maintenance. Alerts are suppressed.");
+    }
+    let derived = store_sensor_data(&state, sensor_data).await;
+
+    Ok((
+        StatusCode::OK,
+        Json(
+            ApiResponse::success("Data received and processed successfully")
+                .with_next_sleep_seconds(state.device_sleep_seconds)
+                .with_derived(derived)
+                .with_quiet(quiet),
+        ),
+    ))
+}
+
+#[instrument(skip(state))]
+async fn handle_sensor_data_batch(
+    State(state): State<AppState>,
+    device: Option<Extension<ProvisionedDevice>>,
+    payload: Result<Json<Vec<SensorData>>, JsonRejection>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Sensor data batch received. Processing ...");
+
+    let batch = match payload {
+        Ok(payload) => payload.0,
+        Err(rejection) => return Err(sensor_data_rejection_response(rejection)),
+    };
+
+    let total = batch.len();
+    let mut accepted = 0;
+    let mut rejected = Vec::new();
+    let mut rate_limited = false;
+    for (index, sensor_data) in batch.into_iter().enumerate() {
+        if let Err(e) = check_device_id(&state, &device, &sensor_data.device_id).await {
+            error!(error = %e, index, "Sensor data sent without the token of the device in batch");
+            rejected.push(RejectedSensorData { index, message: e });
+            continue;
+        }
+
+        // Each reading takes a token, so that a batch can't get around the limit
+        match admit_reading(&state, &sensor_data).await {
+            Ok(is_new) => {
+                // A duplicate counts as accepted so that the device doesn't send it again
+                if is_new {
+                    store_sensor_data(&state, sensor_data).await;
+                }
+                accepted += 1;
+            }
+            Err(ReadingRejection::RateLimited) => {
+                rate_limited = true;
+                rejected.push(RejectedSensorData {
+                    index,
+                    message: RATE_LIMITED_MESSAGE.to_string(),
+                });
+            }
+            Err(ReadingRejection::Invalid(e)) => {
+                error!(error = %e, index, "Invalid sensor data received in batch");
+                rejected.push(RejectedSensorData {
+                    index,
+                    message: e.message,
+                });
+            }
         }
     }
+
+    let message = format!("Accepted {} of {} sensor readings", accepted, total);
+    if rejected.is_empty() {
+        Ok((
+            StatusCode::OK,
+            Json(ApiResponse::success(message).with_batch_result(accepted, rejected)),
+        ))
+    } else if accepted > 0 {
+        Ok((
+            StatusCode::MULTI_STATUS,
+            Json(ApiResponse::partial(message).with_batch_result(accepted, rejected)),
+        ))
+    } else if rate_limited {
+        Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiResponse::error(message).with_batch_result(accepted, rejected)),
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(message).with_batch_result(accepted, rejected)),
+        ))
+    }
 }
 
-#[instrument()]
-async fn handle_sensor_data(
-    payload: Result<Json<SensorData>, JsonRejection>,
+#[instrument(skip(state))]
+async fn handle_get_sensor_data(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<UnitsQuery>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
-    info!("Sensor data received. Processing ...");
+    info!("Sensor data requested for device {}", device_id);
 
-    let sensor_data = match payload {
-        Ok(payload) => payload.0,
-        Err(JsonRejection::MissingJsonContentType(e)) => {
-            error!("The sensor data request did not have the right `Content-Type: application/json` header. Error was {:?}", e);
+    let units_name = query.units.as_deref().unwrap_or("metric");
+    let units = match Units::parse(units_name) {
+        Some(units) => units,
+        None => {
+            error!("Invalid units requested: {}", units_name);
             return Err((
                 StatusCode::BAD_REQUEST,
-                Json(ApiResponse::error(
-                    "The data request did not have the right right `Content-Type: application/json` header.",
-                )),
+                Json(ApiResponse::error("Invalid units")),
             ));
         }
-        Err(JsonRejection::JsonDataError(e)) => {
-            // Couldn't deserialize the body into the target type
-            error!(
-                "Could not deserialize the sensor data request body. Error was {:?}",
-                e
+    };
+
+    let readings = state.latest_sensor_data.read().await;
+    match readings.get(&device_id) {
+        Some(SensorReading {
+            data: sensor_data, ..
+        }) => {
+            let leak_suspected = match state.sensor_history.write().await.get_mut(&device_id) {
+                Some(history) => detect_leak(history.make_contiguous(), &state.leak_detection),
+                None => false,
+            };
+
+            let condensation_risk = condensation_risk(
+                sensor_data.temperature_in_celcius,
+                sensor_data.humidity_in_percent,
+                condensation_threshold(&state, &device_id).await,
             );
-            return Err((
-                StatusCode::NOT_ACCEPTABLE,
-                Json(ApiResponse::error(
-                    "Could not deserialize the sensor data request body.",
-                )),
-            ));
+
+            let triggered_alerts = state
+                .triggered_alerts
+                .read()
+                .await
+                .get(&device_id)
+                .cloned()
+                .unwrap_or_default();
+
+            let latest = LatestSensorData {
+                data: sensor_data.clone(),
+                leak_suspected,
+                condensation_risk,
+                triggered_alerts,
+                units,
+            };
+            let mut body = serde_json::to_value(&latest).map_err(|e| {
+                error!(error = %e, "Failed to serialize the sensor data");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::error("Failed to serialize the sensor data")),
+                )
+            })?;
+            if units == Units::Imperial {
+                if let Some(reading) = body.as_object_mut() {
+                    convert_reading_to_imperial(reading);
+                }
+            }
+
+            Ok((StatusCode::OK, Json(body)))
         }
-        Err(JsonRejection::JsonSyntaxError(e)) => {
-            // Syntax error in the body
-            error!(
-                "The sensor data request body has syntax errors. Error was {:?}",
-                e
-            );
-            return Err((
-                StatusCode::NOT_ACCEPTABLE,
-                Json(ApiResponse::error(
-                    "The sensor data request body has syntax errors",
-                )),
-            ));
+        None => {
+            debug!("No sensor data known for device {}", device_id);
+            Err((
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error(format!(
+                    "No sensor data found for device {}",
+                    device_id
+                ))),
+            ))
         }
-        Err(JsonRejection::BytesRejection(e)) => {
-            // Failed to extract the request body
-            error!(
-                "The sensor data request body could not be extracted. Error was {:?}",
-                e
+    }
+}
+
+/// The query parameters for the live stream of readings
+#[derive(Debug, Deserialize)]
+struct StreamQuery {
+    /// Only stream the readings of this device
+    device_id: Option<String>,
+}
+
+/// Stream each new reading as a Server-Sent Event. The readings are published through a
+/// broadcast channel, so a client that falls behind misses readings instead of slowing down
+/// the ingestion. The stream ends when the service shuts down.
+#[instrument(skip(state))]
+async fn handle_stream_sensor_data(
+    State(state): State<AppState>,
+    Query(query): Query<StreamQuery>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    info!("Live stream of the sensor data requested");
+
+    let updates = BroadcastStream::new(state.sensor_data_updates.subscribe());
+    let events = updates.filter_map(move |update| match update {
+        Ok(sensor_data) => {
+            if query
+                .device_id
+                .as_ref()
+                .is_none_or(|device_id| *device_id == sensor_data.device_id)
+            {
+                Some(
+                    Event::default()
+                        .event("sensor_data")
+                        .json_data(&sensor_data),
+                )
+            } else {
+                None
+            }
+        }
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            debug!(
+                "A live stream client fell behind and missed {} readings",
+                skipped
             );
+            None
+        }
+    });
+
+    let shutting_down = WatchStream::new(state.shutting_down.subscribe())
+        .filter(|shutting_down| *shutting_down)
+        .map(|_| None);
+    let events = events
+        .map(Some)
+        .merge(shutting_down)
+        .map_while(|event| event);
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[instrument(skip(state))]
+async fn handle_get_devices(State(state): State<AppState>) -> impl IntoResponse {
+    info!("Device list requested");
+
+    let now = Utc::now();
+    let mut devices: Vec<DeviceSummary> = state
+        .latest_sensor_data
+        .read()
+        .await
+        .values()
+        .map(|reading| DeviceSummary {
+            device_id: reading.data.device_id.clone(),
+            last_seen: reading.received_at.to_rfc3339(),
+            boot_count: reading.data.boot_count,
+            status: DeviceStatus::from_last_seen(
+                &reading.received_at,
+                &now,
+                state.device_offline_after_in_seconds,
+            ),
+        })
+        .collect();
+    devices.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+
+    (StatusCode::OK, Json(DeviceList { devices }))
+}
+
+#[instrument(skip(state, payload))]
+async fn handle_set_tank_config(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    payload: Result<Json<TankConfig>, JsonRejection>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Tank configuration received for device {}", device_id);
+
+    let tank_config = match payload {
+        Ok(payload) => payload.0,
+        Err(rejection) => {
+            error!(error = %rejection, "Invalid tank configuration received");
             return Err((
-                StatusCode::NOT_ACCEPTABLE,
-                Json(ApiResponse::error(
-                    "The sensor data request body could not be extracted",
-                )),
+                rejection.status(),
+                Json(ApiResponse::error(rejection.body_text())),
             ));
         }
-        Err(e) => {
-            // `JsonRejection` is marked `#[non_exhaustive]` so match must
-            // include a catch-all case.
-            error!(
-                "Could not process the sensor data request. Error was {:?}",
-                e
-            );
+    };
+
+    if let Err(e) = tank_config.validate() {
+        error!(error = %e, "Invalid tank configuration received");
+        return Err((StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))));
+    }
+
+    state
+        .tank_configs
+        .write()
+        .await
+        .insert(device_id, tank_config);
+
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success("Tank configuration stored")),
+    ))
+}
+
+#[instrument(skip(state))]
+async fn handle_get_tank_config(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Tank configuration requested for device {}", device_id);
+
+    match state.tank_configs.read().await.get(&device_id) {
+        Some(tank_config) => Ok((StatusCode::OK, Json(tank_config.clone()))),
+        None => {
+            debug!("No tank configuration known for device {}", device_id);
+            Err((
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error(format!(
+                    "No tank configuration found for device {}",
+                    device_id
+                ))),
+            ))
+        }
+    }
+}
+
+#[instrument(skip(state, payload))]
+async fn handle_set_alert_rules(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    payload: Result<Json<AlertRules>, JsonRejection>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Alert rules received for device {}", device_id);
+
+    let alert_rules = match payload {
+        Ok(payload) => payload.0,
+        Err(rejection) => {
+            error!(error = %rejection, "Invalid alert rules received");
             return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(
-                    "Could not process the sensor data request.",
-                )),
+                rejection.status(),
+                Json(ApiResponse::error(rejection.body_text())),
             ));
         }
     };
 
-    if let Err(e) = sensor_data.validate() {
-        error!(error = %e, "Invalid sensor data received");
+    if let Err(e) = alert_rules.validate() {
+        error!(error = %e, "Invalid alert rules received");
         return Err((StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))));
     }
 
-    let device_scope_attributes = vec![
-        KeyValue::new(
-            opentelemetry_semantic_conventions::resource::DEVICE_ID,
-            sensor_data.device_id.clone(),
-        ),
-        KeyValue::new(
-            opentelemetry_semantic_conventions::resource::DEVICE_MODEL_NAME,
-            "ha-tank-sensor",
-        ),
-    ];
-    let scope = InstrumentationScope::builder("tank_level_device")
-        .with_version(sensor_data.firmware_version.clone())
-        .with_attributes(device_scope_attributes)
-        .build();
+    state
+        .alert_rules
+        .write()
+        .await
+        .insert(device_id, alert_rules);
+
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success("Alert rules stored")),
+    ))
+}
+
+#[instrument(skip(state))]
+async fn handle_get_alerts(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Alerts requested for device {}", device_id);
 
-    let meter = global::meter_with_scope(scope);
-    record_sensor_metrics(&meter, &sensor_data);
+    let rules = match state.alert_rules.read().await.get(&device_id) {
+        Some(alert_rules) => alert_rules.rules.clone(),
+        None => {
+            debug!("No alert rules known for device {}", device_id);
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error(format!(
+                    "No alert rules found for device {}",
+                    device_id
+                ))),
+            ));
+        }
+    };
+    let triggered_alerts = state
+        .triggered_alerts
+        .read()
+        .await
+        .get(&device_id)
+        .cloned()
+        .unwrap_or_default();
 
     Ok((
         StatusCode::OK,
-        Json(ApiResponse::success(
-            "Data received and processed successfully",
-        )),
+        Json(DeviceAlerts {
+            rules,
+            triggered_alerts,
+        }),
     ))
 }
 
 #[instrument(skip(state))]
 async fn handle_log_data(
     State(state): State<AppState>,
+    device: Option<Extension<ProvisionedDevice>>,
     payload: Result<Json<Vec<LogData>>, JsonRejection>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
     info!("Log data received. Processing ...");
@@ -338,6 +4005,11 @@ async fn handle_log_data(
             ));
         }
         Err(JsonRejection::BytesRejection(e)) => {
+            if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                error!("The log data request body is too large. Error was {:?}", e);
+                return Err(payload_too_large_error());
+            }
+
             // Failed to extract the request body
             error!(
                 "The log data request body could not be extracted. Error was {:?}",
@@ -361,11 +4033,27 @@ async fn handle_log_data(
         }
     };
 
+    // Check all the log messages first so that none are stored if one is for another device
+    for log_data in &log_data_list {
+        if let Err(e) = validate_device_id(&log_data.device_id) {
+            error!(error = %e, "Log data with an invalid device ID received");
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::validation_error(e)),
+            ));
+        }
+
+        if let Err(e) = check_device_id(&state, &device, &log_data.device_id).await {
+            error!(error = %e, "Log data sent without the token of the device");
+            return Err((StatusCode::FORBIDDEN, Json(ApiResponse::error(e))));
+        }
+    }
+
     for log_data in log_data_list {
         // Validate log level
-        let level = match log_data.level.to_lowercase().as_str() {
-            "error" | "warn" | "info" | "debug" | "trace" => log_data.level.to_lowercase(),
-            _ => {
+        let level = match parse_level(&log_data.level) {
+            Some(level) => level,
+            None => {
                 error!("Invalid log level received: {}", log_data.level);
                 return Err((
                     StatusCode::BAD_REQUEST,
@@ -379,9 +4067,7 @@ async fn handle_log_data(
             let mappings = state.device_time_mappings.read().await;
             if let Some(mapping) = mappings.get(&log_data.device_id) {
                 if mapping.boot_count == log_data.boot_count {
-                    let tick_diff = log_data.timestamp - mapping.first_tick;
-                    let duration = chrono::Duration::milliseconds(tick_diff as i64);
-                    Some(mapping.first_timestamp + duration)
+                    Some(mapping.timestamp_at(log_data.timestamp))
                 } else {
                     None
                 }
@@ -395,37 +4081,45 @@ async fn handle_log_data(
             .unwrap_or_else(|| Utc::now().to_rfc3339());
 
         // Log the message using tracing with the appropriate level
-        match level.as_str() {
-            "error" => error!(
+        match level {
+            tracing::Level::ERROR => error!(
                 device_id = %log_data.device_id,
                 boot_count = %log_data.boot_count,
                 device_ticks = %log_data.timestamp,
                 timestamp = %timestamp_str,
                 message = %log_data.message,
+                count = %log_data.count,
+                session_id = ?log_data.session_id,
                 "Device log"
             ),
-            "warn" => tracing::warn!(
+            tracing::Level::WARN => tracing::warn!(
                 device_id = %log_data.device_id,
                 boot_count = %log_data.boot_count,
                 device_ticks = %log_data.timestamp,
                 timestamp = %timestamp_str,
                 message = %log_data.message,
+                count = %log_data.count,
+                session_id = ?log_data.session_id,
                 "Device log"
             ),
-            "info" => info!(
+            tracing::Level::INFO => info!(
                 device_id = %log_data.device_id,
                 boot_count = %log_data.boot_count,
                 device_ticks = %log_data.timestamp,
                 timestamp = %timestamp_str,
                 message = %log_data.message,
+                count = %log_data.count,
+                session_id = ?log_data.session_id,
                 "Device log"
             ),
-            "debug" => debug!(
+            tracing::Level::DEBUG => debug!(
                 device_id = %log_data.device_id,
                 boot_count = %log_data.boot_count,
                 device_ticks = %log_data.timestamp,
                 timestamp = %timestamp_str,
                 message = %log_data.message,
+                count = %log_data.count,
+                session_id = ?log_data.session_id,
                 "Device log"
             ),
             _ => tracing::trace!(
@@ -434,9 +4128,26 @@ async fn handle_log_data(
                 device_ticks = %log_data.timestamp,
                 timestamp = %timestamp_str,
                 message = %log_data.message,
+                count = %log_data.count,
+                session_id = ?log_data.session_id,
                 "Device log"
             ),
         }
+
+        // Keep the recent messages so that they can be read back without a log pipeline
+        let mut device_logs = state.device_logs.write().await;
+        buffer_log_entry(
+            device_logs.entry(log_data.device_id.clone()).or_default(),
+            DeviceLogEntry {
+                level: level.as_str().to_lowercase(),
+                message: log_data.message,
+                boot_count: log_data.boot_count,
+                device_ticks: log_data.timestamp,
+                timestamp: timestamp_str,
+                count: log_data.count,
+            },
+            state.device_log_buffer_size,
+        );
     }
 
     Ok((
@@ -446,8 +4157,241 @@ async fn handle_log_data(
 }
 
 #[instrument(skip(state))]
+async fn handle_get_log_data(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<LogQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Log data requested for device {}", device_id);
+
+    let min_level = match query.level.as_deref() {
+        Some(level) => match parse_level(level) {
+            Some(level) => level,
+            None => {
+                error!("Invalid log level requested: {}", level);
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::error("Invalid log level")),
+                ));
+            }
+        },
+        None => tracing::Level::TRACE,
+    };
+
+    match state.device_logs.read().await.get(&device_id) {
+        Some(buffer) => Ok((
+            StatusCode::OK,
+            Json(filter_log_entries(buffer, min_level, query.limit)),
+        )),
+        None => {
+            debug!("No log data known for device {}", device_id);
+            Err((
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error(format!(
+                    "No log data found for device {}",
+                    device_id
+                ))),
+            ))
+        }
+    }
+}
+
+#[instrument(skip(state))]
+async fn handle_get_history(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("History requested for device {}", device_id);
+
+    let metric_name = query.metric.as_deref().unwrap_or("water_level");
+    let metric = match HistoryMetric::parse(metric_name) {
+        Some(metric) => metric,
+        None => {
+            error!("Invalid history metric requested: {}", metric_name);
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Invalid history metric")),
+            ));
+        }
+    };
+
+    let resolution_in_seconds = match query.resolution.as_deref() {
+        Some(resolution) => match parse_resolution(resolution) {
+            Some(seconds) => seconds,
+            None => {
+                error!("Invalid history resolution requested: {}", resolution);
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::error("Invalid history resolution")),
+                ));
+            }
+        },
+        None => DEFAULT_HISTORY_RESOLUTION_IN_SECONDS,
+    };
+
+    let units_name = query.units.as_deref().unwrap_or("metric");
+    let units = match Units::parse(units_name) {
+        Some(units) => units,
+        None => {
+            error!("Invalid units requested: {}", units_name);
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Invalid units")),
+            ));
+        }
+    };
+
+    match state.metric_history.points(&device_id) {
+        Some(points) => {
+            let mut buckets = downsample(&points, metric, resolution_in_seconds);
+            for bucket in &mut buckets {
+                bucket.min = metric.convert(bucket.min, units);
+                bucket.avg = metric.convert(bucket.avg, units);
+                bucket.max = metric.convert(bucket.max, units);
+            }
+
+            Ok((
+                StatusCode::OK,
+                Json(DeviceHistory {
+                    device_id,
+                    metric: metric_name.to_string(),
+                    unit: metric.unit(units).to_string(),
+                    resolution_in_seconds,
+                    buckets,
+                }),
+            ))
+        }
+        None => {
+            debug!("No history known for device {}", device_id);
+            Err((
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error(format!(
+                    "No history found for device {}",
+                    device_id
+                ))),
+            ))
+        }
+    }
+}
+
+#[instrument(skip(state))]
+async fn handle_provision(
+    State(state): State<AppState>,
+    headers: header::HeaderMap,
+    payload: Result<Json<ProvisionRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
+    info!("Provisioning request received. Processing ...");
+
+    let request = match payload {
+        Ok(payload) => payload.0,
+        Err(e) => {
+            error!(
+                "Could not process the provisioning request. Error was {:?}",
+                e
+            );
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(
+                    "Could not process the provisioning request.",
+                )),
+            ));
+        }
+    };
+
+    if let Err(e) = validate_hardware_id(&request.hardware_id) {
+        error!(error = %e, "Invalid hardware ID received");
+        return Err((StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))));
+    }
+
+    let mut registrations = state.device_registrations.write().await;
+
+    // A device that provisions itself again keeps its device ID and token. The hardware ID is
+    // not a secret, so the token is only returned to a device that proves it has the token.
+    if let Some(registration) = registrations.get(&request.hardware_id) {
+        let has_token = header_value(&headers, DEVICE_TOKEN_HEADER_NAME)
+            .is_some_and(|token| keys_match(&token, &registration.token));
+        if !has_token {
+            error!(
+                "Device {} is already provisioned and the request did not carry its token",
+                registration.device_id
+            );
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ApiResponse::error("The device is already provisioned")),
+            ));
+        }
+
+        info!("Device {} is already provisioned", registration.device_id);
+        return Ok((
+            StatusCode::OK,
+            Json(
+                ApiResponse::success("Device already provisioned")
+                    .with_device_registration(registration),
+            ),
+        ));
+    }
+
+    let device_id = request
+        .device_id
+        .filter(|device_id| !device_id.is_empty())
+        .unwrap_or_else(|| default_device_id(&request.hardware_id));
+    if let Err(e) = validate_device_id(&device_id) {
+        error!(error = %e, "Invalid device ID requested");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::validation_error(e)),
+        ));
+    }
+
+    if registrations
+        .values()
+        .any(|registration| registration.device_id == device_id)
+    {
+        error!("Device ID {} is already used by another device", device_id);
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(format!(
+                "The device ID {} is already used by another device",
+                device_id
+            ))),
+        ));
+    }
+
+    let registration = DeviceRegistration {
+        device_id,
+        token: new_device_token(),
+    };
+    let response =
+        ApiResponse::success("Device provisioned").with_device_registration(&registration);
+    registrations.insert(request.hardware_id.clone(), registration.clone());
+
+    // The device would be locked out after a restart if it got a token that isn't stored
+    if let Some(path) = &state.device_registrations_file {
+        if let Err(e) = save_device_registrations(path, &registrations).await {
+            error!(
+                "Failed to write the device registrations to {}. Error was {:?}",
+                path.display(),
+                e
+            );
+            registrations.remove(&request.hardware_id);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(
+                    "Could not store the device registration.",
+                )),
+            ));
+        }
+    }
+
+    info!("Provisioned device {}", registration.device_id);
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+#[instrument(skip(state), fields(session_id))]
 async fn handle_device_timing(
     State(state): State<AppState>,
+    device: Option<Extension<ProvisionedDevice>>,
     payload: Result<Json<DeviceTimingData>, JsonRejection>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse>)> {
     info!("Device timing data received. Processing ...");
@@ -490,6 +4434,14 @@ async fn handle_device_timing(
             ));
         }
         Err(JsonRejection::BytesRejection(e)) => {
+            if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                error!(
+                    "The timing data request body is too large. Error was {:?}",
+                    e
+                );
+                return Err(payload_too_large_error());
+            }
+
             // Failed to extract the request body
             error!(
                 "The timing data request body could not be extracted. Error was {:?}",
@@ -516,18 +4468,51 @@ async fn handle_device_timing(
         }
     };
 
+    tracing::Span::current().record("session_id", timing_data.session_id);
+
+    if let Err(e) = validate_device_id(&timing_data.device_id) {
+        error!(error = %e, "Timing data with an invalid device ID received");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::validation_error(e)),
+        ));
+    }
+
+    if let Err(e) = check_device_id(&state, &device, &timing_data.device_id).await {
+        error!(error = %e, "Timing data sent without the token of the device");
+        return Err((StatusCode::FORBIDDEN, Json(ApiResponse::error(e))));
+    }
+
+    if let Err(e) = validate_device_ticks(timing_data.timestamp) {
+        error!(error = %e, ticks = timing_data.timestamp, "Timing data with implausible device ticks received");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::validation_error(e)),
+        ));
+    }
+
     // Update device time mapping
     let mut mappings = state.device_time_mappings.write().await;
 
-    // Always create new mapping as this is the first contact after WiFi connection
-    mappings.insert(
-        timing_data.device_id.clone(),
-        DeviceTimeMapping {
-            boot_count: timing_data.boot_count,
-            first_tick: timing_data.timestamp,
-            first_timestamp: Utc::now(),
-        },
+    let mapping = anchor_time_mapping(
+        mappings.get(&timing_data.device_id),
+        timing_data.boot_count,
+        timing_data.timestamp,
+        Utc::now(),
+        state.ntp_resync_seconds,
     );
+    let previous_mapping = mappings.insert(timing_data.device_id.clone(), mapping);
+
+    if let Some(previous) = previous_mapping {
+        if previous.ntp_resync_seconds != state.ntp_resync_seconds {
+            info!(
+                device_id = %timing_data.device_id,
+                previous = ?previous.ntp_resync_seconds,
+                current = ?state.ntp_resync_seconds,
+                "Device NTP resync interval changed"
+            );
+        }
+    }
 
     info!(
         device_id = %timing_data.device_id,
@@ -536,14 +4521,39 @@ async fn handle_device_timing(
         "Device timing data received"
     );
 
+    // The device has no clock of its own. It uses the server time to timestamp its payloads
     Ok((
         StatusCode::OK,
-        Json(ApiResponse::success(
-            "Device timing data processed successfully",
-        )),
+        Json(
+            ApiResponse::success("Device timing data processed successfully")
+                .with_server_time()
+                .with_ntp_resync_seconds(state.ntp_resync_seconds),
+        ),
     ))
 }
 
+#[instrument()]
+async fn handle_prometheus_metrics() -> impl IntoResponse {
+    debug!("Prometheus metrics request received");
+    match PROMETHEUS_METRICS.render() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to render the Prometheus metrics. Error was {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to render the metrics")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Liveness check. The service is alive if it can respond to requests.
 #[instrument(fields())]
 async fn handle_health_check() -> impl IntoResponse {
     info!("Health check request received");
@@ -553,6 +4563,132 @@ async fn handle_health_check() -> impl IntoResponse {
     )
 }
 
+/// Readiness check. The service is ready if the last export of the telemetry succeeded.
+#[instrument(skip(state))]
+async fn handle_readiness_check(State(state): State<AppState>) -> impl IntoResponse {
+    info!("Readiness check request received");
+    if state
+        .telemetry_export_healthy
+        .load(std::sync::atomic::Ordering::Relaxed)
+    {
+        (
+            StatusCode::OK,
+            Json(ApiResponse::success("Service is ready")),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Telemetry exports are failing")),
+        )
+    }
+}
+
+/// The providers that export the telemetry
+#[derive(Clone)]
+struct TelemetryProviders {
+    meter: SdkMeterProvider,
+    logger: LoggerProvider,
+    tracer: sdktrace::TracerProvider,
+}
+
+impl TelemetryProviders {
+    /// Export the queued telemetry. Returns, in the order of `TELEMETRY_SIGNALS`, whether each
+    /// flush succeeded. This blocks until the exporters are done.
+    fn force_flush(&self) -> [bool; 3] {
+        [
+            self.meter.force_flush().is_ok(),
+            self.logger
+                .force_flush()
+                .iter()
+                .all(|result| result.is_ok()),
+            self.tracer
+                .force_flush()
+                .iter()
+                .all(|result| result.is_ok()),
+        ]
+    }
+}
+
+/// The self test result for a single telemetry signal
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct SelfTestSignalResult {
+    signal: String,
+    /// `true` if the flush succeeded, if there was one, and no export of the signal failed
+    /// during the self test
+    accepted: bool,
+    /// `true` if the exporter was flushed, in which case an accepted signal was delivered to
+    /// the collector. Otherwise the signal was only queued for the next export.
+    flushed: bool,
+}
+
+/// The result of the telemetry self test
+#[derive(Debug, Serialize, Deserialize)]
+struct SelfTestResult {
+    /// The ID that is attached to the test log message and span, so that they can be found in
+    /// the collectors
+    test_id: String,
+    timestamp: String,
+    passed: bool,
+    signals: Vec<SelfTestSignalResult>,
+}
+
+/// Telemetry self test. Emits a known metric, log message and span and reports for each of
+/// them if the export succeeded. The exporters batch the telemetry, so they are flushed first
+/// if the providers are available. Without the providers the result only confirms that the
+/// telemetry was queued.
+#[instrument(skip(state))]
+async fn handle_selftest(State(state): State<AppState>) -> impl IntoResponse {
+    info!("Self test request received");
+    let failures_before = TELEMETRY_SIGNALS.map(|signal| EXPORT_FAILURES.count(signal));
+
+    let test_id = hex::encode(rand::random::<[u8; 8]>());
+    global::meter("tank-sensor-service")
+        .u64_counter("selftest_total")
+        .with_description("The number of telemetry self tests")
+        .build()
+        .add(1, &[]);
+    info!(selftest_id = %test_id, "Telemetry self test");
+    let mut span = global::tracer("tank-sensor-service").start("selftest");
+    span.set_attribute(KeyValue::new("selftest.id", test_id.clone()));
+    span.end();
+
+    let flush_results = match state.telemetry_providers.clone() {
+        Some(providers) => {
+            match tokio::task::spawn_blocking(move || providers.force_flush()).await {
+                Ok(results) => Some(results),
+                Err(e) => {
+                    error!("Failed to flush the telemetry for the self test: {:?}", e);
+                    Some([false; 3])
+                }
+            }
+        }
+        None => None,
+    };
+
+    let signals: Vec<SelfTestSignalResult> = TELEMETRY_SIGNALS
+        .iter()
+        .enumerate()
+        .map(|(index, signal)| SelfTestSignalResult {
+            signal: signal.to_string(),
+            accepted: EXPORT_FAILURES.count(signal) == failures_before[index]
+                && flush_results.is_none_or(|results| results[index]),
+            flushed: flush_results.is_some(),
+        })
+        .collect();
+
+    let passed = signals.iter().all(|signal| signal.accepted);
+    if !passed {
+        error!("The telemetry self test {} failed", test_id);
+    }
+
+    Json(SelfTestResult {
+        test_id,
+        timestamp: Utc::now().to_rfc3339(),
+        passed,
+        signals,
+    })
+}
+
 fn init_logs(
     config: &ObservabilityConfig,
 ) -> Result<opentelemetry_sdk::logs::LoggerProvider, LogError> {
@@ -564,12 +4700,132 @@ fn init_logs(
 
     Ok(LoggerProvider::builder()
         .with_resource(RESOURCE.clone())
-        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_batch_exporter(
+            FailureReportingLogExporter { inner: exporter },
+            runtime::Tokio,
+        )
         .build())
 }
 
+/// A log exporter that records the failures to export the logs
+#[derive(Debug)]
+struct FailureReportingLogExporter<E> {
+    inner: E,
+}
+
+#[async_trait::async_trait]
+impl<E: opentelemetry_sdk::export::logs::LogExporter> opentelemetry_sdk::export::logs::LogExporter
+    for FailureReportingLogExporter<E>
+{
+    async fn export(
+        &mut self,
+        batch: opentelemetry_sdk::export::logs::LogBatch<'_>,
+    ) -> opentelemetry_sdk::logs::LogResult<()> {
+        let result = self.inner.export(batch).await;
+        if let Err(e) = &result {
+            EXPORT_FAILURES.record("logs", &e.to_string());
+        }
+
+        result
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+/// A span exporter that records the failures to export the traces
+#[derive(Debug)]
+struct FailureReportingSpanExporter<E> {
+    inner: E,
+}
+
+impl<E: opentelemetry_sdk::export::trace::SpanExporter>
+    opentelemetry_sdk::export::trace::SpanExporter for FailureReportingSpanExporter<E>
+{
+    fn export(
+        &mut self,
+        batch: Vec<opentelemetry_sdk::export::trace::SpanData>,
+    ) -> std::pin::Pin<
+        Box<
+            dyn std::future::Future<Output = opentelemetry_sdk::export::trace::ExportResult>
+                + Send
+                + 'static,
+        >,
+    > {
+        let export = self.inner.export(batch);
+        Box::pin(async move {
+            let result = export.await;
+            if let Err(e) = &result {
+                EXPORT_FAILURES.record("traces", &e.to_string());
+            }
+
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(
+        &mut self,
+    ) -> std::pin::Pin<
+        Box<
+            dyn std::future::Future<Output = opentelemetry_sdk::export::trace::ExportResult>
+                + Send
+                + 'static,
+        >,
+    > {
+        self.inner.force_flush()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+/// A metric exporter that keeps track of whether the most recent export succeeded, so that the
+/// readiness check can report when the telemetry can't be delivered.
+#[derive(Debug)]
+struct HealthReportingMetricExporter<E> {
+    inner: E,
+    healthy: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[async_trait::async_trait]
+impl<E: PushMetricExporter> PushMetricExporter for HealthReportingMetricExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricResult<()> {
+        let result = self.inner.export(metrics).await;
+        if let Err(e) = &result {
+            EXPORT_FAILURES.record("metrics", &e.to_string());
+        }
+
+        self.healthy
+            .store(result.is_ok(), std::sync::atomic::Ordering::Relaxed);
+        result
+    }
+
+    async fn force_flush(&self) -> MetricResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn temporality(&self) -> Temporality {
+        self.inner.temporality()
+    }
+}
+
 fn init_metrics(
     config: &ObservabilityConfig,
+    export_healthy: std::sync::Arc<std::sync::atomic::AtomicBool>,
 ) -> Result<opentelemetry_sdk::metrics::SdkMeterProvider, MetricError> {
     debug!("Sending metrics to: {}", config.metrics_push_url.clone());
     let exporter = MetricExporter::builder()
@@ -578,7 +4834,14 @@ fn init_metrics(
         .with_temporality(Temporality::Delta) // Measurements at different times don't mix
         .build()?;
 
-    let reader = PeriodicReader::builder(exporter, runtime::Tokio).build();
+    let exporter = HealthReportingMetricExporter {
+        inner: exporter,
+        healthy: export_healthy,
+    };
+
+    let reader = PeriodicReader::builder(exporter, runtime::Tokio)
+        .with_interval(config.metrics_export_interval)
+        .build();
 
     Ok(SdkMeterProvider::builder()
         .with_reader(reader)
@@ -594,37 +4857,391 @@ fn init_traces(config: &ObservabilityConfig) -> Result<sdktrace::TracerProvider,
         .build()?;
     Ok(sdktrace::TracerProvider::builder()
         .with_resource(RESOURCE.clone())
-        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_batch_exporter(
+            FailureReportingSpanExporter { inner: exporter },
+            runtime::Tokio,
+        )
         .build())
 }
 
+/// The OpenTelemetry instruments of a device
+///
+/// Creating an instrument for every reading is wasteful and some backends treat each new
+/// instrument as a new stream. The instruments are therefore created when they are first used and
+/// reused for the later readings of the device.
+struct DeviceInstruments {
+    firmware_version: String,
+    meter: Meter,
+    f64_gauges: std::sync::Mutex<std::collections::HashMap<String, Gauge<f64>>>,
+    u64_gauges: std::sync::Mutex<std::collections::HashMap<String, Gauge<u64>>>,
+    u64_counters: std::sync::Mutex<std::collections::HashMap<String, Counter<u64>>>,
+    f64_histograms: std::sync::Mutex<std::collections::HashMap<String, Histogram<f64>>>,
+}
+
+impl DeviceInstruments {
+    /// Create the meter of the device. The device and its firmware version are the
+    /// instrumentation scope.
+    fn new(device_id: &str, firmware_version: &str) -> Self {
+        let device_scope_attributes = vec![
+            KeyValue::new(
+                opentelemetry_semantic_conventions::resource::DEVICE_ID,
+                device_id.to_string(),
+            ),
+            KeyValue::new(
+                opentelemetry_semantic_conventions::resource::DEVICE_MODEL_NAME,
+                "ha-tank-sensor",
+            ),
+        ];
+        let scope = InstrumentationScope::builder("tank_level_device")
+            .with_version(firmware_version.to_string())
+            .with_attributes(device_scope_attributes)
+            .build();
+
+        Self::from_meter(firmware_version, global::meter_with_scope(scope))
+    }
+
+    /// Create the instruments with the given meter
+    fn from_meter(firmware_version: &str, meter: Meter) -> Self {
+        Self {
+            firmware_version: firmware_version.to_string(),
+            meter,
+            f64_gauges: std::sync::Mutex::new(std::collections::HashMap::new()),
+            u64_gauges: std::sync::Mutex::new(std::collections::HashMap::new()),
+            u64_counters: std::sync::Mutex::new(std::collections::HashMap::new()),
+            f64_histograms: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+
+    /// Get the instrument with the given name, creating it if it doesn't exist yet
+    fn cached<I: Clone>(
+        instruments: &std::sync::Mutex<std::collections::HashMap<String, I>>,
+        name: &str,
+        build: impl FnOnce() -> I,
+    ) -> I {
+        let mut instruments = instruments
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        instruments
+            .entry(name.to_string())
+            .or_insert_with(build)
+            .clone()
+    }
+
+    fn f64_gauge(&self, name: &str, description: &str, unit: Option<&str>) -> Gauge<f64> {
+        Self::cached(&self.f64_gauges, name, || {
+            let builder = self
+                .meter
+                .f64_gauge(name.to_string())
+                .with_description(description.to_string());
+            let builder = match unit {
+                Some(u) => builder.with_unit(u.to_string()),
+                None => builder,
+            };
+            builder.build()
+        })
+    }
+
+    fn u64_gauge(&self, name: &str, description: &str) -> Gauge<u64> {
+        Self::cached(&self.u64_gauges, name, || {
+            self.meter
+                .u64_gauge(name.to_string())
+                .with_description(description.to_string())
+                .build()
+        })
+    }
+
+    fn u64_counter(&self, name: &str, description: &str) -> Counter<u64> {
+        Self::cached(&self.u64_counters, name, || {
+            self.meter
+                .u64_counter(name.to_string())
+                .with_description(description.to_string())
+                .build()
+        })
+    }
+
+    /// Get the histogram with the given name. The unit and the boundaries are only used when the
+    /// histogram is created.
+    fn f64_histogram(
+        &self,
+        name: &str,
+        description: &str,
+        unit: &str,
+        boundaries: &[f64],
+    ) -> Histogram<f64> {
+        Self::cached(&self.f64_histograms, name, || {
+            self.meter
+                .f64_histogram(name.to_string())
+                .with_description(description.to_string())
+                .with_unit(unit.to_string())
+                .with_boundaries(boundaries.to_vec())
+                .build()
+        })
+    }
+
+    /// The number of instruments that have been created
+    #[cfg(test)]
+    fn count(&self) -> usize {
+        fn len<I>(instruments: &std::sync::Mutex<std::collections::HashMap<String, I>>) -> usize {
+            instruments
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .len()
+        }
+
+        len(&self.f64_gauges)
+            + len(&self.u64_gauges)
+            + len(&self.u64_counters)
+            + len(&self.f64_histograms)
+    }
+}
+
+/// The OpenTelemetry instruments of each device, by device ID
+///
+/// The instruments of a device are replaced when its firmware version changes, because the
+/// firmware version is part of the instrumentation scope.
+#[derive(Clone)]
+struct DeviceInstrumentCache {
+    devices: std::sync::Arc<
+        std::sync::Mutex<std::collections::HashMap<String, std::sync::Arc<DeviceInstruments>>>,
+    >,
+}
+
+impl DeviceInstrumentCache {
+    fn new() -> Self {
+        Self {
+            devices: std::sync::Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
+        }
+    }
+
+    /// Get the instruments of the device, creating them if the device is new or its firmware
+    /// version changed
+    fn for_device(
+        &self,
+        device_id: &str,
+        firmware_version: &str,
+    ) -> std::sync::Arc<DeviceInstruments> {
+        let mut devices = self
+            .devices
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match devices.get(device_id) {
+            Some(instruments) if instruments.firmware_version == firmware_version => {
+                instruments.clone()
+            }
+            _ => {
+                let instruments =
+                    std::sync::Arc::new(DeviceInstruments::new(device_id, firmware_version));
+                devices.insert(device_id.to_string(), instruments.clone());
+                instruments
+            }
+        }
+    }
+
+    /// Remove the instruments of the given devices
+    fn remove_devices(&self, device_ids: &std::collections::HashSet<String>) {
+        self.devices
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|device_id, _| !device_ids.contains(device_id));
+    }
+
+    /// `true` if the instruments of the device are cached
+    #[cfg(test)]
+    fn contains(&self, device_id: &str) -> bool {
+        self.devices
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .contains_key(device_id)
+    }
+}
+
 fn record_gauge<T: Into<f64>>(
-    meter: &Meter,
+    instruments: &DeviceInstruments,
+    device_id: &str,
+    attributes: &[KeyValue],
     name: String,
     description: String,
     unit: Option<String>,
     value: T,
 ) {
-    let builder = meter.f64_gauge(name).with_description(description);
-    let builder = match unit {
-        Some(u) => builder.with_unit(u),
-        None => builder,
-    };
-    let gauge = builder.build();
-    gauge.record(value.into(), &[]);
+    let value = value.into();
+    PROMETHEUS_METRICS.record(&name, &description, device_id, value);
+
+    instruments
+        .f64_gauge(&name, &description, unit.as_deref())
+        .record(value, attributes);
+}
+
+/// The bucket boundaries, in seconds, for the time it takes the device to start the WiFi
+const WIFI_START_TIME_BUCKETS_IN_SECONDS: [f64; 6] = [0.5, 1.0, 2.0, 5.0, 10.0, 30.0];
+
+/// The bucket boundaries, in seconds, for the time the device has been running. The device
+/// resets itself if it stays awake for much longer than two minutes.
+const RUN_TIME_BUCKETS_IN_SECONDS: [f64; 8] = [1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0];
+
+/// The bucket boundaries, in meters per hour, for the rate at which the water level changes
+const WATER_LEVEL_RATE_BUCKETS_IN_METERS_PER_HOUR: [f64; 10] =
+    [0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0];
+
+/// The rate, in meters per hour, at which the water level changed between two readings.
+/// Positive if water was used and negative if the tank was refilled. Zero if no time passed
+/// between the readings.
+fn level_rate(
+    previous_level_in_meters: f32,
+    current_level_in_meters: f32,
+    elapsed_seconds: f32,
+) -> f32 {
+    if elapsed_seconds <= 0.0 {
+        return 0.0;
+    }
+
+    (previous_level_in_meters - current_level_in_meters) / (elapsed_seconds / 3600.0)
+}
+
+/// Record the rate at which the water level changed since the previous reading of the device.
+/// Histograms only take positive values, so the size of the rate is recorded with a `direction`
+/// attribute that is `usage` if the level dropped and `refill` if it rose.
+fn record_level_rate(
+    instruments: &DeviceInstruments,
+    attributes: &[KeyValue],
+    previous: &SensorReading,
+    current: &SensorReading,
+) {
+    let elapsed_seconds =
+        (current.received_at - previous.received_at).num_milliseconds() as f32 / 1000.0;
+    if elapsed_seconds <= 0.0 {
+        return;
+    }
+
+    let rate = level_rate(
+        previous.data.tank_level_in_meters,
+        current.data.tank_level_in_meters,
+        elapsed_seconds,
+    );
+    let direction = if rate < 0.0 { "refill" } else { "usage" };
+    let mut attributes = attributes.to_vec();
+    attributes.push(KeyValue::new("direction", direction));
+
+    instruments
+        .f64_histogram(
+            "water_level_delta_per_hour",
+            "The rate at which the water level changed between two readings",
+            "m/h",
+            &WATER_LEVEL_RATE_BUCKETS_IN_METERS_PER_HOUR,
+        )
+        .record(rate.abs() as f64, &attributes);
+}
+
+/// Record a duration, in seconds, in a histogram with the given bucket boundaries
+fn record_duration_histogram<T: Into<f64>>(
+    instruments: &DeviceInstruments,
+    device_id: &str,
+    attributes: &[KeyValue],
+    name: String,
+    description: String,
+    boundaries_in_seconds: &[f64],
+    value_in_seconds: T,
+) {
+    let value = value_in_seconds.into();
+    PROMETHEUS_METRICS.observe(&name, &description, boundaries_in_seconds, device_id, value);
+
+    instruments
+        .f64_histogram(&name, &description, "sec", boundaries_in_seconds)
+        .record(value, attributes);
+}
+
+/// The attributes that identify the device on the OpenTelemetry metrics. Many backends flatten
+/// the instrumentation scope, so the device and its tags are also added to every data point.
+/// The session ID is left out because every wake cycle would start new time series. It is on
+/// the spans and the logs of the wake cycle instead.
+fn device_attributes(sensor_data: &SensorData) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue::new(
+        opentelemetry_semantic_conventions::resource::DEVICE_ID,
+        sensor_data.device_id.clone(),
+    )];
+    if let Some(tags) = &sensor_data.tags {
+        attributes.extend(
+            tags.iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+        );
+    }
+
+    attributes
 }
 
-fn record_sensor_metrics(meter: &Meter, sensor_data: &SensorData) {
+fn record_sensor_metrics(instruments: &DeviceInstruments, sensor_data: &SensorData) {
+    let attributes = device_attributes(sensor_data);
+
     // Update boot count
-    let boot_count = meter
-        .u64_gauge("device_boot_count")
-        .with_description("The number of times the device has booted")
-        .build();
-    boot_count.record(sensor_data.boot_count as u64, &[]);
+    instruments
+        .u64_gauge(
+            "device_boot_count",
+            "The number of times the device has booted",
+        )
+        .record(sensor_data.boot_count as u64, &attributes);
+    PROMETHEUS_METRICS.record(
+        "device_boot_count",
+        "The number of times the device has booted",
+        &sensor_data.device_id,
+        sensor_data.boot_count as f64,
+    );
+
+    // Older devices don't report the boot reason
+    if let Some(boot_reason) = &sensor_data.boot_reason {
+        instruments
+            .u64_counter(
+                "device_boots_total",
+                "The number of times the device has booted, by boot reason",
+            )
+            .add(
+                1,
+                &[
+                    attributes.as_slice(),
+                    &[KeyValue::new("boot_reason", boot_reason.clone())],
+                ]
+                .concat(),
+            );
+        if let Some(counter) = BOOT_REASONS.as_ref() {
+            counter
+                .with_label_values(&[&sensor_data.device_id, boot_reason])
+                .inc();
+        }
+    }
+
+    // A cold boot points to an unstable power supply. Older devices don't report it.
+    if sensor_data.cold_boot == Some(true) {
+        instruments
+            .u64_counter(
+                "cold_boots_total",
+                "The number of times the device booted after it lost power",
+            )
+            .add(1, &attributes);
+        if let Some(counter) = COLD_BOOTS.as_ref() {
+            counter.with_label_values(&[&sensor_data.device_id]).inc();
+        }
+    }
+
+    // The values of a reading without working sensors are the last good reading of the device.
+    // Older devices don't report it.
+    if sensor_data.sensor_ok == Some(false) {
+        tracing::warn!(device_id = %sensor_data.device_id, "Device could not read its sensors");
+        instruments
+            .u64_counter(
+                "sensor_read_failures_total",
+                "The number of times the device could not read its sensors",
+            )
+            .add(1, &attributes);
+        if let Some(counter) = SENSOR_READ_FAILURES.as_ref() {
+            counter.with_label_values(&[&sensor_data.device_id]).inc();
+        }
+    }
 
     // Update the gauges
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "run_time".to_string(),
         "The amount of time, in seconds, that the device has been running".to_string(),
         Some("sec".to_string()),
@@ -632,15 +5249,54 @@ fn record_sensor_metrics(meter: &Meter, sensor_data: &SensorData) {
     );
 
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "wifi_start_time".to_string(),
         "The amount of time, in seconds, that the wifi took to get started".to_string(),
         Some("sec".to_string()),
         sensor_data.wifi_start_time_in_seconds,
     );
 
+    // The gauges only hold the latest value. The histograms show the spread across the devices.
+    record_duration_histogram(
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
+        "run_time_distribution".to_string(),
+        "The distribution of the amount of time, in seconds, that the device has been running"
+            .to_string(),
+        &RUN_TIME_BUCKETS_IN_SECONDS,
+        sensor_data.run_time_in_seconds,
+    );
+
+    record_duration_histogram(
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
+        "wifi_start_time_distribution".to_string(),
+        "The distribution of the amount of time, in seconds, that the wifi took to get started"
+            .to_string(),
+        &WIFI_START_TIME_BUCKETS_IN_SECONDS,
+        sensor_data.wifi_start_time_in_seconds,
+    );
+
+    if let Some(rssi) = sensor_data.wifi_rssi_in_dbm {
+        record_gauge(
+            instruments,
+            &sensor_data.device_id,
+            &attributes,
+            "wifi_signal_strength".to_string(),
+            "The strength of the wifi signal received by the device".to_string(),
+            Some("dBm".to_string()),
+            rssi,
+        );
+    }
+
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "enclosure_temperature".to_string(),
         "Temperature of the device enclosure in degrees Celcius".to_string(),
         Some("C".to_string()),
@@ -648,7 +5304,9 @@ fn record_sensor_metrics(meter: &Meter, sensor_data: &SensorData) {
     );
 
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "enclosure_air_pressure".to_string(),
         "Air pressure in the device enclosure in Pascal".to_string(),
         Some("Pa".to_string()),
@@ -656,7 +5314,9 @@ fn record_sensor_metrics(meter: &Meter, sensor_data: &SensorData) {
     );
 
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "enclosure_humidity".to_string(),
         "Humidity (%) in the device enclosure as a percentage".to_string(),
         None,
@@ -664,15 +5324,32 @@ fn record_sensor_metrics(meter: &Meter, sensor_data: &SensorData) {
     );
 
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "battery_voltage".to_string(),
         "The voltage of the device battery in Volts.".to_string(),
         Some("V".to_string()),
         sensor_data.battery_voltage,
     );
 
+    // Older devices don't report the battery charge
+    if let Some(battery_in_percent) = sensor_data.battery_in_percent {
+        record_gauge(
+            instruments,
+            &sensor_data.device_id,
+            &attributes,
+            "battery_in_percent".to_string(),
+            "The estimated charge of the device battery.".to_string(),
+            Some("%".to_string()),
+            battery_in_percent,
+        );
+    }
+
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "pressure_sensor_voltage".to_string(),
         "The voltage for the pressure sensor in Volts.".to_string(),
         Some("V".to_string()),
@@ -680,7 +5357,9 @@ fn record_sensor_metrics(meter: &Meter, sensor_data: &SensorData) {
     );
 
     record_gauge(
-        meter,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
         "water_level".to_string(),
         "The level of the water in the tank".to_string(),
         Some("m".to_string()),
@@ -688,16 +5367,77 @@ fn record_sensor_metrics(meter: &Meter, sensor_data: &SensorData) {
     );
 
     record_gauge(
-        meter,
-        "water_temperature".to_string(),
-        "The temperature of the water in the tank".to_string(),
-        Some("C".to_string()),
-        sensor_data.tank_temperature_in_celcius,
+        instruments,
+        &sensor_data.device_id,
+        &attributes,
+        "water_volume".to_string(),
+        "The volume of the water in the tank".to_string(),
+        Some("L".to_string()),
+        sensor_data.tank_volume_in_liters,
     );
+
+    // Devices without a configured tank height don't report the fill percentage
+    if let Some(tank_fill) = sensor_data.tank_fill_in_percent {
+        record_gauge(
+            instruments,
+            &sensor_data.device_id,
+            &attributes,
+            "water_fill".to_string(),
+            "How full the tank is".to_string(),
+            Some("%".to_string()),
+            tank_fill,
+        );
+    }
+
+    // Older devices don't report if the pressure sensor is disconnected
+    if let Some(pressure_sensor_fault) = sensor_data.pressure_sensor_fault {
+        if pressure_sensor_fault {
+            tracing::warn!(device_id = %sensor_data.device_id, "Pressure sensor disconnected");
+        }
+
+        record_gauge(
+            instruments,
+            &sensor_data.device_id,
+            &attributes,
+            "pressure_sensor_fault".to_string(),
+            "Set to 1 if the pressure sensor is disconnected and the water level is not valid."
+                .to_string(),
+            None,
+            if pressure_sensor_fault { 1.0 } else { 0.0 },
+        );
+    }
+
+    // Older devices don't report the sample quality
+    if let Some(sample_quality) = sensor_data.sample_quality {
+        record_gauge(
+            instruments,
+            &sensor_data.device_id,
+            &attributes,
+            "sample_quality".to_string(),
+            "The fraction of the environmental samples that were genuine sensor readings."
+                .to_string(),
+            None,
+            sample_quality,
+        );
+    }
+
+    // Devices without a water temperature probe don't report the water temperature
+    if let Some(tank_temperature) = sensor_data.tank_temperature_in_celcius {
+        record_gauge(
+            instruments,
+            &sensor_data.device_id,
+            &attributes,
+            "water_temperature".to_string(),
+            "The temperature of the water in the tank".to_string(),
+            Some("C".to_string()),
+            tank_temperature,
+        );
+    }
 }
 
 fn setup_telemetry(
     config: &ObservabilityConfig,
+    export_healthy: std::sync::Arc<std::sync::atomic::AtomicBool>,
 ) -> Result<(LoggerProvider, SdkMeterProvider, sdktrace::TracerProvider)> {
     let logger_provider = init_logs(config)?;
 
@@ -730,22 +5470,172 @@ fn setup_telemetry(
         .with_thread_names(true)
         .with_filter(filter_fmt);
 
-    // Initialize the tracing subscriber with the OpenTelemetry layer and the
-    // Fmt layer.
+    // The errors that OpenTelemetry reports about itself are counted as export failures
+    let sdk_error_layer = SdkErrorCounter.with_filter(EnvFilter::new("opentelemetry=warn"));
+
+    // Initialize the tracing subscriber with the OpenTelemetry layer, the Fmt layer and the
+    // layer that counts the OpenTelemetry errors.
     tracing_subscriber::registry()
         .with(otel_layer)
         .with(fmt_layer)
+        .with(sdk_error_layer)
         .init();
 
     let tracer_provider = init_traces(config)?;
     global::set_tracer_provider(tracer_provider.clone());
 
-    let meter_provider = init_metrics(config)?;
+    let meter_provider = init_metrics(config, export_healthy)?;
     global::set_meter_provider(meter_provider.clone());
+    EXPORT_FAILURES.init_otel_counter();
 
     Ok((logger_provider, meter_provider, tracer_provider))
 }
 
+/// Create the CORS layer for the read-only endpoints, or `None` if no origins are allowed
+fn cors_layer(allowed_origins: &[String]) -> Option<CorsLayer> {
+    if allowed_origins.is_empty() {
+        return None;
+    }
+
+    let allow_origin = if allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins: Vec<header::HeaderValue> = allowed_origins
+            .iter()
+            .filter_map(|origin| match header::HeaderValue::from_str(origin) {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    tracing::warn!("Ignoring the invalid CORS origin: {}", origin);
+                    None
+                }
+            })
+            .collect();
+        AllowOrigin::list(origins)
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([axum::http::Method::GET])
+            .allow_headers([header::CONTENT_TYPE]),
+    )
+}
+
+fn create_router(state: AppState) -> Router {
+    // The limits apply to the decompressed body so that a small compressed payload can't
+    // expand into a large one
+    let sensor_body_limit = DefaultBodyLimit::max(state.sensor_body_limit_in_bytes);
+
+    let signed_routes = Router::new()
+        .route("/api/v1/sensor", post(handle_sensor_data))
+        .route("/api/v1/sensor/batch", post(handle_sensor_data_batch))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_payload_signature,
+        ))
+        // The checksum is verified first so that a corrupted payload isn't reported as a
+        // payload with an invalid signature
+        .route_layer(middleware::from_fn(verify_payload_checksum))
+        // The middleware read the body, so the limit has to be set before they run
+        .layer(sensor_body_limit);
+
+    // Provisioned devices may only send data for their own device ID
+    let device_routes = Router::new()
+        .merge(signed_routes)
+        .route(
+            "/api/v1/timing",
+            post(handle_device_timing).layer(sensor_body_limit),
+        )
+        .route(
+            "/api/v1/logs",
+            post(handle_log_data).layer(DefaultBodyLimit::max(state.log_body_limit_in_bytes)),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            check_device_token,
+        ));
+
+    let ingestion_routes = Router::new()
+        .merge(device_routes)
+        .route("/api/v1/provision", post(handle_provision))
+        .route("/api/v1/selftest", post(handle_selftest))
+        .route("/api/v1/config/{device_id}", post(handle_set_tank_config))
+        .route("/api/v1/alerts/{device_id}", post(handle_set_alert_rules))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_ingest_api_key,
+        ))
+        // Devices may compress the payloads. They are decompressed before the signature is
+        // verified because the devices sign the uncompressed payload.
+        .layer(RequestDecompressionLayer::new());
+
+    // Browser based dashboards may read the data, but only the devices may send data
+    let read_routes = Router::new()
+        .route("/api/v1/devices", get(handle_get_devices))
+        .route("/api/v1/stream", get(handle_stream_sensor_data))
+        .route("/api/v1/sensor/{device_id}", get(handle_get_sensor_data))
+        .route("/api/v1/config/{device_id}", get(handle_get_tank_config))
+        .route("/api/v1/alerts/{device_id}", get(handle_get_alerts))
+        .route("/api/v1/logs/{device_id}", get(handle_get_log_data))
+        .route("/api/v1/history/{device_id}", get(handle_get_history));
+    let read_routes = match cors_layer(&state.cors_allowed_origins) {
+        Some(cors) => read_routes.layer(cors),
+        None => read_routes,
+    };
+
+    Router::new()
+        .merge(ingestion_routes)
+        .merge(read_routes)
+        .route("/health", get(handle_health_check))
+        .route("/health/live", get(handle_health_check))
+        .route("/health/ready", get(handle_readiness_check))
+        .route("/metrics", get(handle_prometheus_metrics))
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+/// Wait until the process is asked to stop, either through Ctrl+C or, on Unix, through SIGTERM
+/// which is what container orchestrators send before killing the container.
+async fn signal_handler() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install the Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl+C. Shutting down ..."),
+        _ = terminate => info!("Received SIGTERM. Shutting down ..."),
+    }
+}
+
+/// Serve the application until the shutdown future completes. The live streams of the state
+/// are ended and the other in-flight requests are allowed to finish before this returns.
+async fn serve_until_shutdown(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    state: AppState,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown.await;
+            state.end_live_streams();
+        })
+        .await
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let port = std::env::var("PORT")
@@ -760,34 +5650,246 @@ async fn main() -> Result<()> {
             .unwrap_or_else(|_| "http://localhost:4317".to_string()),
         logs_push_url: std::env::var("LOGS_PUSH_URL")
             .unwrap_or_else(|_| "http://localhost:4317".to_string()),
+        metrics_export_interval: std::env::var("METRICS_EXPORT_INTERVAL_SECONDS")
+            .map(|value| {
+                parse_metrics_export_interval(&value).expect(
+                    "METRICS_EXPORT_INTERVAL_SECONDS must be a number of seconds between 1 and 600",
+                )
+            })
+            .unwrap_or(std::time::Duration::from_secs(
+                DEFAULT_METRICS_EXPORT_INTERVAL_IN_SECONDS,
+            )),
     };
 
     // Initialize telemetry
-    let (logs, metrics, tracing) = setup_telemetry(&config)?;
+    let telemetry_export_healthy = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let (logs, metrics, tracing) = setup_telemetry(&config, telemetry_export_healthy.clone())?;
     info!("Telemetry initialized");
 
+    let ingest_api_key = std::env::var("INGEST_API_KEY").ok();
+    if ingest_api_key.as_deref().unwrap_or_default().is_empty() {
+        tracing::warn!(
+            "INGEST_API_KEY is not set. The ingestion endpoints accept unauthenticated requests."
+        );
+    }
+
+    let ingest_hmac_secret = std::env::var("INGEST_HMAC_SECRET").ok();
+    if ingest_hmac_secret.as_deref().unwrap_or_default().is_empty() {
+        tracing::warn!("INGEST_HMAC_SECRET is not set. Unsigned sensor data is accepted.");
+    }
+
+    let signature_max_age_in_seconds = std::env::var("SIGNATURE_MAX_AGE_SECONDS")
+        .map(|value| {
+            value
+                .parse::<i64>()
+                .expect("SIGNATURE_MAX_AGE_SECONDS must be a valid number of seconds")
+        })
+        .unwrap_or(DEFAULT_SIGNATURE_MAX_AGE_IN_SECONDS);
+
+    let mut leak_detection = LeakDetectionSettings::default();
+    if let Ok(value) = std::env::var("LEAK_DETECTION_MIN_RATE_M_PER_HOUR") {
+        leak_detection.minimum_drop_rate_in_meters_per_hour = value
+            .parse::<f32>()
+            .expect("LEAK_DETECTION_MIN_RATE_M_PER_HOUR must be a valid number");
+    }
+
+    // A maximum rate of zero turns the check off
+    let mut level_plausibility = LevelPlausibilitySettings::default();
+    if let Ok(value) = std::env::var("LEVEL_MAX_CHANGE_RATE_M_PER_HOUR") {
+        let maximum_rate = value
+            .parse::<f32>()
+            .expect("LEVEL_MAX_CHANGE_RATE_M_PER_HOUR must be a valid number");
+        level_plausibility.maximum_change_rate_in_meters_per_hour =
+            Some(maximum_rate).filter(|rate| *rate > 0.0);
+    }
+    if let Ok(value) = std::env::var("LEVEL_CHANGE_TOLERANCE_M") {
+        level_plausibility.tolerance_in_meters = value
+            .parse::<f32>()
+            .expect("LEVEL_CHANGE_TOLERANCE_M must be a valid number");
+    }
+
+    let device_sleep_seconds = std::env::var("DEVICE_SLEEP_SECONDS").ok().map(|value| {
+        value
+            .parse::<u32>()
+            .expect("DEVICE_SLEEP_SECONDS must be a valid number of seconds")
+    });
+
+    let ntp_resync_seconds = std::env::var("NTP_RESYNC_SECONDS").ok().map(|value| {
+        value
+            .parse::<u32>()
+            .expect("NTP_RESYNC_SECONDS must be a valid number of seconds")
+    });
+
+    let device_log_buffer_size = std::env::var("DEVICE_LOG_BUFFER_SIZE")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .expect("DEVICE_LOG_BUFFER_SIZE must be a valid number")
+        })
+        .unwrap_or(DEFAULT_DEVICE_LOG_BUFFER_SIZE);
+
+    let history_max_points = std::env::var("HISTORY_MAX_POINTS")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .expect("HISTORY_MAX_POINTS must be a valid number")
+        })
+        .unwrap_or(DEFAULT_HISTORY_MAX_POINTS);
+
+    let device_offline_after_in_seconds = std::env::var("DEVICE_OFFLINE_AFTER_SECONDS")
+        .map(|value| {
+            value
+                .parse::<i64>()
+                .expect("DEVICE_OFFLINE_AFTER_SECONDS must be a valid number of seconds")
+        })
+        .unwrap_or(DEFAULT_DEVICE_OFFLINE_AFTER_IN_SECONDS);
+
+    let device_data_ttl_in_seconds = std::env::var("DEVICE_DATA_TTL_SECONDS")
+        .map(|value| {
+            value
+                .parse::<i64>()
+                .expect("DEVICE_DATA_TTL_SECONDS must be a valid number of seconds")
+        })
+        .unwrap_or(DEFAULT_DEVICE_DATA_TTL_IN_SECONDS);
+
+    let sensor_body_limit_in_bytes = std::env::var("SENSOR_BODY_LIMIT_BYTES")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .expect("SENSOR_BODY_LIMIT_BYTES must be a valid number of bytes")
+        })
+        .unwrap_or(DEFAULT_SENSOR_BODY_LIMIT_IN_BYTES);
+
+    let log_body_limit_in_bytes = std::env::var("LOG_BODY_LIMIT_BYTES")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .expect("LOG_BODY_LIMIT_BYTES must be a valid number of bytes")
+        })
+        .unwrap_or(DEFAULT_LOG_BODY_LIMIT_IN_BYTES);
+
+    let validation_ranges = match std::env::var("SENSOR_DATA_RANGE_OVERRIDES") {
+        Ok(overrides) => override_validation_ranges(&SENSOR_DATA_RANGES, &overrides)
+            .expect("SENSOR_DATA_RANGE_OVERRIDES must be formatted as <field>=<min>:<max>"),
+        Err(_) => SENSOR_DATA_RANGES.to_vec(),
+    };
+
+    // Without a file the devices lose their registration, and with it their token, when the
+    // service restarts
+    let device_registrations_file = std::env::var("DEVICE_REGISTRATIONS_FILE")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(std::path::PathBuf::from);
+    let device_registrations = match &device_registrations_file {
+        Some(path) => load_device_registrations(path)
+            .expect("DEVICE_REGISTRATIONS_FILE must be a readable file with device registrations"),
+        None => {
+            tracing::warn!(
+                "DEVICE_REGISTRATIONS_FILE is not set. The device registrations are lost when the service restarts."
+            );
+            std::collections::HashMap::new()
+        }
+    };
+
+    let cors_allowed_origins: Vec<String> = std::env::var("CORS_ALLOWED_ORIGINS")
+        .map(|origins| {
+            origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let rate_limiter = std::env::var("DEVICE_RATE_LIMIT_PER_MINUTE")
+        .ok()
+        .map(|value| {
+            let requests_per_minute = value
+                .parse::<u32>()
+                .expect("DEVICE_RATE_LIMIT_PER_MINUTE must be a valid number");
+            let burst = std::env::var("DEVICE_RATE_LIMIT_BURST")
+                .map(|value| {
+                    value
+                        .parse::<u32>()
+                        .expect("DEVICE_RATE_LIMIT_BURST must be a valid number")
+                })
+                .unwrap_or(requests_per_minute);
+            let idle_timeout = std::env::var("DEVICE_RATE_LIMIT_IDLE_SECONDS")
+                .map(|value| {
+                    std::time::Duration::from_secs(
+                        value
+                            .parse::<u64>()
+                            .expect("DEVICE_RATE_LIMIT_IDLE_SECONDS must be a valid number"),
+                    )
+                })
+                .unwrap_or(DEFAULT_RATE_LIMIT_IDLE_TIMEOUT);
+            DeviceRateLimiter::new(requests_per_minute, burst).with_idle_timeout(idle_timeout)
+        });
+
+    let alert_webhook = std::env::var("ALERT_WEBHOOK_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            let min_interval = std::env::var("ALERT_WEBHOOK_MIN_INTERVAL_SECONDS")
+                .map(|value| {
+                    std::time::Duration::from_secs(
+                        value
+                            .parse::<u64>()
+                            .expect("ALERT_WEBHOOK_MIN_INTERVAL_SECONDS must be a valid number"),
+                    )
+                })
+                .unwrap_or(DEFAULT_ALERT_WEBHOOK_MIN_INTERVAL);
+            AlertWebhook::new(url)
+                .expect("Failed to create the client for the alert webhook")
+                .with_min_interval(min_interval)
+        });
+
     // Create app state
-    let state = AppState::new();
+    let state = AppState::new()
+        .with_ingest_api_key(ingest_api_key)
+        .with_ingest_hmac_secret(ingest_hmac_secret)
+        .with_signature_max_age_in_seconds(signature_max_age_in_seconds)
+        .with_leak_detection(leak_detection)
+        .with_level_plausibility(level_plausibility)
+        .with_device_sleep_seconds(device_sleep_seconds)
+        .with_ntp_resync_seconds(ntp_resync_seconds)
+        .with_telemetry_export_healthy(telemetry_export_healthy)
+        .with_telemetry_providers(TelemetryProviders {
+            meter: metrics.clone(),
+            logger: logs.clone(),
+            tracer: tracing.clone(),
+        })
+        .with_device_log_buffer_size(device_log_buffer_size)
+        .with_history_max_points(history_max_points)
+        .with_device_offline_after_in_seconds(device_offline_after_in_seconds)
+        .with_device_data_ttl_in_seconds(device_data_ttl_in_seconds)
+        .with_sensor_body_limit_in_bytes(sensor_body_limit_in_bytes)
+        .with_log_body_limit_in_bytes(log_body_limit_in_bytes)
+        .with_validation_ranges(validation_ranges)
+        .with_device_registrations(device_registrations, device_registrations_file)
+        .with_cors_allowed_origins(cors_allowed_origins)
+        .with_rate_limiter(rate_limiter)
+        .with_alert_webhook(alert_webhook);
+
+    tokio::spawn(sweep_stale_devices(state.clone()));
 
     // Create router with routes
-    let app = Router::new()
-        .route("/api/v1/sensor", post(handle_sensor_data))
-        .route("/api/v1/timing", post(handle_device_timing))
-        .route("/api/v1/logs", post(handle_log_data))
-        .route("/health", get(handle_health_check))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+    let app = create_router(state.clone());
 
     info!("Server starting on port {}", port);
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port))
         .await
         .unwrap();
-    axum::serve(listener, app).await?;
+    let serve_result = serve_until_shutdown(listener, app, state, signal_handler()).await;
 
+    // Flush the remaining telemetry, even if the server stopped because of an error
+    info!("Server stopped. Flushing telemetry ...");
     tracing.shutdown()?;
     metrics.shutdown()?;
     logs.shutdown()?;
 
+    serve_result?;
+
     Ok(())
 }
@@ -1,10 +1,12 @@
 use super::*;
-use axum::body::to_bytes;
+use axum::body::{to_bytes, Body};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::Json;
 use opentelemetry::global;
+use opentelemetry::metrics::MeterProvider;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
+use tower::ServiceExt;
 use tracing_subscriber::fmt::TestWriter;
 
 // SensorData
@@ -14,16 +16,30 @@ fn create_valid_sensor_data() -> SensorData {
         device_id: "test-device-001".to_string(),
         firmware_version: "1.0.0".to_string(),
         boot_count: 1,
+        reading_seq: None,
         run_time_in_seconds: 10.5,
         wifi_start_time_in_seconds: 2.5,
+        wifi_rssi_in_dbm: Some(-60),
+        sensor_ok: Some(true),
         temperature_in_celcius: 25.0,
         humidity_in_percent: 50.0,
         pressure_in_pascal: 101325.0, // standard atmospheric pressure
         brightness_in_percent: 50.0,  // Added missing field
         battery_voltage: 3.7,
+        battery_in_percent: Some(80.0),
         pressure_sensor_voltage: 5.0,
+        pressure_sensor_fault: Some(false),
         tank_level_in_meters: 1.5,
-        tank_temperature_in_celcius: 20.0,
+        tank_volume_in_liters: 10602.9, // 1.5m in a cylinder with a 1.5m radius
+        tank_temperature_in_celcius: Some(20.0),
+        sample_quality: Some(1.0),
+        tank_fill_in_percent: Some(75.0),
+        boot_reason: Some("timer_wake".to_string()),
+        cold_boot: Some(false),
+        session_id: None,
+        raw_voltages: None,
+        tags: None,
+        data_quality: None,
     }
 }
 
@@ -43,11 +59,141 @@ fn test_invalid_boot_count() {
     let result = data.validate();
     assert!(result.is_err(), "Boot count of 0 should be invalid");
     assert_eq!(
-        result.unwrap_err(),
+        result.unwrap_err().message,
         "The device boot count should at least be 1.".to_string()
     );
 }
 
+#[test]
+fn test_valid_device_id() {
+    let mut data = create_valid_sensor_data();
+    for device_id in [
+        "tank_1",
+        "garden-tank",
+        "site.north.tank-2",
+        &"a".repeat(64),
+    ] {
+        data.device_id = device_id.to_string();
+        assert!(
+            data.validate().is_ok(),
+            "Device ID {} should be valid",
+            device_id
+        );
+    }
+}
+
+#[test]
+fn test_invalid_device_id() {
+    let mut data = create_valid_sensor_data();
+
+    // Test empty
+    data.device_id = String::new();
+    let error = data.validate().unwrap_err();
+    assert_eq!(error.field, "device_id");
+    assert_eq!(error.code, ValidationErrorCode::Empty);
+    assert_eq!(error.message, "The device ID should not be empty.");
+
+    // Test too long
+    data.device_id = "a".repeat(MAX_DEVICE_ID_LENGTH + 1);
+    let error = data.validate().unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::TooLong);
+    assert_eq!(
+        error.message,
+        "The device ID should be at most 64 characters long."
+    );
+
+    // Test characters that are not allowed
+    for device_id in ["garden tank", "tank/1", "tank\"1", "tänk"] {
+        data.device_id = device_id.to_string();
+        let error = data.validate().unwrap_err();
+        assert_eq!(
+            error.code,
+            ValidationErrorCode::InvalidFormat,
+            "Device ID {} should be invalid",
+            device_id
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_timing_and_log_data_with_an_invalid_device_id_are_rejected() {
+    let app = create_router(AppState::new());
+    for (uri, body) in [
+        (
+            "/api/v1/timing",
+            r#"{"device_id":"","boot_count":1,"timestamp":1000}"#,
+        ),
+        (
+            "/api/v1/logs",
+            r#"[{"device_id":"garden tank","level":"INFO","message":"Hello","boot_count":1,"timestamp":1000}]"#,
+        ),
+    ] {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+
+        let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(api_response.field.as_deref(), Some("device_id"));
+    }
+}
+
+#[test]
+fn test_valid_firmware_version() {
+    let mut data = create_valid_sensor_data();
+    for version in ["0.1.0", "1.20.300", "1.0.0-beta.1", "2.0.0-rc-1"] {
+        data.firmware_version = version.to_string();
+        assert!(
+            data.validate().is_ok(),
+            "Firmware version {} should be valid",
+            version
+        );
+    }
+}
+
+#[test]
+fn test_invalid_firmware_version() {
+    let mut data = create_valid_sensor_data();
+
+    // Test empty
+    data.firmware_version = String::new();
+    assert_eq!(
+        data.validate().unwrap_err().message,
+        "The firmware version should not be empty.".to_string()
+    );
+
+    // Test too long
+    data.firmware_version = format!("1.0.0-{}", "a".repeat(27));
+    assert_eq!(
+        data.validate().unwrap_err().message,
+        "The firmware version should be at most 32 characters long.".to_string()
+    );
+
+    // Test malformed
+    for version in [
+        "NOT FOUND",
+        "1.0",
+        "1.0.0.0",
+        "1.a.0",
+        "1.0.0-",
+        "1.0.0-beta..1",
+    ] {
+        data.firmware_version = version.to_string();
+        assert_eq!(
+            data.validate().unwrap_err().message,
+            "The firmware version should be formatted as MAJOR.MINOR.PATCH with an optional pre-release suffix."
+                .to_string(),
+            "Firmware version {} should be invalid",
+            version
+        );
+    }
+}
+
 #[test]
 fn test_invalid_run_time() {
     let mut data = create_valid_sensor_data();
@@ -55,8 +201,8 @@ fn test_invalid_run_time() {
     let result = data.validate();
     assert!(result.is_err(), "A negative run time should be invalid");
     assert_eq!(
-        result.unwrap_err(),
-        "Run time out of reasonable range (> 0.0)".to_string()
+        result.unwrap_err().message,
+        "Run time out of reasonable range (0s or more)".to_string()
     );
 }
 
@@ -70,8 +216,43 @@ fn test_invalid_wifi_start_time() {
         "A negative wifi start time should be invalid"
     );
     assert_eq!(
-        result.unwrap_err(),
-        "Wifi start time out of reasonable range (> 0.0)".to_string()
+        result.unwrap_err().message,
+        "Wifi start time out of reasonable range (0s or more)".to_string()
+    );
+}
+
+#[test]
+fn test_invalid_wifi_rssi() {
+    // Test too low
+    let mut data = create_valid_sensor_data();
+    data.wifi_rssi_in_dbm = Some(-101);
+    assert!(
+        data.validate().is_err(),
+        "Wifi signal strength below -100dBm should be invalid"
+    );
+
+    // Test too high
+    data.wifi_rssi_in_dbm = Some(1);
+    assert!(
+        data.validate().is_err(),
+        "Wifi signal strength above 0dBm should be invalid"
+    );
+
+    // Test error message
+    let result = data.validate();
+    assert_eq!(
+        result.unwrap_err().message,
+        "Wifi signal strength out of reasonable range (-100dBm to 0dBm)".to_string()
+    );
+}
+
+#[test]
+fn test_missing_wifi_rssi() {
+    let mut data = create_valid_sensor_data();
+    data.wifi_rssi_in_dbm = None;
+    assert!(
+        data.validate().is_ok(),
+        "A missing wifi signal strength should be valid"
     );
 }
 
@@ -95,7 +276,7 @@ fn test_invalid_temperature() {
     // Test error message
     let result = data.validate();
     assert_eq!(
-        result.unwrap_err(),
+        result.unwrap_err().message,
         "Temperature out of reasonable range (-50°C to 100°C)".to_string()
     );
 }
@@ -120,8 +301,8 @@ fn test_invalid_humidity() {
     // Test error message
     let result = data.validate();
     assert_eq!(
-        result.unwrap_err(),
-        "Humidity must be between 0% and 100%".to_string()
+        result.unwrap_err().message,
+        "Humidity out of reasonable range (0% to 100%)".to_string()
     );
 }
 
@@ -145,11 +326,42 @@ fn test_invalid_pressure() {
     // Test error message
     let result = data.validate();
     assert_eq!(
-        result.unwrap_err(),
-        "Pressure out of reasonable range (500-1500 hPa)".to_string()
+        result.unwrap_err().message,
+        "Pressure out of reasonable range (500hPa to 1500hPa)".to_string()
     );
 }
 
+#[test]
+fn test_pressure_boundaries() {
+    let mut data = create_valid_sensor_data();
+
+    for pressure in [MIN_AIR_PRESSURE_IN_PASCAL, MAX_AIR_PRESSURE_IN_PASCAL] {
+        data.pressure_in_pascal = pressure as f32;
+        assert!(
+            data.validate().is_ok(),
+            "A pressure of {}Pa should be valid",
+            pressure
+        );
+    }
+
+    // The standard atmospheric pressure
+    data.pressure_in_pascal = 101.325e3;
+    assert!(data.validate().is_ok());
+
+    for pressure in [
+        MIN_AIR_PRESSURE_IN_PASCAL - 1.0,
+        MAX_AIR_PRESSURE_IN_PASCAL + 1.0,
+    ] {
+        data.pressure_in_pascal = pressure as f32;
+        assert_eq!(
+            data.validate().unwrap_err().message,
+            "Pressure out of reasonable range (500hPa to 1500hPa)".to_string(),
+            "A pressure of {}Pa should be invalid",
+            pressure
+        );
+    }
+}
+
 #[test]
 fn test_invalid_battery_voltage() {
     // Test too low
@@ -170,8 +382,8 @@ fn test_invalid_battery_voltage() {
     // Test error message
     let result = data.validate();
     assert_eq!(
-        result.unwrap_err(),
-        "Battery voltage out of reasonable range (0.0V to 15.0V)".to_string()
+        result.unwrap_err().message,
+        "Battery voltage out of reasonable range (0V to 15V)".to_string()
     );
 }
 
@@ -195,8 +407,8 @@ fn test_invalid_pressure_sensor_voltage() {
     // Test error message
     let result = data.validate();
     assert_eq!(
-        result.unwrap_err(),
-        "Pressure sensor voltage out of reasonable range (0.0V to 32.0V)".to_string()
+        result.unwrap_err().message,
+        "Pressure sensor voltage out of reasonable range (0V to 32V)".to_string()
     );
 }
 
@@ -220,8 +432,33 @@ fn test_invalid_tank_level() {
     // Test error message
     let result = data.validate();
     assert_eq!(
-        result.unwrap_err(),
-        "Tank water level out of reasonable range (0.0m to 5.0m)".to_string()
+        result.unwrap_err().message,
+        "Tank water level out of reasonable range (0m to 5m)".to_string()
+    );
+}
+
+#[test]
+fn test_invalid_tank_volume() {
+    // Test too low
+    let mut data = create_valid_sensor_data();
+    data.tank_volume_in_liters = -0.1;
+    assert!(
+        data.validate().is_err(),
+        "Tank volume below 0L should be invalid"
+    );
+
+    // Test too high
+    data.tank_volume_in_liters = 100.1e3;
+    assert!(
+        data.validate().is_err(),
+        "Tank volume above 100000L should be invalid"
+    );
+
+    // Test error message
+    let result = data.validate();
+    assert_eq!(
+        result.unwrap_err().message,
+        "Tank water volume out of reasonable range (0L to 100000L)".to_string()
     );
 }
 
@@ -229,14 +466,14 @@ fn test_invalid_tank_level() {
 fn test_invalid_tank_temperature() {
     // Test too low
     let mut data = create_valid_sensor_data();
-    data.tank_temperature_in_celcius = -50.1;
+    data.tank_temperature_in_celcius = Some(-50.1);
     assert!(
         data.validate().is_err(),
         "Tank temperature below -50°C should be invalid"
     );
 
     // Test too high
-    data.tank_temperature_in_celcius = 100.1;
+    data.tank_temperature_in_celcius = Some(100.1);
     assert!(
         data.validate().is_err(),
         "Tank temperature above 100°C should be invalid"
@@ -245,143 +482,4701 @@ fn test_invalid_tank_temperature() {
     // Test error message
     let result = data.validate();
     assert_eq!(
-        result.unwrap_err(),
+        result.unwrap_err().message,
         "Tank water temperature out of reasonable range (-50°C to 100°C)".to_string()
     );
 }
 
 #[test]
-fn test_boundary_values() {
+fn test_missing_tank_temperature() {
     let mut data = create_valid_sensor_data();
-
-    // Test lower boundaries
-    data.boot_count = 1;
-    data.run_time_in_seconds = 0.0;
-    data.wifi_start_time_in_seconds = 0.0;
-    data.temperature_in_celcius = -50.0;
-    data.humidity_in_percent = 0.0;
-    data.pressure_in_pascal = 50.0e3;
-    data.battery_voltage = 0.0;
-    data.pressure_sensor_voltage = 0.0;
-    data.tank_level_in_meters = 0.0;
-    data.tank_temperature_in_celcius = -50.0;
+    data.tank_temperature_in_celcius = None;
     assert!(
         data.validate().is_ok(),
-        "Lower boundary values should be valid"
+        "A missing tank temperature should be valid"
     );
+}
 
-    // Test upper boundaries
-    data.temperature_in_celcius = 100.0;
-    data.humidity_in_percent = 100.0;
-    data.pressure_in_pascal = 150.0e3;
-    data.battery_voltage = 15.0;
-    data.pressure_sensor_voltage = 32.0;
-    data.tank_level_in_meters = 5.0;
-    data.tank_temperature_in_celcius = 100.0;
+#[test]
+fn test_tank_temperature_is_independent_of_enclosure_temperature() {
+    let mut data = create_valid_sensor_data();
+    data.temperature_in_celcius = 35.0;
+    data.tank_temperature_in_celcius = Some(12.5);
+
+    let json = serde_json::to_value(&data).unwrap();
+    assert_eq!(json["temperature_in_celcius"], 35.0);
+    assert_eq!(json["tank_temperature_in_celcius"], 12.5);
+
+    // A device without a water temperature probe reports null
+    let mut json = json;
+    json["tank_temperature_in_celcius"] = serde_json::Value::Null;
+    let parsed: SensorData = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.temperature_in_celcius, 35.0);
+    assert_eq!(parsed.tank_temperature_in_celcius, None);
+}
+
+#[test]
+fn test_invalid_sample_quality() {
+    // Test too low
+    let mut data = create_valid_sensor_data();
+    data.sample_quality = Some(-0.1);
+    assert!(
+        data.validate().is_err(),
+        "Sample quality below 0.0 should be invalid"
+    );
+
+    // Test too high
+    data.sample_quality = Some(1.1);
+    assert!(
+        data.validate().is_err(),
+        "Sample quality above 1.0 should be invalid"
+    );
+
+    // Test error message
+    let result = data.validate();
+    assert_eq!(
+        result.unwrap_err().message,
+        "Sample quality out of reasonable range (0 to 1)".to_string()
+    );
+
+    // Test missing
+    data.sample_quality = None;
     assert!(
         data.validate().is_ok(),
-        "Upper boundary values should be valid"
+        "A missing sample quality should be valid"
     );
 }
 
 #[test]
-fn test_api_response_success() {
-    let response = ApiResponse::success("Test message");
-    assert_eq!(response.status, "success");
-    assert_eq!(response.message, "Test message");
-    // We can't easily test the exact timestamp, but we can check it's not empty
-    assert!(!response.timestamp.is_empty());
+fn test_valid_battery_in_percent() {
+    let mut data = create_valid_sensor_data();
+
+    // Empty, half charged and full batteries
+    for battery_in_percent in [0.0, 50.0, 100.0] {
+        data.battery_in_percent = Some(battery_in_percent);
+        assert!(
+            data.validate().is_ok(),
+            "A battery charge of {}% should be valid",
+            battery_in_percent
+        );
+    }
+
+    // Test missing
+    data.battery_in_percent = None;
+    assert!(
+        data.validate().is_ok(),
+        "A missing battery charge should be valid"
+    );
 }
 
 #[test]
-fn test_api_response_error() {
-    let response = ApiResponse::error("Error message");
-    assert_eq!(response.status, "error");
-    assert_eq!(response.message, "Error message");
-    assert!(!response.timestamp.is_empty());
+fn test_invalid_battery_in_percent() {
+    let mut data = create_valid_sensor_data();
+    data.battery_in_percent = Some(-1.0);
+    assert!(
+        data.validate().is_err(),
+        "A battery charge below 0% should be invalid"
+    );
+
+    data.battery_in_percent = Some(100.5);
+    let error = data.validate().unwrap_err();
+    assert_eq!(error.field, "battery_in_percent");
+    assert_eq!(
+        error.message,
+        "Battery charge out of reasonable range (0% to 100%)".to_string()
+    );
 }
 
-#[tokio::test]
-async fn test_health_check() {
-    // Initialize tracing for the test
-    let _ = tracing_subscriber::fmt()
-        .with_writer(TestWriter::new())
-        .try_init();
+#[test]
+fn test_valid_tank_fill() {
+    let mut data = create_valid_sensor_data();
 
-    let response = handle_health_check().await.into_response();
-    assert_eq!(response.status(), StatusCode::OK);
+    // Empty, half full and full tanks
+    for tank_fill in [0.0, 50.0, 100.0] {
+        data.tank_fill_in_percent = Some(tank_fill);
+        assert!(
+            data.validate().is_ok(),
+            "A tank fill of {}% should be valid",
+            tank_fill
+        );
+    }
 
-    // Convert the response body to bytes and then to a string
-    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
-    let body_str = String::from_utf8(body_bytes.to_vec()).unwrap();
+    // Test missing
+    data.tank_fill_in_percent = None;
+    assert!(
+        data.validate().is_ok(),
+        "A missing tank fill should be valid"
+    );
+}
 
-    // Parse the JSON response
-    let api_response: ApiResponse = serde_json::from_str(body_str.as_str()).unwrap();
-    assert_eq!(api_response.status, "success");
-    assert_eq!(api_response.message, "Service is healthy");
+#[test]
+fn test_invalid_tank_fill() {
+    // Test too low
+    let mut data = create_valid_sensor_data();
+    data.tank_fill_in_percent = Some(-0.1);
+    assert!(
+        data.validate().is_err(),
+        "A tank fill below 0% should be invalid"
+    );
+
+    // Test overfull
+    data.tank_fill_in_percent = Some(103.0);
+    assert!(
+        data.validate().is_err(),
+        "A tank fill above 100% should be invalid"
+    );
+
+    // Test error message
+    let result = data.validate();
+    assert_eq!(
+        result.unwrap_err().message,
+        "Tank fill out of reasonable range (0% to 100%)".to_string()
+    );
 }
 
-#[tokio::test]
-async fn test_handle_sensor_data_valid() {
-    // Initialize tracing for the test
-    let _ = tracing_subscriber::fmt()
-        .with_writer(TestWriter::new())
-        .try_init();
+#[test]
+fn test_valid_boot_reason() {
+    let mut data = create_valid_sensor_data();
 
-    // Initialize global meter provider for the test
-    let meter_provider = SdkMeterProvider::builder().build();
-    global::set_meter_provider(meter_provider);
+    for boot_reason in KNOWN_BOOT_REASONS {
+        data.boot_reason = Some(boot_reason.to_string());
+        assert!(
+            data.validate().is_ok(),
+            "The boot reason {} should be valid",
+            boot_reason
+        );
+    }
 
-    let valid_data = create_valid_sensor_data();
+    // Test missing
+    data.boot_reason = None;
+    assert!(
+        data.validate().is_ok(),
+        "A missing boot reason should be valid"
+    );
+}
+
+#[test]
+fn test_invalid_boot_reason() {
+    let mut data = create_valid_sensor_data();
+    data.boot_reason = Some("reboot".to_string());
+    assert_eq!(
+        data.validate().unwrap_err().message,
+        "The boot reason should be one of: power_on, timer_wake, gpio_wake, brownout, software_reset, watchdog, other, unknown."
+            .to_string()
+    );
 
-    let result = handle_sensor_data(Ok(Json(valid_data))).await;
+    data.boot_reason = Some("".to_string());
     assert!(
-        result.is_ok(),
-        "Valid sensor data should be processed successfully"
+        data.validate().is_err(),
+        "An empty boot reason should be invalid"
     );
+}
 
-    let status = result.unwrap().into_response();
-    assert_eq!(status.status(), StatusCode::OK);
+fn create_tags(tags: &[(&str, &str)]) -> Option<std::collections::HashMap<String, String>> {
+    Some(
+        tags.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    )
 }
 
-#[tokio::test]
-async fn test_handle_sensor_data_invalid() {
-    // Initialize tracing for the test
-    let _ = tracing_subscriber::fmt()
-        .with_writer(TestWriter::new())
-        .try_init();
+#[test]
+fn test_valid_tags() {
+    let mut data = create_valid_sensor_data();
+    data.tags = create_tags(&[("site", "farm"), ("zone", "north")]);
+    assert!(data.validate().is_ok());
 
-    let mut invalid_data = create_valid_sensor_data();
-    invalid_data.boot_count = 0; // Invalid boot count
+    let key = "k".repeat(MAX_TAG_KEY_LENGTH);
+    let value = "v".repeat(MAX_TAG_VALUE_LENGTH);
+    data.tags = create_tags(&[(&key, &value)]);
+    assert!(data.validate().is_ok());
 
-    let result = handle_sensor_data(Ok(Json(invalid_data))).await;
+    let tags: Vec<(String, String)> = (0..MAX_DEVICE_TAGS)
+        .map(|i| (format!("key{}", i), "value".to_string()))
+        .collect();
+    data.tags = Some(tags.into_iter().collect());
+    assert!(data.validate().is_ok());
 
-    match result {
-        Ok(_) => assert!(false, "Invalid sensor data should be rejected"),
-        Err((status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
-    }
+    data.tags = create_tags(&[]);
+    assert!(data.validate().is_ok());
 }
 
 #[test]
-fn test_observability_config_from_env() {
-    // Save original environment
-    let original_metrics = std::env::var("METRICS_PUSH_URL").ok();
-    let original_tracing = std::env::var("TRACING_PUSH_URL").ok();
-    let original_logs = std::env::var("LOGS_PUSH_URL").ok();
+fn test_invalid_tags() {
+    let mut data = create_valid_sensor_data();
 
-    // Set test environment variables
-    std::env::set_var("METRICS_PUSH_URL", "http://test-metrics:4317");
-    std::env::set_var("TRACING_PUSH_URL", "http://test-tracing:4317");
-    std::env::set_var("LOGS_PUSH_URL", "http://test-logs:4317");
+    let key = "k".repeat(MAX_TAG_KEY_LENGTH + 1);
+    data.tags = create_tags(&[(&key, "farm")]);
+    let error = data.validate().unwrap_err();
+    assert_eq!(error.field, "tags");
+    assert_eq!(error.code, ValidationErrorCode::TooLong);
 
-    let config = ObservabilityConfig {
-        metrics_push_url: std::env::var("METRICS_PUSH_URL")
-            .unwrap_or_else(|_| "http://localhost:4317".to_string()),
-        trace_push_url: std::env::var("TRACING_PUSH_URL")
-            .unwrap_or_else(|_| "http://localhost:4317".to_string()),
-        logs_push_url: std::env::var("LOGS_PUSH_URL")
-            .unwrap_or_else(|_| "http://localhost:4317".to_string()),
-    };
+    let value = "v".repeat(MAX_TAG_VALUE_LENGTH + 1);
+    data.tags = create_tags(&[("site", &value)]);
+    let error = data.validate().unwrap_err();
+    assert_eq!(error.field, "tags");
+    assert_eq!(error.code, ValidationErrorCode::TooLong);
+
+    data.tags = create_tags(&[("", "farm")]);
+    let error = data.validate().unwrap_err();
+    assert_eq!(error.field, "tags");
+    assert_eq!(error.code, ValidationErrorCode::Empty);
+
+    let tags: Vec<(String, String)> = (0..=MAX_DEVICE_TAGS)
+        .map(|i| (format!("key{}", i), "value".to_string()))
+        .collect();
+    data.tags = Some(tags.into_iter().collect());
+    let error = data.validate().unwrap_err();
+    assert_eq!(error.field, "tags");
+    assert_eq!(error.code, ValidationErrorCode::TooLong);
+}
+
+#[tokio::test]
+async fn test_boot_reason_is_counted() {
+    let data = SensorData {
+        device_id: "boot-reason-test-device".to_string(),
+        boot_reason: Some("brownout".to_string()),
+        ..create_valid_sensor_data()
+    };
+
+    let app = create_router(AppState::new());
+    for _ in 0..2 {
+        let post_request = Request::builder()
+            .method("POST")
+            .uri("/api/v1/sensor")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&data).unwrap()))
+            .unwrap();
+        let post_response = app.clone().oneshot(post_request).await.unwrap();
+        assert_eq!(post_response.status(), StatusCode::OK);
+    }
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(
+        metrics.contains(
+            "device_boots_total{boot_reason=\"brownout\",device_id=\"boot-reason-test-device\"} 2"
+        ),
+        "The boot reasons should be counted. Metrics were: {}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn test_gpio_wake_is_counted_apart_from_timer_wake() {
+    let app = create_router(AppState::new());
+    for boot_reason in ["timer_wake", "gpio_wake"] {
+        let data = SensorData {
+            device_id: "wake-source-test-device".to_string(),
+            boot_reason: Some(boot_reason.to_string()),
+            ..create_valid_sensor_data()
+        };
+        let post_request = Request::builder()
+            .method("POST")
+            .uri("/api/v1/sensor")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&data).unwrap()))
+            .unwrap();
+        let post_response = app.clone().oneshot(post_request).await.unwrap();
+        assert_eq!(post_response.status(), StatusCode::OK);
+    }
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    for boot_reason in ["timer_wake", "gpio_wake"] {
+        assert!(
+            metrics.contains(&format!(
+                "device_boots_total{{boot_reason=\"{boot_reason}\",device_id=\"wake-source-test-device\"}} 1"
+            )),
+            "The {boot_reason} boots should be counted separately. Metrics were: {metrics}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_cold_boot_is_counted() {
+    let app = create_router(AppState::new());
+    for cold_boot in [true, false, true] {
+        let data = SensorData {
+            device_id: "cold-boot-test-device".to_string(),
+            cold_boot: Some(cold_boot),
+            ..create_valid_sensor_data()
+        };
+        let post_request = Request::builder()
+            .method("POST")
+            .uri("/api/v1/sensor")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&data).unwrap()))
+            .unwrap();
+        let post_response = app.clone().oneshot(post_request).await.unwrap();
+        assert_eq!(post_response.status(), StatusCode::OK);
+    }
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(
+        metrics.contains("cold_boots_total{device_id=\"cold-boot-test-device\"} 2"),
+        "Only the cold boots should be counted. Metrics were: {}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn test_sensor_read_failure_is_counted() {
+    let app = create_router(AppState::new());
+    for sensor_ok in [Some(false), Some(true), None, Some(false)] {
+        let data = SensorData {
+            device_id: "sensor-failure-test-device".to_string(),
+            sensor_ok,
+            ..create_valid_sensor_data()
+        };
+        let post_request = Request::builder()
+            .method("POST")
+            .uri("/api/v1/sensor")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&data).unwrap()))
+            .unwrap();
+        let post_response = app.clone().oneshot(post_request).await.unwrap();
+        assert_eq!(post_response.status(), StatusCode::OK);
+    }
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(
+        metrics.contains("sensor_read_failures_total{device_id=\"sensor-failure-test-device\"} 2"),
+        "Only the readings without working sensors should be counted. Metrics were: {}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn test_device_instruments_are_reused() {
+    let state = AppState::new();
+    store_sensor_data(&state, create_valid_sensor_data()).await;
+
+    let instruments = state
+        .device_instruments
+        .for_device("test-device-001", "1.0.0");
+    let instrument_count = instruments.count();
+    assert!(instrument_count > 0);
+
+    store_sensor_data(&state, create_valid_sensor_data()).await;
+    assert!(std::sync::Arc::ptr_eq(
+        &instruments,
+        &state
+            .device_instruments
+            .for_device("test-device-001", "1.0.0")
+    ));
+    assert_eq!(
+        instruments.count(),
+        instrument_count,
+        "No new instruments should be created for a repeated reading"
+    );
+
+    // The firmware version is part of the instrumentation scope
+    let upgraded = state
+        .device_instruments
+        .for_device("test-device-001", "1.1.0");
+    assert!(!std::sync::Arc::ptr_eq(&instruments, &upgraded));
+    assert_eq!(upgraded.count(), 0);
+}
+
+#[tokio::test]
+async fn test_pressure_sensor_fault_is_recorded() {
+    let data = SensorData {
+        device_id: "pressure-sensor-fault-test-device".to_string(),
+        pressure_sensor_fault: Some(true),
+        tank_level_in_meters: 0.0,
+        tank_volume_in_liters: 0.0,
+        tank_fill_in_percent: Some(0.0),
+        ..create_valid_sensor_data()
+    };
+
+    let app = create_router(AppState::new());
+    let post_request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&data).unwrap()))
+        .unwrap();
+    let post_response = app.oneshot(post_request).await.unwrap();
+    assert_eq!(post_response.status(), StatusCode::OK);
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(
+        metrics
+            .contains("pressure_sensor_fault{device_id=\"pressure-sensor-fault-test-device\"} 1"),
+        "The pressure sensor fault should be recorded. Metrics were: {}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn test_duplicate_reading_is_not_recorded_again() {
+    let state = AppState::new();
+    let app = create_router(state.clone());
+    let data = SensorData {
+        device_id: "duplicate-reading-test-device".to_string(),
+        boot_count: 7,
+        reading_seq: Some(0),
+        ..create_valid_sensor_data()
+    };
+
+    let post = |data: SensorData| {
+        let app = app.clone();
+        async move {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/api/v1/sensor")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(&data).unwrap()))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+            body["status"].as_str().unwrap().to_string()
+        }
+    };
+    let history_length = |state: AppState| async move {
+        state
+            .sensor_history
+            .read()
+            .await
+            .get("duplicate-reading-test-device")
+            .map(|history| history.len())
+            .unwrap_or_default()
+    };
+
+    assert_eq!(post(data.clone()).await, "success");
+    assert_eq!(history_length(state.clone()).await, 1);
+
+    // The same reading again, with a different value to show that it isn't stored
+    let retry = SensorData {
+        tank_level_in_meters: 0.5,
+        ..data.clone()
+    };
+    assert_eq!(post(retry).await, "duplicate");
+    assert_eq!(history_length(state.clone()).await, 1);
+    assert_eq!(
+        state.latest_sensor_data.read().await[&data.device_id]
+            .data
+            .tank_level_in_meters,
+        data.tank_level_in_meters
+    );
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(
+        metrics.contains(&format!(
+            "water_level{{device_id=\"duplicate-reading-test-device\"}} {}",
+            data.tank_level_in_meters
+        )),
+        "The duplicate reading should not change the metrics: {}",
+        metrics
+    );
+
+    // The next reading and the next boot are new readings
+    let next = SensorData {
+        reading_seq: Some(1),
+        ..data.clone()
+    };
+    assert_eq!(post(next).await, "success");
+    let next_boot = SensorData {
+        boot_count: 8,
+        reading_seq: Some(0),
+        ..data.clone()
+    };
+    assert_eq!(post(next_boot).await, "success");
+    assert_eq!(history_length(state).await, 3);
+}
+
+#[tokio::test]
+async fn test_readings_without_sequence_number_are_not_duplicates() {
+    let state = AppState::new();
+    let data = create_valid_sensor_data();
+
+    assert!(!is_duplicate_reading(&state, &data).await);
+    assert!(!is_duplicate_reading(&state, &data).await);
+}
+
+/// Add a time mapping, a reading, the history, the duplicate key, a log message, the
+/// instruments and the metrics for the device, all as if the device was last in contact at the
+/// given time
+async fn insert_device_data(state: &AppState, device_id: &str, contact: chrono::DateTime<Utc>) {
+    let mut data = create_valid_sensor_data();
+    data.device_id = device_id.to_string();
+    let reading = SensorReading {
+        received_at: contact,
+        data,
+    };
+
+    state.device_time_mappings.write().await.insert(
+        device_id.to_string(),
+        DeviceTimeMapping {
+            boot_count: 1,
+            first_tick: 1000,
+            first_timestamp: contact,
+            ntp_resync_seconds: None,
+        },
+    );
+    state
+        .latest_sensor_data
+        .write()
+        .await
+        .insert(device_id.to_string(), reading.clone());
+    state
+        .sensor_history
+        .write()
+        .await
+        .insert(device_id.to_string(), [reading].into());
+    state
+        .recent_reading_keys
+        .write()
+        .await
+        .insert(device_id.to_string(), [(1, 0)].into());
+    state.device_logs.write().await.insert(
+        device_id.to_string(),
+        [create_log_entry("info", "Woke up")].into(),
+    );
+    state.metric_history.record(
+        device_id,
+        HistoryPoint::new(contact, &create_valid_sensor_data()),
+    );
+    state.device_instruments.for_device(device_id, "1.0.0");
+    PROMETHEUS_METRICS.record(
+        "water_level",
+        "The level of the water in the tank",
+        device_id,
+        1.0,
+    );
+    if let Some(counter) = BOOT_REASONS.as_ref() {
+        counter.with_label_values(&[device_id, "timer_wake"]).inc();
+    }
+}
+
+#[tokio::test]
+async fn test_evict_stale_devices_removes_devices_that_are_no_longer_in_contact() {
+    let state = AppState::new().with_device_data_ttl_in_seconds(3600);
+    let now = Utc::now();
+    insert_device_data(&state, "old-tank", now - chrono::Duration::hours(2)).await;
+    insert_device_data(&state, "garden-tank", now - chrono::Duration::minutes(10)).await;
+
+    assert_eq!(evict_stale_devices(&state, now).await, 1);
+
+    assert!(!state
+        .device_time_mappings
+        .read()
+        .await
+        .contains_key("old-tank"));
+    assert!(!state
+        .latest_sensor_data
+        .read()
+        .await
+        .contains_key("old-tank"));
+    assert!(!state.sensor_history.read().await.contains_key("old-tank"));
+    assert!(!state
+        .recent_reading_keys
+        .read()
+        .await
+        .contains_key("old-tank"));
+    assert!(!state.device_logs.read().await.contains_key("old-tank"));
+    assert!(state.metric_history.points("old-tank").is_none());
+    assert!(!state.device_instruments.contains("old-tank"));
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(!metrics.contains("device_id=\"old-tank\""));
+
+    assert!(state
+        .device_time_mappings
+        .read()
+        .await
+        .contains_key("garden-tank"));
+    assert!(state
+        .latest_sensor_data
+        .read()
+        .await
+        .contains_key("garden-tank"));
+    assert!(state
+        .sensor_history
+        .read()
+        .await
+        .contains_key("garden-tank"));
+    assert!(state
+        .recent_reading_keys
+        .read()
+        .await
+        .contains_key("garden-tank"));
+    assert!(state.device_logs.read().await.contains_key("garden-tank"));
+    assert!(state.metric_history.points("garden-tank").is_some());
+    assert!(state.device_instruments.contains("garden-tank"));
+}
+
+#[tokio::test]
+async fn test_evict_stale_devices_keeps_a_device_with_a_recent_time_mapping() {
+    let state = AppState::new().with_device_data_ttl_in_seconds(3600);
+    let now = Utc::now();
+    insert_device_data(&state, "garden-tank", now - chrono::Duration::hours(2)).await;
+
+    // The device woke up but failed to send its sensor data
+    state
+        .device_time_mappings
+        .write()
+        .await
+        .get_mut("garden-tank")
+        .unwrap()
+        .first_timestamp = now - chrono::Duration::minutes(1);
+
+    assert_eq!(evict_stale_devices(&state, now).await, 0);
+    assert!(state
+        .latest_sensor_data
+        .read()
+        .await
+        .contains_key("garden-tank"));
+}
+
+/// The metrics as formatted by `format_metrics` in the firmware, including the trailing newline
+const FIRMWARE_METRICS: &str = "{\"device_id\":\"garden-tank\",\"firmware_version\":\"0.1.0\",\"boot_count\":42,\"reading_seq\":0,\"boot_reason\":\"timer_wake\",\"cold_boot\":false,\"session_id\":2891340177,\"run_time_in_seconds\":6.284,\"wifi_start_time_in_seconds\":1.917,\"wifi_rssi_in_dbm\":-67,\"sensor_ok\":true,\"temperature_in_celcius\":18.42,\"humidity_in_percent\":63.10,\"pressure_in_pascal\":101012.3,\"brightness_in_percent\":2.150,\"battery_voltage\":12.614,\"battery_in_percent\":89.5,\"pressure_sensor_voltage\":23.982,\"pressure_sensor_fault\":false,\"tank_level_in_meters\":1.204,\"tank_volume_in_liters\":8510.2,\"tank_temperature_in_celcius\":14.75,\"sample_quality\":1.00,\"tank_fill_in_percent\":60.2}\n";
+
+/// The metrics of a device without a water temperature sensor, a WiFi signal strength or a
+/// configured tank height
+const FIRMWARE_METRICS_WITH_NULLS: &str = "{\"device_id\":\"garden-tank\",\"firmware_version\":\"0.1.0\",\"boot_count\":1,\"reading_seq\":0,\"boot_reason\":\"power_on\",\"cold_boot\":true,\"session_id\":2891340177,\"run_time_in_seconds\":7.001,\"wifi_start_time_in_seconds\":2.305,\"wifi_rssi_in_dbm\":null,\"sensor_ok\":true,\"temperature_in_celcius\":18.42,\"humidity_in_percent\":63.10,\"pressure_in_pascal\":101012.3,\"brightness_in_percent\":2.150,\"battery_voltage\":12.614,\"battery_in_percent\":89.5,\"pressure_sensor_voltage\":23.982,\"pressure_sensor_fault\":false,\"tank_level_in_meters\":1.204,\"tank_volume_in_liters\":8510.2,\"tank_temperature_in_celcius\":null,\"sample_quality\":0.50,\"tank_fill_in_percent\":null}\n";
+
+/// The metrics of a device that could not read its sensors. The water level and the battery
+/// voltage are the last good reading and the environmental data is a synthetic standard
+/// atmosphere.
+const FIRMWARE_FALLBACK_METRICS: &str = "{\"device_id\":\"garden-tank\",\"firmware_version\":\"0.1.0\",\"boot_count\":43,\"reading_seq\":0,\"boot_reason\":\"timer_wake\",\"cold_boot\":false,\"session_id\":2891340177,\"run_time_in_seconds\":6.912,\"wifi_start_time_in_seconds\":1.884,\"wifi_rssi_in_dbm\":-67,\"sensor_ok\":false,\"temperature_in_celcius\":20.00,\"humidity_in_percent\":50.00,\"pressure_in_pascal\":101325.0,\"brightness_in_percent\":0.000,\"battery_voltage\":12.614,\"battery_in_percent\":89.5,\"pressure_sensor_voltage\":0.000,\"pressure_sensor_fault\":false,\"tank_level_in_meters\":1.204,\"tank_volume_in_liters\":8510.2,\"tank_temperature_in_celcius\":null,\"sample_quality\":0.00,\"tank_fill_in_percent\":60.2}\n";
+
+/// The metrics of a device that could not read its sensors and has no good reading yet
+const FIRMWARE_FALLBACK_METRICS_WITHOUT_LAST_READING: &str = "{\"device_id\":\"garden-tank\",\"firmware_version\":\"0.1.0\",\"boot_count\":1,\"reading_seq\":0,\"boot_reason\":\"power_on\",\"cold_boot\":true,\"session_id\":2891340177,\"run_time_in_seconds\":6.912,\"wifi_start_time_in_seconds\":1.884,\"wifi_rssi_in_dbm\":-67,\"sensor_ok\":false,\"temperature_in_celcius\":20.00,\"humidity_in_percent\":50.00,\"pressure_in_pascal\":101325.0,\"brightness_in_percent\":0.000,\"battery_voltage\":0.000,\"battery_in_percent\":0.0,\"pressure_sensor_voltage\":0.000,\"pressure_sensor_fault\":true,\"tank_level_in_meters\":0.000,\"tank_volume_in_liters\":0.0,\"tank_temperature_in_celcius\":null,\"sample_quality\":0.00,\"tank_fill_in_percent\":0.0}\n";
+
+#[test]
+fn test_firmware_metrics_deserialize() {
+    let data: SensorData = serde_json::from_str(FIRMWARE_METRICS).unwrap();
+    assert_eq!(
+        data,
+        SensorData {
+            device_id: "garden-tank".to_string(),
+            firmware_version: "0.1.0".to_string(),
+            boot_count: 42,
+            reading_seq: Some(0),
+            run_time_in_seconds: 6.284,
+            wifi_start_time_in_seconds: 1.917,
+            wifi_rssi_in_dbm: Some(-67),
+            sensor_ok: Some(true),
+            temperature_in_celcius: 18.42,
+            humidity_in_percent: 63.1,
+            pressure_in_pascal: 101012.3,
+            brightness_in_percent: 2.15,
+            battery_voltage: 12.614,
+            battery_in_percent: Some(89.5),
+            pressure_sensor_voltage: 23.982,
+            pressure_sensor_fault: Some(false),
+            tank_level_in_meters: 1.204,
+            tank_volume_in_liters: 8510.2,
+            tank_temperature_in_celcius: Some(14.75),
+            sample_quality: Some(1.0),
+            tank_fill_in_percent: Some(60.2),
+            boot_reason: Some("timer_wake".to_string()),
+            cold_boot: Some(false),
+            session_id: Some(2891340177),
+            raw_voltages: None,
+            tags: None,
+            data_quality: None,
+        }
+    );
+    assert!(data.validate().is_ok());
+
+    let data: SensorData = serde_json::from_str(FIRMWARE_METRICS_WITH_NULLS).unwrap();
+    assert_eq!(data.wifi_rssi_in_dbm, None);
+    assert_eq!(data.tank_temperature_in_celcius, None);
+    assert_eq!(data.tank_fill_in_percent, None);
+    assert_eq!(data.cold_boot, Some(true));
+    assert!(data.validate().is_ok());
+
+    // Older firmware doesn't report if the pressure sensor is disconnected
+    let json = FIRMWARE_METRICS.replace("\"pressure_sensor_fault\":false,", "");
+    let data: SensorData = serde_json::from_str(&json).unwrap();
+    assert_eq!(data.pressure_sensor_fault, None);
+
+    // Older firmware doesn't report the battery charge
+    let json = FIRMWARE_METRICS.replace("\"battery_in_percent\":89.5,", "");
+    let data: SensorData = serde_json::from_str(&json).unwrap();
+    assert_eq!(data.battery_in_percent, None);
+
+    // Older firmware doesn't report if the device lost power
+    let json = FIRMWARE_METRICS.replace("\"cold_boot\":false,", "");
+    let data: SensorData = serde_json::from_str(&json).unwrap();
+    assert_eq!(data.cold_boot, None);
+
+    // Older firmware doesn't report if the sensors could be read
+    let json = FIRMWARE_METRICS.replace("\"sensor_ok\":true,", "");
+    let data: SensorData = serde_json::from_str(&json).unwrap();
+    assert_eq!(data.sensor_ok, None);
+
+    // Older firmware doesn't send the ID of the wake cycle
+    let json = FIRMWARE_METRICS.replace("\"session_id\":2891340177,", "");
+    let data: SensorData = serde_json::from_str(&json).unwrap();
+    assert_eq!(data.session_id, None);
+
+    // Older firmware doesn't number the readings
+    let json = FIRMWARE_METRICS.replace("\"reading_seq\":0,", "");
+    let data: SensorData = serde_json::from_str(&json).unwrap();
+    assert_eq!(data.reading_seq, None);
+
+    // The tags are only sent if they are configured on the device
+    let json = FIRMWARE_METRICS.replace(
+        "\"tank_fill_in_percent\":60.2}",
+        "\"tank_fill_in_percent\":60.2,\"tags\":{\"site\":\"farm\",\"zone\":\"north\"}}",
+    );
+    let data: SensorData = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        data.tags,
+        create_tags(&[("site", "farm"), ("zone", "north")])
+    );
+    assert!(data.validate().is_ok());
+}
+
+#[test]
+fn test_firmware_fallback_metrics_deserialize() {
+    let data: SensorData = serde_json::from_str(FIRMWARE_FALLBACK_METRICS).unwrap();
+    assert_eq!(data.sensor_ok, Some(false));
+    assert_eq!(data.tank_level_in_meters, 1.204);
+    assert_eq!(data.battery_voltage, 12.614);
+    assert_eq!(data.pressure_sensor_fault, Some(false));
+    assert_eq!(data.sample_quality, Some(0.0));
+    assert_eq!(data.tank_temperature_in_celcius, None);
+    assert!(data.validate().is_ok());
+
+    // Without a good reading the water level is marked as not valid
+    let data: SensorData =
+        serde_json::from_str(FIRMWARE_FALLBACK_METRICS_WITHOUT_LAST_READING).unwrap();
+    assert_eq!(data.sensor_ok, Some(false));
+    assert_eq!(data.pressure_sensor_fault, Some(true));
+    assert!(data.validate().is_ok());
+}
+
+#[test]
+fn test_firmware_metrics_match_the_sensor_data_fields() {
+    let firmware: serde_json::Value = serde_json::from_str(FIRMWARE_METRICS).unwrap();
+    let data: SensorData = serde_json::from_str(FIRMWARE_METRICS).unwrap();
+    let service = serde_json::to_value(&data).unwrap();
+
+    let field_names = |value: &serde_json::Value| {
+        value
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+
+    // The raw voltages are only sent by devices in calibration mode
+    assert_eq!(field_names(&firmware), field_names(&service));
+    assert_eq!(serde_json::from_value::<SensorData>(service).unwrap(), data);
+}
+
+/// The timing data as formatted by `format_timing_data` in the firmware, in the same wake cycle as
+/// `FIRMWARE_METRICS`
+const FIRMWARE_TIMING: &str = "{\"device_id\":\"garden-tank\",\"boot_count\":42,\"session_id\":2891340177,\"timestamp\":1917000}\n";
+
+/// The logs as serialised by `transmit_logs` in the firmware, in the same wake cycle as
+/// `FIRMWARE_METRICS`
+const FIRMWARE_LOGS: &str = r#"[{"device_id":"garden-tank","level":"INFO","message":"Connecting to WiFi network","boot_count":42,"timestamp":1204,"count":1,"session_id":2891340177}]"#;
+
+#[test]
+fn test_firmware_payloads_share_the_session_id() {
+    let metrics: SensorData = serde_json::from_str(FIRMWARE_METRICS).unwrap();
+    let timing: DeviceTimingData = serde_json::from_str(FIRMWARE_TIMING).unwrap();
+    let logs: Vec<LogData> = serde_json::from_str(FIRMWARE_LOGS).unwrap();
+
+    assert_eq!(metrics.session_id, Some(2891340177));
+    assert_eq!(timing.session_id, metrics.session_id);
+    assert_eq!(logs[0].session_id, metrics.session_id);
+
+    // The session ID would start new time series on every wake cycle, so it isn't a metric
+    // attribute
+    assert!(device_attributes(&metrics)
+        .iter()
+        .all(|attribute| attribute.key.as_str() != "session_id"));
+
+    // Older firmware doesn't send the ID of the wake cycle
+    let timing: DeviceTimingData =
+        serde_json::from_str(&FIRMWARE_TIMING.replace("\"session_id\":2891340177,", "")).unwrap();
+    assert_eq!(timing.session_id, None);
+    let logs: Vec<LogData> =
+        serde_json::from_str(&FIRMWARE_LOGS.replace(",\"session_id\":2891340177", "")).unwrap();
+    assert_eq!(logs[0].session_id, None);
+}
+
+/// The metrics as formatted by `format_metrics` in the firmware for the fixed values that are
+/// used when the firmware is built with `SIMULATE_SENSORS`, without a tank geometry configured
+const FIRMWARE_METRICS_SIMULATED: &str = "{\"device_id\":\"tank_1\",\"firmware_version\":\"0.1.0\",\"boot_count\":1,\"reading_seq\":0,\"boot_reason\":\"power_on\",\"cold_boot\":true,\"session_id\":2891340177,\"run_time_in_seconds\":5.000,\"wifi_start_time_in_seconds\":1.500,\"wifi_rssi_in_dbm\":-60,\"temperature_in_celcius\":20.00,\"humidity_in_percent\":50.00,\"pressure_in_pascal\":101325.0,\"brightness_in_percent\":50.000,\"battery_voltage\":12.600,\"battery_in_percent\":88.3,\"pressure_sensor_voltage\":24.000,\"pressure_sensor_fault\":false,\"tank_level_in_meters\":1.000,\"tank_volume_in_liters\":0.0,\"tank_temperature_in_celcius\":15.00,\"sample_quality\":0.00,\"tank_fill_in_percent\":null}\n";
+
+#[tokio::test]
+async fn test_simulated_firmware_metrics_are_accepted() {
+    let data: SensorData = serde_json::from_str(FIRMWARE_METRICS_SIMULATED).unwrap();
+    assert_eq!(data.sample_quality, Some(0.0));
+
+    let app = create_router(AppState::new());
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(FIRMWARE_METRICS_SIMULATED))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_raw_voltages_round_trip() {
+    let json = r#"{"device_id":"test-device-001","firmware_version":"1.0.0","boot_count":1,"run_time_in_seconds":10.5,"wifi_start_time_in_seconds":2.5,"wifi_rssi_in_dbm":-60,"temperature_in_celcius":25.0,"humidity_in_percent":50.0,"pressure_in_pascal":101325.0,"brightness_in_percent":50.0,"battery_voltage":3.7,"pressure_sensor_voltage":5.0,"tank_level_in_meters":1.5,"tank_volume_in_liters":10602.9,"tank_temperature_in_celcius":20.0,"sample_quality":1.0,"tank_fill_in_percent":75.0,"raw_voltages":{"a0":1.6500,"a1":0.5200,"a2":0.3841,"a3":1.6235}}"#;
+
+    let data: SensorData = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        data.raw_voltages,
+        Some(RawVoltages {
+            a0: 1.65,
+            a1: 0.52,
+            a2: 0.3841,
+            a3: 1.6235,
+        })
+    );
+    assert!(data.validate().is_ok());
+
+    let round_tripped: SensorData =
+        serde_json::from_str(&serde_json::to_string(&data).unwrap()).unwrap();
+    assert_eq!(round_tripped, data);
+}
+
+#[test]
+fn test_raw_voltages_omitted_outside_calibration_mode() {
+    let data = create_valid_sensor_data();
+
+    let json = serde_json::to_value(&data).unwrap();
+    assert!(json.get("raw_voltages").is_none());
+
+    let deserialized: SensorData = serde_json::from_value(json).unwrap();
+    assert_eq!(deserialized.raw_voltages, None);
+}
+
+#[test]
+fn test_boundary_values() {
+    let mut data = create_valid_sensor_data();
+
+    // Test lower boundaries
+    data.boot_count = 1;
+    data.run_time_in_seconds = 0.0;
+    data.wifi_start_time_in_seconds = 0.0;
+    data.wifi_rssi_in_dbm = Some(-100);
+    data.temperature_in_celcius = -50.0;
+    data.humidity_in_percent = 0.0;
+    data.pressure_in_pascal = 50.0e3;
+    data.battery_voltage = 0.0;
+    data.pressure_sensor_voltage = 0.0;
+    data.tank_level_in_meters = 0.0;
+    data.tank_volume_in_liters = 0.0;
+    data.tank_temperature_in_celcius = Some(-50.0);
+    data.sample_quality = Some(0.0);
+    assert!(
+        data.validate().is_ok(),
+        "Lower boundary values should be valid"
+    );
+
+    // Test upper boundaries
+    data.wifi_rssi_in_dbm = Some(0);
+    data.temperature_in_celcius = 100.0;
+    data.humidity_in_percent = 100.0;
+    data.pressure_in_pascal = 150.0e3;
+    data.battery_voltage = 15.0;
+    data.pressure_sensor_voltage = 32.0;
+    data.tank_level_in_meters = 5.0;
+    data.tank_volume_in_liters = 100.0e3;
+    data.tank_temperature_in_celcius = Some(100.0);
+    data.sample_quality = Some(1.0);
+    assert!(
+        data.validate().is_ok(),
+        "Upper boundary values should be valid"
+    );
+}
+
+/// Set a field of the sensor data by the name that the devices use
+fn with_field(data: &SensorData, field: &str, value: f64) -> SensorData {
+    let mut json = serde_json::to_value(data).unwrap();
+    // Whole numbers are written as integers so that they can be read into integer fields
+    json[field] = if value.fract() == 0.0 {
+        serde_json::json!(value as i64)
+    } else {
+        serde_json::json!(value)
+    };
+    serde_json::from_value(json).unwrap()
+}
+
+#[test]
+fn test_validation_range_boundaries() {
+    let data = create_valid_sensor_data();
+
+    for range in SENSOR_DATA_RANGES {
+        assert!(
+            with_field(&data, range.field, range.min).validate().is_ok(),
+            "The lower boundary of {} should be valid",
+            range.field
+        );
+
+        // Integer fields can only be just outside the range by a whole number
+        let width = range.max - range.min;
+        let step = if width.is_infinite() || width >= 100.0 {
+            1.0
+        } else {
+            width * 1e-3
+        };
+
+        let below = with_field(&data, range.field, range.min - step);
+        assert_eq!(
+            below.validate().unwrap_err().message,
+            format!(
+                "{} out of reasonable range ({})",
+                range.label,
+                range.describe()
+            ),
+            "A value just below the range of {} should be invalid",
+            range.field
+        );
+
+        if range.max.is_infinite() {
+            continue;
+        }
+
+        assert!(
+            with_field(&data, range.field, range.max).validate().is_ok(),
+            "The upper boundary of {} should be valid",
+            range.field
+        );
+
+        let above = with_field(&data, range.field, range.max + step);
+        assert!(
+            above.validate().is_err(),
+            "A value just above the range of {} should be invalid",
+            range.field
+        );
+    }
+}
+
+#[test]
+fn test_validate_with_other_ranges() {
+    let data = SensorData {
+        battery_voltage: 20.0,
+        ..create_valid_sensor_data()
+    };
+    assert!(data.validate().is_err());
+
+    // A 24V battery
+    let ranges = override_validation_ranges(&SENSOR_DATA_RANGES, "battery_voltage=0:30").unwrap();
+    assert!(data.validate_with(&ranges).is_ok());
+    assert_eq!(ranges.len(), SENSOR_DATA_RANGES.len());
+}
+
+#[test]
+fn test_override_validation_ranges_with_invalid_overrides() {
+    for overrides in [
+        "battery_voltage",
+        "battery_voltage=0",
+        "battery_voltage=zero:30",
+        "battery_voltage=30:0",
+        "unknown_field=0:30",
+    ] {
+        assert!(
+            override_validation_ranges(&SENSOR_DATA_RANGES, overrides).is_err(),
+            "The override {} should be invalid",
+            overrides
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_uses_the_configured_ranges() {
+    let data = SensorData {
+        battery_voltage: 20.0,
+        ..create_valid_sensor_data()
+    };
+
+    let result = handle_sensor_data(State(AppState::new()), None, Ok(Json(data.clone()))).await;
+    assert!(result.is_err(), "The default ranges should reject the data");
+
+    let ranges = override_validation_ranges(&SENSOR_DATA_RANGES, "battery_voltage=0:30").unwrap();
+    let state = AppState::new().with_validation_ranges(ranges);
+    let result = handle_sensor_data(State(state), None, Ok(Json(data))).await;
+    assert!(
+        result.is_ok(),
+        "The configured ranges should accept the data"
+    );
+}
+
+#[test]
+fn test_api_response_success() {
+    let response = ApiResponse::success("Test message");
+    assert_eq!(response.status, "success");
+    assert_eq!(response.message, "Test message");
+    // We can't easily test the exact timestamp, but we can check it's not empty
+    assert!(!response.timestamp.is_empty());
+}
+
+#[test]
+fn test_api_response_error() {
+    let response = ApiResponse::error("Error message");
+    assert_eq!(response.status, "error");
+    assert_eq!(response.message, "Error message");
+    assert!(!response.timestamp.is_empty());
+}
+
+#[tokio::test]
+async fn test_health_check() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let response = handle_health_check().await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Convert the response body to bytes and then to a string
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body_str = String::from_utf8(body_bytes.to_vec()).unwrap();
+
+    // Parse the JSON response
+    let api_response: ApiResponse = serde_json::from_str(body_str.as_str()).unwrap();
+    assert_eq!(api_response.status, "success");
+    assert_eq!(api_response.message, "Service is healthy");
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_valid() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    // Initialize global meter provider for the test
+    let meter_provider = SdkMeterProvider::builder().build();
+    global::set_meter_provider(meter_provider);
+
+    let valid_data = create_valid_sensor_data();
+
+    let result = handle_sensor_data(State(AppState::new()), None, Ok(Json(valid_data))).await;
+    assert!(
+        result.is_ok(),
+        "Valid sensor data should be processed successfully"
+    );
+
+    let status = result.unwrap().into_response();
+    assert_eq!(status.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_invalid() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let mut invalid_data = create_valid_sensor_data();
+    invalid_data.boot_count = 0; // Invalid boot count
+
+    let result = handle_sensor_data(State(AppState::new()), None, Ok(Json(invalid_data))).await;
+
+    match result {
+        Ok(_) => assert!(false, "Invalid sensor data should be rejected"),
+        Err((status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
+    }
+}
+
+/// Log output that is kept in memory so that the tests can check it
+#[derive(Clone, Default)]
+struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+    type Writer = CapturedLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Run the handler with a subscriber that logs the fields of the span when it closes
+async fn sensor_data_span_logs(sensor_data: SensorData) -> String {
+    use tracing::instrument::WithSubscriber;
+
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .finish();
+
+    let _ = handle_sensor_data(State(AppState::new()), None, Ok(Json(sensor_data)))
+        .with_subscriber(subscriber)
+        .await;
+
+    logs.contents()
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_records_the_reading_on_the_span() {
+    let mut sensor_data = create_valid_sensor_data();
+    sensor_data.boot_count = 7;
+
+    let logs = sensor_data_span_logs(sensor_data).await;
+
+    let close = logs
+        .lines()
+        .find(|line| line.contains("handle_sensor_data") && line.contains("close"))
+        .expect("The span should be entered and closed");
+    assert!(close.contains("device_id=\"test-device-001\""), "{close}");
+    assert!(close.contains("boot_count=7"), "{close}");
+    assert!(close.contains("firmware_version=\"1.0.0\""), "{close}");
+    assert!(close.contains("validation=\"passed\""), "{close}");
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_records_a_failed_validation_on_the_span() {
+    let mut sensor_data = create_valid_sensor_data();
+    sensor_data.boot_count = 0;
+
+    let logs = sensor_data_span_logs(sensor_data).await;
+
+    let close = logs
+        .lines()
+        .find(|line| line.contains("handle_sensor_data") && line.contains("close"))
+        .expect("The span should be entered and closed");
+    assert!(close.contains("validation=\"failed\""), "{close}");
+}
+
+#[test]
+fn test_validation_error_field_and_code() {
+    let cases = [
+        (
+            with_field(&create_valid_sensor_data(), "boot_count", 0.0),
+            "boot_count",
+            ValidationErrorCode::OutOfRange,
+        ),
+        (
+            with_field(&create_valid_sensor_data(), "humidity_in_percent", 101.0),
+            "humidity_in_percent",
+            ValidationErrorCode::OutOfRange,
+        ),
+        (
+            SensorData {
+                firmware_version: String::new(),
+                ..create_valid_sensor_data()
+            },
+            "firmware_version",
+            ValidationErrorCode::Empty,
+        ),
+        (
+            SensorData {
+                firmware_version: format!("1.0.0-{}", "a".repeat(27)),
+                ..create_valid_sensor_data()
+            },
+            "firmware_version",
+            ValidationErrorCode::TooLong,
+        ),
+        (
+            SensorData {
+                firmware_version: "1.0".to_string(),
+                ..create_valid_sensor_data()
+            },
+            "firmware_version",
+            ValidationErrorCode::InvalidFormat,
+        ),
+        (
+            SensorData {
+                boot_reason: Some("reboot".to_string()),
+                ..create_valid_sensor_data()
+            },
+            "boot_reason",
+            ValidationErrorCode::UnknownValue,
+        ),
+    ];
+
+    for (data, field, code) in cases {
+        let error = data.validate().unwrap_err();
+        assert_eq!(error.field, field, "{}", error.message);
+        assert_eq!(error.code, code, "{}", error.message);
+    }
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_invalid_response_names_the_field() {
+    let app = create_router(AppState::new());
+
+    let data = with_field(&create_valid_sensor_data(), "humidity_in_percent", 101.0);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&data).unwrap()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body["status"], "error");
+    assert_eq!(body["field"], "humidity_in_percent");
+    assert_eq!(body["code"], "OUT_OF_RANGE");
+    assert_eq!(
+        body["message"],
+        "Humidity out of reasonable range (0% to 100%)"
+    );
+}
+
+#[test]
+fn test_api_response_error_omits_field_and_code() {
+    let body = serde_json::to_value(ApiResponse::error("Invalid API key")).unwrap();
+    assert!(body.get("field").is_none());
+    assert!(body.get("code").is_none());
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_returns_configured_sleep_duration() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new().with_device_sleep_seconds(Some(600));
+    let response = handle_sensor_data(State(state), None, Ok(Json(create_valid_sensor_data())))
+        .await
+        .unwrap()
+        .into_response();
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body["next_sleep_seconds"], 600);
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_omits_sleep_duration_when_not_configured() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let response = handle_sensor_data(
+        State(AppState::new()),
+        None,
+        Ok(Json(create_valid_sensor_data())),
+    )
+    .await
+    .unwrap()
+    .into_response();
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert!(body.get("next_sleep_seconds").is_none());
+}
+
+#[test]
+fn test_rate_limiter_allows_burst_then_recovers() {
+    // One request every 10 seconds, with a burst of 3
+    let rate_limiter = DeviceRateLimiter::new(6, 3);
+    let now = std::time::Instant::now();
+
+    for _ in 0..3 {
+        assert!(rate_limiter.try_acquire("test-device-001", now));
+    }
+    assert!(!rate_limiter.try_acquire("test-device-001", now));
+
+    // Other devices have their own bucket
+    assert!(rate_limiter.try_acquire("test-device-002", now));
+
+    // A token is added after 10 seconds
+    let later = now + std::time::Duration::from_secs(10);
+    assert!(rate_limiter.try_acquire("test-device-001", later));
+    assert!(!rate_limiter.try_acquire("test-device-001", later));
+}
+
+#[test]
+fn test_rate_limiter_evicts_idle_devices() {
+    let rate_limiter =
+        DeviceRateLimiter::new(6, 3).with_idle_timeout(std::time::Duration::from_secs(60));
+    let now = std::time::Instant::now();
+
+    assert!(rate_limiter.try_acquire("test-device-001", now));
+    assert!(rate_limiter.try_acquire("test-device-002", now));
+    assert_eq!(rate_limiter.tracked_devices(), 2);
+
+    let later = now + std::time::Duration::from_secs(61);
+    assert!(rate_limiter.try_acquire("test-device-001", later));
+    assert_eq!(rate_limiter.tracked_devices(), 1);
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_rate_limited() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new().with_rate_limiter(Some(DeviceRateLimiter::new(1, 2)));
+    let data = create_valid_sensor_data();
+
+    for _ in 0..2 {
+        let result = handle_sensor_data(State(state.clone()), None, Ok(Json(data.clone()))).await;
+        assert!(
+            result.is_ok(),
+            "Requests within the burst should be accepted"
+        );
+    }
+
+    let result = handle_sensor_data(State(state), None, Ok(Json(data))).await;
+    match result {
+        Ok(_) => panic!("Requests beyond the burst should be rejected"),
+        Err((status, Json(response))) => {
+            assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(response.status, "error");
+        }
+    }
+}
+
+fn create_tank_config() -> TankConfig {
+    TankConfig {
+        shape: TankShape::Cylinder {
+            radius_in_meters: 1.5,
+        },
+        max_height_in_meters: 2.0,
+        alert_thresholds: AlertThresholds {
+            low_level_in_percent: Some(20.0),
+            high_level_in_percent: Some(95.0),
+            condensation_humidity_in_percent: None,
+        },
+        quiet_until_in_seconds: None,
+    }
+}
+
+fn create_tank_config_request(method: &str, body: Option<&TankConfig>) -> Request {
+    let builder = Request::builder()
+        .method(method)
+        .uri("/api/v1/config/test-device-001");
+    match body {
+        Some(config) => builder
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_string(config).unwrap()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    }
+}
+
+#[test]
+fn test_invalid_tank_config() {
+    let mut config = create_tank_config();
+    config.shape = TankShape::Rectangular {
+        width_in_meters: 2.0,
+        length_in_meters: 0.0,
+    };
+    assert_eq!(
+        config.validate().unwrap_err(),
+        "The tank dimensions should be larger than 0 m.".to_string()
+    );
+
+    let mut config = create_tank_config();
+    config.max_height_in_meters = f32::NAN;
+    assert_eq!(
+        config.validate().unwrap_err(),
+        "The maximum tank height should be larger than 0 m.".to_string()
+    );
+
+    let mut config = create_tank_config();
+    config.alert_thresholds.high_level_in_percent = Some(120.0);
+    assert!(config.validate().is_err());
+
+    let mut config = create_tank_config();
+    config.alert_thresholds.low_level_in_percent = Some(96.0);
+    assert!(config.validate().is_err());
+
+    let mut config = create_tank_config();
+    config.alert_thresholds.condensation_humidity_in_percent = Some(120.0);
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_tank_config_set_get_and_overwrite() {
+    let app = create_router(AppState::new());
+
+    let response = app
+        .clone()
+        .oneshot(create_tank_config_request("GET", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let config = create_tank_config();
+    let response = app
+        .clone()
+        .oneshot(create_tank_config_request("POST", Some(&config)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(create_tank_config_request("GET", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stored_config: TankConfig = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(stored_config, config);
+
+    let new_config = TankConfig {
+        shape: TankShape::Rectangular {
+            width_in_meters: 2.0,
+            length_in_meters: 3.0,
+        },
+        ..create_tank_config()
+    };
+    let response = app
+        .clone()
+        .oneshot(create_tank_config_request("POST", Some(&new_config)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .oneshot(create_tank_config_request("GET", None))
+        .await
+        .unwrap();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stored_config: TankConfig = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(stored_config, new_config);
+}
+
+#[tokio::test]
+async fn test_tank_config_rejects_invalid_geometry() {
+    let app = create_router(AppState::new());
+
+    let mut config = create_tank_config();
+    config.max_height_in_meters = -1.0;
+    let response = app
+        .oneshot(create_tank_config_request("POST", Some(&config)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_uses_stored_tank_config() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new();
+    state.tank_configs.write().await.insert(
+        "test-device-001".to_string(),
+        TankConfig {
+            shape: TankShape::Rectangular {
+                width_in_meters: 2.0,
+                length_in_meters: 3.0,
+            },
+            ..create_tank_config()
+        },
+    );
+
+    let data = SensorData {
+        tank_level_in_meters: 1.0,
+        tank_volume_in_liters: 0.0,
+        tank_fill_in_percent: None,
+        ..create_valid_sensor_data()
+    };
+    let result = handle_sensor_data(State(state.clone()), None, Ok(Json(data))).await;
+    assert!(result.is_ok(), "Valid sensor data should be processed");
+
+    let readings = state.latest_sensor_data.read().await;
+    let stored = &readings.get("test-device-001").unwrap().data;
+    assert_eq!(stored.tank_fill_in_percent, Some(50.0));
+    assert!((stored.tank_volume_in_liters - 6000.0).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_returns_the_derived_values() {
+    let state = AppState::new();
+    state.tank_configs.write().await.insert(
+        "derived-values-test-device".to_string(),
+        TankConfig {
+            shape: TankShape::Rectangular {
+                width_in_meters: 2.0,
+                length_in_meters: 3.0,
+            },
+            ..create_tank_config()
+        },
+    );
+    let app = create_router(state);
+
+    let data = SensorData {
+        device_id: "derived-values-test-device".to_string(),
+        tank_level_in_meters: 1.0,
+        tank_volume_in_liters: 0.0,
+        tank_fill_in_percent: None,
+        ..create_valid_sensor_data()
+    };
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&data).unwrap()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let response: ApiResponse = serde_json::from_slice(&body_bytes).unwrap();
+    let derived = response
+        .derived
+        .expect("The response should contain the derived values");
+    assert_eq!(derived.tank_fill_in_percent, Some(50.0));
+    assert!((derived.tank_volume_in_liters - 6000.0).abs() < 0.01);
+    assert!(!derived.leak_suspected);
+    assert_eq!(derived.data_quality, DataQuality::Ok);
+}
+
+#[test]
+fn test_tank_config_is_quiet_until_the_end_of_maintenance() {
+    let now = Utc::now();
+    let mut config = create_tank_config();
+    assert!(!config.is_quiet(now));
+
+    config.quiet_until_in_seconds = Some(now.timestamp() + 3600);
+    assert!(config.is_quiet(now));
+
+    // Quiet mode ends by itself
+    assert!(!config.is_quiet(now + chrono::Duration::hours(2)));
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_in_quiet_mode_suppresses_alerts() {
+    let device_id = "quiet-mode-test-device";
+    let state = AppState::new();
+    let mut config = create_tank_config();
+    config.quiet_until_in_seconds = Some(Utc::now().timestamp() + 3600);
+    state
+        .tank_configs
+        .write()
+        .await
+        .insert(device_id.to_string(), config.clone());
+
+    // Condensation is likely for this reading
+    let data = SensorData {
+        device_id: device_id.to_string(),
+        temperature_in_celcius: 20.0,
+        humidity_in_percent: 95.0,
+        ..create_valid_sensor_data()
+    };
+    let post = |data: SensorData| {
+        let state = state.clone();
+        async move {
+            let result = handle_sensor_data(State(state), None, Ok(Json(data))).await;
+            let response = match result {
+                Ok(r) => r.into_response(),
+                Err(_) => panic!("Valid sensor data should be processed"),
+            };
+            let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<ApiResponse>(&body_bytes).unwrap()
+        }
+    };
+    let condensation_counted = || {
+        PROMETHEUS_METRICS.render().unwrap().contains(&format!(
+            "device_condensation_risk_total{{device_id=\"{device_id}\"}}"
+        ))
+    };
+
+    let response = post(data.clone()).await;
+    assert_eq!(response.quiet, Some(true));
+    assert!(
+        !condensation_counted(),
+        "No alerts should be raised during maintenance"
+    );
+
+    // Once the maintenance is over the alerts are raised again
+    config.quiet_until_in_seconds = Some(Utc::now().timestamp() - 1);
+    state
+        .tank_configs
+        .write()
+        .await
+        .insert(device_id.to_string(), config);
+
+    let response = post(data).await;
+    assert_eq!(response.quiet, None);
+    assert!(condensation_counted());
+}
+
+#[test]
+fn test_eval_rule_operators() {
+    let cases = [
+        (AlertOperator::GreaterThan, 1.1, true),
+        (AlertOperator::GreaterThan, 1.0, false),
+        (AlertOperator::GreaterThanOrEqual, 1.0, true),
+        (AlertOperator::GreaterThanOrEqual, 0.9, false),
+        (AlertOperator::LessThan, 0.9, true),
+        (AlertOperator::LessThan, 1.0, false),
+        (AlertOperator::LessThanOrEqual, 1.0, true),
+        (AlertOperator::LessThanOrEqual, 1.1, false),
+        (AlertOperator::Equal, 1.0, true),
+        (AlertOperator::Equal, 1.1, false),
+        (AlertOperator::NotEqual, 1.1, true),
+        (AlertOperator::NotEqual, 1.0, false),
+    ];
+    for (operator, value, expected) in cases {
+        assert_eq!(
+            eval_rule(value, operator, 1.0),
+            expected,
+            "{value} {operator:?} 1.0"
+        );
+    }
+}
+
+#[test]
+fn test_eval_rule_never_triggers_on_nan() {
+    for operator in [
+        AlertOperator::GreaterThan,
+        AlertOperator::GreaterThanOrEqual,
+        AlertOperator::LessThan,
+        AlertOperator::LessThanOrEqual,
+        AlertOperator::Equal,
+        AlertOperator::NotEqual,
+    ] {
+        assert!(!eval_rule(f32::NAN, operator, 1.0), "{operator:?}");
+    }
+}
+
+#[test]
+fn test_eval_rule_with_infinite_values() {
+    assert!(eval_rule(f32::INFINITY, AlertOperator::GreaterThan, 1.0));
+    assert!(eval_rule(f32::NEG_INFINITY, AlertOperator::LessThan, -1.0));
+    assert!(!eval_rule(
+        f32::INFINITY,
+        AlertOperator::LessThanOrEqual,
+        f32::MAX
+    ));
+}
+
+#[test]
+fn test_alert_operators_use_the_symbols() {
+    let rules: AlertRules = serde_json::from_str(
+        r#"{"rules":[{"metric":"battery_voltage","operator":"<=","threshold":11.9}]}"#,
+    )
+    .unwrap();
+    assert_eq!(rules.rules[0].operator, AlertOperator::LessThanOrEqual);
+
+    assert!(serde_json::from_str::<AlertRules>(
+        r#"{"rules":[{"metric":"battery_voltage","operator":"=<","threshold":11.9}]}"#,
+    )
+    .is_err());
+}
+
+#[test]
+fn test_invalid_alert_rules() {
+    let rule = |metric: &str, threshold: f32| AlertRule {
+        metric: metric.to_string(),
+        operator: AlertOperator::GreaterThan,
+        threshold,
+    };
+
+    let rules = AlertRules {
+        rules: vec![rule("water_level", 1.0), rule("battery_voltage", 12.0)],
+    };
+    assert!(rules.validate().is_ok());
+
+    let rules = AlertRules {
+        rules: vec![rule("humidity_of_the_moon", 1.0)],
+    };
+    assert!(rules.validate().is_err());
+
+    let rules = AlertRules {
+        rules: vec![rule("water_level", f32::INFINITY)],
+    };
+    assert!(rules.validate().is_err());
+
+    let rules = AlertRules {
+        rules: vec![rule("water_level", 1.0); MAX_ALERT_RULES + 1],
+    };
+    assert!(rules.validate().is_err());
+}
+
+#[test]
+fn test_alert_rules_for_metrics_that_are_not_reported_are_not_triggered() {
+    let rules = AlertRules {
+        rules: vec![AlertRule {
+            metric: "tank_temperature".to_string(),
+            operator: AlertOperator::LessThan,
+            threshold: 100.0,
+        }],
+    };
+    let data = SensorData {
+        tank_temperature_in_celcius: None,
+        ..create_valid_sensor_data()
+    };
+
+    assert!(rules
+        .evaluate(&HistoryPoint::new(Utc::now(), &data))
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_alert_rules_are_evaluated_for_each_reading() {
+    let device_id = "alert-rule-test-device";
+    let app = create_router(AppState::new());
+
+    let alerts_request = |method: &str, body: Option<&AlertRules>| {
+        let builder = Request::builder()
+            .method(method)
+            .uri(format!("/api/v1/alerts/{device_id}"));
+        match body {
+            Some(rules) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(rules).unwrap()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        }
+    };
+    let sensor_request = |data: &SensorData| {
+        Request::builder()
+            .method("POST")
+            .uri("/api/v1/sensor")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(data).unwrap()))
+            .unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(alerts_request("GET", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let rules = AlertRules {
+        rules: vec![
+            AlertRule {
+                metric: "battery_voltage".to_string(),
+                operator: AlertOperator::LessThan,
+                threshold: 3.5,
+            },
+            AlertRule {
+                metric: "water_level".to_string(),
+                operator: AlertOperator::GreaterThanOrEqual,
+                threshold: 1.5,
+            },
+        ],
+    };
+    let response = app
+        .clone()
+        .oneshot(alerts_request("POST", Some(&rules)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The water level is exactly at the threshold, the battery is above it
+    let data = SensorData {
+        device_id: device_id.to_string(),
+        battery_voltage: 3.7,
+        tank_level_in_meters: 1.5,
+        ..create_valid_sensor_data()
+    };
+    let response = app.clone().oneshot(sensor_request(&data)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(alerts_request("GET", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body["rules"].as_array().unwrap().len(), 2);
+    let triggered = body["triggered_alerts"].as_array().unwrap();
+    assert_eq!(triggered.len(), 1);
+    assert_eq!(triggered[0]["metric"], "water_level");
+    assert_eq!(triggered[0]["operator"], ">=");
+    assert_eq!(triggered[0]["value"], 1.5);
+
+    let active_alerts = PROMETHEUS_METRICS.render().unwrap();
+    assert!(active_alerts.contains(&format!("alerts_active{{device_id=\"{device_id}\"}} 1")));
+
+    // The alerts follow the latest reading
+    let data = SensorData {
+        battery_voltage: 3.4,
+        tank_level_in_meters: 1.0,
+        ..data
+    };
+    let response = app.clone().oneshot(sensor_request(&data)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+        .uri(format!("/api/v1/sensor/{device_id}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let triggered = body["triggered_alerts"].as_array().unwrap();
+    assert_eq!(triggered.len(), 1);
+    assert_eq!(triggered[0]["metric"], "battery_voltage");
+}
+
+#[test]
+fn test_newly_triggered_alerts_are_the_transitions() {
+    let alert = |metric: &str, value: f32| TriggeredAlert {
+        metric: metric.to_string(),
+        operator: AlertOperator::GreaterThan,
+        threshold: 1.0,
+        value,
+    };
+
+    let previous = vec![alert("water_level", 1.5)];
+    let current = vec![alert("water_level", 1.8), alert("battery_voltage", 13.0)];
+    assert_eq!(
+        newly_triggered_alerts(&previous, &current),
+        vec![alert("battery_voltage", 13.0)]
+    );
+    assert!(newly_triggered_alerts(&current, &previous).is_empty());
+}
+
+#[tokio::test]
+async fn test_alert_webhook_debounces_notifications() {
+    let webhook = AlertWebhook::new("http://127.0.0.1:1/alerts".to_string())
+        .unwrap()
+        .with_min_interval(std::time::Duration::from_secs(60));
+    let alert = TriggeredAlert {
+        metric: "water_level".to_string(),
+        operator: AlertOperator::GreaterThan,
+        threshold: 1.0,
+        value: 1.5,
+    };
+
+    let now = std::time::Instant::now();
+    assert!(webhook.try_notify("device-1", &alert, now));
+    assert!(!webhook.try_notify("device-1", &alert, now + std::time::Duration::from_secs(59)));
+    assert!(webhook.try_notify("device-2", &alert, now));
+    assert!(webhook.try_notify("device-1", &alert, now + std::time::Duration::from_secs(60)));
+}
+
+/// Start a server that passes the body of each request it receives to the returned channel.
+/// Returns the URL of the server.
+async fn start_mock_webhook() -> (
+    String,
+    tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>,
+) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let app = Router::new().route(
+        "/alerts",
+        post(move |Json(body): Json<serde_json::Value>| {
+            let sender = sender.clone();
+            async move {
+                let _ = sender.send(body);
+                StatusCode::OK
+            }
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    (url, receiver)
+}
+
+/// Send readings with the given water levels for a device with a rule that triggers above 1 m
+async fn send_levels_with_alert_webhook(webhook: AlertWebhook, device_id: &str, levels: &[f32]) {
+    let state = AppState::new().with_alert_webhook(Some(webhook));
+    state.alert_rules.write().await.insert(
+        device_id.to_string(),
+        AlertRules {
+            rules: vec![AlertRule {
+                metric: "water_level".to_string(),
+                operator: AlertOperator::GreaterThan,
+                threshold: 1.0,
+            }],
+        },
+    );
+
+    for level in levels {
+        let data = SensorData {
+            device_id: device_id.to_string(),
+            tank_level_in_meters: *level,
+            ..create_valid_sensor_data()
+        };
+        let result = handle_sensor_data(State(state.clone()), None, Ok(Json(data))).await;
+        assert!(result.is_ok(), "Valid sensor data should be processed");
+    }
+}
+
+/// Collect the notifications that arrive within a short time
+async fn received_notifications(
+    receiver: &mut tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>,
+) -> Vec<serde_json::Value> {
+    let mut notifications = Vec::new();
+    while let Ok(Some(notification)) =
+        tokio::time::timeout(std::time::Duration::from_millis(500), receiver.recv()).await
+    {
+        notifications.push(notification);
+    }
+
+    notifications
+}
+
+#[tokio::test]
+async fn test_alert_webhook_is_notified_once_per_transition() {
+    let (url, mut receiver) = start_mock_webhook().await;
+    let webhook = AlertWebhook::new(url)
+        .unwrap()
+        .with_min_interval(std::time::Duration::ZERO);
+
+    // Triggers, stays triggered, clears and triggers again
+    send_levels_with_alert_webhook(webhook, "webhook-test-device", &[0.5, 1.5, 1.6, 0.5, 1.5])
+        .await;
+
+    let notifications = received_notifications(&mut receiver).await;
+    assert_eq!(notifications.len(), 2);
+    for notification in notifications {
+        assert_eq!(notification["device_id"], "webhook-test-device");
+        assert_eq!(notification["metric"], "water_level");
+        assert_eq!(notification["operator"], ">");
+        assert_eq!(notification["value"], 1.5);
+        assert!(notification["triggered_at"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn test_alert_webhook_is_not_notified_for_a_flapping_value() {
+    let (url, mut receiver) = start_mock_webhook().await;
+    let webhook = AlertWebhook::new(url)
+        .unwrap()
+        .with_min_interval(std::time::Duration::from_secs(3600));
+
+    send_levels_with_alert_webhook(
+        webhook,
+        "flapping-webhook-test-device",
+        &[1.5, 0.9, 1.1, 0.9, 1.2],
+    )
+    .await;
+
+    assert_eq!(received_notifications(&mut receiver).await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_failing_alert_webhook_does_not_block_the_sensor_data() {
+    // Nothing listens on this port
+    let webhook = AlertWebhook::new("http://127.0.0.1:1/alerts".to_string()).unwrap();
+
+    send_levels_with_alert_webhook(webhook, "failing-webhook-test-device", &[1.5]).await;
+}
+
+#[tokio::test]
+async fn test_invalid_alert_rules_are_rejected() {
+    let app = create_router(AppState::new());
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/alerts/test-device-001")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            r#"{"rules":[{"metric":"unknown","operator":">","threshold":1.0}]}"#,
+        ))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+async fn post_sensor_batch(batch: &[SensorData]) -> (StatusCode, ApiResponse) {
+    post_sensor_batch_with_state(AppState::new(), batch).await
+}
+
+async fn post_sensor_batch_with_state(
+    state: AppState,
+    batch: &[SensorData],
+) -> (StatusCode, ApiResponse) {
+    let app = create_router(state);
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor/batch")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(batch).unwrap()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_batch_all_valid() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let batch = vec![create_valid_sensor_data(), create_valid_sensor_data()];
+
+    let (status, response) = post_sensor_batch(&batch).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.status, "success");
+    assert_eq!(response.accepted, Some(2));
+    assert!(response.rejected.is_empty());
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_batch_all_invalid() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let mut invalid_boot_count = create_valid_sensor_data();
+    invalid_boot_count.boot_count = 0;
+    let mut invalid_humidity = create_valid_sensor_data();
+    invalid_humidity.humidity_in_percent = 100.1;
+    let batch = vec![invalid_boot_count, invalid_humidity];
+
+    let (status, response) = post_sensor_batch(&batch).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.status, "error");
+    assert_eq!(response.accepted, Some(0));
+    assert_eq!(
+        response.rejected,
+        vec![
+            RejectedSensorData {
+                index: 0,
+                message: "The device boot count should at least be 1.".to_string(),
+            },
+            RejectedSensorData {
+                index: 1,
+                message: "Humidity out of reasonable range (0% to 100%)".to_string(),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_batch_mixed() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let mut invalid_data = create_valid_sensor_data();
+    invalid_data.tank_level_in_meters = 5.1;
+    let batch = vec![
+        create_valid_sensor_data(),
+        invalid_data,
+        create_valid_sensor_data(),
+    ];
+
+    let (status, response) = post_sensor_batch(&batch).await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(response.status, "partial");
+    assert_eq!(response.accepted, Some(2));
+    assert_eq!(
+        response.rejected,
+        vec![RejectedSensorData {
+            index: 1,
+            message: "Tank water level out of reasonable range (0m to 5m)".to_string(),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_batch_sent_again_is_not_stored_twice() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new();
+    let batch: Vec<SensorData> = (0..3)
+        .map(|reading_seq| SensorData {
+            reading_seq: Some(reading_seq),
+            ..create_valid_sensor_data()
+        })
+        .collect();
+
+    for _ in 0..2 {
+        let (status, response) = post_sensor_batch_with_state(state.clone(), &batch).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.accepted, Some(3));
+    }
+
+    let history = state.sensor_history.read().await;
+    assert_eq!(history.get("test-device-001").map(|h| h.len()), Some(3));
+}
+
+#[tokio::test]
+async fn test_duplicate_of_a_batch_reading_is_detected_by_the_single_endpoint() {
+    let state = AppState::new();
+    let batch: Vec<SensorData> = (0..3)
+        .map(|reading_seq| SensorData {
+            reading_seq: Some(reading_seq),
+            ..create_valid_sensor_data()
+        })
+        .collect();
+
+    post_sensor_batch_with_state(state.clone(), &batch).await;
+
+    let response = handle_sensor_data(State(state), None, Ok(Json(batch[0].clone())))
+        .await
+        .map(IntoResponse::into_response)
+        .unwrap();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let response: ApiResponse = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response.status, "duplicate");
+}
+
+#[tokio::test]
+async fn test_handle_sensor_data_batch_rate_limited() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new().with_rate_limiter(Some(DeviceRateLimiter::new(1, 2)));
+    let batch = vec![
+        create_valid_sensor_data(),
+        create_valid_sensor_data(),
+        create_valid_sensor_data(),
+    ];
+
+    let (status, response) = post_sensor_batch_with_state(state.clone(), &batch).await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(response.accepted, Some(2));
+    assert_eq!(
+        response.rejected,
+        vec![RejectedSensorData {
+            index: 2,
+            message: "Too many requests from this device".to_string(),
+        }]
+    );
+
+    let (status, response) = post_sensor_batch_with_state(state, &batch[..1]).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.accepted, Some(0));
+}
+
+#[tokio::test]
+async fn test_prometheus_metrics_contain_posted_sensor_data() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let mut data = create_valid_sensor_data();
+    data.device_id = "prometheus-test-device".to_string();
+
+    let app = create_router(AppState::new());
+
+    let post_request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&data).unwrap()))
+        .unwrap();
+    let post_response = app.clone().oneshot(post_request).await.unwrap();
+    assert_eq!(post_response.status(), StatusCode::OK);
+
+    let scrape_request = Request::builder()
+        .method("GET")
+        .uri("/metrics")
+        .body(Body::empty())
+        .unwrap();
+    let scrape_response = app.oneshot(scrape_request).await.unwrap();
+    assert_eq!(scrape_response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(scrape_response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body_str = String::from_utf8(body_bytes.to_vec()).unwrap();
+    assert!(
+        body_str.contains("water_level{device_id=\"prometheus-test-device\"} 1.5"),
+        "The scraped metrics should contain the water level of the device. Metrics were: {}",
+        body_str
+    );
+}
+
+#[tokio::test]
+async fn test_get_sensor_data_returns_latest_reading() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new();
+    let data = create_valid_sensor_data();
+
+    let result = handle_sensor_data(State(state.clone()), None, Ok(Json(data.clone()))).await;
+    assert!(result.is_ok(), "Valid sensor data should be processed");
+
+    let result = handle_get_sensor_data(
+        State(state),
+        Path("test-device-001".to_string()),
+        Query(UnitsQuery { units: None }),
+    )
+    .await;
+    let response = match result {
+        Ok(r) => r.into_response(),
+        Err(_) => panic!("The sensor data for a known device should be returned"),
+    };
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stored_data: SensorData = serde_json::from_slice(&body_bytes).unwrap();
+    // The service judges the quality of the reading when it is stored
+    assert_eq!(
+        stored_data,
+        SensorData {
+            data_quality: Some(DataQuality::Ok),
+            ..data
+        }
+    );
+}
+
+#[test]
+fn test_meters_to_feet() {
+    assert_eq!(meters_to_feet(0.0), 0.0);
+    assert!((meters_to_feet(0.3048) - 1.0).abs() < 1e-6);
+    assert!((meters_to_feet(1.5) - 4.92126).abs() < 1e-4);
+}
+
+#[test]
+fn test_liters_to_gallons() {
+    assert_eq!(liters_to_gallons(0.0), 0.0);
+    assert!((liters_to_gallons(3.785_411_8) - 1.0).abs() < 1e-6);
+    assert!((liters_to_gallons(1000.0) - 264.172).abs() < 1e-2);
+}
+
+#[tokio::test]
+async fn test_get_sensor_data_in_imperial_units() {
+    let state = AppState::new();
+    let data = create_valid_sensor_data();
+    store_sensor_data(&state, data.clone()).await;
+    let app = create_router(state);
+
+    let request = Request::builder()
+        .method("GET")
+        .uri(format!("/api/v1/sensor/{}?units=imperial", data.device_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body["units"], "imperial");
+    let level = body["tank_level_in_feet"].as_f64().unwrap() as f32;
+    assert_eq!(level, meters_to_feet(data.tank_level_in_meters));
+    let volume = body["tank_volume_in_gallons"].as_f64().unwrap() as f32;
+    assert_eq!(volume, liters_to_gallons(data.tank_volume_in_liters));
+    assert!(body.get("tank_level_in_meters").is_none());
+    assert!(body.get("tank_volume_in_liters").is_none());
+
+    // The readings are stored in metric units
+    let readings = get_latest_reading(&app, &data.device_id).await;
+    assert_eq!(readings["units"], "metric");
+    assert_eq!(
+        readings["tank_level_in_meters"].as_f64().unwrap() as f32,
+        data.tank_level_in_meters
+    );
+
+    let request = Request::builder()
+        .method("GET")
+        .uri(format!("/api/v1/sensor/{}?units=furlongs", data.device_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Get the latest reading of the device from the sensor data endpoint without a units query
+async fn get_latest_reading(app: &Router, device_id: &str) -> serde_json::Value {
+    let request = Request::builder()
+        .method("GET")
+        .uri(format!("/api/v1/sensor/{}", device_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body_bytes).unwrap()
+}
+
+#[tokio::test]
+async fn test_get_sensor_data_unknown_device() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let result = handle_get_sensor_data(
+        State(AppState::new()),
+        Path("unknown-device".to_string()),
+        Query(UnitsQuery { units: None }),
+    )
+    .await;
+
+    match result {
+        Ok(_) => panic!("An unknown device should not return sensor data"),
+        Err((status, _)) => assert_eq!(status, StatusCode::NOT_FOUND),
+    }
+}
+
+fn create_sensor_history(start_hour: u32, levels: &[f32]) -> Vec<SensorReading> {
+    use chrono::TimeZone;
+
+    let start = Utc.with_ymd_and_hms(2025, 1, 1, start_hour, 0, 0).unwrap();
+    levels
+        .iter()
+        .enumerate()
+        .map(|(index, level)| SensorReading {
+            received_at: start + chrono::Duration::minutes(30 * index as i64),
+            data: SensorData {
+                tank_level_in_meters: *level,
+                ..create_valid_sensor_data()
+            },
+        })
+        .collect()
+}
+
+#[test]
+fn test_detect_leak_slow_drop_at_night() {
+    // 1 cm every half hour is 2 cm per hour
+    let history = create_sensor_history(1, &[1.50, 1.49, 1.48, 1.47, 1.46, 1.45]);
+    assert!(detect_leak(&history, &LeakDetectionSettings::default()));
+}
+
+#[test]
+fn test_detect_leak_sharp_drop_is_usage() {
+    let history = create_sensor_history(1, &[1.50, 1.49, 1.48, 1.30, 1.29, 1.28]);
+    assert!(!detect_leak(&history, &LeakDetectionSettings::default()));
+}
+
+#[test]
+fn test_detect_leak_during_the_day() {
+    let history = create_sensor_history(12, &[1.50, 1.49, 1.48, 1.47, 1.46, 1.45]);
+    assert!(!detect_leak(&history, &LeakDetectionSettings::default()));
+}
+
+#[test]
+fn test_detect_leak_stable_level() {
+    let history = create_sensor_history(1, &[1.50, 1.50, 1.50, 1.50, 1.50, 1.50]);
+    assert!(!detect_leak(&history, &LeakDetectionSettings::default()));
+}
+
+#[test]
+fn test_detect_leak_not_enough_readings() {
+    let history = create_sensor_history(1, &[1.50, 1.49, 1.48]);
+    assert!(!detect_leak(&history, &LeakDetectionSettings::default()));
+}
+
+#[test]
+fn test_level_rate_when_water_is_used() {
+    // 5 cm in half an hour is 10 cm per hour
+    let rate = level_rate(1.50, 1.45, 1800.0);
+    assert!((rate - 0.1).abs() < 1e-4, "rate was {rate}");
+}
+
+#[test]
+fn test_level_rate_when_the_tank_is_refilled() {
+    let rate = level_rate(1.00, 1.60, 3600.0);
+    assert!((rate + 0.6).abs() < 1e-4, "rate was {rate}");
+}
+
+#[test]
+fn test_level_rate_without_elapsed_time() {
+    assert_eq!(level_rate(1.50, 1.00, 0.0), 0.0);
+    assert_eq!(level_rate(1.50, 1.00, -60.0), 0.0);
+}
+
+#[test]
+fn test_plausible_level_changes() {
+    let settings = LevelPlausibilitySettings::default();
+
+    // Water being used and a refill of 40 cm in half an hour
+    let history = create_sensor_history(1, &[1.50, 1.48, 1.88]);
+    assert!(is_plausible_level_change(
+        &history[0],
+        &history[1],
+        &settings
+    ));
+    assert!(is_plausible_level_change(
+        &history[1],
+        &history[2],
+        &settings
+    ));
+
+    // Sensor noise between two readings that arrive at the same time
+    let noisy = SensorReading {
+        data: SensorData {
+            tank_level_in_meters: 1.53,
+            ..history[0].data.clone()
+        },
+        ..history[0].clone()
+    };
+    assert!(is_plausible_level_change(&history[0], &noisy, &settings));
+}
+
+#[test]
+fn test_implausible_level_changes() {
+    let settings = LevelPlausibilitySettings::default();
+
+    // A tank can't fill from 0.5 m to 4 m, or empty from 4 m to 0.5 m, in half an hour
+    let history = create_sensor_history(1, &[0.5, 4.0, 0.5]);
+    assert!(!is_plausible_level_change(
+        &history[0],
+        &history[1],
+        &settings
+    ));
+    assert!(!is_plausible_level_change(
+        &history[1],
+        &history[2],
+        &settings
+    ));
+
+    // The jump is plausible if the check is turned off
+    let disabled = LevelPlausibilitySettings {
+        maximum_change_rate_in_meters_per_hour: None,
+        ..LevelPlausibilitySettings::default()
+    };
+    assert!(is_plausible_level_change(
+        &history[0],
+        &history[1],
+        &disabled
+    ));
+
+    // The level of a disconnected pressure sensor can't be judged
+    let mut faulty = history[1].clone();
+    faulty.data.pressure_sensor_fault = Some(true);
+    assert!(is_plausible_level_change(&history[0], &faulty, &settings));
+}
+
+#[tokio::test]
+async fn test_implausible_level_change_is_flagged() {
+    let state = AppState::new();
+    for level in [0.5, 0.52, 4.0] {
+        let data = SensorData {
+            device_id: "plausibility-test-device".to_string(),
+            tank_level_in_meters: level,
+            ..create_valid_sensor_data()
+        };
+        let result = handle_sensor_data(State(state.clone()), None, Ok(Json(data))).await;
+        assert!(
+            result.is_ok(),
+            "Implausible readings should not be rejected"
+        );
+
+        let expected = if level == 4.0 {
+            DataQuality::ImplausibleLevelChange
+        } else {
+            DataQuality::Ok
+        };
+        let readings = state.latest_sensor_data.read().await;
+        let stored = &readings.get("plausibility-test-device").unwrap().data;
+        assert_eq!(stored.data_quality, Some(expected), "level {level}");
+    }
+}
+
+fn create_history_point(minutes: i64, level: f32) -> HistoryPoint {
+    use chrono::TimeZone;
+
+    let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+    HistoryPoint::new(
+        start + chrono::Duration::minutes(minutes),
+        &SensorData {
+            tank_level_in_meters: level,
+            ..create_valid_sensor_data()
+        },
+    )
+}
+
+#[test]
+fn test_downsample_into_hourly_buckets() {
+    let points = [
+        create_history_point(0, 1.0),
+        create_history_point(20, 2.0),
+        create_history_point(40, 3.0),
+        create_history_point(60, 1.5),
+        create_history_point(119, 2.5),
+        // No readings between 02:00 and 03:00
+        create_history_point(185, 4.0),
+    ];
+
+    let buckets = downsample(&points, HistoryMetric::WaterLevel, 3600);
+    assert_eq!(
+        buckets,
+        vec![
+            HistoryBucket {
+                start: "2025-01-01T00:00:00+00:00".to_string(),
+                min: 1.0,
+                avg: 2.0,
+                max: 3.0,
+                count: 3,
+            },
+            HistoryBucket {
+                start: "2025-01-01T01:00:00+00:00".to_string(),
+                min: 1.5,
+                avg: 2.0,
+                max: 2.5,
+                count: 2,
+            },
+            HistoryBucket {
+                start: "2025-01-01T03:00:00+00:00".to_string(),
+                min: 4.0,
+                avg: 4.0,
+                max: 4.0,
+                count: 1,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_downsample_skips_missing_values() {
+    let mut points = [create_history_point(0, 1.0), create_history_point(10, 2.0)];
+    points[0].tank_temperature_in_celcius = None;
+
+    let buckets = downsample(&points, HistoryMetric::TankTemperature, 3600);
+    assert_eq!(buckets.len(), 1);
+    assert_eq!(buckets[0].count, 1);
+}
+
+#[test]
+fn test_parse_resolution() {
+    assert_eq!(parse_resolution("30s"), Some(30));
+    assert_eq!(parse_resolution("15m"), Some(900));
+    assert_eq!(parse_resolution("1h"), Some(3600));
+    assert_eq!(parse_resolution("24h"), Some(24 * 3600));
+
+    assert_eq!(parse_resolution("0h"), None);
+    assert_eq!(parse_resolution("25h"), None);
+    assert_eq!(parse_resolution("1d"), None);
+    assert_eq!(parse_resolution("h"), None);
+    assert_eq!(parse_resolution(""), None);
+}
+
+#[test]
+fn test_metric_history_removes_old_readings() {
+    let history = MetricHistory::new(HISTORY_POINTS_PER_DEVICE, DEFAULT_HISTORY_MAX_POINTS);
+    history.record("device-a", create_history_point(0, 1.0));
+    history.record("device-a", create_history_point(60, 2.0));
+    history.record("device-a", create_history_point(26 * 60, 3.0));
+
+    let points = history.points("device-a").unwrap();
+    assert_eq!(points.len(), 1);
+    assert_eq!(points[0].tank_level_in_meters, 3.0);
+}
+
+#[test]
+fn test_metric_history_limits_readings_per_device() {
+    let history = MetricHistory::new(2, DEFAULT_HISTORY_MAX_POINTS);
+    history.record("device-a", create_history_point(0, 1.0));
+    history.record("device-a", create_history_point(1, 2.0));
+    history.record("device-a", create_history_point(2, 3.0));
+
+    let levels: Vec<f32> = history
+        .points("device-a")
+        .unwrap()
+        .iter()
+        .map(|point| point.tank_level_in_meters)
+        .collect();
+    assert_eq!(levels, vec![2.0, 3.0]);
+}
+
+#[test]
+fn test_metric_history_evicts_oldest_reading_of_all_devices() {
+    let history = MetricHistory::new(HISTORY_POINTS_PER_DEVICE, 3);
+    history.record("device-a", create_history_point(0, 1.0));
+    history.record("device-b", create_history_point(1, 2.0));
+    history.record("device-a", create_history_point(2, 3.0));
+    history.record("device-b", create_history_point(3, 4.0));
+    assert_eq!(history.len(), 3);
+
+    let levels: Vec<f32> = history
+        .points("device-a")
+        .unwrap()
+        .iter()
+        .map(|point| point.tank_level_in_meters)
+        .collect();
+    assert_eq!(levels, vec![3.0]);
+    assert_eq!(history.points("device-b").unwrap().len(), 2);
+
+    // Devices without readings are removed
+    history.record("device-b", create_history_point(4, 5.0));
+    history.record("device-b", create_history_point(5, 6.0));
+    assert!(history.points("device-a").is_none());
+}
+
+#[tokio::test]
+async fn test_get_history_returns_buckets() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new();
+    let data = create_valid_sensor_data();
+    store_sensor_data(&state, data.clone()).await;
+    store_sensor_data(
+        &state,
+        SensorData {
+            tank_level_in_meters: data.tank_level_in_meters + 0.5,
+            ..data.clone()
+        },
+    )
+    .await;
+
+    let app = create_router(state);
+    let request = Request::builder()
+        .method("GET")
+        .uri(format!(
+            "/api/v1/history/{}?metric=water_level&resolution=24h",
+            data.device_id
+        ))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let history: DeviceHistory = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(history.device_id, data.device_id);
+    assert_eq!(history.metric, "water_level");
+    assert_eq!(history.unit, "m");
+    assert_eq!(history.resolution_in_seconds, 24 * 3600);
+
+    // Both readings may fall on either side of a bucket boundary
+    let count: usize = history.buckets.iter().map(|bucket| bucket.count).sum();
+    assert_eq!(count, 2);
+    let min = history
+        .buckets
+        .iter()
+        .map(|bucket| bucket.min)
+        .fold(f32::MAX, f32::min);
+    let max = history
+        .buckets
+        .iter()
+        .map(|bucket| bucket.max)
+        .fold(f32::MIN, f32::max);
+    assert_eq!(min, data.tank_level_in_meters);
+    assert_eq!(max, data.tank_level_in_meters + 0.5);
+}
+
+#[tokio::test]
+async fn test_get_history_rejects_invalid_query() {
+    let state = AppState::new();
+    let data = create_valid_sensor_data();
+    store_sensor_data(&state, data.clone()).await;
+    let app = create_router(state);
+
+    for query in ["metric=unknown", "resolution=1d", "units=furlongs"] {
+        let request = Request::builder()
+            .method("GET")
+            .uri(format!("/api/v1/history/{}?{}", data.device_id, query))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_get_history_in_imperial_units() {
+    let state = AppState::new();
+    let data = create_valid_sensor_data();
+    store_sensor_data(&state, data.clone()).await;
+    let app = create_router(state);
+
+    for (metric, unit, expected) in [
+        (
+            "water_level",
+            "ft",
+            meters_to_feet(data.tank_level_in_meters),
+        ),
+        (
+            "tank_volume",
+            "gal",
+            liters_to_gallons(data.tank_volume_in_liters),
+        ),
+        ("battery_voltage", "V", data.battery_voltage),
+    ] {
+        let request = Request::builder()
+            .method("GET")
+            .uri(format!(
+                "/api/v1/history/{}?metric={}&units=imperial",
+                data.device_id, metric
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let history: DeviceHistory = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(history.unit, unit);
+        assert_eq!(history.buckets.len(), 1);
+        assert_eq!(history.buckets[0].min, expected, "{}", metric);
+        assert_eq!(history.buckets[0].avg, expected, "{}", metric);
+        assert_eq!(history.buckets[0].max, expected, "{}", metric);
+    }
+}
+
+#[tokio::test]
+async fn test_get_history_unknown_device() {
+    let app = create_router(AppState::new());
+    let request = Request::builder()
+        .method("GET")
+        .uri("/api/v1/history/unknown-device")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_device_status_from_last_seen() {
+    let now = Utc::now();
+    assert_eq!(
+        DeviceStatus::from_last_seen(&now, &now, 600),
+        DeviceStatus::Online
+    );
+    assert_eq!(
+        DeviceStatus::from_last_seen(&(now - chrono::Duration::seconds(600)), &now, 600),
+        DeviceStatus::Online
+    );
+    assert_eq!(
+        DeviceStatus::from_last_seen(&(now - chrono::Duration::seconds(601)), &now, 600),
+        DeviceStatus::Offline
+    );
+}
+
+/// Open the live stream of readings and return the body as a stream of chunks
+async fn open_stream(app: Router, uri: &str) -> axum::body::BodyDataStream {
+    let request = Request::builder()
+        .method("GET")
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+
+    response.into_body().into_data_stream()
+}
+
+#[tokio::test]
+async fn test_stream_pushes_new_readings() {
+    let app = create_router(AppState::new());
+    let mut stream = open_stream(app.clone(), "/api/v1/stream").await;
+    let mut device_stream =
+        open_stream(app.clone(), "/api/v1/stream?device_id=test-device-001").await;
+    let mut other_device_stream =
+        open_stream(app.clone(), "/api/v1/stream?device_id=other-device").await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_string(&create_valid_sensor_data()).unwrap(),
+        ))
+        .unwrap();
+    // Keep the router alive, the streams end when the state is dropped
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for stream in [&mut stream, &mut device_stream] {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+            .await
+            .expect("The reading should be pushed to the stream")
+            .unwrap()
+            .unwrap();
+        let event = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(event.starts_with("event: sensor_data\n"));
+
+        let data = event
+            .lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .unwrap();
+        let sensor_data: SensorData = serde_json::from_str(data).unwrap();
+        assert_eq!(sensor_data.device_id, "test-device-001");
+    }
+
+    // The reading is for another device
+    assert!(tokio::time::timeout(
+        std::time::Duration::from_millis(100),
+        other_device_stream.next()
+    )
+    .await
+    .is_err());
+}
+
+async fn get_devices(state: AppState) -> DeviceList {
+    let app = create_router(state);
+    let request = Request::builder()
+        .method("GET")
+        .uri("/api/v1/devices")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body_bytes).unwrap()
+}
+
+#[tokio::test]
+async fn test_get_devices_lists_every_device() {
+    let state = AppState::new();
+    assert!(get_devices(state.clone()).await.devices.is_empty());
+
+    for (device_id, boot_count) in [("device-b", 7), ("device-a", 3)] {
+        store_sensor_data(
+            &state,
+            SensorData {
+                device_id: device_id.to_string(),
+                boot_count,
+                ..create_valid_sensor_data()
+            },
+        )
+        .await;
+    }
+
+    let devices = get_devices(state).await.devices;
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].device_id, "device-a");
+    assert_eq!(devices[0].boot_count, 3);
+    assert_eq!(devices[0].status, DeviceStatus::Online);
+    assert_eq!(devices[1].device_id, "device-b");
+    assert_eq!(devices[1].boot_count, 7);
+    assert_eq!(devices[1].status, DeviceStatus::Online);
+    let last_seen = chrono::DateTime::parse_from_rfc3339(&devices[0].last_seen).unwrap();
+    assert!(Utc::now() - last_seen.with_timezone(&Utc) < chrono::Duration::seconds(60));
+}
+
+#[tokio::test]
+async fn test_get_devices_reports_stale_device_offline() {
+    let state = AppState::new().with_device_offline_after_in_seconds(600);
+    let data = create_valid_sensor_data();
+    store_sensor_data(&state, data.clone()).await;
+    assert_eq!(
+        get_devices(state.clone()).await.devices[0].status,
+        DeviceStatus::Online
+    );
+
+    // The device stops sending data
+    let last_seen = Utc::now() - chrono::Duration::seconds(601);
+    state
+        .latest_sensor_data
+        .write()
+        .await
+        .get_mut(&data.device_id)
+        .unwrap()
+        .received_at = last_seen;
+
+    let devices = get_devices(state.clone()).await.devices;
+    assert_eq!(devices[0].status, DeviceStatus::Offline);
+    assert_eq!(devices[0].last_seen, last_seen.to_rfc3339());
+
+    // The device comes back
+    store_sensor_data(&state, data).await;
+    assert_eq!(
+        get_devices(state).await.devices[0].status,
+        DeviceStatus::Online
+    );
+}
+
+#[tokio::test]
+async fn test_get_sensor_data_contains_leak_suspected() {
+    // Initialize tracing for the test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(TestWriter::new())
+        .try_init();
+
+    let state = AppState::new();
+    let data = create_valid_sensor_data();
+
+    let result = handle_sensor_data(State(state.clone()), None, Ok(Json(data))).await;
+    assert!(result.is_ok(), "Valid sensor data should be processed");
+
+    let result = handle_get_sensor_data(
+        State(state),
+        Path("test-device-001".to_string()),
+        Query(UnitsQuery { units: None }),
+    )
+    .await;
+    let response = match result {
+        Ok(r) => r.into_response(),
+        Err(_) => panic!("The sensor data for a known device should be returned"),
+    };
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body["leak_suspected"], serde_json::Value::Bool(false));
+}
+
+#[test]
+fn test_dew_point() {
+    // (temperature, relative humidity, dew point) from psychrometric tables
+    let cases = [
+        (20.0, 50.0, 9.3),
+        (25.0, 60.0, 16.7),
+        (30.0, 80.0, 26.2),
+        (10.0, 90.0, 8.4),
+        (0.0, 100.0, 0.0),
+        (-10.0, 80.0, -12.8),
+    ];
+
+    for (temperature, humidity, expected) in cases {
+        let dew_point = dew_point_c(temperature, humidity);
+        assert!(
+            (dew_point - expected).abs() < 0.1,
+            "The dew point at {} °C and {}% should be {} °C but was {} °C",
+            temperature,
+            humidity,
+            expected,
+            dew_point
+        );
+    }
+
+    assert!(dew_point_c(20.0, 0.0).is_finite());
+}
+
+#[test]
+fn test_condensation_risk() {
+    // The dew point is 18.3 °C
+    assert!(condensation_risk(20.0, 90.0, 85.0));
+
+    // The humidity is below the threshold
+    assert!(!condensation_risk(20.0, 80.0, 85.0));
+    assert!(!condensation_risk(20.0, 90.0, 95.0));
+
+    // The dew point is 16.7 °C, which is not close to the temperature
+    assert!(!condensation_risk(25.0, 60.0, 50.0));
+}
+
+#[tokio::test]
+async fn test_get_sensor_data_contains_condensation_risk() {
+    let state = AppState::new();
+    let data = SensorData {
+        device_id: "condensation-test-device".to_string(),
+        temperature_in_celcius: 20.0,
+        humidity_in_percent: 95.0,
+        ..create_valid_sensor_data()
+    };
+    store_sensor_data(&state, data.clone()).await;
+
+    let condensation_risk = |response: Response| async {
+        let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+        body["condensation_risk"].clone()
+    };
+
+    let result = handle_get_sensor_data(
+        State(state.clone()),
+        Path(data.device_id.clone()),
+        Query(UnitsQuery { units: None }),
+    )
+    .await;
+    let response = match result {
+        Ok(r) => r.into_response(),
+        Err(_) => panic!("The sensor data for a known device should be returned"),
+    };
+    assert_eq!(
+        condensation_risk(response).await,
+        serde_json::Value::Bool(true)
+    );
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(
+        metrics
+            .contains("device_condensation_risk_total{device_id=\"condensation-test-device\"} 1"),
+        "The condensation risk should be counted: {}",
+        metrics
+    );
+
+    // A higher threshold for the device clears the risk
+    let mut config = create_tank_config();
+    config.alert_thresholds.condensation_humidity_in_percent = Some(98.0);
+    state
+        .tank_configs
+        .write()
+        .await
+        .insert(data.device_id.clone(), config);
+
+    let result = handle_get_sensor_data(
+        State(state),
+        Path(data.device_id.clone()),
+        Query(UnitsQuery { units: None }),
+    )
+    .await;
+    let response = match result {
+        Ok(r) => r.into_response(),
+        Err(_) => panic!("The sensor data for a known device should be returned"),
+    };
+    assert_eq!(
+        condensation_risk(response).await,
+        serde_json::Value::Bool(false)
+    );
+}
+
+fn gzip(body: &str) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(body.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn create_gzip_request(uri: &str, body: &str) -> Request {
+    Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_ENCODING, "gzip")
+        .body(Body::from(gzip(body)))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_gzip_sensor_data_is_accepted() {
+    let app = create_router(AppState::new());
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+
+    let response = app
+        .clone()
+        .oneshot(create_gzip_request("/api/v1/sensor", &body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+        .method("GET")
+        .uri("/api/v1/sensor/test-device-001")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stored_data: SensorData = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(
+        stored_data,
+        SensorData {
+            data_quality: Some(DataQuality::Ok),
+            ..create_valid_sensor_data()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_gzip_log_data_is_accepted() {
+    let app = create_router(AppState::new());
+    let body = r#"[{"device_id":"test-device-001","level":"INFO","message":"Hello","boot_count":1,"timestamp":1000}]"#;
+
+    let response = app
+        .oneshot(create_gzip_request("/api/v1/logs", body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_log_data_over_the_body_limit_is_rejected() {
+    let app = create_router(AppState::new().with_log_body_limit_in_bytes(1024));
+    let log_data: Vec<LogData> = (0..100)
+        .map(|_| create_log_data("test-device-001", "INFO", "Hello"))
+        .collect();
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/logs")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&log_data).unwrap()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let api_response: ApiResponse = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(api_response.status, "error");
+}
+
+#[tokio::test]
+async fn test_sensor_data_body_limit_applies_after_decompression() {
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let app = create_router(AppState::new().with_sensor_body_limit_in_bytes(body.len() - 1));
+
+    let request = create_gzip_request("/api/v1/sensor", &body);
+    assert!(gzip(&body).len() < body.len());
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+fn create_log_data(device_id: &str, level: &str, message: &str) -> LogData {
+    LogData {
+        device_id: device_id.to_string(),
+        level: level.to_string(),
+        message: message.to_string(),
+        boot_count: 1,
+        timestamp: 1000,
+        session_id: None,
+        count: 1,
+    }
+}
+
+fn create_log_entry(level: &str, message: &str) -> DeviceLogEntry {
+    DeviceLogEntry {
+        level: level.to_string(),
+        message: message.to_string(),
+        boot_count: 1,
+        device_ticks: 1000,
+        timestamp: "2025-01-01T00:00:00+00:00".to_string(),
+        count: 1,
+    }
+}
+
+async fn get_logs(app: Router, uri: &str) -> (StatusCode, Vec<DeviceLogEntry>) {
+    let request = Request::builder()
+        .method("GET")
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or_default(),
+    )
+}
+
+#[test]
+fn test_parse_level() {
+    assert_eq!(parse_level("error"), Some(tracing::Level::ERROR));
+    assert_eq!(parse_level("WARN"), Some(tracing::Level::WARN));
+    assert_eq!(parse_level("Info"), Some(tracing::Level::INFO));
+    assert_eq!(parse_level("debug"), Some(tracing::Level::DEBUG));
+    assert_eq!(parse_level("trace"), Some(tracing::Level::TRACE));
+    assert_eq!(parse_level("fatal"), None);
+}
+
+#[test]
+fn test_filter_log_entries_by_level() {
+    let buffer: std::collections::VecDeque<DeviceLogEntry> = [
+        create_log_entry("error", "first"),
+        create_log_entry("info", "second"),
+        create_log_entry("warn", "third"),
+        create_log_entry("debug", "fourth"),
+    ]
+    .into_iter()
+    .collect();
+
+    let messages = |level, limit| {
+        filter_log_entries(&buffer, level, limit)
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(messages(tracing::Level::WARN, None), vec!["first", "third"]);
+    assert_eq!(
+        messages(tracing::Level::INFO, None),
+        vec!["first", "second", "third"]
+    );
+    assert_eq!(messages(tracing::Level::TRACE, None).len(), 4);
+
+    // The limit keeps the most recent messages
+    assert_eq!(
+        messages(tracing::Level::TRACE, Some(2)),
+        vec!["third", "fourth"]
+    );
+}
+
+#[test]
+fn test_buffer_log_entry_evicts_the_oldest_entries() {
+    let mut buffer = std::collections::VecDeque::new();
+    for index in 0..5 {
+        buffer_log_entry(
+            &mut buffer,
+            create_log_entry("info", &format!("message {}", index)),
+            3,
+        );
+    }
+
+    let messages: Vec<_> = buffer.iter().map(|entry| entry.message.as_str()).collect();
+    assert_eq!(messages, vec!["message 2", "message 3", "message 4"]);
+}
+
+#[tokio::test]
+async fn test_get_log_data_filters_by_level() {
+    let state = AppState::new();
+    let logs = vec![
+        create_log_data("log-test-device", "INFO", "Connected"),
+        create_log_data("log-test-device", "WARN", "Weak signal"),
+        create_log_data("log-test-device", "ERROR", "Sensor failed"),
+        create_log_data("other-device", "ERROR", "Other failure"),
+    ];
+    let result = handle_log_data(State(state.clone()), None, Ok(Json(logs))).await;
+    assert!(result.is_ok(), "Valid log data should be processed");
+
+    let app = create_router(state);
+    let (status, entries) = get_logs(app.clone(), "/api/v1/logs/log-test-device?level=warn").await;
+    assert_eq!(status, StatusCode::OK);
+    let messages: Vec<_> = entries.iter().map(|entry| entry.message.as_str()).collect();
+    assert_eq!(messages, vec!["Weak signal", "Sensor failed"]);
+    assert_eq!(entries[0].level, "warn");
+
+    let (status, entries) = get_logs(app.clone(), "/api/v1/logs/log-test-device?limit=1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].message, "Sensor failed");
+
+    let (status, _) = get_logs(app.clone(), "/api/v1/logs/log-test-device?level=fatal").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = get_logs(app, "/api/v1/logs/unknown-device").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_get_log_data_keeps_the_most_recent_entries() {
+    let state = AppState::new().with_device_log_buffer_size(2);
+    let logs = (0..4)
+        .map(|index| create_log_data("log-test-device", "info", &format!("message {}", index)))
+        .collect();
+    let result = handle_log_data(State(state.clone()), None, Ok(Json(logs))).await;
+    assert!(result.is_ok(), "Valid log data should be processed");
+
+    let (status, entries) = get_logs(create_router(state), "/api/v1/logs/log-test-device").await;
+    assert_eq!(status, StatusCode::OK);
+    let messages: Vec<_> = entries.iter().map(|entry| entry.message.as_str()).collect();
+    assert_eq!(messages, vec!["message 2", "message 3"]);
+}
+
+#[tokio::test]
+async fn test_get_log_data_keeps_the_repeat_count() {
+    let app = create_router(AppState::new());
+    let body = r#"[
+        {"device_id":"log-test-device","level":"ERROR","message":"Retrying","boot_count":1,"timestamp":1000,"count":7},
+        {"device_id":"log-test-device","level":"INFO","message":"Connected","boot_count":1,"timestamp":2000}
+    ]"#;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/logs")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (status, entries) = get_logs(app, "/api/v1/logs/log-test-device").await;
+    assert_eq!(status, StatusCode::OK);
+    let counts: Vec<_> = entries.iter().map(|entry| entry.count).collect();
+    assert_eq!(counts, vec![7, 1]);
+}
+
+fn create_timing_request(authorization: Option<&str>) -> Request {
+    let timing_data = DeviceTimingData {
+        device_id: "auth-test-device".to_string(),
+        boot_count: 1,
+        timestamp: 1000,
+        session_id: None,
+    };
+
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri("/api/v1/timing")
+        .header(header::CONTENT_TYPE, "application/json");
+    if let Some(value) = authorization {
+        builder = builder.header(header::AUTHORIZATION, value);
+    }
+
+    builder
+        .body(Body::from(serde_json::to_string(&timing_data).unwrap()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_ingestion_without_api_key_is_rejected() {
+    let app = create_router(AppState::new().with_ingest_api_key(Some("secret".to_string())));
+
+    let response = app.oneshot(create_timing_request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_ingestion_with_invalid_api_key_is_rejected() {
+    let app = create_router(AppState::new().with_ingest_api_key(Some("secret".to_string())));
+
+    let response = app
+        .oneshot(create_timing_request(Some("Bearer wrong")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_ingestion_with_valid_api_key_is_accepted() {
+    let app = create_router(AppState::new().with_ingest_api_key(Some("secret".to_string())));
+
+    let response = app
+        .oneshot(create_timing_request(Some("Bearer secret")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_ingestion_without_configured_api_key_is_open() {
+    let app = create_router(AppState::new().with_ingest_api_key(Some(String::new())));
+
+    let response = app.oneshot(create_timing_request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_health_check_does_not_require_api_key() {
+    let app = create_router(AppState::new().with_ingest_api_key(Some("secret".to_string())));
+
+    let request = Request::builder()
+        .method("GET")
+        .uri("/health")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_health_live_is_ok() {
+    let app = create_router(AppState::new());
+
+    let request = Request::builder()
+        .method("GET")
+        .uri("/health/live")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_health_ready_when_exports_succeed() {
+    let healthy = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let app = create_router(AppState::new().with_telemetry_export_healthy(healthy));
+
+    let request = Request::builder()
+        .method("GET")
+        .uri("/health/ready")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_health_ready_when_exports_fail() {
+    let healthy = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let app = create_router(AppState::new().with_telemetry_export_healthy(healthy.clone()));
+
+    // The export pipeline reports a failure after the router has been created
+    healthy.store(false, std::sync::atomic::Ordering::Relaxed);
+
+    let request = Request::builder()
+        .method("GET")
+        .uri("/health/ready")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let api_response: ApiResponse = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(api_response.status, "error");
+}
+
+fn create_cors_request(method: &str, origin: &str) -> Request {
+    let builder = Request::builder()
+        .method(method)
+        .uri("/api/v1/sensor/test-device-001")
+        .header("origin", origin);
+    let builder = if method == "OPTIONS" {
+        builder.header("access-control-request-method", "GET")
+    } else {
+        builder
+    };
+
+    builder.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn test_cors_allowed_origin() {
+    let app = create_router(
+        AppState::new()
+            .with_cors_allowed_origins(vec!["https://dashboard.example.com".to_string()]),
+    );
+
+    let response = app
+        .oneshot(create_cors_request("GET", "https://dashboard.example.com"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .unwrap(),
+        "https://dashboard.example.com"
+    );
+}
+
+#[tokio::test]
+async fn test_cors_disallowed_origin() {
+    let app = create_router(
+        AppState::new()
+            .with_cors_allowed_origins(vec!["https://dashboard.example.com".to_string()]),
+    );
+
+    let response = app
+        .oneshot(create_cors_request("GET", "https://evil.example.com"))
+        .await
+        .unwrap();
+    assert!(response
+        .headers()
+        .get("access-control-allow-origin")
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_cors_preflight() {
+    let app = create_router(
+        AppState::new()
+            .with_cors_allowed_origins(vec!["https://dashboard.example.com".to_string()]),
+    );
+
+    let response = app
+        .oneshot(create_cors_request(
+            "OPTIONS",
+            "https://dashboard.example.com",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .unwrap(),
+        "https://dashboard.example.com"
+    );
+}
+
+#[tokio::test]
+async fn test_cors_is_not_applied_to_ingestion() {
+    let app = create_router(AppState::new().with_cors_allowed_origins(vec!["*".to_string()]));
+
+    let request = Request::builder()
+        .method("OPTIONS")
+        .uri("/api/v1/sensor")
+        .header("origin", "https://dashboard.example.com")
+        .header("access-control-request-method", "POST")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert!(response
+        .headers()
+        .get("access-control-allow-origin")
+        .is_none());
+}
+
+/// A metric exporter that fails every export, as if the collector can't be reached
+#[derive(Debug)]
+struct FailingMetricExporter;
+
+#[async_trait::async_trait]
+impl PushMetricExporter for FailingMetricExporter {
+    async fn export(&self, _metrics: &mut ResourceMetrics) -> MetricResult<()> {
+        Err(MetricError::Other("collector unreachable".to_string()))
+    }
+
+    async fn force_flush(&self) -> MetricResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricResult<()> {
+        Ok(())
+    }
+
+    fn temporality(&self) -> Temporality {
+        Temporality::Delta
+    }
+}
+
+/// The histograms that were exported, by name, with their bucket boundaries and the number of
+/// recorded values
+type ExportedHistograms = std::sync::Arc<std::sync::Mutex<Vec<(String, Vec<f64>, u64)>>>;
+
+/// The name and attributes of every exported gauge data point
+type ExportedGauges = std::sync::Arc<std::sync::Mutex<Vec<(String, Vec<KeyValue>)>>>;
+
+/// A metric exporter that keeps the exported histograms and gauges
+#[derive(Debug, Default)]
+struct RecordingMetricExporter {
+    histograms: ExportedHistograms,
+    gauges: ExportedGauges,
+}
+
+#[async_trait::async_trait]
+impl PushMetricExporter for RecordingMetricExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricResult<()> {
+        let mut histograms = self.histograms.lock().unwrap();
+        let mut gauges = self.gauges.lock().unwrap();
+        for scope_metrics in &metrics.scope_metrics {
+            for metric in &scope_metrics.metrics {
+                if let Some(gauge) = metric
+                    .data
+                    .as_any()
+                    .downcast_ref::<opentelemetry_sdk::metrics::data::Gauge<f64>>()
+                {
+                    for data_point in &gauge.data_points {
+                        gauges.push((metric.name.to_string(), data_point.attributes.clone()));
+                    }
+                }
+
+                if let Some(histogram) = metric
+                    .data
+                    .as_any()
+                    .downcast_ref::<opentelemetry_sdk::metrics::data::Histogram<f64>>(
+                ) {
+                    for data_point in &histogram.data_points {
+                        histograms.push((
+                            metric.name.to_string(),
+                            data_point.bounds.clone(),
+                            data_point.count,
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn force_flush(&self) -> MetricResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricResult<()> {
+        Ok(())
+    }
+
+    fn temporality(&self) -> Temporality {
+        Temporality::Cumulative
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_run_time_and_wifi_start_time_are_recorded_as_histograms() {
+    let exporter = RecordingMetricExporter::default();
+    let histograms = exporter.histograms.clone();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter, runtime::Tokio).build())
+        .build();
+    let instruments =
+        DeviceInstruments::from_meter("1.0.0", meter_provider.meter("histogram-test"));
+
+    for (run_time, wifi_start_time) in [(4.0, 0.8), (6.5, 1.5), (12.0, 3.0), (45.0, 20.0)] {
+        let data = SensorData {
+            device_id: "histogram-test-device".to_string(),
+            run_time_in_seconds: run_time,
+            wifi_start_time_in_seconds: wifi_start_time,
+            ..create_valid_sensor_data()
+        };
+        record_sensor_metrics(&instruments, &data);
+    }
+
+    meter_provider.force_flush().unwrap();
+
+    let histograms = histograms.lock().unwrap();
+    let wifi_start_time = histograms
+        .iter()
+        .find(|(name, _, _)| name == "wifi_start_time_distribution")
+        .expect("The WiFi start time histogram should be exported");
+    assert_eq!(
+        wifi_start_time.1,
+        WIFI_START_TIME_BUCKETS_IN_SECONDS.to_vec()
+    );
+    assert_eq!(wifi_start_time.2, 4);
+
+    let run_time = histograms
+        .iter()
+        .find(|(name, _, _)| name == "run_time_distribution")
+        .expect("The run time histogram should be exported");
+    assert_eq!(run_time.1, RUN_TIME_BUCKETS_IN_SECONDS.to_vec());
+    assert_eq!(run_time.2, 4);
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(
+        metrics
+            .contains("wifi_start_time_distribution_count{device_id=\"histogram-test-device\"} 4"),
+        "The histograms should be scraped. Metrics were: {}",
+        metrics
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_level_rate_is_recorded_as_a_histogram() {
+    let exporter = RecordingMetricExporter::default();
+    let histograms = exporter.histograms.clone();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter, runtime::Tokio).build())
+        .build();
+    let instruments =
+        DeviceInstruments::from_meter("1.0.0", meter_provider.meter("level-rate-test"));
+
+    // Usage, a refill and two readings that arrived at the same time
+    let history = create_sensor_history(1, &[1.50, 1.45, 1.90]);
+    let attributes = device_attributes(&history[0].data);
+    record_level_rate(&instruments, &attributes, &history[0], &history[1]);
+    record_level_rate(&instruments, &attributes, &history[1], &history[2]);
+    record_level_rate(&instruments, &attributes, &history[2], &history[2]);
+
+    meter_provider.force_flush().unwrap();
+
+    let histograms = histograms.lock().unwrap();
+    let level_rate: Vec<_> = histograms
+        .iter()
+        .filter(|(name, _, _)| name == "water_level_delta_per_hour")
+        .collect();
+    assert!(
+        !level_rate.is_empty(),
+        "The level rate histogram should be exported"
+    );
+    for (_, boundaries, _) in &level_rate {
+        assert_eq!(
+            *boundaries,
+            WATER_LEVEL_RATE_BUCKETS_IN_METERS_PER_HOUR.to_vec()
+        );
+    }
+    // Usage and refills are separate data points, the readings without elapsed time are skipped
+    let count: u64 = level_rate.iter().map(|(_, _, count)| count).sum();
+    assert_eq!(count, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_metrics_have_the_device_id_as_attribute() {
+    let exporter = RecordingMetricExporter::default();
+    let gauges = exporter.gauges.clone();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter, runtime::Tokio).build())
+        .build();
+
+    // Both devices use the same meter, so only the attributes tell them apart
+    let instruments =
+        DeviceInstruments::from_meter("1.0.0", meter_provider.meter("attribute-test"));
+    for device_id in ["attribute-test-device-1", "attribute-test-device-2"] {
+        let data = SensorData {
+            device_id: device_id.to_string(),
+            ..create_valid_sensor_data()
+        };
+        record_sensor_metrics(&instruments, &data);
+    }
+
+    meter_provider.force_flush().unwrap();
+
+    let gauges = gauges.lock().unwrap();
+    for device_id in ["attribute-test-device-1", "attribute-test-device-2"] {
+        let expected = KeyValue::new(
+            opentelemetry_semantic_conventions::resource::DEVICE_ID,
+            device_id,
+        );
+        assert!(
+            gauges
+                .iter()
+                .any(|(name, attributes)| name == "water_level" && attributes.contains(&expected)),
+            "The water level of {} should be exported with the device ID. Gauges were: {:?}",
+            device_id,
+            gauges
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_metrics_have_the_device_tags_as_attributes() {
+    let exporter = RecordingMetricExporter::default();
+    let gauges = exporter.gauges.clone();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter, runtime::Tokio).build())
+        .build();
+
+    let instruments = DeviceInstruments::from_meter("1.0.0", meter_provider.meter("tag-test"));
+    let data = SensorData {
+        device_id: "tag-test-device".to_string(),
+        tags: create_tags(&[("site", "farm"), ("zone", "north")]),
+        ..create_valid_sensor_data()
+    };
+    record_sensor_metrics(&instruments, &data);
+
+    meter_provider.force_flush().unwrap();
+
+    let gauges = gauges.lock().unwrap();
+    let (_, attributes) = gauges
+        .iter()
+        .find(|(name, _)| name == "water_level")
+        .expect("The water level should be exported");
+    for expected in [
+        KeyValue::new(
+            opentelemetry_semantic_conventions::resource::DEVICE_ID,
+            "tag-test-device",
+        ),
+        KeyValue::new("site", "farm"),
+        KeyValue::new("zone", "north"),
+    ] {
+        assert!(
+            attributes.contains(&expected),
+            "The water level should have the attribute {:?}. Attributes were: {:?}",
+            expected,
+            attributes
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_failed_metric_export_is_counted() {
+    let healthy = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let exporter = HealthReportingMetricExporter {
+        inner: FailingMetricExporter,
+        healthy: healthy.clone(),
+    };
+
+    let failures_before = EXPORT_FAILURES.count("metrics");
+
+    let mut metrics = ResourceMetrics {
+        resource: Resource::empty(),
+        scope_metrics: vec![],
+    };
+    assert!(exporter.export(&mut metrics).await.is_err());
+
+    assert_eq!(EXPORT_FAILURES.count("metrics"), failures_before + 1);
+    assert!(!healthy.load(std::sync::atomic::Ordering::Relaxed));
+
+    let metrics = PROMETHEUS_METRICS.render().unwrap();
+    assert!(metrics.contains("export_failures_total{signal=\"metrics\"}"));
+}
+
+#[tokio::test]
+async fn test_selftest_reports_each_signal() {
+    let app = create_router(AppState::new());
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/selftest")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let result: SelfTestResult = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(result.test_id.len(), 16);
+    assert!(chrono::DateTime::parse_from_rfc3339(&result.timestamp).is_ok());
+
+    let signals: Vec<&str> = result.signals.iter().map(|s| s.signal.as_str()).collect();
+    assert_eq!(signals, vec!["metrics", "logs", "traces"]);
+
+    // Without the providers the telemetry can't be flushed
+    assert!(result.signals.iter().all(|s| !s.flushed));
+}
+
+#[test]
+fn test_export_failures_are_logged_once_per_interval_per_signal() {
+    let failures = ExportFailures::new(None, std::time::Duration::from_secs(60));
+    let now = std::time::Instant::now();
+
+    assert!(failures.should_log("traces", now));
+    assert!(!failures.should_log("traces", now + std::time::Duration::from_secs(10)));
+
+    // A different signal is logged straight away
+    assert!(failures.should_log("logs", now));
+
+    // The signal is logged again once the interval has passed
+    assert!(failures.should_log("traces", now + std::time::Duration::from_secs(61)));
+    assert_eq!(failures.last_logged.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_sdk_errors_are_counted_by_signal() {
+    assert_eq!(
+        sdk_error_signal("BatchSpanProcessor.SpanDroppingStarted"),
+        Some("traces")
+    );
+    assert_eq!(
+        sdk_error_signal("BatchLogProcessor.LogsDropped"),
+        Some("logs")
+    );
+    assert_eq!(
+        sdk_error_signal("PeriodReaderCollectError"),
+        Some("metrics")
+    );
+    assert_eq!(
+        sdk_error_signal("BaggagePropagator.Extract.InvalidUTF8"),
+        Some("other")
+    );
+
+    // The exporters count the failed exports themselves
+    assert_eq!(sdk_error_signal("BatchSpanProcessor.Export.Error"), None);
+    assert_eq!(sdk_error_signal("PeriodicReader.ExportFailed"), None);
+}
+
+#[test]
+fn test_sdk_error_events_are_counted_as_export_failures() {
+    let subscriber = tracing_subscriber::registry().with(SdkErrorCounter);
+    let failures_before = EXPORT_FAILURES.count("traces");
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::warn!(name: "BatchSpanProcessor.SpanDroppingStarted", "");
+    });
+
+    assert_eq!(EXPORT_FAILURES.count("traces"), failures_before + 1);
+}
+
+fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn create_signed_sensor_request(body: &str, timestamp: i64, signature: Option<&str>) -> Request {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_TIMESTAMP_HEADER_NAME, timestamp.to_string());
+    if let Some(value) = signature {
+        builder = builder.header(SIGNATURE_HEADER_NAME, value);
+    }
+
+    builder.body(Body::from(body.to_string())).unwrap()
+}
+
+#[test]
+fn test_signature_from_the_device_is_valid() {
+    // The signature that the device creates for this secret, timestamp and body, see the auth
+    // tests of the tank-sensor-level-core crate
+    let signature = "b04351e03a6ea4f58fc2a7307c9c623465ff36bbeade3905451f19e6576dbc1f";
+
+    assert!(signature_is_valid(
+        "test-secret",
+        "1700000000",
+        br#"{"device_id":"tank"}"#,
+        signature
+    ));
+    assert!(!signature_is_valid(
+        "test-secret",
+        "1700000001",
+        br#"{"device_id":"tank"}"#,
+        signature
+    ));
+}
+
+#[tokio::test]
+async fn test_sensor_data_with_valid_signature_is_accepted() {
+    let app = create_router(AppState::new().with_ingest_hmac_secret(Some("secret".to_string())));
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let timestamp = Utc::now().timestamp();
+    let signature = sign("secret", &timestamp.to_string(), &body);
+
+    let response = app
+        .oneshot(create_signed_sensor_request(
+            &body,
+            timestamp,
+            Some(&signature),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_signed_sensor_data_within_a_large_body_limit_is_accepted() {
+    let limit = 2 * 1024 * 1024;
+    let app = create_router(
+        AppState::new()
+            .with_ingest_hmac_secret(Some("secret".to_string()))
+            .with_sensor_body_limit_in_bytes(limit),
+    );
+
+    // Whitespace keeps the JSON valid while making the body larger than 1 MiB
+    let json = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let body = format!("{json}{}", " ".repeat(limit - json.len()));
+    let timestamp = Utc::now().timestamp();
+    let signature = sign("secret", &timestamp.to_string(), &body);
+
+    let response = app
+        .oneshot(create_signed_sensor_request(
+            &body,
+            timestamp,
+            Some(&signature),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_signed_sensor_data_over_the_body_limit_is_rejected() {
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let app = create_router(
+        AppState::new()
+            .with_ingest_hmac_secret(Some("secret".to_string()))
+            .with_sensor_body_limit_in_bytes(body.len() - 1),
+    );
+
+    let timestamp = Utc::now().timestamp();
+    let signature = sign("secret", &timestamp.to_string(), &body);
+
+    let response = app
+        .oneshot(create_signed_sensor_request(
+            &body,
+            timestamp,
+            Some(&signature),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_gzip_sensor_data_with_valid_signature_is_accepted() {
+    let app = create_router(AppState::new().with_ingest_hmac_secret(Some("secret".to_string())));
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let timestamp = Utc::now().timestamp();
+    let signature = sign("secret", &timestamp.to_string(), &body);
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_ENCODING, "gzip")
+        .header(SIGNATURE_TIMESTAMP_HEADER_NAME, timestamp.to_string())
+        .header(SIGNATURE_HEADER_NAME, signature)
+        .body(Body::from(gzip(&body)))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_sensor_data_with_tampered_body_is_rejected() {
+    let app = create_router(AppState::new().with_ingest_hmac_secret(Some("secret".to_string())));
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let timestamp = Utc::now().timestamp();
+    let signature = sign("secret", &timestamp.to_string(), &body);
+
+    let tampered_body = body.replace("\"boot_count\":1", "\"boot_count\":2");
+    assert_ne!(body, tampered_body);
+
+    let response = app
+        .oneshot(create_signed_sensor_request(
+            &tampered_body,
+            timestamp,
+            Some(&signature),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+fn create_checksummed_sensor_request(body: &str, checksum: &str) -> Request {
+    Request::builder()
+        .method("POST")
+        .uri("/api/v1/sensor")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(PAYLOAD_CRC_HEADER_NAME, checksum)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[test]
+fn test_crc32_matches_the_standard_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[tokio::test]
+async fn test_checksummed_sensor_data_within_a_large_body_limit_is_accepted() {
+    let limit = 2 * 1024 * 1024;
+    let app = create_router(AppState::new().with_sensor_body_limit_in_bytes(limit));
+
+    // Whitespace keeps the JSON valid while making the body larger than 1 MiB
+    let json = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let body = format!("{json}{}", " ".repeat(limit - json.len()));
+    let checksum = format!("{:08x}", crc32(body.as_bytes()));
+
+    let response = app
+        .oneshot(create_checksummed_sensor_request(&body, &checksum))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_checksummed_sensor_data_over_the_body_limit_is_rejected() {
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let app = create_router(AppState::new().with_sensor_body_limit_in_bytes(body.len() - 1));
+    let checksum = format!("{:08x}", crc32(body.as_bytes()));
+
+    let response = app
+        .oneshot(create_checksummed_sensor_request(&body, &checksum))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_sensor_data_with_matching_checksum_is_accepted() {
+    let app = create_router(AppState::new());
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let checksum = format!("{:08x}", crc32(body.as_bytes()));
+
+    let response = app
+        .oneshot(create_checksummed_sensor_request(&body, &checksum))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_sensor_data_with_mismatched_checksum_is_rejected_as_corrupt() {
+    let app = create_router(AppState::new());
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let checksum = format!("{:08x}", crc32(body.as_bytes()));
+
+    let tampered_body = body.replace("\"boot_count\":1", "\"boot_count\":2");
+    assert_ne!(body, tampered_body);
+
+    let response = app
+        .oneshot(create_checksummed_sensor_request(&tampered_body, &checksum))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let response: ApiResponse = serde_json::from_slice(&body).unwrap();
+    assert!(response.message.starts_with("Corrupt payload"));
+}
+
+#[tokio::test]
+async fn test_sensor_data_with_invalid_checksum_header_is_rejected() {
+    let app = create_router(AppState::new());
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+
+    let response = app
+        .oneshot(create_checksummed_sensor_request(&body, "not-hex"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_sensor_data_with_stale_signature_is_rejected() {
+    let app = create_router(
+        AppState::new()
+            .with_ingest_hmac_secret(Some("secret".to_string()))
+            .with_signature_max_age_in_seconds(60),
+    );
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let timestamp = Utc::now().timestamp() - 61;
+    let signature = sign("secret", &timestamp.to_string(), &body);
+
+    let response = app
+        .oneshot(create_signed_sensor_request(
+            &body,
+            timestamp,
+            Some(&signature),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_sensor_data_without_signature_is_rejected() {
+    let app = create_router(AppState::new().with_ingest_hmac_secret(Some("secret".to_string())));
+
+    let body = serde_json::to_string(&create_valid_sensor_data()).unwrap();
+    let response = app
+        .oneshot(create_signed_sensor_request(
+            &body,
+            Utc::now().timestamp(),
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_timing_response_contains_server_time() {
+    let app = create_router(AppState::new());
+
+    let response = app.oneshot(create_timing_request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: ApiResponse = serde_json::from_slice(&body_bytes).unwrap();
+    let server_time = body.server_time_in_seconds.unwrap();
+    assert!((Utc::now().timestamp() - server_time).abs() <= 5);
+}
+
+#[tokio::test]
+async fn test_timing_response_contains_ntp_resync_seconds() {
+    let state = AppState::new().with_ntp_resync_seconds(Some(21600));
+    let app = create_router(state.clone());
+
+    let response = app.oneshot(create_timing_request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: ApiResponse = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body.ntp_resync_seconds, Some(21600));
+
+    // The interval that the device was asked to use is stored with the device
+    let mappings = state.device_time_mappings.read().await;
+    assert_eq!(
+        mappings.get("auth-test-device").unwrap().ntp_resync_seconds,
+        Some(21600)
+    );
+}
+
+#[tokio::test]
+async fn test_timing_response_without_ntp_resync_seconds() {
+    let state = AppState::new();
+    let app = create_router(state.clone());
+
+    let response = app.oneshot(create_timing_request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Without a configured interval the field is left out so that the devices use their default
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert!(body.get("ntp_resync_seconds").is_none());
+
+    let mappings = state.device_time_mappings.read().await;
+    assert_eq!(
+        mappings.get("auth-test-device").unwrap().ntp_resync_seconds,
+        None
+    );
+}
+
+fn create_time_mapping(boot_count: u32, first_tick: u64) -> DeviceTimeMapping {
+    DeviceTimeMapping {
+        boot_count,
+        first_tick,
+        first_timestamp: Utc::now() - chrono::Duration::seconds(5),
+        ntp_resync_seconds: None,
+    }
+}
+
+#[test]
+fn test_anchor_time_mapping_keeps_the_anchor_for_the_same_boot() {
+    let previous = create_time_mapping(42, 1000);
+
+    let mapping = anchor_time_mapping(Some(&previous), 42, 3000, Utc::now(), Some(21600));
+
+    assert_eq!(mapping.boot_count, 42);
+    assert_eq!(mapping.first_tick, 1000);
+    assert_eq!(mapping.first_timestamp, previous.first_timestamp);
+    assert_eq!(mapping.ntp_resync_seconds, Some(21600));
+}
+
+#[test]
+fn test_anchor_time_mapping_anchors_a_new_boot() {
+    let previous = create_time_mapping(42, 1000);
+    let now = Utc::now();
+
+    let mapping = anchor_time_mapping(Some(&previous), 43, 3000, now, None);
+
+    assert_eq!(mapping.boot_count, 43);
+    assert_eq!(mapping.first_tick, 3000);
+    assert_eq!(mapping.first_timestamp, now);
+}
+
+#[test]
+fn test_anchor_time_mapping_anchors_again_when_the_ticks_start_over() {
+    let previous = create_time_mapping(42, 5000);
+    let now = Utc::now();
+
+    let mapping = anchor_time_mapping(Some(&previous), 42, 1000, now, None);
+
+    assert_eq!(mapping.first_tick, 1000);
+    assert_eq!(mapping.first_timestamp, now);
+}
+
+#[test]
+fn test_anchor_time_mapping_anchors_the_first_timing_data() {
+    let now = Utc::now();
+
+    let mapping = anchor_time_mapping(None, 1, 1000, now, None);
+
+    assert_eq!(mapping.first_tick, 1000);
+    assert_eq!(mapping.first_timestamp, now);
+}
+
+#[test]
+fn test_time_mapping_timestamp_before_the_anchor() {
+    let mapping = create_time_mapping(42, 5000);
+
+    assert_eq!(
+        mapping.timestamp_at(3000),
+        mapping.first_timestamp - chrono::Duration::milliseconds(2000)
+    );
+}
+
+#[tokio::test]
+async fn test_timing_data_of_the_same_boot_keeps_the_mapping() {
+    let state = AppState::new();
+    let post = |timestamp| {
+        handle_device_timing(
+            State(state.clone()),
+            None,
+            Ok(Json(DeviceTimingData {
+                device_id: "garden-tank".to_string(),
+                boot_count: 42,
+                timestamp,
+                session_id: None,
+            })),
+        )
+    };
+
+    assert!(post(1000).await.is_ok());
+    let first = state.device_time_mappings.read().await["garden-tank"].clone();
+    assert!(post(2500).await.is_ok());
+    let second = state.device_time_mappings.read().await["garden-tank"].clone();
+
+    assert_eq!(second.first_tick, first.first_tick);
+    assert_eq!(second.first_timestamp, first.first_timestamp);
+}
+
+#[tokio::test]
+async fn test_timing_data_with_implausible_ticks_is_rejected() {
+    let state = AppState::new();
+
+    let result = handle_device_timing(
+        State(state.clone()),
+        None,
+        Ok(Json(DeviceTimingData {
+            device_id: "garden-tank".to_string(),
+            boot_count: 42,
+            timestamp: MAX_DEVICE_TICKS + 1,
+            session_id: None,
+        })),
+    )
+    .await;
+
+    match result {
+        Ok(_) => panic!("Timing data with implausible ticks should be rejected"),
+        Err((status, Json(response))) => {
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(response.field.as_deref(), Some("timestamp"));
+        }
+    }
+    assert!(state.device_time_mappings.read().await.is_empty());
+}
+
+async fn provision(
+    app: Router,
+    hardware_id: &str,
+    device_id: Option<&str>,
+) -> (StatusCode, ApiResponse) {
+    provision_with_token(app, hardware_id, device_id, None).await
+}
+
+async fn provision_with_token(
+    app: Router,
+    hardware_id: &str,
+    device_id: Option<&str>,
+    token: Option<&str>,
+) -> (StatusCode, ApiResponse) {
+    let provision_request = ProvisionRequest {
+        hardware_id: hardware_id.to_string(),
+        device_id: device_id.map(|id| id.to_string()),
+    };
+    let mut request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/provision")
+        .header(header::CONTENT_TYPE, "application/json");
+    if let Some(token) = token {
+        request = request.header(DEVICE_TOKEN_HEADER_NAME, token);
+    }
+    let request = request
+        .body(Body::from(
+            serde_json::to_string(&provision_request).unwrap(),
+        ))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+fn create_timing_request_with_token(token: Option<&str>) -> Request {
+    let mut request = create_timing_request(None);
+    if let Some(token) = token {
+        request.headers_mut().insert(
+            DEVICE_TOKEN_HEADER_NAME,
+            header::HeaderValue::from_str(token).unwrap(),
+        );
+    }
+
+    request
+}
+
+#[test]
+fn test_validate_hardware_id() {
+    assert!(validate_hardware_id("AA:BB:CC:DD:EE:FF").is_ok());
+    assert!(validate_hardware_id("esp32c6_0001-a").is_ok());
+
+    assert_eq!(
+        validate_hardware_id("").unwrap_err(),
+        "The hardware ID should not be empty."
+    );
+    assert_eq!(
+        validate_hardware_id(&"a".repeat(MAX_HARDWARE_ID_LENGTH + 1)).unwrap_err(),
+        "The hardware ID should be at most 64 characters long."
+    );
+    assert!(validate_hardware_id("AA BB").is_err());
+    assert!(validate_hardware_id("AA/BB").is_err());
+}
+
+#[tokio::test]
+async fn test_provision_new_device() {
+    let state = AppState::new();
+    let app = create_router(state.clone());
+
+    let (status, response) = provision(app, "AA:BB:CC:DD:EE:01", Some("garden-tank")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(response.device_id.as_deref(), Some("garden-tank"));
+
+    let token = response.device_token.unwrap();
+    assert_eq!(token.len(), 2 * DEVICE_TOKEN_LENGTH);
+    assert!(token.bytes().all(|b| b.is_ascii_hexdigit()));
+
+    let registrations = state.device_registrations.read().await;
+    assert_eq!(
+        registrations.get("AA:BB:CC:DD:EE:01"),
+        Some(&DeviceRegistration {
+            device_id: "garden-tank".to_string(),
+            token,
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_provision_assigns_a_device_id() {
+    let app = create_router(AppState::new());
+
+    let (status, response) = provision(app, "AA:BB:CC:DD:EE:02", None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(response.device_id.as_deref(), Some("device-aabbccddee02"));
+}
+
+#[tokio::test]
+async fn test_reprovision_returns_the_same_token() {
+    let app = create_router(AppState::new());
+
+    let (status, first) = provision(app.clone(), "AA:BB:CC:DD:EE:03", Some("tank_3")).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // The device keeps its device ID, even if it asks for another one
+    let (status, second) = provision_with_token(
+        app,
+        "AA:BB:CC:DD:EE:03",
+        Some("tank_4"),
+        first.device_token.as_deref(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(second.device_id.as_deref(), Some("tank_3"));
+    assert_eq!(second.device_token, first.device_token);
+}
+
+#[tokio::test]
+async fn test_reprovision_without_the_token_is_rejected() {
+    let app = create_router(AppState::new());
+
+    let (status, _) = provision(app.clone(), "AA:BB:CC:DD:EE:08", Some("tank_8")).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Anyone can read the hardware ID of a device, so it doesn't prove anything
+    let (status, response) = provision(app.clone(), "AA:BB:CC:DD:EE:08", Some("tank_8")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(response.device_token, None);
+
+    let (status, response) =
+        provision_with_token(app, "AA:BB:CC:DD:EE:08", Some("tank_8"), Some("wrong")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(response.device_token, None);
+}
+
+#[tokio::test]
+async fn test_provision_rejects_a_device_id_of_another_device() {
+    let app = create_router(AppState::new());
+
+    let (status, _) = provision(app.clone(), "AA:BB:CC:DD:EE:04", Some("tank_5")).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, response) = provision(app, "AA:BB:CC:DD:EE:05", Some("tank_5")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(response.device_token, None);
+}
+
+#[tokio::test]
+async fn test_provision_rejects_an_invalid_hardware_id() {
+    let app = create_router(AppState::new());
+
+    let (status, response) = provision(app, "", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.message, "The hardware ID should not be empty.");
+}
+
+#[tokio::test]
+async fn test_provision_rejects_an_invalid_device_id() {
+    let app = create_router(AppState::new());
+
+    let (status, response) = provision(app.clone(), "AA:BB:CC:DD:EE:07", Some("garden tank")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.device_token, None);
+
+    // The device ID that the server assigns is always valid
+    let hardware_id = "A".repeat(MAX_HARDWARE_ID_LENGTH);
+    let (status, response) = provision(app, &hardware_id, None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let device_id = response.device_id.unwrap();
+    assert_eq!(device_id.len(), MAX_DEVICE_ID_LENGTH);
+    assert!(validate_device_id(&device_id).is_ok());
+}
+
+#[tokio::test]
+async fn test_ingestion_checks_the_device_token() {
+    let app = create_router(AppState::new());
+
+    // Devices that weren't provisioned don't send a token
+    let response = app
+        .clone()
+        .oneshot(create_timing_request_with_token(None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (_, response) = provision(app.clone(), "AA:BB:CC:DD:EE:06", Some("auth-test-device")).await;
+    let token = response.device_token.unwrap();
+
+    // Once the device is provisioned its data is only accepted with the token
+    let response = app
+        .clone()
+        .oneshot(create_timing_request_with_token(None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = app
+        .clone()
+        .oneshot(create_timing_request_with_token(Some("wrong")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .oneshot(create_timing_request_with_token(Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_device_token_of_another_device_is_rejected() {
+    let app = create_router(AppState::new());
+
+    let (_, response) = provision(app.clone(), "AA:BB:CC:DD:EE:07", Some("other-device")).await;
+    let token = response.device_token.unwrap();
+
+    // The timing request is for auth-test-device
+    let response = app
+        .oneshot(create_timing_request_with_token(Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_device_registrations_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!(
+        "tsl_service_device_registrations_{}.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let registrations = load_device_registrations(&path).unwrap();
+    assert!(registrations.is_empty());
+    let app =
+        create_router(AppState::new().with_device_registrations(registrations, Some(path.clone())));
+    let (status, response) = provision(app, "AA:BB:CC:DD:EE:09", Some("auth-test-device")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let token = response.device_token.unwrap();
+
+    // The restarted service still knows the device and its token
+    let registrations = load_device_registrations(&path).unwrap();
+    let app =
+        create_router(AppState::new().with_device_registrations(registrations, Some(path.clone())));
+
+    let response = app
+        .clone()
+        .oneshot(create_timing_request_with_token(None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = app
+        .oneshot(create_timing_request_with_token(Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_provision_fails_if_the_registration_cannot_be_stored() {
+    let path = std::env::temp_dir()
+        .join("tsl_service_missing_directory")
+        .join("device_registrations.json");
+    let state =
+        AppState::new().with_device_registrations(std::collections::HashMap::new(), Some(path));
+    let app = create_router(state.clone());
+
+    let (status, response) = provision(app, "AA:BB:CC:DD:EE:0A", Some("tank_10")).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.device_token, None);
+    assert!(state.device_registrations.read().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_serve_returns_after_shutdown_signal() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+
+    let state = AppState::new();
+    let server = tokio::spawn(serve_until_shutdown(
+        listener,
+        create_router(state.clone()),
+        state,
+        async move {
+            let _ = shutdown_receiver.await;
+        },
+    ));
+
+    shutdown_sender.send(()).unwrap();
+
+    let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+        .await
+        .expect("The server should stop after the shutdown signal")
+        .unwrap();
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_serve_returns_after_shutdown_signal_with_an_open_live_stream() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+
+    let state = AppState::new();
+    let server = tokio::spawn(serve_until_shutdown(
+        listener,
+        create_router(state.clone()),
+        state,
+        async move {
+            let _ = shutdown_receiver.await;
+        },
+    ));
+
+    let stream = reqwest::get(format!("http://{address}/api/v1/stream"))
+        .await
+        .unwrap();
+    assert_eq!(stream.status(), StatusCode::OK);
+
+    shutdown_sender.send(()).unwrap();
+
+    let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+        .await
+        .expect("The server should stop while a live stream is open")
+        .unwrap();
+    assert!(result.is_ok());
+
+    // The stream ended instead of being cut off
+    let body = tokio::time::timeout(std::time::Duration::from_secs(5), stream.bytes())
+        .await
+        .expect("The live stream should end when the server stops");
+    assert!(body.is_ok());
+}
+
+#[test]
+fn test_observability_config_from_env() {
+    // Save original environment
+    let original_metrics = std::env::var("METRICS_PUSH_URL").ok();
+    let original_tracing = std::env::var("TRACING_PUSH_URL").ok();
+    let original_logs = std::env::var("LOGS_PUSH_URL").ok();
+
+    // Set test environment variables
+    std::env::set_var("METRICS_PUSH_URL", "http://test-metrics:4317");
+    std::env::set_var("TRACING_PUSH_URL", "http://test-tracing:4317");
+    std::env::set_var("LOGS_PUSH_URL", "http://test-logs:4317");
+
+    let config = ObservabilityConfig {
+        metrics_push_url: std::env::var("METRICS_PUSH_URL")
+            .unwrap_or_else(|_| "http://localhost:4317".to_string()),
+        trace_push_url: std::env::var("TRACING_PUSH_URL")
+            .unwrap_or_else(|_| "http://localhost:4317".to_string()),
+        logs_push_url: std::env::var("LOGS_PUSH_URL")
+            .unwrap_or_else(|_| "http://localhost:4317".to_string()),
+        metrics_export_interval: std::time::Duration::from_secs(
+            DEFAULT_METRICS_EXPORT_INTERVAL_IN_SECONDS,
+        ),
+    };
 
     assert_eq!(config.metrics_push_url, "http://test-metrics:4317");
     assert_eq!(config.trace_push_url, "http://test-tracing:4317");
@@ -402,6 +5197,47 @@ fn test_observability_config_from_env() {
     }
 }
 
+#[test]
+fn test_parse_metrics_export_interval() {
+    assert_eq!(
+        parse_metrics_export_interval("1"),
+        Ok(std::time::Duration::from_secs(1))
+    );
+    assert_eq!(
+        parse_metrics_export_interval(" 15 "),
+        Ok(std::time::Duration::from_secs(15))
+    );
+    assert_eq!(
+        parse_metrics_export_interval("600"),
+        Ok(std::time::Duration::from_secs(600))
+    );
+
+    for value in ["0", "601", "-5", "1.5", "", "soon"] {
+        assert!(
+            parse_metrics_export_interval(value).is_err(),
+            "{} should not be a valid export interval",
+            value
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_metrics_provider_builds_with_a_custom_export_interval() {
+    let config = ObservabilityConfig {
+        metrics_push_url: "http://localhost:4317".to_string(),
+        trace_push_url: "http://localhost:4317".to_string(),
+        logs_push_url: "http://localhost:4317".to_string(),
+        metrics_export_interval: parse_metrics_export_interval("5").unwrap(),
+    };
+
+    let provider = init_metrics(
+        &config,
+        std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true)),
+    )
+    .expect("The meter provider should build with a custom export interval");
+    provider.shutdown().unwrap();
+}
+
 #[test]
 fn test_observability_config_defaults() {
     // Save original environment
@@ -421,6 +5257,9 @@ fn test_observability_config_defaults() {
             .unwrap_or_else(|_| "http://localhost:4317".to_string()),
         logs_push_url: std::env::var("LOGS_PUSH_URL")
             .unwrap_or_else(|_| "http://localhost:4317".to_string()),
+        metrics_export_interval: std::time::Duration::from_secs(
+            DEFAULT_METRICS_EXPORT_INTERVAL_IN_SECONDS,
+        ),
     };
 
     assert_eq!(config.metrics_push_url, "http://localhost:4317");